@@ -0,0 +1,243 @@
+//! `include_pug!`, which compiles a template when the macro is expanded and
+//! embeds the HTML as a string literal, and the sources behind `templates!`.
+//!
+//! Used through `pug_cli` with the `macros` feature.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitStr, Token};
+
+struct IncludePug {
+    path: LitStr,
+    pretty: bool,
+    doctype: Option<String>,
+}
+
+impl Parse for IncludePug {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path = input.parse()?;
+        let mut include = IncludePug {
+            path,
+            pretty: false,
+            doctype: None,
+        };
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let option: Ident = input.parse()?;
+            match option.to_string().as_str() {
+                "pretty" => include.pretty = true,
+                "doctype" => {
+                    input.parse::<Token![=]>()?;
+                    include.doctype = Some(input.parse::<LitStr>()?.value());
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        option.span(),
+                        "expected `pretty` or `doctype = \"...\"`",
+                    ))
+                }
+            }
+        }
+        Ok(include)
+    }
+}
+
+/// Compiles a template at build time: `include_pug!("templates/404.pug")`.
+///
+/// The path is relative to `CARGO_MANIFEST_DIR`. `pretty` and
+/// `doctype = "..."` may follow it. A template pug can't compile is a compile
+/// error at the macro call, and the template is tracked like `include_str!`
+/// so editing it triggers a rebuild.
+#[proc_macro]
+pub fn include_pug(input: TokenStream) -> TokenStream {
+    let include = syn::parse_macro_input!(input as IncludePug);
+    match compile(&include) {
+        Ok((path, html)) => {
+            let path = path.to_string_lossy();
+            quote!({
+                const _: &[u8] = include_bytes!(#path);
+                #html
+            })
+            .into()
+        }
+        Err(message) => syn::Error::new(include.path.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+/// The sources behind `pug_cli::templates!`: every `.pug` file under the
+/// directory, relative to `CARGO_MANIFEST_DIR`, as a sorted
+/// `&[(name, include_str!(file))]` where the name is the path from the
+/// directory without the extension, `pages/home`.
+///
+/// A file name or source that isn't UTF-8 is a compile error, as are two
+/// names that differ only in case, which can't be checked out side by side
+/// on a case-insensitive filesystem.
+#[doc(hidden)]
+#[proc_macro]
+pub fn embed_templates(input: TokenStream) -> TokenStream {
+    let dir = syn::parse_macro_input!(input as LitStr);
+    match embedded(&dir.value()) {
+        Ok(templates) => {
+            let (names, paths): (Vec<_>, Vec<_>) = templates.into_iter().unzip();
+            quote!(&[#((#names, include_str!(#paths))),*]).into()
+        }
+        Err(message) => syn::Error::new(dir.span(), message)
+            .to_compile_error()
+            .into(),
+    }
+}
+
+/// The name and full path of each template under `dir`, by name.
+fn embedded(dir: &str) -> Result<Vec<(String, String)>, String> {
+    let root = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(dir);
+    if !root.is_dir() {
+        return Err(format!("{} isn't a directory", dir));
+    }
+    let mut files = Vec::new();
+    find_templates(&root, &mut files).map_err(|e| format!("couldn't read {}: {}", dir, e))?;
+
+    let mut templates = BTreeMap::new();
+    let mut folded: BTreeMap<String, String> = BTreeMap::new();
+    for file in files {
+        let relative = file.strip_prefix(&root).unwrap_or(&file);
+        let shown = relative.display();
+        let mut name = String::new();
+        for component in relative.with_extension("").components() {
+            let component = component
+                .as_os_str()
+                .to_str()
+                .ok_or_else(|| format!("{} doesn't have a UTF-8 name", shown))?;
+            if !name.is_empty() {
+                name.push('/');
+            }
+            name.push_str(component);
+        }
+        let source = std::fs::read(&file).map_err(|e| format!("couldn't read {}: {}", shown, e))?;
+        if std::str::from_utf8(&source).is_err() {
+            return Err(format!("{} isn't UTF-8", shown));
+        }
+        if let Some(other) = folded.insert(name.to_lowercase(), name.clone()) {
+            return Err(format!(
+                "templates {:?} and {:?} differ only in case",
+                other, name
+            ));
+        }
+        let path = file
+            .to_str()
+            .ok_or_else(|| format!("{} doesn't have a UTF-8 path", shown))?;
+        templates.insert(name, path.to_owned());
+    }
+    Ok(templates.into_iter().collect())
+}
+
+fn find_templates(dir: &Path, templates: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_templates(&path, templates)?;
+        } else if path.extension().is_some_and(|extension| extension == "pug") {
+            templates.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn compile(include: &IncludePug) -> Result<(PathBuf, String), String> {
+    let root = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    let path = root.join(include.path.value());
+    let source = std::fs::read(&path)
+        .map_err(|e| format!("couldn't read {}: {}", include.path.value(), e))?;
+
+    let mut command = Command::new("pug");
+    command.arg("--path").arg(&path);
+    if include.pretty {
+        command.arg("--pretty");
+    }
+    if let Some(doctype) = &include.doctype {
+        command.arg("--doctype").arg(doctype);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("couldn't run pug: {}", e))?;
+    // an early exit closes stdin; stderr explains it
+    let _ = child.stdin.take().unwrap().write_all(&source);
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("couldn't run pug: {}", e))?;
+
+    if output.stderr.is_empty() && output.status.success() {
+        Ok((path, String::from_utf8_lossy(&output.stdout).into()))
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!(
+            "pug failed to compile {}: {}",
+            include.path.value(),
+            summarize(&stderr)
+        ))
+    }
+}
+
+/// `line:column: message` from pug's error output, or its first line.
+fn summarize(stderr: &str) -> String {
+    let mut lines = stderr.lines();
+    let header = lines.by_ref().find_map(|line| {
+        let line = line.trim();
+        let location = line.strip_prefix("Error: ").unwrap_or(line);
+        let mut parts = location.rsplitn(3, ':');
+        let column: u32 = parts.next()?.parse().ok()?;
+        let line: u32 = parts.next()?.parse().ok()?;
+        parts.next()?;
+        Some(format!("{}:{}", line, column))
+    });
+    let header = match header {
+        Some(header) => header,
+        None => {
+            return stderr
+                .lines()
+                .next()
+                .unwrap_or("no output")
+                .trim()
+                .to_owned()
+        }
+    };
+    // the message follows the source excerpt and a blank line
+    let message = lines
+        .skip_while(|line| !line.trim().is_empty())
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("")
+        .trim();
+    format!("{}: {}", header, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::embedded;
+
+    #[test]
+    fn rejects_templates_that_arent_utf8() {
+        // embedded resolves the directory from CARGO_MANIFEST_DIR, which cargo
+        // sets to macros/ for these tests.
+        assert_eq!(
+            embedded("tests/ui/non-utf8"),
+            Err("menu.pug isn't UTF-8".to_owned())
+        );
+    }
+}
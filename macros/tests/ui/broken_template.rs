@@ -0,0 +1,7 @@
+// trybuild compiles this from target/tests/trybuild/pug_cli_macros, so the
+// template is reached from there.
+const PAGE: &str = pug_cli_macros::include_pug!("../../../../macros/tests/ui/broken.pug");
+
+fn main() {
+    println!("{}", PAGE);
+}
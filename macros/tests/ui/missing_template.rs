@@ -0,0 +1,5 @@
+const PAGE: &str = pug_cli_macros::include_pug!("tests/ui/no-such-template.pug");
+
+fn main() {
+    println!("{}", PAGE);
+}
@@ -0,0 +1,5 @@
+const PAGE: &str = pug_cli_macros::include_pug!("tests/ui/broken.pug", minify);
+
+fn main() {
+    println!("{}", PAGE);
+}
@@ -0,0 +1,152 @@
+//! Returning rendered templates from actix-web handlers.
+//!
+//! Register a `PugEngine` or plain `PugOptions` as app data, directly or
+//! wrapped in `web::Data`; a `PugResponse` renders with them and falls back to
+//! `PugEngine::default()` when there are none.
+
+use crate::{
+    evaluate_string_with_options, evaluate_with_options, CompileError, PugEngine, PugOptions,
+};
+use actix_web::body::BoxBody;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde_json::Value;
+use std::path::PathBuf;
+
+enum Source {
+    File(PathBuf),
+    String(String),
+}
+
+/// A template and its locals, rendered into a `text/html` response.
+///
+/// A `CompileError` becomes a 500 with a plain-text page. Debug builds show
+/// the error itself; release builds only say that rendering failed, since pug
+/// errors quote template source. With `PugEngine::debug_errors` the page is
+/// `CompileError::to_html` instead.
+///
+/// Rendering happens when actix asks for the response, on the worker thread,
+/// because `Responder::respond_to` is not async. Handlers rendering expensive
+/// templates should render with the async API and return the string instead.
+pub struct PugResponse {
+    source: Source,
+    locals: Option<Value>,
+}
+
+impl PugResponse {
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        PugResponse {
+            source: Source::File(path.into()),
+            locals: None,
+        }
+    }
+
+    pub fn string(template: impl Into<String>) -> Self {
+        PugResponse {
+            source: Source::String(template.into()),
+            locals: None,
+        }
+    }
+
+    pub fn locals(mut self, locals: Value) -> Self {
+        self.locals = Some(locals);
+        self
+    }
+
+    fn render(self, options: PugOptions) -> Result<String, CompileError> {
+        let options = match self.locals {
+            Some(locals) => options.with_object(locals),
+            None => options,
+        };
+        match self.source {
+            Source::File(path) => evaluate_with_options(path, options),
+            Source::String(template) => evaluate_string_with_options(template, options),
+        }
+    }
+}
+
+fn app_engine(req: &HttpRequest) -> PugEngine {
+    if let Some(engine) = req.app_data::<PugEngine>() {
+        engine.clone()
+    } else if let Some(engine) = req.app_data::<web::Data<PugEngine>>() {
+        engine.get_ref().clone()
+    } else if let Some(options) = req.app_data::<PugOptions>() {
+        PugEngine::new(options.clone())
+    } else if let Some(options) = req.app_data::<web::Data<PugOptions>>() {
+        PugEngine::new(options.get_ref().clone())
+    } else {
+        PugEngine::default()
+    }
+}
+
+fn error_page(error: &CompileError, engine: &PugEngine) -> HttpResponse {
+    if let Some(page) = engine.error_page(error) {
+        return HttpResponse::InternalServerError()
+            .content_type(ContentType::html())
+            .body(page);
+    }
+    let body = if cfg!(debug_assertions) {
+        format!("Template rendering failed\n\n{}", error)
+    } else {
+        "Template rendering failed".to_owned()
+    };
+    HttpResponse::InternalServerError()
+        .content_type(ContentType::plaintext())
+        .body(body)
+}
+
+impl Responder for PugResponse {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let engine = app_engine(req);
+        match self.render(engine.defaults().clone()) {
+            Ok(html) => HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(html),
+            Err(error) => error_page(&error, &engine),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    async fn hello() -> PugResponse {
+        PugResponse::string("h1 hello #{language}").locals(json!({ "language": "pug" }))
+    }
+
+    async fn broken() -> PugResponse {
+        PugResponse::file("test/missing.pug")
+    }
+
+    #[actix_web::test]
+    async fn renders_html_and_maps_errors() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PugOptions::new()))
+                .route("/hello", web::get().to(hello))
+                .route("/broken", web::get().to(broken)),
+        )
+        .await;
+
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/hello").to_request()).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+        assert_eq!("<h1>hello pug</h1>", test::read_body(response).await);
+
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/broken").to_request()).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        let body = test::read_body(response).await;
+        assert!(body.starts_with(b"Template rendering failed"));
+    }
+}
@@ -0,0 +1,340 @@
+//! Async versions of the evaluate functions built on `async-process`, for
+//! async-std and smol users who don't want a tokio runtime.
+//!
+//! These share the argv construction and output interpretation of the blocking
+//! API, so they behave exactly like their `pug_cli::tokio` counterparts,
+//! including killing the child when a future is dropped mid-compile.
+
+use super::instrument::Compile;
+use super::invocation::{interpret_output, tolerate_closed_stdin, Input, Invocation};
+use super::limits::{check_read, pipe_error};
+use super::retry::next_delay;
+use super::stderr_mode::{self, StderrMode};
+use super::{CompileError, PugOptions};
+use async_io::Timer;
+use async_process::Command;
+use futures_lite::future;
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::{Output, Stdio};
+
+/// Reads `pipe` to the end, copying it to this process's stderr as it comes
+/// when `tee` is set, and failing as soon as more than `limit` bytes have
+/// come through.
+async fn read_all(
+    pipe: Option<impl AsyncRead + Unpin>,
+    tee: bool,
+    limit: Option<u64>,
+) -> std::io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let pipe = match pipe {
+        Some(pipe) => pipe,
+        None => return Ok(buffer),
+    };
+    let mut pipe = pipe.take(limit.map_or(u64::MAX, |limit| limit + 1));
+    let mut chunk = [0; 8192];
+    loop {
+        let read = pipe.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(buffer);
+        }
+        if tee {
+            stderr_mode::forward(&chunk[..read]);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        check_read(&buffer, limit)?;
+    }
+}
+
+/// Spawns the invocation and collects its output.
+///
+/// Stdin is written while stdout and stderr are being drained, so neither a
+/// large template nor a large result can fill a pipe and stall the child.
+/// A child outliving the invocation's timeout, writing more than
+/// `max_output_bytes` or failing otherwise is killed and reaped before the
+/// error is returned.
+async fn run(invocation: Invocation) -> Result<Output, CompileError> {
+    let compile = Compile::start(&invocation);
+    let output = compile.instrument(collect(invocation)).await;
+    compile.observe(output)
+}
+
+async fn collect(invocation: Invocation) -> Result<Output, CompileError> {
+    if let Some(output) = invocation.run_executor() {
+        return output;
+    }
+    let tee = invocation.stderr_mode == StderrMode::Tee;
+    let limit = invocation.post_process.limits.output;
+    let mut command = Command::from(invocation.command());
+    let retry = invocation.retry;
+    let program = invocation.program;
+    command.args(invocation.args).kill_on_drop(true);
+
+    let input = match invocation.input {
+        Input::File(path) => {
+            command.stdin(File::open(&path).map_err(CompileError::io_at("open template", &path))?);
+            None
+        }
+        Input::Bytes(bytes) => {
+            command.stdin(Stdio::piped());
+            Some(bytes)
+        }
+    };
+
+    command
+        .stdout(Stdio::piped())
+        .stderr(invocation.stderr_mode.stdio());
+    let mut attempt = 1;
+    let mut child = loop {
+        match command.spawn() {
+            Ok(child) => break child,
+            Err(error) => {
+                let delay = next_delay(retry.as_ref(), attempt, error)
+                    .map_err(CompileError::io_at("run pug", &program))?;
+                Timer::after(delay).await;
+                attempt += 1;
+            }
+        }
+    };
+
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let write_input = async move {
+        if let (Some(mut stdin), Some(input)) = (stdin, input) {
+            tolerate_closed_stdin(stdin.write_all(&input).await)?;
+            tolerate_closed_stdin(stdin.close().await)?;
+        }
+        Ok(())
+    };
+    let collect = async {
+        let ((_, stdout), stderr) = future::try_zip(
+            future::try_zip(write_input, read_all(stdout, false, limit)),
+            read_all(stderr, tee, limit),
+        )
+        .await?;
+        let status = child.status().await?;
+        Ok(Some(Output {
+            status,
+            stdout,
+            stderr,
+        }))
+    };
+
+    let output = match invocation.timeout {
+        None => collect.await,
+        Some(timeout) => {
+            let expire = async {
+                Timer::after(timeout).await;
+                Ok(None)
+            };
+            future::or(collect, expire).await
+        }
+    };
+    let error = match output {
+        Ok(Some(output)) => return Ok(output),
+        Ok(None) => CompileError::Timeout(invocation.timeout.unwrap_or_default()),
+        Err(e) => pipe_error(e),
+    };
+    let _ = child.kill();
+    let _ = child.status().await;
+    Err(error)
+}
+
+pub async fn evaluate_with_options_async(
+    file: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let invocation = Invocation::file(file, options)?;
+    let post_process = invocation.post_process.clone();
+    run(invocation)
+        .await
+        .and_then(|output| interpret_output(output, post_process))
+}
+
+pub async fn evaluate_string_with_options_async(
+    s: String,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let invocation = Invocation::string(s, options)?;
+    let post_process = invocation.post_process.clone();
+    run(invocation)
+        .await
+        .and_then(|output| interpret_output(output, post_process))
+}
+
+/// `evaluate_url` for async-std, downloading on the `blocking` thread pool
+/// since reqwest's async client needs a tokio runtime.
+#[cfg(feature = "remote")]
+pub async fn evaluate_url_async(
+    url: impl Into<crate::RemoteTemplate>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let remote = url.into();
+    let fetching = remote.clone();
+    let source = blocking::unblock(move || fetching.fetch()).await?;
+    evaluate_string_with_options_async(source, remote.options(options)).await
+}
+
+pub async fn evaluate_string_async(s: String) -> Result<String, CompileError> {
+    let options = PugOptions::new();
+    evaluate_string_with_options_async(s, options).await
+}
+
+pub async fn evaluate_async(file: impl Into<PathBuf>) -> Result<String, CompileError> {
+    let options = PugOptions::new();
+    evaluate_with_options_async(file, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::{fake_binary, process_exists};
+    use std::time::Duration;
+
+    #[::async_std::test]
+    async fn evaluate_file() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_async("test/hello.pug").await.unwrap()
+        );
+    }
+
+    #[::async_std::test]
+    async fn evaluate_with_string() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_string_async(String::from("h1 hello pug"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[::async_std::test]
+    async fn evaluate_with_string_and_json() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_string_with_options_async(
+                String::from("h1 hello #{language}"),
+                PugOptions::new().with_object(r#"{"language": "pug"}"#)
+            )
+            .await
+            .unwrap()
+        )
+    }
+
+    #[::async_std::test]
+    async fn concurrent_renders() {
+        let renders: Vec<_> = (0..8)
+            .map(|i| {
+                ::async_std::task::spawn(evaluate_string_with_options_async(
+                    String::from("p #{n}"),
+                    PugOptions::new().with_object(format!(r#"{{"n": {}}}"#, i)),
+                ))
+            })
+            .collect();
+
+        for (i, render) in renders.into_iter().enumerate() {
+            assert_eq!(format!("<p>{}</p>", i), render.await.unwrap());
+        }
+    }
+
+    #[cfg(unix)]
+    #[::async_std::test]
+    async fn timeout_kills_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > '{}'\nexec sleep 30\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "slow-pug", &script);
+
+        let timeout = Duration::from_millis(300);
+        let result = evaluate_string_with_options_async(
+            String::from("h1 slow"),
+            PugOptions::new().binary(binary).timeout(timeout),
+        )
+        .await;
+
+        assert!(matches!(result, Err(CompileError::Timeout(t)) if t == timeout));
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        assert!(!process_exists(&pid));
+    }
+
+    #[cfg(unix)]
+    #[::async_std::test]
+    async fn dropping_the_future_kills_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > '{}'\nexec sleep 30\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "slow-pug", &script);
+
+        let render = ::async_std::task::spawn(evaluate_string_with_options_async(
+            "p x\n".repeat(1_000_000),
+            PugOptions::new().binary(binary),
+        ));
+        ::async_std::task::sleep(Duration::from_millis(300)).await;
+        assert!(render.cancel().await.is_none());
+
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while process_exists(&pid) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "child outlived its future"
+            );
+            ::async_std::task::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[cfg(unix)]
+    #[::async_std::test]
+    async fn sandbox_rejects_includes_and_bounds_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let ran = dir.path().join("ran");
+        let never = fake_binary(dir.path(), "never", &format!("touch '{}'\n", ran.display()));
+        let include = evaluate_string_with_options_async(
+            "p before\ninclude /etc/hostname".into(),
+            PugOptions::new().binary(never).sandbox(),
+        )
+        .await;
+        assert!(
+            matches!(include, Err(CompileError::Sandbox(_))),
+            "{:?}",
+            include
+        );
+        assert!(!ran.exists());
+
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "echo $$ > '{}'\ncat > /dev/null\nexec yes '<p>flood</p>'\n",
+            pid_file.display()
+        );
+        let flood = fake_binary(dir.path(), "flood", &script);
+        let options = PugOptions::new().binary(flood).sandbox();
+        let flooded = evaluate_string_with_options_async("p".into(), options).await;
+        assert!(matches!(flooded, Err(CompileError::OutputLimit(limit)) if limit == 1 << 20));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        assert!(!process_exists(&pid));
+
+        let errors = fake_binary(
+            dir.path(),
+            "errors",
+            "cat > /dev/null\nexec yes error >&2\n",
+        );
+        let options = PugOptions::new().binary(errors).sandbox();
+        let flooded = evaluate_string_with_options_async("p".into(), options).await;
+        assert!(matches!(flooded, Err(CompileError::OutputLimit(_))));
+
+        let options = PugOptions::new().sandbox();
+        let output = evaluate_string_with_options_async("p hello".into(), options).await;
+        assert_eq!("<p>hello</p>", output.unwrap());
+    }
+
+    #[::async_std::test]
+    async fn large_input() {
+        let template = "p hello\n".repeat(100_000);
+        let html = evaluate_string_async(template).await.unwrap();
+        assert_eq!("<p>hello</p>".repeat(100_000), html);
+    }
+}
@@ -0,0 +1,175 @@
+//! Returning rendered templates from axum handlers.
+//!
+//! `IntoResponse` is synchronous and can't see the request, so a `Pug`
+//! response only records what to render; the `render_templates` middleware
+//! renders it with the `PugEngine` installed as an `Extension`, or
+//! `PugEngine::default()` when there is none:
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .route("/", get(home))
+//!     .layer(middleware::from_fn(pug_cli::axum::render_templates))
+//!     .layer(Extension(PugEngine::new(PugOptions::new().pretty())));
+//! ```
+
+use crate::tokio::evaluate_with_options_async;
+use crate::{CompileError, PugEngine};
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Response};
+use serde_json::Value;
+use std::convert::Infallible;
+use std::path::PathBuf;
+
+/// A template path and its locals, rendered into a `text/html` response.
+pub struct Pug<P>(pub P, pub Value);
+
+/// What a `Pug` response asks `render_templates` to render.
+#[derive(Clone)]
+struct PendingRender {
+    path: PathBuf,
+    locals: Value,
+}
+
+impl<P: Into<PathBuf>> IntoResponse for Pug<P> {
+    fn into_response(self) -> Response {
+        let mut response = (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "pug template was not rendered; is the render_templates middleware installed?",
+        )
+            .into_response();
+        response.extensions_mut().insert(PendingRender {
+            path: self.0.into(),
+            locals: self.1,
+        });
+        response
+    }
+}
+
+impl IntoResponse for CompileError {
+    fn into_response(self) -> Response {
+        let body = match self {
+            CompileError::PugError(message) => message,
+            error => error.to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+    }
+}
+
+/// Takes the engine from an `Extension`, or the default one.
+impl<S: Send + Sync> FromRequestParts<S> for PugEngine {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<PugEngine>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Middleware rendering `Pug` responses returned by the inner service.
+///
+/// Failures are answered as `CompileError`'s own response, or with its HTML
+/// page when the engine has `debug_errors` on.
+pub async fn render_templates(engine: PugEngine, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let pending = match response.extensions_mut().remove::<PendingRender>() {
+        Some(pending) => pending,
+        None => return response,
+    };
+
+    let options = engine.defaults().clone().with_object(pending.locals);
+    match evaluate_with_options_async(pending.path, options).await {
+        Ok(html) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            html,
+        )
+            .into_response(),
+        Err(error) => match engine.error_page(&error) {
+            Some(page) => (StatusCode::INTERNAL_SERVER_ERROR, Html(page)).into_response(),
+            None => error.into_response(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PugOptions;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Extension, Router};
+    use http_body_util::BodyExt;
+    use serde_json::json;
+    use std::fs;
+    use tower::ServiceExt;
+
+    async fn get_page(app: Router, uri: &str) -> (StatusCode, Option<String>, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_owned());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[::tokio::test]
+    async fn renders_pug_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.pug");
+        let bad = dir.path().join("bad.pug");
+        fs::write(&good, "h1 hello #{language}").unwrap();
+        fs::write(&bad, "  h1 indented too early").unwrap();
+
+        let app = Router::new()
+            .route(
+                "/good",
+                get(move || async move {
+                    Ok::<_, CompileError>(Pug(good, json!({ "language": "pug" })))
+                }),
+            )
+            .route("/bad", get(move || async move { Pug(bad, json!({})) }))
+            .layer(middleware::from_fn(render_templates))
+            .layer(Extension(PugEngine::new(PugOptions::new())));
+
+        let (status, content_type, body) = get_page(app.clone(), "/good").await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(Some("text/html; charset=utf-8"), content_type.as_deref());
+        assert_eq!("<h1>hello pug</h1>", body);
+
+        let (status, _, body) = get_page(app, "/bad").await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert!(body.contains("unexpected token"), "{}", body);
+    }
+
+    #[::tokio::test]
+    async fn debug_errors_render_the_error_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let bad = dir.path().join("bad.pug");
+        fs::write(&bad, "  h1 <indented>").unwrap();
+
+        let engine = PugEngine::new(PugOptions::new()).debug_errors(true);
+        let app = Router::new()
+            .route("/bad", get(move || async move { Pug(bad, json!({})) }))
+            .layer(middleware::from_fn(render_templates))
+            .layer(Extension(engine));
+
+        let (status, content_type, body) = get_page(app, "/bad").await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!(Some("text/html; charset=utf-8"), content_type.as_deref());
+        assert!(body.contains("line 1, column 3"), "{}", body);
+        assert!(body.contains("&lt;indented&gt;"), "{}", body);
+    }
+}
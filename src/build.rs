@@ -0,0 +1,847 @@
+//! Compiling templates from `build.rs`, so a binary can ship them as static
+//! strings.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     pug_cli::build::compile_dir_to_out_dir("templates", pug_cli::PugOptions::new());
+//! }
+//!
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+//! ```
+
+mod assets;
+mod client;
+#[cfg(feature = "compress")]
+mod compress;
+mod graph;
+mod hashed;
+mod locals;
+mod manifest;
+mod progress;
+mod report;
+
+pub use assets::Assets;
+pub use client::{
+    compile_client_assets, compile_client_assets_into, ClientAssetOptions, ClientAssets,
+    ModuleFormat,
+};
+#[cfg(feature = "compress")]
+pub use compress::Compression;
+#[cfg(feature = "compress")]
+pub(crate) use compress::Precompress;
+pub use graph::{dependency_graph, DepGraph, Dependency, DependencyKind};
+pub use hashed::HashNaming;
+pub use locals::generate_locals_bindings;
+pub use manifest::build_from_manifest;
+pub use progress::render_files;
+pub use report::{compile_batch, BatchFile, BatchReport};
+#[cfg(feature = "serde")]
+pub use report::{BatchErrorJson, BatchFileJson, BatchReportJson, BatchSummaryJson};
+
+use crate::{
+    evaluate_with_options, evaluate_with_warnings, CompileError, PugJsonObject, PugOptions,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What `compile_dir` produced.
+pub struct BuildOutput {
+    /// The compiled HTML files, one per template.
+    pub pages: Vec<PathBuf>,
+    /// The template each of `pages` was compiled from, in the same order.
+    pub templates: Vec<PathBuf>,
+    /// The generated module of `pub const` strings, or of byte strings when
+    /// `PugOptions::output_encoding` writes pages as something other than
+    /// UTF-8.
+    pub module: PathBuf,
+    /// Every template, every file they include or extend, their
+    /// `PugOptions::sidecar_locals` files and the file the locals are read
+    /// from, if any.
+    pub dependencies: Vec<PathBuf>,
+    /// The templates pug warned about, with its warnings, and the
+    /// `PugOptions::assets` that couldn't be copied, with why.
+    pub warnings: Vec<(PathBuf, Vec<String>)>,
+    /// The `manifest.json` written with `PugOptions::hashed_output`.
+    pub manifest: Option<PathBuf>,
+    /// The compressed copies of `pages` written with
+    /// `PugOptions::precompress`: derived from pages rather than compiled.
+    pub compressed: Vec<PathBuf>,
+    /// The static files `PugOptions::assets` copied into the output
+    /// directory. Ones already there unchanged aren't copied or listed.
+    pub assets: Vec<PathBuf>,
+}
+
+/// Compiles every `.pug` file under `src` into `$OUT_DIR`, writes
+/// `$OUT_DIR/templates.rs` and prints the `cargo:rerun-if-changed` lines.
+/// Pug's warnings become `cargo:warning` lines.
+///
+/// Panics with the failing file, line and message if a template is broken,
+/// which is how a build script fails the build.
+pub fn compile_dir_to_out_dir(src: impl AsRef<Path>, options: PugOptions) -> BuildOutput {
+    let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR is only set for build scripts");
+    let src = src.as_ref();
+    println!("cargo:rerun-if-changed={}", src.display());
+
+    match compile_dir(src, out_dir, options) {
+        Ok(output) => {
+            for dependency in &output.dependencies {
+                println!("cargo:rerun-if-changed={}", dependency.display());
+            }
+            for (template, warnings) in &output.warnings {
+                for warning in warnings {
+                    println!("cargo:warning={}: {}", template.display(), warning);
+                }
+            }
+            output
+        }
+        Err((path, error)) => panic!("{}", error.describe(&path)),
+    }
+}
+
+/// Like `compile_dir_to_out_dir`, writing into `out_dir` and returning the
+/// first failure instead of panicking.
+///
+/// Each template lands at its path relative to `src` with an `.html`
+/// extension, or wherever `PugOptions::output_naming` puts it; two templates
+/// named onto the same page fail with `CompileError::OutputCollision` before
+/// anything is written. Its constant in `templates.rs` is that relative path without the
+/// extension, upper-cased, with every other character replaced by `_`:
+/// `pages/home.pug` becomes `PAGES_HOME`. With `PugOptions::front_matter`,
+/// each page's front matter is merged over the locals it compiles with, and
+/// with `PugOptions::hashed_output` pages get content-hashed names listed
+/// in `manifest.json`.
+pub fn compile_dir(
+    src: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: PugOptions,
+) -> Result<BuildOutput, (PathBuf, CompileError)> {
+    let (src, out_dir) = (src.as_ref(), out_dir.as_ref());
+    let mut templates = Vec::new();
+    find_templates(src, &mut templates);
+    templates.sort();
+
+    let mut pages = Vec::new();
+    let mut dependencies = BTreeSet::new();
+    let mut warnings = Vec::new();
+    let mut module = String::from("// Generated by pug_cli::build. Do not edit.\n\n");
+    let naming = options.output_naming.clone().unwrap_or_default();
+
+    let mut compiled = Vec::new();
+    for template in &templates {
+        let output = evaluate_with_warnings(template, options.clone())
+            .map_err(|error| (template.clone(), error))?;
+        if !output.warnings.is_empty() {
+            warnings.push((template.clone(), output.warnings));
+        }
+        let relative = template.strip_prefix(src).unwrap_or(template);
+        let page = out_dir.join(naming.page(relative, &output.html));
+        compiled.push((template, relative, page, output.html));
+    }
+    let mut sources: BTreeMap<&Path, Vec<PathBuf>> = BTreeMap::new();
+    for (template, _, page, _) in &compiled {
+        sources
+            .entry(page)
+            .or_default()
+            .push(template.to_path_buf());
+    }
+    if let Some((page, templates)) = sources.into_iter().find(|(_, sources)| sources.len() > 1) {
+        let error = CompileError::OutputCollision {
+            page: page.to_owned(),
+            templates: templates.clone(),
+        };
+        return Err((templates[1].clone(), error));
+    }
+
+    let mut hashed = BTreeMap::new();
+    let mut compiled_templates = Vec::new();
+    #[cfg_attr(not(feature = "compress"), allow(unused_mut))]
+    let mut compressed = Vec::new();
+    // pages that aren't UTF-8 on disk can't be `include_str!`ed
+    let constant = match options.encodes_files() {
+        true => ("&[u8]", "include_bytes"),
+        false => ("&str", "include_str"),
+    };
+    for (template, relative, page, html) in compiled {
+        let io_error = |op, path: &Path| {
+            let fail = CompileError::io_at(op, path);
+            |e| (template.clone(), fail(e))
+        };
+        let bytes = options
+            .file_bytes(&html)
+            .map_err(|error| (template.clone(), error))?;
+        let page = match options.hashed_output {
+            Some(naming) => {
+                let name = naming
+                    .write(&page, &bytes)
+                    .map_err(io_error("write page", &page))?;
+                hashed.insert(page, name.clone());
+                name
+            }
+            None => {
+                if let Some(parent) = page.parent() {
+                    fs::create_dir_all(parent).map_err(io_error("create directory", parent))?;
+                }
+                fs::write(&page, &bytes).map_err(io_error("write page", &page))?;
+                page
+            }
+        };
+        #[cfg(feature = "compress")]
+        if let Some(precompress) = &options.precompress {
+            let copies = precompress
+                .write(&page, &bytes)
+                .map_err(io_error("write compressed copies of", &page))?;
+            compressed.extend(copies);
+        }
+
+        let _ = writeln!(
+            module,
+            "pub const {}: {} = {}!({:?});",
+            const_name(relative),
+            constant.0,
+            constant.1,
+            page.to_string_lossy()
+        );
+        collect_dependencies(template, &mut dependencies);
+        if options.sidecar_locals {
+            dependencies.extend(crate::sidecar::find(template));
+        }
+        pages.push(page);
+        compiled_templates.push(template.clone());
+    }
+
+    dependencies.extend(locals_files(&options));
+
+    let mut assets = Vec::new();
+    if let Some(wanted) = &options.assets {
+        let copied = wanted.copy(src, out_dir, options.sidecar_locals)?;
+        dependencies.extend(copied.sources);
+        warnings.extend(copied.skipped);
+        assets = copied.copied;
+    }
+
+    let module_path = out_dir.join("templates.rs");
+    fs::write(&module_path, module).map_err(|e| {
+        (
+            module_path.clone(),
+            CompileError::io_at("write module", &module_path)(e),
+        )
+    })?;
+    let manifest = match options.hashed_output {
+        Some(_) => Some({
+            let manifest = out_dir.join("manifest.json");
+            hashed::write_manifest(out_dir, &hashed).map_err(|e| {
+                (
+                    manifest.clone(),
+                    CompileError::io_at("write manifest", &manifest)(e),
+                )
+            })?
+        }),
+        None => None,
+    };
+
+    Ok(BuildOutput {
+        pages,
+        templates: compiled_templates,
+        module: module_path,
+        dependencies: dependencies.into_iter().collect(),
+        warnings,
+        manifest,
+        compressed,
+        assets,
+    })
+}
+
+/// Compiles every template in `paths` (files, or directories searched for
+/// `.pug` files) purely to check its syntax, in parallel, and reports every
+/// one that fails.
+///
+/// Templates are compiled with `--client`, which needs no locals, and the
+/// output is thrown away. Failures come back in path order. A panic while
+/// checking, say in `PugOptions::locals_for`, is passed on rather than
+/// taken for a pass.
+pub fn check_templates<I>(paths: I, options: PugOptions) -> Result<(), Vec<(PathBuf, CompileError)>>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let templates = expand(paths);
+    let options = options.client();
+    let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk = templates.len().div_ceil(workers).max(1);
+
+    let mut failures: Vec<(PathBuf, CompileError)> = std::thread::scope(|scope| {
+        let checks: Vec<_> = templates
+            .chunks(chunk)
+            .map(|templates| {
+                let options = options.clone();
+                scope.spawn(move || {
+                    templates
+                        .iter()
+                        .filter_map(|template| {
+                            evaluate_with_options(template, options.clone())
+                                .err()
+                                .map(|error| (template.clone(), error))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        checks
+            .into_iter()
+            .flat_map(|check| {
+                check
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        failures.sort_by(|a, b| a.0.cmp(&b.0));
+        Err(failures)
+    }
+}
+
+/// `check_templates` for `build.rs`: prints `cargo:rerun-if-changed` for the
+/// given paths, every template and everything they include, and panics with
+/// one line per broken template.
+pub fn check_templates_in_build<I>(paths: I, options: PugOptions)
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+    let mut dependencies = BTreeSet::new();
+    for template in expand(paths.clone()) {
+        collect_dependencies(&template, &mut dependencies);
+    }
+    for path in paths.iter().chain(&dependencies) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+
+    if let Err(failures) = check_templates(paths, options) {
+        let report: Vec<String> = failures
+            .iter()
+            .map(|(path, error)| error.describe(path))
+            .collect();
+        panic!(
+            "{} broken template(s):\n{}",
+            failures.len(),
+            report.join("\n")
+        );
+    }
+}
+
+/// Files as given, directories replaced by the templates under them.
+fn expand<I>(paths: I) -> Vec<PathBuf>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let mut templates = Vec::new();
+    for path in paths.into_iter().map(Into::into) {
+        if path.is_dir() {
+            find_templates(&path, &mut templates);
+        } else {
+            templates.push(path);
+        }
+    }
+    templates.sort();
+    templates
+}
+
+/// Every `.pug` file under `dir`, in path order.
+pub fn templates_in(dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut templates = Vec::new();
+    find_templates(dir.as_ref(), &mut templates);
+    templates.sort();
+    templates
+}
+
+pub(crate) fn find_templates(dir: &Path, templates: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_templates(&path, templates);
+        } else if path.extension() == Some(OsStr::new("pug")) {
+            templates.push(path);
+        }
+    }
+}
+
+fn const_name(relative: &Path) -> String {
+    let stem = relative.with_extension("");
+    let mut name: String = stem
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Adds `template` and, recursively, whatever it includes or extends.
+pub(crate) fn collect_dependencies(template: &Path, dependencies: &mut BTreeSet<PathBuf>) {
+    if !dependencies.insert(template.to_owned()) {
+        return;
+    }
+    let source = match fs::read_to_string(template) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+    let dir = template.parent().unwrap_or_else(|| Path::new(""));
+    for referenced in source.lines().filter_map(referenced_file) {
+        let mut path = dir.join(referenced);
+        if path.extension().is_none() {
+            path.set_extension("pug");
+        }
+        collect_dependencies(&path, dependencies);
+    }
+}
+
+/// The files the locals in `options` are read from, which every template
+/// compiled with them depends on.
+pub(crate) fn locals_files(options: &PugOptions) -> Vec<PathBuf> {
+    match &options.object {
+        Some(PugJsonObject::Path(path)) | Some(PugJsonObject::JsModule(path)) => {
+            vec![path.clone()]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The file named by an `include` or `extends` line, filters included.
+fn referenced_file(line: &str) -> Option<&str> {
+    reference(line).map(|(_, file)| file)
+}
+
+/// `referenced_file`, with whether the line includes or extends it.
+fn reference(line: &str) -> Option<(DependencyKind, &str)> {
+    let line = line.trim_start();
+    let (kind, rest) = match line.strip_prefix("include") {
+        Some(rest) => (DependencyKind::Include, rest),
+        None => (DependencyKind::Extends, line.strip_prefix("extends")?),
+    };
+    let rest = match rest.strip_prefix(':') {
+        Some(filtered) => filtered.split_once(char::is_whitespace)?.1,
+        None if rest.starts_with(char::is_whitespace) => rest,
+        None => return None,
+    };
+    let file = rest.trim();
+    if file.is_empty() || file.starts_with('/') {
+        None
+    } else {
+        Some((kind, file))
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::executor::MockExecutor;
+    use crate::OutputNaming;
+
+    pub(super) fn fixture(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, source) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn compiles_a_tree_and_generates_constants() {
+        let src = fixture(&[
+            ("index.pug", "h1 home"),
+            ("pages/about-us.pug", "include ../partials/footer\np about"),
+            ("partials/footer.pug", "footer bye"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+
+        let output = compile_dir(src.path(), out.path(), PugOptions::new()).unwrap();
+
+        assert_eq!(
+            "<h1>home</h1>",
+            fs::read_to_string(out.path().join("index.html")).unwrap()
+        );
+        assert!(out.path().join("pages/about-us.html").exists());
+        let module = fs::read_to_string(&output.module).unwrap();
+        assert!(module.contains("pub const INDEX: &str = include_str!("));
+        assert!(module.contains("pub const PAGES_ABOUT_US: &str"));
+        assert!(module.contains("pub const PARTIALS_FOOTER: &str"));
+        assert!(output
+            .dependencies
+            .contains(&src.path().join("pages/../partials/footer.pug")));
+    }
+
+    #[test]
+    fn names_pages_after_the_output_naming() {
+        let src = fixture(&[
+            ("index.pug", "h1 home"),
+            ("blog/post.pug", "p post"),
+            ("blog/2024/recap.pug", "p recap"),
+        ]);
+        let compile = |pattern| {
+            let out = tempfile::tempdir().unwrap();
+            let naming = OutputNaming::new(pattern).unwrap();
+            let options = PugOptions::new().output_naming(naming);
+            let output = compile_dir(src.path(), out.path(), options).unwrap();
+            let pages: Vec<String> = output
+                .pages
+                .iter()
+                .map(|page| page.strip_prefix(out.path()).unwrap().display().to_string())
+                .collect();
+            assert!(output.pages.iter().all(|page| page.exists()));
+            pages
+        };
+        assert_eq!(
+            vec![
+                "recap.partial.html",
+                "post.partial.html",
+                "index.partial.html"
+            ],
+            compile("{name}.partial.html")
+        );
+        assert_eq!(
+            vec![
+                "blog/2024/recap/index.html",
+                "blog/post/index.html",
+                "index/index.html"
+            ],
+            compile("{dir}/{name}/index.html")
+        );
+    }
+
+    #[test]
+    fn reports_templates_named_onto_the_same_page() {
+        let src = fixture(&[("a/page.pug", "p a"), ("b/page.pug", "p b")]);
+        let out = tempfile::tempdir().unwrap();
+        let naming = OutputNaming::new("{name}.html").unwrap();
+        let options = PugOptions::new().output_naming(naming);
+
+        let (_, error) = compile_dir(src.path(), out.path(), options).err().unwrap();
+        match &error {
+            CompileError::OutputCollision { page, templates } => {
+                assert_eq!(&out.path().join("page.html"), page);
+                assert_eq!(
+                    &vec![src.path().join("a/page.pug"), src.path().join("b/page.pug")],
+                    templates
+                );
+            }
+            error => panic!("{:?}", error),
+        }
+        assert!(!out.path().join("page.html").exists());
+    }
+
+    #[test]
+    fn hashes_page_names_and_keeps_a_manifest() {
+        let src = fixture(&[("home.pug", "h1 home"), ("blog/post.pug", "p post")]);
+        let out = tempfile::tempdir().unwrap();
+        let options = PugOptions::new().hashed_output(HashNaming::new(6).unwrap());
+        let build = || {
+            let output = compile_dir(src.path(), out.path(), options.clone()).unwrap();
+            let manifest = fs::read_to_string(output.manifest.unwrap()).unwrap();
+            let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+            let files = manifest["files"].as_object().unwrap().clone();
+            let home = out.path().join(files["home.html"].as_str().unwrap());
+            (files, home)
+        };
+
+        let (files, home) = build();
+        assert_eq!(
+            vec!["blog/post.html", "home.html"],
+            files.keys().collect::<Vec<_>>()
+        );
+        assert_eq!("<h1>home</h1>", fs::read_to_string(&home).unwrap());
+        assert!(!out.path().join("home.html").exists());
+        let name = home.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("home.") && name.ends_with(".html") && name.len() == 16);
+
+        // unchanged, so the same names
+        assert_eq!((files.clone(), home.clone()), build());
+
+        fs::write(src.path().join("home.pug"), "h1 welcome").unwrap();
+        let (changed, new_home) = build();
+        assert_ne!(home, new_home);
+        assert_eq!(files["blog/post.html"], changed["blog/post.html"]);
+        assert!(!home.exists(), "the stale page is removed");
+        assert_eq!("<h1>welcome</h1>", fs::read_to_string(new_home).unwrap());
+    }
+
+    #[test]
+    fn front_matter_supplies_per_page_locals() {
+        let src = fixture(&[
+            ("home.pug", "//- ---\n//- title: Home\n//- ---\nh1 #{title}"),
+            ("plain.pug", "h1 #{title}"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+        let options = PugOptions::new()
+            .with_object(serde_json::json!({ "title": "Site" }))
+            .front_matter();
+
+        compile_dir(src.path(), out.path(), options).unwrap();
+        let page = |name| fs::read_to_string(out.path().join(name)).unwrap();
+        assert_eq!("<h1>Home</h1>", page("home.html"));
+        assert_eq!("<h1>Site</h1>", page("plain.html"));
+    }
+
+    #[test]
+    fn round_trips_paths_with_spaces_and_unicode() {
+        let out = tempfile::tempdir().unwrap();
+        let out_dir = out.path().join("Out Dir/Résultats.");
+
+        let output = compile_dir("test/paths/Landing Pages", &out_dir, PugOptions::new()).unwrap();
+        let pages = vec![
+            out_dir.join("Vol. 2./notes..html"),
+            out_dir.join("Été 2024/hero.html"),
+        ];
+        assert_eq!(pages, output.pages);
+        assert_eq!("<h1>Été 2024</h1>", fs::read_to_string(&pages[1]).unwrap());
+        let module = fs::read_to_string(&output.module).unwrap();
+        assert!(module.contains(&format!("include_str!({:?})", pages[1].to_string_lossy())));
+
+        let failures = check_templates(vec!["test/paths"], PugOptions::new())
+            .err()
+            .unwrap();
+        assert_eq!(1, failures.len());
+        let description = failures[0].1.describe(&failures[0].0);
+        assert!(
+            description.starts_with("test/paths/Broken Pages/à refaire.pug:1:3: "),
+            "{}",
+            description
+        );
+    }
+
+    #[test]
+    fn check_reports_exactly_the_broken_files() {
+        let src = fixture(&[
+            ("a.pug", "p #{needs.locals}"),
+            ("nested/b.pug", "  p indented too early"),
+            ("nested/c.pug", "ul\n  li fine"),
+        ]);
+
+        let failures = check_templates(vec![src.path()], PugOptions::new())
+            .err()
+            .unwrap();
+        assert_eq!(1, failures.len());
+        assert_eq!(src.path().join("nested/b.pug"), failures[0].0);
+        assert!(failures[0]
+            .1
+            .describe(&failures[0].0)
+            .contains("b.pug:1:3: "));
+
+        fs::write(src.path().join("nested/b.pug"), "p fixed").unwrap();
+        assert!(check_templates(vec![src.path()], PugOptions::new()).is_ok());
+
+        let panicking = PugOptions::new().locals_for(|_| panic!("no locals"));
+        let check = || check_templates(vec![src.path()], panicking);
+        let checked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(check));
+        assert!(checked.is_err());
+    }
+
+    #[test]
+    fn collects_warnings_per_template() {
+        let src = fixture(&[("a.pug", "p a"), ("b.pug", "p b")]);
+        let out = tempfile::tempdir().unwrap();
+        let warning = MockExecutor::succeeding("<p>x</p>").with_stderr("Warning: missing space\n");
+
+        let output =
+            compile_dir(src.path(), out.path(), PugOptions::new().executor(warning)).unwrap();
+        assert_eq!(2, output.pages.len());
+        let warned: Vec<_> = output
+            .warnings
+            .iter()
+            .map(|(template, warnings)| (template.file_name().unwrap(), warnings.len()))
+            .collect();
+        assert_eq!(vec![("a.pug".as_ref(), 1), ("b.pug".as_ref(), 1)], warned);
+    }
+
+    #[test]
+    fn check_reports_every_missing_filter() {
+        let src = fixture(&[
+            ("a.pug", ":markdown-it\n  # hi"),
+            ("b.pug", "div\n  :no-such-filter\n    text"),
+        ]);
+
+        let failures = check_templates(vec![src.path()], PugOptions::new())
+            .err()
+            .unwrap();
+        let filters: Vec<&str> = failures
+            .iter()
+            .map(|(_, error)| match error {
+                CompileError::MissingFilter { filter, .. } => filter.as_str(),
+                error => panic!("{:?}", error),
+            })
+            .collect();
+        assert_eq!(vec!["markdown-it", "no-such-filter"], filters);
+    }
+
+    #[test]
+    fn reports_the_broken_file_and_line() {
+        let src = fixture(&[
+            ("ok.pug", "p fine"),
+            ("broken.pug", "  p indented too early"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+
+        let (path, error) = compile_dir(src.path(), out.path(), PugOptions::new())
+            .err()
+            .unwrap();
+        assert_eq!(src.path().join("broken.pug"), path);
+        let message = error.describe(&path);
+        assert!(message.contains("broken.pug:1:3: "), "{}", message);
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn generates_modules_that_compile_in_each_encoding() {
+        use crate::OutputEncoding;
+        let src = fixture(&[("index.pug", "p Café")]);
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        for (encoding, constant, page) in [
+            (OutputEncoding::Utf8, "&str", &b"<p>Caf\xC3\xA9</p>"[..]),
+            (
+                OutputEncoding::Utf8Bom,
+                "&[u8]",
+                b"\xEF\xBB\xBF<p>Caf\xC3\xA9</p>",
+            ),
+            (
+                OutputEncoding::Lossy(encoding_rs::WINDOWS_1252),
+                "&[u8]",
+                b"<p>Caf\xE9</p>",
+            ),
+        ] {
+            let out = tempfile::tempdir().unwrap();
+            let options = PugOptions::new().output_encoding(encoding);
+            let output = compile_dir(src.path(), out.path(), options).unwrap();
+            let module = fs::read_to_string(&output.module).unwrap();
+            assert!(
+                module.contains(&format!("pub const INDEX: {} =", constant)),
+                "{}",
+                module
+            );
+
+            // the crate a build script's module ends up in
+            let lib = out.path().join("lib.rs");
+            let source = format!(
+                "include!({:?});\n\
+                 pub fn index() -> &'static [u8] {{ INDEX.as_ref() }}\n\
+                 #[test]\n\
+                 fn page() {{ assert_eq!({:?}, index()); }}\n",
+                output.module.to_string_lossy(),
+                page
+            );
+            fs::write(&lib, source).unwrap();
+            let tests = out.path().join("tests");
+            let status = std::process::Command::new(&rustc)
+                .args(["--edition", "2018", "--test", "-o"])
+                .arg(&tests)
+                .arg(&lib)
+                .status()
+                .unwrap();
+            assert!(status.success(), "{:?}: {}", encoding, module);
+            let ran = std::process::Command::new(&tests).output().unwrap();
+            assert!(ran.status.success(), "{:?}: {:?}", encoding, ran);
+        }
+    }
+
+    #[test]
+    fn copies_assets_once() {
+        let src = fixture(&[
+            ("index.pug", "link(href=\"css/site.css\")"),
+            ("css/site.css", "body { margin: 0 }"),
+            ("img/logo.svg", "<svg/>"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+        let options = PugOptions::new().assets(Assets::AllButTemplates);
+
+        let first = compile_dir(src.path(), out.path(), options.clone()).unwrap();
+        let css = out.path().join("css/site.css");
+        assert_eq!(
+            vec![css.clone(), out.path().join("img/logo.svg")],
+            first.assets
+        );
+        assert_eq!("body { margin: 0 }", fs::read_to_string(&css).unwrap());
+        assert_eq!(
+            fs::metadata(src.path().join("css/site.css"))
+                .and_then(|m| m.modified())
+                .unwrap(),
+            fs::metadata(&css).and_then(|m| m.modified()).unwrap()
+        );
+        assert!(first
+            .dependencies
+            .contains(&src.path().join("img/logo.svg")));
+
+        let second = compile_dir(src.path(), out.path(), options).unwrap();
+        assert!(second.assets.is_empty(), "{:?}", second.assets);
+        assert_eq!(vec![out.path().join("index.html")], second.pages);
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn writes_compressed_copies_of_large_pages() {
+        use std::io::Read;
+
+        let long = format!("p {}", "pug ".repeat(400));
+        let src = fixture(&[("long.pug", &long), ("short.pug", "p hi")]);
+        let out = tempfile::tempdir().unwrap();
+        let options = PugOptions::new()
+            .precompress(&[
+                Compression::Gzip { level: 9 },
+                Compression::Brotli { quality: 11 },
+            ])
+            .hashed_output(HashNaming::default());
+
+        let output = compile_dir(src.path(), out.path(), options).unwrap();
+
+        let long_page = output
+            .pages
+            .iter()
+            .find(|page| {
+                page.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("long.")
+            })
+            .unwrap();
+        let sibling = |extension: &str| {
+            let mut name = long_page.clone().into_os_string();
+            name.push(extension);
+            PathBuf::from(name)
+        };
+        // the short page is under the default minimum size
+        assert_eq!(vec![sibling(".gz"), sibling(".br")], output.compressed);
+        let page = fs::read(long_page).unwrap();
+        let mut html = Vec::new();
+        flate2::read::GzDecoder::new(fs::File::open(sibling(".gz")).unwrap())
+            .read_to_end(&mut html)
+            .unwrap();
+        assert_eq!(page, html);
+        html.clear();
+        brotli::Decompressor::new(fs::File::open(sibling(".br")).unwrap(), 4096)
+            .read_to_end(&mut html)
+            .unwrap();
+        assert_eq!(page, html);
+    }
+}
@@ -0,0 +1,191 @@
+//! Static files carried from the source tree into the output directory, so
+//! it can be served as it is.
+
+use crate::CompileError;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which files `PugOptions::assets` has `compile_dir` copy. Templates are
+/// never copied, nor with `PugOptions::sidecar_locals` the data files next
+/// to them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Assets {
+    /// Every other file.
+    AllButTemplates,
+    /// Files whose path under the source directory matches one of the
+    /// patterns, where `*` matches within a directory and `**` across them:
+    /// `css/**`, `**/*.png`.
+    Matching(Vec<String>),
+}
+
+/// What `Assets::copy` did.
+#[derive(Default)]
+pub(crate) struct Copied {
+    /// Every asset found, copied or not.
+    pub(crate) sources: Vec<PathBuf>,
+    /// Where the ones that had changed were copied to.
+    pub(crate) copied: Vec<PathBuf>,
+    /// Files that couldn't be copied, and why.
+    pub(crate) skipped: Vec<(PathBuf, Vec<String>)>,
+}
+
+impl Assets {
+    /// Copies the assets under `src` to the same place under `out_dir`, with
+    /// their modification times. One already there with the same size and
+    /// time is taken to be unchanged and left alone.
+    ///
+    /// A dangling symlink, or a file deleted while the build runs, is
+    /// skipped with a note in `Copied::skipped`; any other failure is one
+    /// for the build.
+    pub(crate) fn copy(
+        &self,
+        src: &Path,
+        out_dir: &Path,
+        sidecars: bool,
+    ) -> Result<Copied, (PathBuf, CompileError)> {
+        let mut copied = Copied::default();
+        let mut files = Vec::new();
+        find(src, out_dir, &mut files, &mut copied.skipped);
+        files.sort();
+        for file in files {
+            let relative = file.strip_prefix(src).unwrap_or(&file);
+            if !self.wants(relative, &file, sidecars) {
+                continue;
+            }
+            let to = out_dir.join(relative);
+            match copy_if_changed(&file, &to) {
+                Ok(true) => copied.copied.push(to),
+                Ok(false) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !file.exists() => {
+                    let note = "disappeared before it could be copied".to_owned();
+                    copied.skipped.push((file, vec![note]));
+                    continue;
+                }
+                Err(e) => {
+                    let error = CompileError::io_at("copy asset", &file)(e);
+                    return Err((file, error));
+                }
+            }
+            copied.sources.push(file);
+        }
+        Ok(copied)
+    }
+
+    fn wants(&self, relative: &Path, file: &Path, sidecars: bool) -> bool {
+        if file.extension() == Some(OsStr::new("pug")) {
+            return false;
+        }
+        if sidecars && crate::sidecar::find(&file.with_extension("pug")).as_deref() == Some(file) {
+            return false;
+        }
+        match self {
+            Assets::AllButTemplates => true,
+            Assets::Matching(patterns) => {
+                let path: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+                let path = path.join("/");
+                patterns
+                    .iter()
+                    .any(|pattern| crate::site::matches(pattern, &path))
+            }
+        }
+    }
+}
+
+/// Every file under `dir`, outside `out_dir`, following symlinks.
+fn find(
+    dir: &Path,
+    out_dir: &Path,
+    files: &mut Vec<PathBuf>,
+    skipped: &mut Vec<(PathBuf, Vec<String>)>,
+) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                if path != out_dir {
+                    find(&path, out_dir, files, skipped);
+                }
+            }
+            Ok(_) => files.push(path),
+            Err(_) if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) => {
+                skipped.push((path, vec!["is a dangling symlink".to_owned()]));
+            }
+            // gone since it was listed
+            Err(_) => {}
+        }
+    }
+}
+
+/// Copies `from` to `to` unless it's there already; whether it copied.
+fn copy_if_changed(from: &Path, to: &Path) -> io::Result<bool> {
+    let source = fs::metadata(from)?;
+    let modified = source.modified()?;
+    if let Ok(existing) = fs::metadata(to) {
+        if existing.len() == source.len() && existing.modified().ok() == Some(modified) {
+            return Ok(false);
+        }
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to)?;
+    File::options()
+        .write(true)
+        .open(to)?
+        .set_modified(modified)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::tests::fixture;
+
+    #[test]
+    fn picks_files_by_pattern() {
+        let src = fixture(&[
+            ("index.pug", "p"),
+            ("index.json", "{}"),
+            ("css/site.css", "p {}"),
+            ("img/logo.png", "png"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+
+        let patterns = Assets::Matching(vec!["css/**".into(), "**/*.pug".into()]);
+        let copied = patterns.copy(src.path(), out.path(), false).unwrap();
+        assert_eq!(vec![out.path().join("css/site.css")], copied.copied);
+
+        let copied = Assets::AllButTemplates
+            .copy(src.path(), out.path(), true)
+            .unwrap();
+        assert_eq!(vec![out.path().join("img/logo.png")], copied.copied);
+        assert_eq!(2, copied.sources.len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_dangling_symlinks() {
+        let src = fixture(&[("site.css", "p {}")]);
+        std::os::unix::fs::symlink(src.path().join("gone.css"), src.path().join("link.css"))
+            .unwrap();
+        let out = tempfile::tempdir().unwrap();
+
+        let copied = Assets::AllButTemplates
+            .copy(src.path(), out.path(), false)
+            .unwrap();
+        assert_eq!(vec![out.path().join("site.css")], copied.copied);
+        assert_eq!(
+            vec![(
+                src.path().join("link.css"),
+                vec!["is a dangling symlink".to_owned()]
+            )],
+            copied.skipped
+        );
+    }
+}
@@ -0,0 +1,478 @@
+//! Client-side templates compiled to JavaScript and embedded in the binary.
+
+use super::locals::{declared_locals, Local};
+use super::{collect_dependencies, find_templates, hashed, HashNaming};
+use crate::invocation::program;
+use crate::pug_error::pug_failure;
+use crate::{CompileError, PugOptions};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// How each compiled template function is exposed to the page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModuleFormat {
+    /// A bare `function fooTemplate(locals)`, for a plain `<script>` tag.
+    Global,
+    /// `module.exports = fooTemplate;`
+    CommonJs,
+    /// `export default fooTemplate;`
+    EsModule,
+}
+
+#[derive(Clone)]
+pub struct ClientAssetOptions {
+    pug: PugOptions,
+    format: ModuleFormat,
+    hashed_output: Option<HashNaming>,
+    dts: bool,
+}
+
+impl ClientAssetOptions {
+    pub fn new() -> Self {
+        ClientAssetOptions {
+            pug: PugOptions::new(),
+            format: ModuleFormat::Global,
+            hashed_output: None,
+            dts: false,
+        }
+    }
+
+    pub fn module_format(mut self, format: ModuleFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Writes each script under a content-hashed name, `card.9c0d11e2.js`,
+    /// with `pug_client/manifest.json` listing them; see `HashNaming`. The
+    /// generated module still names assets by their logical name.
+    pub fn hashed_output(mut self, naming: HashNaming) -> Self {
+        self.hashed_output = Some(naming);
+        self
+    }
+
+    /// Writes a TypeScript declaration next to each script,
+    /// `user-card.d.ts` beside `user-card.js`, declaring the function in the
+    /// module format's shape. The locals are typed from the template's
+    /// `//- @locals` comments (see `generate_locals_bindings`) if it has
+    /// any, and `Record<string, unknown>` otherwise.
+    pub fn emit_dts(mut self, emit: bool) -> Self {
+        self.dts = emit;
+        self
+    }
+
+    /// Options passed to pug alongside `--client --name-after-file`.
+    pub fn pug_options(mut self, options: PugOptions) -> Self {
+        self.pug = options;
+        self
+    }
+}
+
+impl Default for ClientAssetOptions {
+    fn default() -> Self {
+        ClientAssetOptions::new()
+    }
+}
+
+/// What `compile_client_assets_into` produced.
+pub struct ClientAssets {
+    /// The JavaScript files, one per template.
+    pub scripts: Vec<PathBuf>,
+    /// The `.d.ts` files written with `ClientAssetOptions::emit_dts`, in the
+    /// same order as `scripts`.
+    pub declarations: Vec<PathBuf>,
+    /// The generated module mapping asset names to their contents.
+    pub module: PathBuf,
+    /// Every template and every file they include or extend.
+    pub dependencies: Vec<PathBuf>,
+    /// The `manifest.json` written with `ClientAssetOptions::hashed_output`.
+    pub manifest: Option<PathBuf>,
+}
+
+/// Compiles every `.pug` file under `src_dir` into a client-side function in
+/// `$OUT_DIR/pug_client/`, writes `$OUT_DIR/pug_client.rs` and prints the
+/// `cargo:rerun-if-changed` lines.
+///
+/// The module holds `PUG_CLIENT_ASSETS`, a slice of `(name, javascript)`
+/// pairs, and `pug_client_asset(name)` to look one up. `widgets/user-card.pug`
+/// is named `widgets/user-card.js` and defines `userCardTemplate`. Panics on
+/// the first broken template.
+pub fn compile_client_assets(
+    src_dir: impl AsRef<Path>,
+    options: ClientAssetOptions,
+) -> ClientAssets {
+    let out_dir = std::env::var_os("OUT_DIR").expect("OUT_DIR is only set for build scripts");
+    let src_dir = src_dir.as_ref();
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+
+    match compile_client_assets_into(src_dir, out_dir, options) {
+        Ok(assets) => {
+            for dependency in &assets.dependencies {
+                println!("cargo:rerun-if-changed={}", dependency.display());
+            }
+            assets
+        }
+        Err((path, error)) => panic!("{}", error.describe(&path)),
+    }
+}
+
+/// Like `compile_client_assets`, writing into `out_dir` and returning the
+/// first failure instead of panicking.
+pub fn compile_client_assets_into(
+    src_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: ClientAssetOptions,
+) -> Result<ClientAssets, (PathBuf, CompileError)> {
+    let (src_dir, out_dir) = (src_dir.as_ref(), out_dir.as_ref());
+    let mut templates = Vec::new();
+    find_templates(src_dir, &mut templates);
+    templates.sort();
+
+    let mut scripts = Vec::new();
+    let mut declarations = Vec::new();
+    let mut dependencies = BTreeSet::new();
+    let mut module = String::from(
+        "// Generated by pug_cli::build. Do not edit.\n\n\
+         pub static PUG_CLIENT_ASSETS: &[(&str, &str)] = &[\n",
+    );
+
+    let root = out_dir.join("pug_client");
+    let mut hashed = BTreeMap::new();
+    for template in &templates {
+        let relative = template.strip_prefix(src_dir).unwrap_or(template);
+        let script_dir = root.join(relative.parent().unwrap_or(Path::new("")));
+        let mut script = compile_client(template, &script_dir, &options)
+            .map_err(|error| (template.clone(), error))?;
+        if let Some(naming) = options.hashed_output {
+            let rename = || -> Result<PathBuf, CompileError> {
+                let js = fs::read(&script).map_err(CompileError::io_at("read script", &script))?;
+                let name = naming
+                    .write(&script, &js)
+                    .map_err(CompileError::io_at("write script", &script))?;
+                fs::remove_file(&script).map_err(CompileError::io_at("remove script", &script))?;
+                Ok(name)
+            };
+            let name = rename().map_err(|error| (template.clone(), error))?;
+            hashed.insert(script, name.clone());
+            script = name;
+        }
+        if options.dts {
+            let declaration = script.with_extension("d.ts");
+            write_declaration(template, &declaration, options.format)
+                .map_err(|error| (template.clone(), error))?;
+            declarations.push(declaration);
+        }
+
+        let name = relative.with_extension("js");
+        let name: Vec<_> = name.iter().map(|part| part.to_string_lossy()).collect();
+        let _ = writeln!(
+            module,
+            "    ({:?}, include_str!({:?})),",
+            name.join("/"),
+            script.to_string_lossy()
+        );
+        collect_dependencies(template, &mut dependencies);
+        scripts.push(script);
+    }
+
+    module.push_str(
+        "];\n\n\
+         pub fn pug_client_asset(name: &str) -> Option<&'static str> {\n    \
+         PUG_CLIENT_ASSETS\n        \
+         .iter()\n        \
+         .find(|(asset, _)| *asset == name)\n        \
+         .map(|(_, script)| *script)\n\
+         }\n",
+    );
+    let module_path = out_dir.join("pug_client.rs");
+    fs::write(&module_path, module).map_err(|e| {
+        (
+            module_path.clone(),
+            CompileError::io_at("write module", &module_path)(e),
+        )
+    })?;
+    let manifest = match options.hashed_output {
+        Some(_) => Some({
+            let manifest = root.join("manifest.json");
+            hashed::write_manifest(&root, &hashed).map_err(|e| {
+                (
+                    manifest.clone(),
+                    CompileError::io_at("write manifest", &manifest)(e),
+                )
+            })?
+        }),
+        None => None,
+    };
+
+    Ok(ClientAssets {
+        scripts,
+        declarations,
+        module: module_path,
+        dependencies: dependencies.into_iter().collect(),
+        manifest,
+    })
+}
+
+/// Runs pug on `template`, writing `<stem>.js` into `dir`, and wraps it.
+fn compile_client(
+    template: &Path,
+    dir: &Path,
+    options: &ClientAssetOptions,
+) -> Result<PathBuf, CompileError> {
+    fs::create_dir_all(dir).map_err(CompileError::io_at("create directory", dir))?;
+    let pug = options.pug.clone().client().out_dir(dir);
+    let program = program(&pug);
+    let output = Command::new(&program)
+        .args(pug)
+        .arg("--name-after-file")
+        .arg("--silent")
+        .arg(template)
+        .output()
+        .map_err(CompileError::io_at("run pug", &program))?;
+    if !output.status.success() || !output.stderr.is_empty() {
+        return Err(pug_failure(String::from_utf8_lossy(&output.stderr).into()));
+    }
+
+    let stem = template.file_stem().unwrap_or_default().to_string_lossy();
+    let script = dir.join(format!("{}.js", stem));
+    let mut js =
+        fs::read_to_string(&script).map_err(CompileError::io_at("read script", &script))?;
+    match options.format {
+        ModuleFormat::Global => {}
+        ModuleFormat::CommonJs => {
+            let _ = write!(js, "\nmodule.exports = {};\n", function_name(&stem));
+        }
+        ModuleFormat::EsModule => {
+            let _ = write!(js, "\nexport default {};\n", function_name(&stem));
+        }
+    }
+    fs::write(&script, js).map_err(CompileError::io_at("write script", &script))?;
+    Ok(script)
+}
+
+/// Writes to `declaration` the TypeScript for the function
+/// `compile_client` made from `template`.
+fn write_declaration(
+    template: &Path,
+    declaration: &Path,
+    format: ModuleFormat,
+) -> Result<(), CompileError> {
+    let stem = template.file_stem().unwrap_or_default().to_string_lossy();
+    let source =
+        fs::read_to_string(template).map_err(CompileError::io_at("read template", template))?;
+    let locals = declared_locals(&source);
+    let signature = format!(
+        "function {}({}): string;",
+        function_name(&stem),
+        locals_parameter(&locals)
+    );
+    let mut dts = String::from(
+        "// Generated by pug_cli::build. Do not edit.
+
+",
+    );
+    let _ = match format {
+        ModuleFormat::Global => writeln!(dts, "declare {}", signature),
+        ModuleFormat::CommonJs => writeln!(
+            dts,
+            "declare {}
+export = {};",
+            signature,
+            function_name(&stem)
+        ),
+        ModuleFormat::EsModule => writeln!(dts, "export default {}", signature),
+    };
+    fs::write(declaration, dts).map_err(CompileError::io_at("write declaration", declaration))
+}
+
+/// `locals?: Record<string, unknown>`, or an object type of the `@locals`
+/// declared, optional only if every one of them is.
+fn locals_parameter(locals: &[Local]) -> String {
+    if locals.is_empty() {
+        return "locals?: Record<string, unknown>".into();
+    }
+    let fields: Vec<String> = locals
+        .iter()
+        .map(|local| {
+            let optional = if local.optional { "?" } else { "" };
+            format!("{}{}: {}", local.name, optional, ts_type(&local.kind))
+        })
+        .collect();
+    let optional = if locals.iter().all(|local| local.optional) {
+        "?"
+    } else {
+        ""
+    };
+    format!("locals{}: {{ {} }}", optional, fields.join("; "))
+}
+
+fn ts_type(kind: &str) -> &'static str {
+    match kind {
+        "string" => "string",
+        "number" | "integer" => "number",
+        "boolean" => "boolean",
+        "array" => "unknown[]",
+        "object" => "Record<string, unknown>",
+        _ => "unknown",
+    }
+}
+
+/// The name `--name-after-file` gives the function: the lower-cased stem with
+/// each run of other characters dropped and the letter after it capitalised,
+/// plus `Template`.
+fn function_name(stem: &str) -> String {
+    let mut name = String::new();
+    let mut capitalise = false;
+    for c in stem.to_lowercase().chars() {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            if capitalise && c.is_ascii_lowercase() && !name.is_empty() {
+                name.push(c.to_ascii_uppercase());
+            } else {
+                name.push(c);
+            }
+            capitalise = false;
+        } else {
+            capitalise = true;
+        }
+    }
+    name.push_str("Template");
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::fixture;
+    use super::*;
+
+    #[test]
+    fn embeds_every_template_as_a_module() {
+        let src = fixture(&[
+            ("user-card.pug", "div.card\n  p= name"),
+            ("widgets/list.pug", "ul\n  li item"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+        let options = ClientAssetOptions::new().module_format(ModuleFormat::CommonJs);
+
+        let assets = compile_client_assets_into(src.path(), out.path(), options).unwrap();
+
+        let module = fs::read_to_string(&assets.module).unwrap();
+        assert!(module.contains("(\"user-card.js\", include_str!("));
+        assert!(module.contains("(\"widgets/list.js\", include_str!("));
+        assert_eq!(2, assets.scripts.len());
+        for (script, function) in assets
+            .scripts
+            .iter()
+            .zip(["userCardTemplate", "listTemplate"])
+        {
+            let loaded = Command::new("node")
+                .arg("-e")
+                .arg("process.stdout.write(typeof require(process.argv[1]))")
+                .arg(script)
+                .output()
+                .unwrap();
+            assert_eq!("function", String::from_utf8_lossy(&loaded.stdout));
+            assert!(fs::read_to_string(script)
+                .unwrap()
+                .contains(&format!("module.exports = {};", function)));
+        }
+    }
+
+    #[test]
+    fn hashes_script_names() {
+        let src = fixture(&[("card.pug", "div.card")]);
+        let out = tempfile::tempdir().unwrap();
+        let options = ClientAssetOptions::new().hashed_output(HashNaming::default());
+
+        let assets = compile_client_assets_into(src.path(), out.path(), options).unwrap();
+        let script = &assets.scripts[0];
+        let name = script.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("card.") && name.ends_with(".js") && name.len() == 16);
+        assert!(!out.path().join("pug_client/card.js").exists());
+        let manifest = fs::read_to_string(assets.manifest.unwrap()).unwrap();
+        assert!(
+            manifest.contains(&format!("\"card.js\": \"{}\"", name)),
+            "{}",
+            manifest
+        );
+        let module = fs::read_to_string(&assets.module).unwrap();
+        assert!(module.contains("(\"card.js\", include_str!("), "{}", module);
+    }
+
+    #[test]
+    fn declares_each_module_format() {
+        let src = fixture(&[
+            ("user-card.pug", "div.card #{name}"),
+            (
+                "typed.pug",
+                "//- @locals title: string, count?: integer, tags?: array
+h1 #{title}",
+            ),
+        ]);
+        let header = "// Generated by pug_cli::build. Do not edit.
+
+";
+        let untyped = "function userCardTemplate(locals?: Record<string, unknown>): string;";
+        for (format, expected) in [
+            (
+                ModuleFormat::Global,
+                format!(
+                    "declare {}
+",
+                    untyped
+                ),
+            ),
+            (
+                ModuleFormat::CommonJs,
+                format!(
+                    "declare {}
+export = userCardTemplate;
+",
+                    untyped
+                ),
+            ),
+            (
+                ModuleFormat::EsModule,
+                format!(
+                    "export default {}
+",
+                    untyped
+                ),
+            ),
+        ] {
+            let out = tempfile::tempdir().unwrap();
+            let options = ClientAssetOptions::new()
+                .module_format(format)
+                .emit_dts(true);
+            let assets = compile_client_assets_into(src.path(), out.path(), options).unwrap();
+            assert_eq!(
+                vec![
+                    out.path().join("pug_client/typed.d.ts"),
+                    out.path().join("pug_client/user-card.d.ts"),
+                ],
+                assets.declarations
+            );
+            let read = |dts: &PathBuf| fs::read_to_string(dts).unwrap();
+            assert_eq!(
+                format!("{}{}", header, expected),
+                read(&assets.declarations[1])
+            );
+            assert!(read(&assets.declarations[0]).contains(
+                "function typedTemplate(locals: { title: string; count?: number; tags?: unknown[] }): string;"
+            ));
+        }
+
+        let out = tempfile::tempdir().unwrap();
+        let assets =
+            compile_client_assets_into(src.path(), out.path(), ClientAssetOptions::new()).unwrap();
+        assert!(assets.declarations.is_empty());
+        assert!(!out.path().join("pug_client/user-card.d.ts").exists());
+    }
+
+    #[test]
+    fn names_functions_like_pug() {
+        assert_eq!("userCardTemplate", function_name("user-card"));
+        assert_eq!("myWidgetV2Template", function_name("My_Widget.v2"));
+    }
+}
@@ -0,0 +1,88 @@
+//! Pre-compressed copies of compiled files, for servers and CDNs that pick
+//! the encoding by `Accept-Encoding` instead of compressing each response.
+
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Files smaller than this aren't worth compressing unless
+/// `PugOptions::precompress_min_size` says otherwise.
+pub(crate) const MIN_SIZE: u64 = 1024;
+
+/// An encoding for `PugOptions::precompress`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// `page.html.gz`, at `level` 0 (stored) to 9 (smallest); higher
+    /// levels count as 9.
+    Gzip { level: u32 },
+    /// `page.html.br`, at `quality` 0 (fastest) to 11 (smallest); higher
+    /// qualities count as 11.
+    Brotli { quality: u32 },
+}
+
+impl Compression {
+    fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip { .. } => "gz",
+            Compression::Brotli { .. } => "br",
+        }
+    }
+
+    fn compress(&self, contents: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            Compression::Gzip { level } => {
+                let level = flate2::Compression::new(level.min(9));
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(contents)?;
+                encoder.finish()
+            }
+            Compression::Brotli { quality } => {
+                // the format's largest window, as `brotli` the tool uses
+                let mut encoder =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, quality.min(11), 24);
+                encoder.write_all(contents)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+/// The encodings set with `PugOptions::precompress` and the size below which
+/// files are left alone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Precompress {
+    pub(crate) encodings: Vec<Compression>,
+    pub(crate) min_size: u64,
+}
+
+impl Default for Precompress {
+    fn default() -> Self {
+        Precompress {
+            encodings: Vec::new(),
+            min_size: MIN_SIZE,
+        }
+    }
+}
+
+impl Precompress {
+    /// Writes a compressed sibling of `path`, which holds `contents`, for
+    /// each encoding, and returns them; nothing if `contents` is too small.
+    pub(crate) fn write(&self, path: &Path, contents: &[u8]) -> io::Result<Vec<PathBuf>> {
+        if (contents.len() as u64) < self.min_size {
+            return Ok(Vec::new());
+        }
+        let mut written = Vec::new();
+        for encoding in &self.encodings {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".");
+            name.push(encoding.extension());
+            let sibling = PathBuf::from(name);
+            fs::write(&sibling, encoding.compress(contents)?)?;
+            written.push(sibling);
+        }
+        Ok(written)
+    }
+}
@@ -0,0 +1,290 @@
+//! Which templates include or extend which, for tooling and refactoring.
+
+use super::{find_templates, reference};
+use crate::CompileError;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// How one template depends on another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DependencyKind {
+    Include,
+    Extends,
+}
+
+/// `from` includes or extends `to`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Dependency {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub kind: DependencyKind,
+}
+
+/// The templates under a directory and the `include`s and `extends` between
+/// them, from `dependency_graph`. Paths are the directory joined with each
+/// template's path, with `.` and `..` resolved.
+#[derive(Clone, Debug)]
+pub struct DepGraph {
+    dir: PathBuf,
+    templates: BTreeSet<PathBuf>,
+    dependencies: Vec<Dependency>,
+}
+
+/// Reads every `.pug` file under `dir` and the files they include or
+/// extend, recursively, into a `DepGraph`.
+///
+/// Templates that include or extend each other in a cycle, which pug can't
+/// compile, fail with `CompileError::DependencyCycle` listing the cycle.
+/// Included files that don't exist are kept as templates with no
+/// dependencies of their own.
+pub fn dependency_graph(dir: &Path) -> Result<DepGraph, CompileError> {
+    let mut pending = Vec::new();
+    find_templates(dir, &mut pending);
+    let mut templates = BTreeSet::new();
+    let mut dependencies = BTreeSet::new();
+    while let Some(template) = pending.pop() {
+        let template = normalize(&template);
+        if !templates.insert(template.clone()) {
+            continue;
+        }
+        if template.extension().is_some_and(|ext| ext != "pug") {
+            // plain text and filtered includes don't include anything
+            continue;
+        }
+        let source = match fs::read_to_string(&template) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let parent = template.parent().unwrap_or_else(|| Path::new(""));
+        for (kind, file) in source.lines().filter_map(reference) {
+            let mut to = parent.join(file);
+            if to.extension().is_none() {
+                to.set_extension("pug");
+            }
+            let to = normalize(&to);
+            pending.push(to.clone());
+            dependencies.insert(Dependency {
+                from: template.clone(),
+                to,
+                kind,
+            });
+        }
+    }
+    let graph = DepGraph {
+        dir: dir.to_owned(),
+        templates,
+        dependencies: dependencies.into_iter().collect(),
+    };
+    match graph.cycle() {
+        Some(cycle) => Err(CompileError::DependencyCycle(cycle)),
+        None => Ok(graph),
+    }
+}
+
+impl DepGraph {
+    pub fn templates(&self) -> impl Iterator<Item = &Path> {
+        self.templates.iter().map(PathBuf::as_path)
+    }
+
+    /// Every edge, sorted by `from`, then `to`.
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.dependencies
+    }
+
+    /// The templates nothing includes or extends: the pages.
+    pub fn roots(&self) -> Vec<&Path> {
+        let depended_on: BTreeSet<&Path> =
+            self.dependencies.iter().map(|d| d.to.as_path()).collect();
+        self.templates()
+            .filter(|template| !depended_on.contains(template))
+            .collect()
+    }
+
+    /// Every template that includes or extends `template`, directly or
+    /// through others: what needs recompiling when it changes. `template` is
+    /// resolved against the graph's directory if it's relative.
+    pub fn dependents_of(&self, template: impl AsRef<Path>) -> Vec<&Path> {
+        let template = self.resolve(template.as_ref());
+        let mut dependents = BTreeSet::new();
+        let mut pending = vec![template.as_path()];
+        while let Some(to) = pending.pop() {
+            for dependency in self.dependencies.iter().filter(|d| d.to == to) {
+                if dependents.insert(dependency.from.as_path()) {
+                    pending.push(&dependency.from);
+                }
+            }
+        }
+        dependents.into_iter().collect()
+    }
+
+    /// The graph in Graphviz's DOT language, one node per template named by
+    /// its path relative to the directory. `extends` edges are solid and
+    /// `include` edges dashed.
+    pub fn to_dot(&self) -> String {
+        let name = |path: &Path| {
+            let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+            let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+            format!(
+                "\"{}\"",
+                parts.join("/").replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        };
+        let mut dot = String::from("digraph templates {\n    node [shape=box];\n");
+        for template in &self.templates {
+            let _ = writeln!(dot, "    {};", name(template));
+        }
+        for dependency in &self.dependencies {
+            let style = match dependency.kind {
+                DependencyKind::Extends => "style=solid, label=\"extends\"",
+                DependencyKind::Include => "style=dashed, label=\"include\"",
+            };
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [{}];",
+                name(&dependency.from),
+                name(&dependency.to),
+                style
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn resolve(&self, template: &Path) -> PathBuf {
+        let template = normalize(template);
+        if self.templates.contains(&template) || template.is_absolute() {
+            template
+        } else {
+            normalize(&self.dir.join(template))
+        }
+    }
+
+    /// A path through the graph that comes back to where it started, as
+    /// the templates along it with the first repeated at the end.
+    fn cycle(&self) -> Option<Vec<PathBuf>> {
+        let mut edges: BTreeMap<&Path, Vec<&Path>> = BTreeMap::new();
+        for dependency in &self.dependencies {
+            edges
+                .entry(&dependency.from)
+                .or_default()
+                .push(&dependency.to);
+        }
+        let mut done = BTreeSet::new();
+        for start in self.templates() {
+            // an iterative depth-first search, the current path on `stack`
+            let mut stack: Vec<(&Path, usize)> = vec![(start, 0)];
+            while let Some(&mut (node, ref mut next)) = stack.last_mut() {
+                let children = edges.get(node).map(Vec::as_slice).unwrap_or_default();
+                match children.get(*next) {
+                    Some(&child) => {
+                        *next += 1;
+                        if let Some(at) = stack.iter().position(|&(seen, _)| seen == child) {
+                            let mut cycle: Vec<PathBuf> =
+                                stack[at..].iter().map(|&(n, _)| n.to_owned()).collect();
+                            cycle.push(child.to_owned());
+                            return Some(cycle);
+                        }
+                        if !done.contains(child) {
+                            stack.push((child, 0));
+                        }
+                    }
+                    None => {
+                        done.insert(node);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// `path` with `.` dropped and `..` taking off the component before it.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::fixture;
+    use super::*;
+
+    #[test]
+    fn graphs_includes_and_extends() {
+        let src = fixture(&[
+            (
+                "layout.pug",
+                "html\n  include partials/nav\n  block content",
+            ),
+            ("partials/nav.pug", "nav\n  include ../partials/logo"),
+            ("partials/logo.pug", "img"),
+            (
+                "index.pug",
+                "extends layout\nblock content\n  include:markdown-it intro.md",
+            ),
+            (
+                "about.pug",
+                "extends ./layout.pug\nblock content\n  p about",
+            ),
+            ("intro.md", "# hi"),
+        ]);
+        let graph = dependency_graph(src.path()).unwrap();
+        let dot = graph.to_dot();
+        for edge in [
+            "\"index.pug\" -> \"layout.pug\" [style=solid, label=\"extends\"];",
+            "\"about.pug\" -> \"layout.pug\" [style=solid, label=\"extends\"];",
+            "\"index.pug\" -> \"intro.md\" [style=dashed, label=\"include\"];",
+            "\"layout.pug\" -> \"partials/nav.pug\" [style=dashed, label=\"include\"];",
+            "\"partials/nav.pug\" -> \"partials/logo.pug\" [style=dashed, label=\"include\"];",
+        ] {
+            assert!(dot.contains(edge), "{} missing from\n{}", edge, dot);
+        }
+        assert!(dot.starts_with("digraph templates {\n"));
+        assert_eq!(5, dot.matches(" -> ").count(), "{}", dot);
+
+        let at = |name: &str| src.path().join(name);
+        assert_eq!(vec![at("about.pug"), at("index.pug")], graph.roots());
+        assert_eq!(
+            vec![
+                at("about.pug"),
+                at("index.pug"),
+                at("layout.pug"),
+                at("partials/nav.pug")
+            ],
+            graph.dependents_of("partials/logo.pug")
+        );
+        assert!(graph.dependents_of(at("index.pug")).is_empty());
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let src = fixture(&[
+            ("a.pug", "include b"),
+            ("b.pug", "include c"),
+            ("c.pug", "extends a"),
+            ("d.pug", "include c"),
+        ]);
+        match dependency_graph(src.path()).unwrap_err() {
+            CompileError::DependencyCycle(cycle) => {
+                let names: Vec<_> = cycle
+                    .iter()
+                    .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect();
+                assert_eq!(vec!["a.pug", "b.pug", "c.pug", "a.pug"], names);
+            }
+            error => panic!("{:?}", error),
+        }
+    }
+}
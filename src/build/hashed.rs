@@ -0,0 +1,167 @@
+//! Content-hashed file names for pages and scripts served from a CDN.
+
+use crate::sha256::sha256_hex;
+use crate::CompileError;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Puts a hash of each compiled file's contents in its name, before the
+/// extension: `home.html` is written as `home.3fa4b2c1.html`. The hash is
+/// the first `length` hex digits of the SHA-256 of the file, so a name
+/// changes exactly when its contents do and the file can be cached forever.
+///
+/// Files with the same name and a different hash of the same length, left by
+/// earlier builds, are deleted. Next to the files, `manifest.json` maps each
+/// logical name to the name it was written as, both relative to the manifest
+/// with `/` separators:
+///
+/// ```json
+/// {
+///   "files": {
+///     "blog/post.html": "blog/post.3fa4b2c1.html"
+///   },
+///   "version": 1
+/// }
+/// ```
+///
+/// Keys are sorted and the file is rewritten whole on each build; `version`
+/// changes only if the layout does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashNaming {
+    length: usize,
+}
+
+impl HashNaming {
+    /// Hashes of `length` hex digits, failing with
+    /// `CompileError::InvalidOptions` unless it's between 1 and 64.
+    pub fn new(length: usize) -> Result<Self, CompileError> {
+        if !(1..=64).contains(&length) {
+            return Err(CompileError::InvalidOptions(format!(
+                "hashed names need 1 to 64 hex digits, not {}",
+                length
+            )));
+        }
+        Ok(HashNaming { length })
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// `path` with the hash of `contents` before its extension.
+    pub(crate) fn name(&self, path: &Path, contents: &[u8]) -> PathBuf {
+        let hash = sha256_hex(contents);
+        let (stem, extension) = split_name(path);
+        let mut name = format!("{}.{}", stem, &hash[..self.length]);
+        if let Some(extension) = extension {
+            name.push('.');
+            name.push_str(&extension);
+        }
+        path.with_file_name(name)
+    }
+
+    /// Writes `contents` to the hashed name for `path` and deletes the
+    /// variants earlier builds left, returning the hashed name.
+    pub(crate) fn write(&self, path: &Path, contents: &[u8]) -> io::Result<PathBuf> {
+        let hashed = self.name(path, contents);
+        if let Some(parent) = hashed.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&hashed, contents)?;
+        self.remove_stale(path, &hashed)?;
+        Ok(hashed)
+    }
+
+    fn remove_stale(&self, path: &Path, current: &Path) -> io::Result<()> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (stem, extension) = split_name(path);
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?.path();
+            if entry == current {
+                continue;
+            }
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            let hash = name
+                .strip_prefix(&format!("{}.", stem))
+                .and_then(|rest| match &extension {
+                    Some(extension) => rest.strip_suffix(&format!(".{}", extension)),
+                    None => Some(rest),
+                });
+            let stale = hash.is_some_and(|hash| {
+                hash.len() == self.length && hash.bytes().all(|b| b.is_ascii_hexdigit())
+            });
+            if stale {
+                fs::remove_file(&entry)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for HashNaming {
+    /// Eight hex digits.
+    fn default() -> Self {
+        HashNaming { length: 8 }
+    }
+}
+
+/// Writes `root/manifest.json` for `files`, each logical path relative to
+/// `root` mapped to the hashed one.
+pub(crate) fn write_manifest(
+    root: &Path,
+    files: &BTreeMap<PathBuf, PathBuf>,
+) -> io::Result<PathBuf> {
+    let relative = |path: &Path| {
+        let path = path.strip_prefix(root).unwrap_or(path);
+        let parts: Vec<_> = path.iter().map(|part| part.to_string_lossy()).collect();
+        parts.join("/")
+    };
+    let files: Map<String, Value> = files
+        .iter()
+        .map(|(logical, hashed)| (relative(logical), Value::String(relative(hashed))))
+        .collect();
+    let mut manifest = Map::new();
+    manifest.insert("files".into(), Value::Object(files));
+    manifest.insert("version".into(), 1.into());
+    let mut json = serde_json::to_string_pretty(&manifest)?;
+    json.push('\n');
+    fs::create_dir_all(root)?;
+    let path = root.join("manifest.json");
+    fs::write(&path, json)?;
+    Ok(path)
+}
+
+/// The file name of `path` split at its last `.`.
+fn split_name(path: &Path) -> (String, Option<String>) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned());
+    (stem.into_owned(), extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puts_the_hash_before_the_extension() {
+        let naming = HashNaming::new(6).unwrap();
+        assert_eq!(
+            PathBuf::from("blog/post.ba7816.html"),
+            naming.name(Path::new("blog/post.html"), b"abc")
+        );
+        assert_eq!(
+            PathBuf::from("LICENSE.ba7816"),
+            naming.name(Path::new("LICENSE"), b"abc")
+        );
+        assert!(HashNaming::new(0).is_err());
+        assert!(HashNaming::new(65).is_err());
+    }
+}
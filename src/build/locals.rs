@@ -0,0 +1,220 @@
+//! Typed locals structs generated from `//- @locals` comments.
+//!
+//! A template declares the locals it expects in one or more unbuffered
+//! comments:
+//!
+//! ```text
+//! //- @locals title: string, items: array, user?: object
+//! ```
+//!
+//! `string`, `number`, `integer`, `boolean`, `array` and `object` map to
+//! Rust types; anything else becomes `serde_json::Value`. A `?` after the name
+//! makes the field an `Option`.
+
+use super::find_templates;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub(super) struct Local {
+    pub(super) name: String,
+    /// The type as written, `string` or `array`.
+    pub(super) kind: String,
+    pub(super) optional: bool,
+}
+
+/// Writes a module to `out_file` with a `Serialize` struct for every template
+/// under `src_dir` that declares `@locals`, each with a `render(&self)` that
+/// compiles its template with `self` as the locals.
+///
+/// `pages/user-card.pug` becomes `PagesUserCardLocals`. The generated code
+/// needs `serde` (with `derive`) and `serde_json` as dependencies.
+pub fn generate_locals_bindings(
+    src_dir: impl AsRef<Path>,
+    out_file: impl AsRef<Path>,
+) -> io::Result<()> {
+    let src_dir = src_dir.as_ref();
+    let mut templates = Vec::new();
+    find_templates(src_dir, &mut templates);
+    templates.sort();
+
+    let mut module = String::from("// Generated by pug_cli::build. Do not edit.\n");
+    for template in templates {
+        let locals = declared_locals(&fs::read_to_string(&template)?);
+        if locals.is_empty() {
+            continue;
+        }
+        let relative = template.strip_prefix(src_dir).unwrap_or(&template);
+        write_struct(&mut module, &struct_name(relative), &template, &locals);
+    }
+    fs::write(out_file, module)
+}
+
+pub(super) fn declared_locals(source: &str) -> Vec<Local> {
+    source
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("//-"))
+        .filter_map(|comment| comment.trim().strip_prefix("@locals"))
+        .flat_map(|declarations| declarations.split(','))
+        .filter_map(|declaration| {
+            let (name, kind) = match declaration.split_once(':') {
+                Some((name, kind)) => (name.trim(), kind.trim()),
+                None => (declaration.trim(), ""),
+            };
+            let (name, optional) = match name.strip_suffix('?') {
+                Some(name) => (name.trim(), true),
+                None => (name, false),
+            };
+            if name.is_empty() {
+                return None;
+            }
+            Some(Local {
+                name: name.to_owned(),
+                kind: kind.to_owned(),
+                optional,
+            })
+        })
+        .collect()
+}
+
+fn rust_type(kind: &str) -> &'static str {
+    match kind {
+        "string" => "String",
+        "number" => "f64",
+        "integer" => "i64",
+        "boolean" => "bool",
+        "array" => "Vec<serde_json::Value>",
+        "object" => "serde_json::Map<String, serde_json::Value>",
+        _ => "serde_json::Value",
+    }
+}
+
+fn write_struct(module: &mut String, name: &str, template: &Path, locals: &[Local]) {
+    let _ = writeln!(
+        module,
+        "\n#[derive(serde::Serialize)]\npub struct {} {{",
+        name
+    );
+    for local in locals {
+        let field = field_name(&local.name);
+        if field.trim_start_matches("r#") != local.name {
+            let _ = writeln!(module, "    #[serde(rename = {:?})]", local.name);
+        }
+        if local.optional {
+            let _ = writeln!(
+                module,
+                "    #[serde(skip_serializing_if = \"Option::is_none\")]\n    pub {}: Option<{}>,",
+                field,
+                rust_type(&local.kind)
+            );
+        } else {
+            let _ = writeln!(module, "    pub {}: {},", field, rust_type(&local.kind));
+        }
+    }
+    let _ = writeln!(
+        module,
+        "}}
+
+impl {name} {{
+    pub const TEMPLATE: &'static str = {path:?};
+
+    pub fn render(&self) -> Result<String, pug_cli::CompileError> {{
+        let locals = serde_json::to_value(self).expect(\"locals serialize to JSON\");
+        pug_cli::evaluate_with_options(
+            Self::TEMPLATE,
+            pug_cli::PugOptions::new().with_object(locals),
+        )
+    }}
+}}",
+        name = name,
+        path = template.to_string_lossy()
+    );
+}
+
+/// `user-card` becomes `UserCard`, each path component in turn, plus `Locals`.
+fn struct_name(relative: &Path) -> String {
+    let mut name = String::new();
+    for word in relative
+        .with_extension("")
+        .to_string_lossy()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+    {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars);
+        }
+    }
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name.push_str("Locals");
+    name
+}
+
+/// `userName` becomes `user_name`; keywords are written as raw identifiers.
+fn field_name(local: &str) -> String {
+    let mut field = String::new();
+    for (i, c) in local.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                field.push('_');
+            }
+            field.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            field.push(c);
+        } else {
+            field.push('_');
+        }
+    }
+    const KEYWORDS: &[&str] = &[
+        "as", "break", "const", "continue", "else", "enum", "false", "fn", "for", "if", "impl",
+        "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static",
+        "struct", "trait", "true", "type", "use", "where", "while",
+    ];
+    if KEYWORDS.contains(&field.as_str()) {
+        field.insert_str(0, "r#");
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod generated {
+        include!("../../test/locals/bindings.rs");
+    }
+
+    #[test]
+    fn generates_the_checked_in_bindings() {
+        let out = tempfile::tempdir().unwrap();
+        let out_file = out.path().join("bindings.rs");
+        generate_locals_bindings("test/locals", &out_file).unwrap();
+        assert_eq!(
+            fs::read_to_string("test/locals/bindings.rs").unwrap(),
+            fs::read_to_string(out_file).unwrap()
+        );
+    }
+
+    #[test]
+    fn generated_structs_render() {
+        let greeting = generated::GreetingLocals {
+            title: "Hello".into(),
+            user_name: "pug".into(),
+            visits: 3,
+            footer: None,
+        };
+        assert_eq!(
+            "<h1>Hello</h1><p>pug has visited 3 times</p>",
+            greeting.render().unwrap()
+        );
+
+        let list = generated::NestedTodoListLocals {
+            items: vec!["a".into(), "b".into()],
+            r#type: serde_json::json!("ordered"),
+        };
+        assert!(list.render().is_ok());
+    }
+}
@@ -0,0 +1,264 @@
+//! Builds described by a manifest file rather than code.
+
+use super::report::{run, BatchReport, Job};
+use crate::{health_with, CompileError, PugJsonObject, PugOptions};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Compiles the pages `manifest` lists, each with `base` plus its own
+/// settings, through `compile_batch`'s parallel machinery.
+///
+/// The manifest is TOML if its name ends in `.toml` (which needs the `toml`
+/// feature) and JSON otherwise, with one table per page:
+///
+/// ```toml
+/// [[page]]
+/// src = "pages/about.pug"
+/// out = "dist/about/index.html"
+/// locals = "data/about.json"
+/// doctype = "html"
+/// pretty = true
+/// ```
+///
+/// or `{ "page": [{ "src": ..., "out": ... }] }`. `src` and `out` are
+/// required; `locals` is a JSON file merged over `base`'s locals the way
+/// `PugOptions::merge` merges them, and `doctype` and `pretty` replace
+/// `base`'s. Relative paths are relative to the manifest's directory.
+///
+/// A manifest that doesn't parse, has keys other than these, lists a `src`
+/// that doesn't exist or names the same `out` twice fails with
+/// `CompileError::Manifest` before anything compiles; compile failures
+/// after that are in the report. `PugOptions::hashed_output` fails with
+/// `CompileError::InvalidOptions`, since each page's `out` is its name.
+pub fn build_from_manifest(
+    manifest: impl AsRef<Path>,
+    base: PugOptions,
+) -> Result<BatchReport, CompileError> {
+    let manifest = manifest.as_ref();
+    if base.hashed_output.is_some() {
+        return Err(CompileError::InvalidOptions(
+            "a manifest gives each page's output, which hashed_output would rename".into(),
+        ));
+    }
+    let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+    let pages = parse(manifest)?;
+
+    let mut outputs: BTreeMap<PathBuf, &Path> = BTreeMap::new();
+    for page in &pages {
+        let src = dir.join(&page.src);
+        if !src.is_file() {
+            return Err(CompileError::Manifest(format!(
+                "{} doesn't exist",
+                page.src.display()
+            )));
+        }
+        if let Some(first) = outputs.insert(dir.join(&page.out), &page.src) {
+            return Err(CompileError::Manifest(format!(
+                "{} and {} are both written to {}",
+                first.display(),
+                page.src.display(),
+                page.out.display()
+            )));
+        }
+    }
+
+    let pug_version = health_with(&base).ok().map(|health| health.pug_version);
+    let jobs = pages
+        .iter()
+        .map(|page| {
+            let mut overrides = PugOptions::new();
+            if let Some(locals) = &page.locals {
+                overrides = overrides.with_object(PugJsonObject::Path(dir.join(locals)));
+            }
+            if let Some(doctype) = &page.doctype {
+                overrides = overrides.doctype(doctype.clone());
+            }
+            if let Some(pretty) = page.pretty {
+                overrides = overrides.set_flag(crate::Flag::Pretty, pretty);
+            }
+            let out = dir.join(&page.out);
+            Job {
+                source: dir.join(&page.src),
+                output: Box::new(move |_: &str| out.clone()),
+                options: base.clone().merge(overrides),
+            }
+        })
+        .collect();
+    Ok(run(jobs, pug_version, None))
+}
+
+/// One `[[page]]`.
+#[derive(Debug)]
+struct Page {
+    src: PathBuf,
+    out: PathBuf,
+    locals: Option<PathBuf>,
+    doctype: Option<String>,
+    pretty: Option<bool>,
+}
+
+fn parse(manifest: &Path) -> Result<Vec<Page>, CompileError> {
+    let invalid =
+        |reason: String| CompileError::Manifest(format!("{}: {}", manifest.display(), reason));
+    let text = fs::read_to_string(manifest).map_err(|e| invalid(e.to_string()))?;
+    let root = if manifest.extension().is_some_and(|ext| ext == "toml") {
+        toml_to_json(&text).map_err(invalid)?
+    } else {
+        serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?
+    };
+    let pages = match root.get("page") {
+        Some(Value::Array(pages)) => pages,
+        _ => return Err(invalid("expected a list of pages under `page`".into())),
+    };
+    pages
+        .iter()
+        .enumerate()
+        .map(|(at, page)| {
+            page_from(page).map_err(|reason| invalid(format!("page {}: {}", at + 1, reason)))
+        })
+        .collect()
+}
+
+fn page_from(page: &Value) -> Result<Page, String> {
+    let table = page.as_object().ok_or("expected a table")?;
+    if let Some(key) = table
+        .keys()
+        .find(|key| !["src", "out", "locals", "doctype", "pretty"].contains(&key.as_str()))
+    {
+        return Err(format!("unknown key `{}`", key));
+    }
+    let string = |key: &str| match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(format!("`{}` should be a string", key)),
+    };
+    let required = |key: &str| string(key)?.ok_or_else(|| format!("`{}` is missing", key));
+    let pretty = match table.get("pretty") {
+        None => None,
+        Some(Value::Bool(pretty)) => Some(*pretty),
+        Some(_) => return Err("`pretty` should be true or false".into()),
+    };
+    Ok(Page {
+        src: required("src")?.into(),
+        out: required("out")?.into(),
+        locals: string("locals")?.map(PathBuf::from),
+        doctype: string("doctype")?,
+        pretty,
+    })
+}
+
+#[cfg(feature = "toml")]
+fn toml_to_json(text: &str) -> Result<Value, String> {
+    let value: toml::Value = toml::from_str(text).map_err(|e| e.to_string())?;
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "toml"))]
+fn toml_to_json(_: &str) -> Result<Value, String> {
+    Err("TOML manifests need the `toml` feature".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::fixture;
+    use super::*;
+
+    const MANIFEST: &str = r#"{
+        "page": [
+            { "src": "pages/about.pug", "out": "dist/about/index.html", "locals": "data/about.json" },
+            { "src": "pages/team.pug", "out": "dist/team/index.html", "locals": "data/about.json" },
+            { "src": "pages/home.pug", "out": "public/index.html", "doctype": "html", "pretty": true }
+        ]
+    }"#;
+
+    fn site(manifest: &str) -> tempfile::TempDir {
+        fixture(&[
+            ("pages/about.pug", "h1 About #{name}"),
+            ("pages/team.pug", "p #{name} #{site}"),
+            ("pages/home.pug", "main\n  h1 Home #{site}"),
+            ("data/about.json", r#"{ "name": "Acme" }"#),
+            ("build.json", manifest),
+        ])
+    }
+
+    #[test]
+    fn builds_each_page_with_its_own_settings() {
+        let dir = site(MANIFEST);
+        let base = PugOptions::new().with_object(serde_json::json!({ "site": "acme.test" }));
+
+        let report = build_from_manifest(dir.path().join("build.json"), base).unwrap();
+
+        assert_eq!((3, 0), (report.succeeded(), report.failed()));
+        let read = |page: &str| fs::read_to_string(dir.path().join(page)).unwrap();
+        assert_eq!("<h1>About Acme</h1>", read("dist/about/index.html"));
+        assert_eq!("<p>Acme acme.test</p>", read("dist/team/index.html"));
+        assert_eq!(
+            "<main>\n  <h1>Home acme.test</h1>\n</main>",
+            read("public/index.html")
+        );
+        let json = report.to_json();
+        assert_eq!(3, json["summary"]["total"]);
+        assert_eq!(
+            dir.path().join("public/index.html").to_str().unwrap(),
+            json["files"][2]["output"]
+        );
+        assert_eq!(
+            dir.path().join("pages/about.pug").to_str().unwrap(),
+            json["files"][0]["source"]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_manifests_before_compiling() {
+        let check = |manifest: &str, expected: &str| {
+            let dir = site(manifest);
+            match build_from_manifest(dir.path().join("build.json"), PugOptions::new()) {
+                Err(CompileError::Manifest(reason)) => {
+                    assert!(reason.contains(expected), "{}", reason)
+                }
+                other => panic!("{:?}", other.map(|report| report.to_json())),
+            }
+        };
+        check(
+            r#"{ "page": [{ "src": "pages/gone.pug", "out": "a.html" }] }"#,
+            "pages/gone.pug doesn't exist",
+        );
+        check(
+            r#"{ "page": [
+                { "src": "pages/about.pug", "out": "a.html" },
+                { "src": "pages/team.pug", "out": "./a.html" }
+            ] }"#,
+            "are both written to",
+        );
+        check(
+            r#"{ "page": [{ "src": "pages/about.pug", "output": "a.html" }] }"#,
+            "page 1: unknown key `output`",
+        );
+        check(r#"{ "pages": [] }"#, "under `page`");
+
+        let dir = site(MANIFEST);
+        let hashed = PugOptions::new().hashed_output(crate::build::HashNaming::default());
+        assert!(matches!(
+            build_from_manifest(dir.path().join("build.json"), hashed),
+            Err(CompileError::InvalidOptions(_))
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn reads_toml() {
+        let dir = site("");
+        let manifest = dir.path().join("build.toml");
+        let toml = "[[page]]\nsrc = \"pages/about.pug\"\nout = \"dist/about.html\"\nlocals = \"data/about.json\"\n";
+        fs::write(&manifest, toml).unwrap();
+
+        let report = build_from_manifest(&manifest, PugOptions::new()).unwrap();
+        assert_eq!(1, report.succeeded());
+        assert_eq!(
+            "<h1>About Acme</h1>",
+            fs::read_to_string(dir.path().join("dist/about.html")).unwrap()
+        );
+    }
+}
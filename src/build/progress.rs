@@ -0,0 +1,239 @@
+//! Compiling many templates with one pug process, reporting each page as pug
+//! writes it.
+
+use crate::invocation::{failure, Invocation};
+use crate::{CompileError, PugOptions};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Instant;
+
+enum Line {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
+
+/// Compiles `inputs` (templates, or directories of them) with a single pug
+/// process writing into `PugOptions::out_dir`, and returns the pages it
+/// wrote.
+///
+/// `on_file_rendered` is called on the calling thread with each page as soon
+/// as pug reports it with its `rendered <file>` line, rather than once every
+/// template is done. Whatever else pug prints is kept for the error if it
+/// fails. `PugOptions::timeout` applies to the whole run.
+pub fn render_files<I>(
+    inputs: I,
+    options: PugOptions,
+    mut on_file_rendered: impl FnMut(&Path),
+) -> Result<Vec<PathBuf>, CompileError>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    if options.out_dir.is_none() {
+        return Err(CompileError::InvalidOptions(
+            "render_files needs PugOptions::out_dir".into(),
+        ));
+    }
+    let inputs: Vec<PathBuf> = inputs.into_iter().map(Into::into).collect();
+    let invocation = Invocation::files(&inputs, options)?;
+
+    let mut pages = Vec::new();
+    let mut stderr = String::new();
+    let mut line = |line: Line| {
+        let (line, from_stderr) = match line {
+            Line::Stdout(line) => (line, false),
+            Line::Stderr(line) => (line, true),
+        };
+        if let Some(page) = rendered(&line) {
+            on_file_rendered(&page);
+            pages.push(page);
+        } else if from_stderr {
+            stderr.push_str(&String::from_utf8_lossy(&line));
+        }
+    };
+
+    let status = match invocation.run_executor() {
+        Some(output) => {
+            let output = output?;
+            for stdout in output.stdout.split_inclusive(|&b| b == b'\n') {
+                line(Line::Stdout(stdout.to_vec()));
+            }
+            for stderr in output.stderr.split_inclusive(|&b| b == b'\n') {
+                line(Line::Stderr(stderr.to_vec()));
+            }
+            output.status
+        }
+        None => {
+            let mut child = invocation
+                .command()
+                .args(&invocation.args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(CompileError::io_at("run pug", &invocation.program))?;
+            let (lines, received) = mpsc::channel();
+            let stdout = child
+                .stdout
+                .take()
+                .map(|pipe| tail(pipe, lines.clone(), Line::Stdout));
+            let stderr = child
+                .stderr
+                .take()
+                .map(|pipe| tail(pipe, lines, Line::Stderr));
+
+            let deadline = invocation.timeout.map(|timeout| Instant::now() + timeout);
+            loop {
+                let next = match deadline {
+                    Some(deadline) => {
+                        received.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => received.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match next {
+                    Ok(next) => line(next),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(CompileError::Timeout(
+                            invocation.timeout.unwrap_or_default(),
+                        ));
+                    }
+                }
+            }
+            stdout.into_iter().chain(stderr).for_each(|tail| {
+                let _ = tail.join();
+            });
+            child.wait().map_err(CompileError::Io)?
+        }
+    };
+
+    if status.success() {
+        Ok(pages)
+    } else {
+        Err(failure(status, &stderr, &invocation.post_process))
+    }
+}
+
+/// Sends `pipe` line by line, the last one whether or not it ends in a
+/// newline.
+fn tail(
+    pipe: impl Read + Send + 'static,
+    lines: Sender<Line>,
+    kind: fn(Vec<u8>) -> Line,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut pipe = BufReader::new(pipe);
+        loop {
+            let mut line = Vec::new();
+            match pipe.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if lines.send(kind(line)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// The page in pug's `rendered <file>` progress line, which may be coloured.
+fn rendered(line: &[u8]) -> Option<PathBuf> {
+    let line = String::from_utf8_lossy(line);
+    let mut plain = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // an SGR sequence, `ESC [ ... m`
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    let page = plain.trim().strip_prefix("rendered ")?.trim();
+    if page.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(page))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reads_plain_and_coloured_progress_lines() {
+        assert_eq!(
+            Some(PathBuf::from("out/a b.html")),
+            rendered(b"  rendered out/a b.html\n")
+        );
+        assert_eq!(
+            Some(PathBuf::from("/out/index.html")),
+            rendered(b"  \x1b[90mrendered\x1b[39m \x1b[90m/out/index.html\x1b[39m")
+        );
+        assert_eq!(None, rendered(b"Warning: something\n"));
+        assert_eq!(None, rendered(b"rendered \n"));
+    }
+
+    #[test]
+    fn reports_every_page_pug_writes() {
+        let out = tempfile::tempdir().unwrap();
+        let inputs = ["test/hello.pug", "test/greeting.pug", "test/reindent.pug"];
+        let mut reported = Vec::new();
+        let options = PugOptions::new().out_dir(out.path());
+        let pages = render_files(inputs, options, |page| reported.push(page.to_owned())).unwrap();
+        assert_eq!(3, pages.len());
+        assert_eq!(pages, reported);
+        for (input, page) in inputs.iter().zip(&pages) {
+            assert_eq!(Path::new(input).file_stem(), page.file_stem());
+            assert!(page.starts_with(out.path()));
+            assert!(page.exists());
+        }
+        assert!(out.path().join("hello.html").exists());
+
+        let missing = render_files(["test/hello.pug"], PugOptions::new(), |_| {});
+        assert!(matches!(missing, Err(CompileError::InvalidOptions(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn calls_back_while_pug_is_still_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let slow = crate::test_support::fake_binary(
+            dir.path(),
+            "slow-pug",
+            "echo '  rendered out/a.html'\nsleep 0.3\necho 'Warning: b.pug is odd' >&2\n\
+             echo '  rendered out/b.html'\nsleep 0.3\nprintf '  rendered out/c.html'\n\
+             sleep 0.3\necho 'Error: c.pug went wrong' >&2\nexit 1\n",
+        );
+        let (sent, received) = mpsc::channel();
+        let options = PugOptions::new().binary(slow).out_dir("out");
+        let error = render_files(["a.pug", "b.pug", "c.pug"], options, |page| {
+            sent.send((page.to_owned(), Instant::now())).unwrap();
+        })
+        .unwrap_err();
+        let returned = Instant::now();
+
+        let reported: Vec<(PathBuf, Instant)> = received.try_iter().collect();
+        let pages: Vec<&Path> = reported.iter().map(|(page, _)| page.as_path()).collect();
+        assert_eq!(
+            vec![
+                Path::new("out/a.html"),
+                Path::new("out/b.html"),
+                Path::new("out/c.html")
+            ],
+            pages
+        );
+        assert!(returned - reported[0].1 >= Duration::from_millis(800));
+        assert!(returned - reported[1].1 >= Duration::from_millis(500));
+        assert!(error.to_string().contains("c.pug went wrong"), "{}", error);
+        assert!(error.to_string().contains("b.pug is odd"), "{}", error);
+    }
+}
@@ -0,0 +1,496 @@
+//! Compiling a batch of templates past failures, with a report CI can read.
+
+use super::hashed;
+use crate::{evaluate_with_warnings, health_with, CompileError, PugOptions};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// What `compile_batch` did with each template.
+#[derive(Debug)]
+pub struct BatchReport {
+    /// One per input, in input order.
+    pub files: Vec<BatchFile>,
+    /// The whole batch, wall clock.
+    pub duration: Duration,
+    /// What the pug that ran reported from `--version`, if it could be asked.
+    pub pug_version: Option<String>,
+    /// The `manifest.json` written with `PugOptions::hashed_output`, or why
+    /// it couldn't be.
+    pub manifest: Option<Result<PathBuf, CompileError>>,
+}
+
+/// One template in a `BatchReport`.
+#[derive(Debug)]
+pub struct BatchFile {
+    pub source: PathBuf,
+    /// The page written, `None` if the compile failed.
+    pub output: Option<PathBuf>,
+    pub duration: Duration,
+    pub bytes_written: u64,
+    pub warnings: Vec<String>,
+    pub error: Option<CompileError>,
+    /// The data file merged over its locals by `PugOptions::sidecar_locals`.
+    pub sidecar: Option<PathBuf>,
+    /// The compressed copies of `output` written with
+    /// `PugOptions::precompress`: derived from the page rather than compiled.
+    pub compressed: Vec<PathBuf>,
+}
+
+/// Compiles each of `inputs` into `out_dir`, named by `PugOptions::output_naming`
+/// from its file name, in parallel, and carries on past failures. With
+/// `PugOptions::hashed_output` pages get content-hashed names listed in
+/// `out_dir/manifest.json`, as with `compile_dir`.
+pub fn compile_batch<I>(inputs: I, out_dir: impl AsRef<Path>, options: PugOptions) -> BatchReport
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let out_dir = out_dir.as_ref();
+    let pug_version = health_with(&options).ok().map(|health| health.pug_version);
+    let naming = options.output_naming.clone().unwrap_or_default();
+    let jobs = inputs
+        .into_iter()
+        .map(|source| {
+            let source: PathBuf = source.into();
+            let name = source.file_name().map(PathBuf::from).unwrap_or_default();
+            let naming = naming.clone();
+            let output: Output = Box::new(move |html: &str| out_dir.join(naming.page(&name, html)));
+            Job {
+                source,
+                output,
+                options: options.clone(),
+            }
+        })
+        .collect();
+    let manifest_root = options.hashed_output.map(|_| out_dir);
+    run(jobs, pug_version, manifest_root)
+}
+
+/// Where a `Job` writes its page, given the HTML.
+pub(crate) type Output<'a> = Box<dyn Fn(&str) -> PathBuf + Send + Sync + 'a>;
+
+/// One template for `run` to compile and write.
+pub(crate) struct Job<'a> {
+    pub(crate) source: PathBuf,
+    pub(crate) output: Output<'a>,
+    pub(crate) options: PugOptions,
+}
+
+/// Compiles `jobs` on as many threads as there are cores and reports on each
+/// in order, writing `manifest.json` into `manifest_root` if given for the
+/// pages `PugOptions::hashed_output` named.
+pub(crate) fn run(
+    jobs: Vec<Job<'_>>,
+    pug_version: Option<String>,
+    manifest_root: Option<&Path>,
+) -> BatchReport {
+    let started = Instant::now();
+    let workers = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk = jobs.len().div_ceil(workers).max(1);
+    let compiled: Vec<_> = std::thread::scope(|scope| {
+        let compiles: Vec<_> = jobs
+            .chunks(chunk)
+            .map(|jobs| scope.spawn(move || jobs.iter().map(compile).collect::<Vec<_>>()))
+            .collect();
+        compiles
+            .into_iter()
+            .flat_map(|compile| compile.join().expect("compiling a batch panicked"))
+            .collect()
+    });
+    let manifest = manifest_root.map(|root| {
+        let hashed: BTreeMap<PathBuf, PathBuf> = compiled
+            .iter()
+            .filter_map(|(file, named)| Some((named.clone()?, file.output.clone()?)))
+            .collect();
+        let manifest = root.join("manifest.json");
+        hashed::write_manifest(root, &hashed)
+            .map_err(CompileError::io_at("write manifest", &manifest))
+    });
+    BatchReport {
+        files: compiled.into_iter().map(|(file, _)| file).collect(),
+        duration: started.elapsed(),
+        pug_version,
+        manifest,
+    }
+}
+
+/// The job's report, and the page it was named as before any hashing.
+fn compile(job: &Job<'_>) -> (BatchFile, Option<PathBuf>) {
+    let started = Instant::now();
+    let sidecar = match job.options.sidecar_locals {
+        true => crate::sidecar::find(&job.source),
+        false => None,
+    };
+    let compiled = evaluate_with_warnings(&job.source, job.options.clone()).and_then(|output| {
+        let named = (job.output)(&output.html);
+        let bytes = job.options.file_bytes(&output.html)?;
+        let page = match job.options.hashed_output {
+            Some(naming) => naming
+                .write(&named, &bytes)
+                .map_err(CompileError::io_at("write page", &named))?,
+            None => {
+                if let Some(parent) = named.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(CompileError::io_at("create directory", parent))?;
+                }
+                fs::write(&named, &bytes).map_err(CompileError::io_at("write page", &named))?;
+                named.clone()
+            }
+        };
+        #[cfg(feature = "compress")]
+        let compressed = match &job.options.precompress {
+            Some(precompress) => precompress
+                .write(&page, &bytes)
+                .map_err(CompileError::io_at("write compressed copies of", &page))?,
+            None => Vec::new(),
+        };
+        #[cfg(not(feature = "compress"))]
+        let compressed = Vec::new();
+        let written = bytes.len() as u64;
+        Ok((named, page, written, compressed, output))
+    });
+    let (named, output, bytes_written, compressed, warnings, error) = match compiled {
+        Ok((named, page, written, compressed, output)) => (
+            Some(named),
+            Some(page),
+            written,
+            compressed,
+            output.warnings,
+            None,
+        ),
+        Err(error) => (None, None, 0, Vec::new(), Vec::new(), Some(error)),
+    };
+    let file = BatchFile {
+        source: job.source.clone(),
+        output,
+        duration: started.elapsed(),
+        bytes_written,
+        warnings,
+        error,
+        sidecar,
+        compressed,
+    };
+    (file, named)
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.error.is_none())
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.files.len() - self.succeeded()
+    }
+
+    /// The report as JSON, in this shape (`BatchReportJson` with the `serde`
+    /// feature):
+    ///
+    /// ```json
+    /// {
+    ///   "version": 1,
+    ///   "summary": {
+    ///     "total": 2, "succeeded": 1, "failed": 1,
+    ///     "duration_ms": 84, "pug_version": "3.0.2"
+    ///   },
+    ///   "files": [
+    ///     {
+    ///       "source": "views/home.pug", "output": "out/home.html",
+    ///       "status": "ok", "duration_ms": 41, "bytes_written": 1834,
+    ///       "warnings": [], "error": null, "sidecar": "views/home.json"
+    ///     },
+    ///     {
+    ///       "source": "views/broken.pug", "output": null,
+    ///       "status": "failed", "duration_ms": 43, "bytes_written": 0,
+    ///       "warnings": [], "sidecar": null,
+    ///       "error": {
+    ///         "file": "views/broken.pug", "line": 3, "column": 5,
+    ///         "message": "Unexpected token `indent`"
+    ///       }
+    ///     }
+    ///   ]
+    /// }
+    /// ```
+    ///
+    /// `status` is `"ok"` or `"failed"`. An error's `line` and `column` are
+    /// `null` when it didn't come from pug parsing the template, `sidecar`
+    /// when there's no `PugOptions::sidecar_locals` file for the page, and
+    /// `pug_version` when pug couldn't be asked. Durations are whole
+    /// milliseconds; `version` changes only if the layout does.
+    pub fn to_json(&self) -> Value {
+        let files: Vec<Value> = self.files.iter().map(file_json).collect();
+        json!({
+            "version": 1,
+            "summary": {
+                "total": self.files.len(),
+                "succeeded": self.succeeded(),
+                "failed": self.failed(),
+                "duration_ms": millis(self.duration),
+                "pug_version": self.pug_version,
+            },
+            "files": files,
+        })
+    }
+
+    /// Writes `to_json`, pretty-printed, to `path`.
+    pub fn write_json(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut json = serde_json::to_string_pretty(&self.to_json())?;
+        json.push('\n');
+        fs::write(path, json)
+    }
+}
+
+fn file_json(file: &BatchFile) -> Value {
+    let error = file.error.as_ref().map(|error| match error.details() {
+        Some(details) => json!({
+            "file": file.source,
+            "line": details.line,
+            "column": details.column,
+            "message": details.message,
+        }),
+        None => json!({
+            "file": file.source,
+            "line": null,
+            "column": null,
+            "message": error.to_string(),
+        }),
+    });
+    json!({
+        "source": file.source,
+        "output": file.output,
+        "status": if file.error.is_none() { "ok" } else { "failed" },
+        "duration_ms": millis(file.duration),
+        "bytes_written": file.bytes_written,
+        "warnings": file.warnings,
+        "error": error,
+        "sidecar": file.sidecar,
+    })
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+/// `BatchReport::to_json` as Rust types.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BatchReportJson {
+    pub version: u32,
+    pub summary: BatchSummaryJson,
+    pub files: Vec<BatchFileJson>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BatchSummaryJson {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub duration_ms: u64,
+    pub pug_version: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BatchFileJson {
+    pub source: PathBuf,
+    pub output: Option<PathBuf>,
+    /// `"ok"` or `"failed"`.
+    pub status: String,
+    pub duration_ms: u64,
+    pub bytes_written: u64,
+    pub warnings: Vec<String>,
+    pub error: Option<BatchErrorJson>,
+    pub sidecar: Option<PathBuf>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BatchErrorJson {
+    pub file: PathBuf,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::fixture;
+    use super::*;
+
+    #[test]
+    fn reports_each_file_and_a_summary() {
+        let src = fixture(&[
+            ("home.pug", "h1 home"),
+            ("broken.pug", "  p indented too early"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+        let inputs = [src.path().join("home.pug"), src.path().join("broken.pug")];
+
+        let report = compile_batch(&inputs, out.path(), PugOptions::new());
+        let json = report.to_json();
+
+        assert_eq!((1, 1), (report.succeeded(), report.failed()));
+        assert_eq!(2, json["summary"]["total"]);
+        assert!(json["summary"]["pug_version"].is_string());
+        let home = &json["files"][0];
+        assert_eq!("ok", home["status"]);
+        assert_eq!(
+            out.path().join("home.html").to_str().unwrap(),
+            home["output"]
+        );
+        assert_eq!("<h1>home</h1>".len() as u64, home["bytes_written"]);
+        assert!(home["error"].is_null());
+        let broken = &json["files"][1];
+        assert_eq!("failed", broken["status"]);
+        assert!(broken["output"].is_null());
+        assert_eq!(1, broken["error"]["line"]);
+        assert_eq!(3, broken["error"]["column"]);
+        assert_eq!(inputs[1].to_str().unwrap(), broken["error"]["file"]);
+
+        let path = out.path().join("report.json");
+        report.write_json(&path).unwrap();
+        let written: Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(json, written);
+    }
+
+    #[test]
+    fn hashes_names_into_a_manifest() {
+        use crate::build::HashNaming;
+
+        let src = fixture(&[("home.pug", "p home")]);
+        let out = tempfile::tempdir().unwrap();
+        let inputs = [src.path().join("home.pug")];
+        let options = PugOptions::new().hashed_output(HashNaming::new(6).unwrap());
+
+        let report = compile_batch(&inputs, out.path(), options);
+        let page = report.files[0].output.clone().unwrap();
+        let name = page.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(
+            name.starts_with("home.") && name.ends_with(".html") && name.len() == 16,
+            "{}",
+            name
+        );
+        assert_eq!("<p>home</p>", fs::read_to_string(&page).unwrap());
+        let manifest = report.manifest.unwrap().unwrap();
+        let manifest: Value = serde_json::from_slice(&fs::read(manifest).unwrap()).unwrap();
+        assert_eq!(json!({ "home.html": name }), manifest["files"]);
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn compresses_each_page() {
+        use crate::build::Compression;
+        use std::io::Read;
+
+        let src = fixture(&[("home.pug", "p home"), ("about.pug", "p about")]);
+        let out = tempfile::tempdir().unwrap();
+        let inputs = [src.path().join("home.pug"), src.path().join("about.pug")];
+        let options = PugOptions::new()
+            .precompress(&[Compression::Brotli { quality: 5 }])
+            .precompress_min_size(0);
+
+        let report = compile_batch(&inputs, out.path(), options);
+        for (file, name) in report.files.iter().zip(["home", "about"]) {
+            let compressed = out.path().join(format!("{}.html.br", name));
+            assert_eq!(vec![compressed.clone()], file.compressed);
+            let mut html = String::new();
+            brotli::Decompressor::new(fs::File::open(compressed).unwrap(), 4096)
+                .read_to_string(&mut html)
+                .unwrap();
+            assert_eq!(format!("<p>{}</p>", name), html);
+        }
+    }
+
+    #[test]
+    fn derives_locals_from_each_path() {
+        let src = fixture(&[
+            ("about.pug", "h1 #{slug}\np #{site}"),
+            ("contact-us.pug", "h1 #{slug}"),
+            ("secret.pug", "h1 #{slug}"),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+        let inputs =
+            ["about.pug", "contact-us.pug", "secret.pug"].map(|name| src.path().join(name));
+        let options = PugOptions::new()
+            .with_object(json!({ "site": "acme", "slug": "none" }))
+            .locals_for(|path: &Path| {
+                let slug = path.file_stem().unwrap().to_string_lossy();
+                if slug == "secret" {
+                    return Err(CompileError::InvalidOptions("no locals for secret".into()));
+                }
+                Ok(json!({ "slug": slug }).into())
+            });
+
+        let report = compile_batch(&inputs, out.path(), options);
+
+        assert_eq!((2, 1), (report.succeeded(), report.failed()));
+        let read = |page: &str| fs::read_to_string(out.path().join(page)).unwrap();
+        assert_eq!("<h1>about</h1><p>acme</p>", read("about.html"));
+        assert_eq!("<h1>contact-us</h1>", read("contact-us.html"));
+        assert!(matches!(
+            &report.files[2].error,
+            Some(CompileError::InvalidOptions(reason)) if reason.contains("secret")
+        ));
+    }
+
+    #[test]
+    fn merges_each_pages_sidecar() {
+        let src = fixture(&[
+            ("about.pug", "h1 #{site.name} #{site.year}"),
+            ("about.json", r#"{ "site": { "name": "About" } }"#),
+            ("home.pug", "h1 #{site.name} #{site.year}"),
+            ("broken.pug", "h1 #{site.name}"),
+            ("broken.json", r#"{ "site": "#),
+        ]);
+        let out = tempfile::tempdir().unwrap();
+        let inputs = ["about.pug", "home.pug", "broken.pug"].map(|name| src.path().join(name));
+        let options = PugOptions::new()
+            .with_object(json!({ "site": { "name": "Acme", "year": 2024 } }))
+            .sidecar_locals(true);
+
+        let report = compile_batch(&inputs, out.path(), options);
+
+        assert_eq!((2, 1), (report.succeeded(), report.failed()));
+        let read = |page: &str| fs::read_to_string(out.path().join(page)).unwrap();
+        assert_eq!("<h1>About 2024</h1>", read("about.html"));
+        assert_eq!("<h1>Acme 2024</h1>", read("home.html"));
+        let json = report.to_json();
+        assert_eq!(
+            src.path().join("about.json").to_str().unwrap(),
+            json["files"][0]["sidecar"]
+        );
+        assert!(json["files"][1]["sidecar"].is_null());
+        match &report.files[2].error {
+            Some(error @ CompileError::Sidecar { path, .. }) => {
+                assert_eq!(&src.path().join("broken.json"), path);
+                assert!(error.to_string().contains("broken.json"), "{}", error);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_the_json_types() {
+        let src = fixture(&[("broken.pug", "  p indented too early")]);
+        let out = tempfile::tempdir().unwrap();
+        let report = compile_batch(
+            [src.path().join("broken.pug")],
+            out.path(),
+            PugOptions::new(),
+        );
+        let json = report.to_json();
+
+        let typed: BatchReportJson = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(1, typed.summary.failed);
+        assert_eq!(Some(1), typed.files[0].error.as_ref().unwrap().line);
+        assert_eq!(json, serde_json::to_value(&typed).unwrap());
+    }
+}
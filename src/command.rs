@@ -0,0 +1,105 @@
+//! The pug process the `evaluate` functions run, for callers who need to set
+//! it up or run it themselves: `pre_exec` hooks, process groups, piping it
+//! into another process.
+
+use crate::invocation::{interpret_output, program, PostProcess};
+use crate::{CompileError, PugOptions};
+use std::process::{Command, Output, Stdio};
+
+/// Builds the command pug is run with, ready to spawn.
+pub struct PugCommand;
+
+impl PugCommand {
+    /// pug as `evaluate_string_with_options` runs it: the `binary` (or the
+    /// one on `PATH`), the arguments `options` iterates to, the environment
+    /// and, on Unix, the resource limits for `memory_limit` and
+    /// `cpu_time_limit`, with stdin, stdout and stderr piped. Write the
+    /// template to stdin, close it and hand the output to `process_output`.
+    ///
+    /// What the crate does around pug rather than through its arguments
+    /// isn't part of it: preludes, front matter, timeouts, retries and
+    /// executors. Locals are passed inline even with `locals_via_file`, as
+    /// nothing would be left to remove the file, and the options aren't
+    /// validated; `PugOptions::validate` does that.
+    pub fn from_options(options: &PugOptions) -> Command {
+        let mut options = options.clone().stdin();
+        let program = program(&options);
+        if options.flavor.is_none() {
+            options.flavor = crate::health::known_flavor(&program);
+        }
+        let mut command = options.limits.command(&program);
+        command
+            .args(options)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+
+    /// `from_options` as a tokio command.
+    #[cfg(feature = "tokio")]
+    pub fn tokio(options: &PugOptions) -> ::tokio::process::Command {
+        ::tokio::process::Command::from(PugCommand::from_options(options))
+    }
+}
+
+/// The HTML in the `output` of a `PugCommand` run with `options`,
+/// post-processed as `evaluate_string_with_options` would have, or the error
+/// it would have returned.
+pub fn process_output(output: Output, options: &PugOptions) -> Result<String, CompileError> {
+    interpret_output(output, PostProcess::new(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate_string_with_options;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn options() -> PugOptions {
+        PugOptions::new()
+            .with_object(json!({ "language": "pug" }))
+            .doctype("html".into())
+            .binary("pug")
+    }
+
+    #[test]
+    fn runs_like_evaluate() {
+        let command = PugCommand::from_options(&options());
+        assert_eq!("pug", command.get_program());
+        let args: Vec<String> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(options().into_iter().collect::<Vec<_>>(), args);
+
+        let template = "h1 hello #{language}";
+        let mut child = PugCommand::from_options(&options()).spawn().unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(template.as_bytes()).unwrap();
+        drop(stdin);
+        let html = process_output(child.wait_with_output().unwrap(), &options());
+        assert_eq!(
+            evaluate_string_with_options(template.into(), options()).unwrap(),
+            html.unwrap()
+        );
+
+        let mut child = PugCommand::from_options(&options()).spawn().unwrap();
+        child.stdin.take().unwrap().write_all(b"  p").unwrap();
+        let error = process_output(child.wait_with_output().unwrap(), &options()).unwrap_err();
+        assert!(matches!(error, CompileError::PugError(_)), "{:?}", error);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[::tokio::test]
+    async fn runs_under_tokio() {
+        use ::tokio::io::AsyncWriteExt;
+        let mut child = PugCommand::tokio(&options()).spawn().unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(b"p #{language}").await.unwrap();
+        drop(stdin);
+        let output = child.wait_with_output().await.unwrap();
+        assert_eq!("<p>pug</p>", process_output(output, &options()).unwrap());
+    }
+}
@@ -0,0 +1,105 @@
+//! Compiling a string template from a file among others, for the `extends`
+//! and includes that only pug can resolve.
+
+use crate::temp_file::TempFile;
+use crate::{evaluate_with_options, CompileError, PugOptions};
+use std::path::Path;
+
+/// Compiles `source` as if it were a template in `context_dir`, so relative
+/// `extends ./layout` and includes resolve from there. If `context_dir` is a
+/// file, such as the layout itself, the template goes alongside it instead.
+///
+/// The source is written to a randomly named file there for the compile and
+/// removed afterwards, whether or not it compiled. Errors in it name the
+/// template by `PugOptions::source_name`, or `Pug` as for other string
+/// templates, rather than by that file.
+pub fn evaluate_string_in_context(
+    source: &str,
+    context_dir: &Path,
+    mut options: PugOptions,
+) -> Result<String, CompileError> {
+    let dir = if context_dir.is_file() {
+        context_dir.parent().unwrap_or_else(|| Path::new(""))
+    } else {
+        context_dir
+    };
+    let name = options.source_name.take().unwrap_or_else(|| "Pug".into());
+    let file = TempFile::private_in(dir, ".pug", source.as_bytes())
+        .map_err(CompileError::io_at("write template in", dir))?;
+    evaluate_with_options(file.path(), options).map_err(|error| match error {
+        CompileError::PugError(stderr) => {
+            CompileError::PugError(rename(&stderr, file.path(), &name))
+        }
+        other => other,
+    })
+}
+
+/// `stderr` with the temp file's path, relative or absolute, as `name`.
+fn rename(stderr: &str, path: &Path, name: &str) -> String {
+    let mut renamed = stderr.to_owned();
+    if let Ok(absolute) = std::env::current_dir().map(|dir| dir.join(path)) {
+        renamed = renamed.replace(&*absolute.to_string_lossy(), name);
+    }
+    renamed.replace(&*path.to_string_lossy(), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn entries(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn extends_a_layout_and_cleans_up() {
+        let dir = Path::new("test/include");
+        let before = entries(dir);
+        let source = "extends ./layout\nblock content\n  p From a string";
+        assert_eq!(
+            "<html><head><title>Site</title></head>\
+             <body><p>From a string</p><footer>(c)</footer></body></html>",
+            evaluate_string_in_context(source, dir, PugOptions::new()).unwrap()
+        );
+        let alongside =
+            evaluate_string_in_context(source, &dir.join("layout.pug"), PugOptions::new());
+        assert!(alongside.unwrap().contains("From a string"));
+        assert_eq!(before, entries(dir));
+    }
+
+    #[test]
+    fn names_errors_by_source_name() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("layout.pug"), "main\n  block content\n").unwrap();
+        let options = PugOptions::new().source_name("emails/welcome");
+
+        let source = "extends layout\nblock sidebar\n  p ok";
+        let error = evaluate_string_in_context(source, dir.path(), options).unwrap_err();
+        let details = error.details().unwrap();
+        assert_eq!(
+            ("emails/welcome", 2),
+            (details.filename.as_str(), details.line)
+        );
+        assert_eq!(1, entries(dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_symlinked_context_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let views = dir.path().join("views");
+        fs::create_dir(&views).unwrap();
+        fs::write(
+            views.join("layout.pug"),
+            "main\n  block content\n    p Default\n",
+        )
+        .unwrap();
+        let link = dir.path().join("linked");
+        std::os::unix::fs::symlink(&views, &link).unwrap();
+
+        let page = evaluate_string_in_context("extends layout", &link, PugOptions::new()).unwrap();
+        assert_eq!("<main><p>Default</p></main>", page);
+        assert_eq!(1, entries(&views));
+    }
+}
@@ -0,0 +1,264 @@
+//! Checks for the environmental problems that stop pug from running at all:
+//! a missing binary, a broken install, no node, or an unwritable out dir.
+
+use crate::health::parse_pug_version;
+use crate::invocation::program;
+use crate::{evaluate_string_with_options, PugOptions};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// One check `doctor` ran.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    /// What was found, or what went wrong.
+    pub detail: String,
+    /// What to do about a failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            passed: true,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Check {
+        Check {
+            name,
+            passed: false,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// What `doctor` found, in the order the checks ran. `Display` prints one
+/// line per check, with hints under the failures.
+#[derive(Clone, Debug)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = if check.passed { "ok" } else { "FAIL" };
+            writeln!(f, "[{:>4}] {}: {}", status, check.name, check.detail)?;
+            if let Some(hint) = &check.hint {
+                writeln!(f, "       hint: {}", hint)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every check against the default `pug` on `PATH`.
+pub fn doctor() -> DoctorReport {
+    doctor_with(PugOptions::new())
+}
+
+/// Like `doctor`, running the binary set with `PugOptions::binary` and also
+/// checking that `PugOptions::out_dir` can be written to.
+///
+/// Later checks still run when an earlier one fails, so the report shows
+/// everything that's wrong at once.
+pub fn doctor_with(options: PugOptions) -> DoctorReport {
+    let mut checks = vec![
+        check_binary(&options),
+        check_version(&options),
+        check_node(),
+        check_compile(&options),
+    ];
+    if let Some(out_dir) = &options.out_dir {
+        checks.push(check_out_dir(out_dir));
+    }
+    DoctorReport { checks }
+}
+
+const INSTALL_HINT: &str = "install it with `npm install -g pug-cli`";
+
+fn check_binary(options: &PugOptions) -> Check {
+    let name = "pug binary";
+    let binary = program(options);
+    if let Some(found) = resolve(&binary) {
+        return Check::pass(name, found.display().to_string());
+    }
+    let hint = if options.binary.is_some() {
+        "check the path given to `PugOptions::binary`".into()
+    } else if resolve(Path::new("npx")).is_some() {
+        format!(
+            "{}, or use `PugOptions::binary` with a script that runs `npx pug`",
+            INSTALL_HINT
+        )
+    } else {
+        format!("{}; node and npm are needed first", INSTALL_HINT)
+    };
+    Check::fail(name, format!("{} not found", binary.display()), hint)
+}
+
+fn check_version(options: &PugOptions) -> Check {
+    let name = "pug version";
+    match run_version(&program(options)) {
+        Ok(output) => match parse_pug_version(&output) {
+            Some(version) => Check::pass(name, version),
+            None => Check::fail(
+                name,
+                format!("unrecognised `--version` output: {:?}", output.trim()),
+                format!("this doesn't look like pug-cli; {}", INSTALL_HINT),
+            ),
+        },
+        Err(reason) => Check::fail(name, reason, INSTALL_HINT),
+    }
+}
+
+fn check_node() -> Check {
+    let name = "node";
+    match run_version(Path::new("node")) {
+        Ok(output) => Check::pass(name, output.trim()),
+        Err(reason) => Check::fail(
+            name,
+            reason,
+            "pug-cli runs on node; install it from https://nodejs.org",
+        ),
+    }
+}
+
+fn check_compile(options: &PugOptions) -> Check {
+    let name = "compile";
+    let options = PugOptions::new().binary(program(options));
+    match evaluate_string_with_options("h1 ok".into(), options) {
+        Ok(html) if html.trim() == "<h1>ok</h1>" => Check::pass(name, "`h1 ok` compiled"),
+        Ok(html) => Check::fail(
+            name,
+            format!("`h1 ok` compiled to {:?}", html),
+            "the binary ran but isn't pug; check which `pug` is on PATH",
+        ),
+        Err(error) => Check::fail(
+            name,
+            error.to_string(),
+            "fix the checks above, then try `echo 'h1 ok' | pug`",
+        ),
+    }
+}
+
+fn check_out_dir(out_dir: &Path) -> Check {
+    let name = "out dir";
+    let probe = out_dir.join(".pug_cli_doctor");
+    let written = fs::create_dir_all(out_dir)
+        .and_then(|_| fs::write(&probe, b""))
+        .and_then(|_| fs::remove_file(&probe));
+    match written {
+        Ok(()) => Check::pass(name, format!("{} is writable", out_dir.display())),
+        Err(e) => Check::fail(
+            name,
+            format!("can't write to {}: {}", out_dir.display(), e),
+            "choose another directory or fix its permissions",
+        ),
+    }
+}
+
+/// `program --version`'s stdout, or why it couldn't be run.
+fn run_version(program: &Path) -> Result<String, String> {
+    let output = Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("couldn't run {}: {}", program.display(), e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into())
+    } else {
+        Err(format!(
+            "{} --version exited with {}",
+            program.display(),
+            output.status
+        ))
+    }
+}
+
+/// Where `binary` would be run from: the path itself if it has a directory
+/// part, otherwise the first match on `PATH`.
+pub(crate) fn resolve(binary: &Path) -> Option<PathBuf> {
+    if binary.components().count() > 1 {
+        return Some(binary.to_owned()).filter(|path| path.is_file());
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(binary);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        // npm installs `pug.cmd` on Windows
+        ["cmd", "exe"]
+            .iter()
+            .map(|extension| candidate.with_extension(extension))
+            .find(|path| cfg!(windows) && path.is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invocation::PUG_BINARY;
+    #[cfg(unix)]
+    use crate::test_support::fake_binary;
+
+    #[test]
+    fn passes_with_a_working_install() {
+        let out = tempfile::tempdir().unwrap();
+        let report = doctor_with(PugOptions::new().out_dir(out.path().join("new")));
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(5, report.checks.len());
+        assert_eq!(Some(PUG_BINARY), report.checks[0].detail.rsplit('/').next());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_each_failure_with_a_hint() {
+        let dir = tempfile::tempdir().unwrap();
+        let impostor = fake_binary(
+            dir.path(),
+            "impostor",
+            "cat > /dev/null 2>&1\necho 'impostor 1.0'\n",
+        );
+        let not_a_dir = dir.path().join("file");
+        fs::write(&not_a_dir, "").unwrap();
+
+        let report = doctor_with(PugOptions::new().binary(&impostor).out_dir(&not_a_dir));
+        let failed: Vec<&str> = report.failures().map(|check| check.name).collect();
+        assert_eq!(vec!["pug version", "compile", "out dir"], failed);
+        assert!(report.failures().all(|check| check.hint.is_some()));
+        let text = report.to_string();
+        assert!(text.contains("[  ok] pug binary: "), "{}", text);
+        assert!(
+            text.contains("[FAIL] pug version: unrecognised"),
+            "{}",
+            text
+        );
+
+        let missing = doctor_with(PugOptions::new().binary(dir.path().join("missing")));
+        assert!(!missing.checks[0].passed);
+        assert!(missing.checks[0]
+            .hint
+            .as_ref()
+            .unwrap()
+            .contains("PugOptions::binary"));
+    }
+}
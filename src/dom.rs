@@ -0,0 +1,123 @@
+//! Compiled pages parsed into a [`scraper`] DOM, for pulling out titles,
+//! links or fragments after rendering.
+
+use crate::{evaluate_with_options, CompileError, PugOptions};
+use scraper::{ElementRef, Html, Selector};
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Compiles `template` and parses the result as an HTML document.
+pub fn evaluate_to_dom(
+    template: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<Html, CompileError> {
+    evaluate_with_options(template, options).map(|html| Html::parse_document(&html))
+}
+
+/// A CSS selector that couldn't be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectorError {
+    pub selector: String,
+    pub reason: String,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid selector {:?}: {}", self.selector, self.reason)
+    }
+}
+
+impl error::Error for SelectorError {}
+
+/// Compiled HTML together with its parsed document.
+pub struct CompiledPage {
+    html: String,
+    dom: Html,
+}
+
+impl CompiledPage {
+    /// Compiles `template` and parses it.
+    pub fn evaluate(
+        template: impl Into<PathBuf>,
+        options: PugOptions,
+    ) -> Result<CompiledPage, CompileError> {
+        evaluate_with_options(template, options).map(CompiledPage::parse)
+    }
+
+    /// Parses HTML that has already been rendered.
+    pub fn parse(html: String) -> CompiledPage {
+        let dom = Html::parse_document(&html);
+        CompiledPage { html, dom }
+    }
+
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    pub fn dom(&self) -> &Html {
+        &self.dom
+    }
+
+    /// Every element matching `selector`, in document order.
+    pub fn select(&self, selector: &str) -> Result<Vec<ElementRef<'_>>, SelectorError> {
+        let parsed = Selector::parse(selector).map_err(|e| SelectorError {
+            selector: selector.to_owned(),
+            reason: e.to_string(),
+        })?;
+        Ok(self.dom.select(&parsed).collect())
+    }
+
+    /// The text of the first `<title>`, trimmed.
+    pub fn title(&self) -> Option<String> {
+        let title = Selector::parse("title").unwrap();
+        self.dom
+            .select(&title)
+            .next()
+            .map(|title| title.text().collect::<String>().trim().to_owned())
+    }
+
+    /// The `href` of every element that has one (`a`, `link`, `area`, ...),
+    /// in document order.
+    pub fn links(&self) -> Vec<&str> {
+        let linked = Selector::parse("[href]").unwrap();
+        self.dom
+            .select(&linked)
+            .filter_map(|element| element.value().attr("href"))
+            .collect()
+    }
+}
+
+impl fmt::Debug for CompiledPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledPage")
+            .field("html", &self.html)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_by_selector() {
+        let page = CompiledPage::evaluate("test/dom.pug", PugOptions::new()).unwrap();
+
+        assert_eq!(Some("Dom page".to_owned()), page.title());
+        assert_eq!(vec!["/", "/about"], page.links());
+        let items: Vec<String> = page
+            .select("ul.items > li")
+            .unwrap()
+            .iter()
+            .map(|li| li.text().collect())
+            .collect();
+        assert_eq!(vec!["one", "two"], items);
+
+        let error = page.select("ul >> li[").unwrap_err();
+        assert_eq!("ul >> li[", error.selector);
+
+        let dom = evaluate_to_dom("test/dom.pug", PugOptions::new()).unwrap();
+        assert_eq!(1, dom.select(&Selector::parse("nav").unwrap()).count());
+    }
+}
@@ -0,0 +1,118 @@
+//! Inlining CSS onto elements for HTML email, which most mail clients need
+//! since they ignore `<style>` blocks.
+//!
+//! The step runs on HTML that has already been compiled, so it composes with
+//! any way of rendering:
+//!
+//! ```ignore
+//! let inline = InlineCssOptions::new().stylesheet("emails/base.css");
+//! let emails: Vec<_> = compile_many_async(templates, options, 8)
+//!     .await
+//!     .into_iter()
+//!     .map(|html| html.and_then(|html| inline_css(&html, &inline)))
+//!     .collect();
+//! ```
+
+use crate::CompileError;
+use css_inline::CSSInliner;
+use std::borrow::Cow;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug)]
+pub struct InlineCssOptions {
+    stylesheets: Vec<PathBuf>,
+    keep_style_tags: bool,
+}
+
+impl InlineCssOptions {
+    pub fn new() -> Self {
+        InlineCssOptions {
+            stylesheets: Vec::new(),
+            keep_style_tags: false,
+        }
+    }
+
+    /// Inlines the rules in this file as well as the document's own `<style>`
+    /// blocks. May be given more than once; later files win ties.
+    pub fn stylesheet(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stylesheets.push(path.into());
+        self
+    }
+
+    /// Leaves the `<style>` tags in place after inlining them, for clients
+    /// that do support them (media queries, for instance). They are removed
+    /// by default.
+    pub fn keep_style_tags(mut self, keep: bool) -> Self {
+        self.keep_style_tags = keep;
+        self
+    }
+}
+
+impl Default for InlineCssOptions {
+    fn default() -> Self {
+        InlineCssOptions::new()
+    }
+}
+
+/// Moves the CSS from `html`'s `<style>` tags and the extra stylesheets onto
+/// the `style` attributes of the elements it matches.
+///
+/// A stylesheet that can't be read fails with `CompileError::IoAt`, CSS the
+/// inliner can't handle with `CompileError::PostProcessing`. Remote
+/// stylesheets referenced by `<link>` tags are not fetched.
+pub fn inline_css(html: &str, options: &InlineCssOptions) -> Result<String, CompileError> {
+    let mut extra_css = String::new();
+    for stylesheet in &options.stylesheets {
+        let css = fs::read_to_string(stylesheet)
+            .map_err(CompileError::io_at("read stylesheet", stylesheet))?;
+        extra_css.push_str(&css);
+        extra_css.push('\n');
+    }
+
+    let inliner = CSSInliner::options()
+        .keep_style_tags(options.keep_style_tags)
+        .load_remote_stylesheets(false)
+        .extra_css(Some(Cow::Owned(extra_css)))
+        .build();
+    inliner
+        .inline(html)
+        .map_err(|e| CompileError::PostProcessing(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate;
+
+    #[test]
+    fn moves_class_rules_onto_the_element() {
+        let html = evaluate("test/email.pug").unwrap();
+        let options = InlineCssOptions::new().stylesheet("test/email.css");
+
+        let inlined = inline_css(&html, &options).unwrap();
+
+        assert!(
+            inlined.contains(r#"<p class="greeting" style="margin: 0;color: red;">Hello</p>"#),
+            "{}",
+            inlined
+        );
+        assert!(!inlined.contains("<style>"));
+
+        let kept = inline_css(&html, &options.keep_style_tags(true)).unwrap();
+        assert!(kept.contains("<style>"));
+    }
+
+    #[test]
+    fn missing_stylesheet_is_an_io_error() {
+        let options = InlineCssOptions::new().stylesheet("test/missing.css");
+        let result = inline_css("<p>hi</p>", &options);
+        assert!(matches!(
+            result,
+            Err(CompileError::IoAt {
+                op: "read stylesheet",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+//! Template sources compiled into the binary by `templates!`, rendered with
+//! locals at runtime.
+
+use crate::include::inline_includes_of;
+use crate::{evaluate_string_with_options, CompileError, PugJsonObject, PugOptions};
+use std::sync::OnceLock;
+
+/// The templates `templates!` embedded, rendered by name. Usable as a
+/// `static`, and `Send` and `Sync` like `TemplateRegistry`.
+///
+/// There are no files at runtime, so a template's includes and `extends`
+/// are spliced in from the other embedded templates, with
+/// `inline_includes`, the first time it's rendered. Only `.pug` files are
+/// embedded; including anything else fails with `CompileError::Include`.
+pub struct EmbeddedTemplates {
+    /// `(name, source)`, by name.
+    templates: &'static [(&'static str, &'static str)],
+    /// Each template's source with its includes inlined, in the same order.
+    inlined: OnceLock<Box<[OnceLock<String>]>>,
+}
+
+impl EmbeddedTemplates {
+    #[doc(hidden)]
+    pub const fn new(templates: &'static [(&'static str, &'static str)]) -> Self {
+        EmbeddedTemplates {
+            templates,
+            inlined: OnceLock::new(),
+        }
+    }
+
+    /// Renders the template embedded as `name` with `locals`, failing with
+    /// `CompileError::UnknownTemplate` if there's none.
+    pub fn render(
+        &self,
+        name: &str,
+        locals: impl Into<PugJsonObject>,
+    ) -> Result<String, CompileError> {
+        self.render_with_options(name, PugOptions::new().with_object(locals))
+    }
+
+    /// `render` with `options` in place of just the locals.
+    pub fn render_with_options(
+        &self,
+        name: &str,
+        options: PugOptions,
+    ) -> Result<String, CompileError> {
+        let index = self
+            .templates
+            .binary_search_by(|(embedded, _)| (*embedded).cmp(name))
+            .map_err(|_| CompileError::UnknownTemplate(name.to_owned()))?;
+        let file = format!("{}.pug", name);
+        let source = self.inlined(index, &file)?;
+        let options = match options.source_name {
+            Some(_) => options,
+            None => options.source_name(file),
+        };
+        evaluate_string_with_options(source, options)
+    }
+
+    /// The embedded names, in order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.templates.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn inlined(&self, index: usize, file: &str) -> Result<String, CompileError> {
+        let cells = self
+            .inlined
+            .get_or_init(|| self.templates.iter().map(|_| OnceLock::new()).collect());
+        if let Some(inlined) = cells[index].get() {
+            return Ok(inlined.clone());
+        }
+        let resolver = |path: &str| {
+            let name = path.strip_suffix(".pug")?;
+            let index = self
+                .templates
+                .binary_search_by(|(embedded, _)| (*embedded).cmp(name))
+                .ok()?;
+            Some(self.templates[index].1.to_owned())
+        };
+        let inlined = inline_includes_of(file, self.templates[index].1, resolver)?;
+        Ok(cells[index].get_or_init(|| inlined).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    static TEMPLATES: EmbeddedTemplates = crate::templates!("test/embedded");
+
+    #[test]
+    fn renders_embedded_templates_by_name() {
+        assert_eq!(
+            vec!["greeting", "pages/home", "partials/nav"],
+            TEMPLATES.names()
+        );
+        assert_eq!(
+            "<p>Hello, Ada</p>",
+            TEMPLATES
+                .render("greeting", json!({ "name": "Ada" }))
+                .unwrap()
+        );
+        for _ in 0..2 {
+            let home = TEMPLATES.render("pages/home", json!({ "title": "Home" }));
+            assert_eq!("<nav>Site</nav><h1>Home</h1>", home.unwrap());
+        }
+
+        let missing = TEMPLATES.render("pages/missing", json!({})).unwrap_err();
+        assert!(matches!(&missing, CompileError::UnknownTemplate(name) if name == "pages/missing"));
+    }
+
+    #[test]
+    fn names_errors_by_template_unless_told_otherwise() {
+        let templates = EmbeddedTemplates::new(&[("broken", "p ok\n| piped\n  b bad")]);
+        let filename = |options| {
+            let error = templates
+                .render_with_options("broken", options)
+                .unwrap_err();
+            error.details().unwrap().filename
+        };
+        assert_eq!("broken.pug", filename(PugOptions::new()));
+        let named = PugOptions::new().source_name("views/broken");
+        assert_eq!("views/broken", filename(named));
+    }
+}
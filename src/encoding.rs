@@ -0,0 +1,123 @@
+//! Writing pages in something other than plain UTF-8, for consumers that
+//! insist on it.
+
+use crate::CompileError;
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+/// How `PugOptions::output_encoding` has pages written to files. The HTML
+/// returned as a `String` is always UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// As pug writes it.
+    #[default]
+    Utf8,
+    /// UTF-8 behind a byte order mark.
+    Utf8Bom,
+    /// A legacy encoding such as `encoding_rs::WINDOWS_1252`. Characters it
+    /// has no bytes for become character references, `&#8364;`, unless
+    /// `PugOptions::strict_encoding` is set. UTF-16 and `replacement` can't
+    /// be encoded into, so they write UTF-8, as encoding_rs does.
+    Lossy(&'static Encoding),
+}
+
+/// What `PugOptions::output_encoding` and `strict_encoding` set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Encode {
+    pub(crate) encoding: OutputEncoding,
+    pub(crate) strict: bool,
+}
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+impl Encode {
+    /// `html` as the bytes to write.
+    pub(crate) fn apply<'a>(&self, html: &'a str) -> Result<Cow<'a, [u8]>, CompileError> {
+        match self.encoding {
+            OutputEncoding::Utf8 => Ok(Cow::Borrowed(html.as_bytes())),
+            OutputEncoding::Utf8Bom => Ok(Cow::Owned([BOM, html.as_bytes()].concat())),
+            OutputEncoding::Lossy(encoding) => {
+                let (bytes, used, unmappable) = encoding.encode(html);
+                if unmappable && self.strict {
+                    let mut buffer = [0; 4];
+                    let character = html
+                        .chars()
+                        .find(|c| encoding.encode(c.encode_utf8(&mut buffer)).2)
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    return Err(CompileError::Unencodable {
+                        encoding: used.name().to_owned(),
+                        character,
+                    });
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Whether `apply` writes plain UTF-8.
+    pub(crate) fn is_utf8(&self) -> bool {
+        match self.encoding {
+            OutputEncoding::Utf8 => true,
+            OutputEncoding::Utf8Bom => false,
+            OutputEncoding::Lossy(encoding) => encoding.output_encoding() == encoding_rs::UTF_8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_to_file, PugOptions};
+    use std::fs;
+
+    #[test]
+    fn writes_each_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("menu.pug");
+        fs::write(&template, "p Café crème €5").unwrap();
+        let write = |name: &str, options: PugOptions| {
+            let page = dir.path().join(name);
+            evaluate_to_file(&template, &page, options).map(|_| fs::read(page).unwrap())
+        };
+
+        let utf8 = write("utf8.html", PugOptions::new()).unwrap();
+        assert_eq!("<p>Café crème €5</p>".as_bytes(), &*utf8);
+        let bom = write(
+            "bom.html",
+            PugOptions::new().output_encoding(OutputEncoding::Utf8Bom),
+        )
+        .unwrap();
+        assert_eq!((BOM, &*utf8), bom.split_at(3));
+        let (decoded, _, malformed) = encoding_rs::UTF_8.decode(&bom);
+        assert_eq!(("<p>Café crème €5</p>", false), (&*decoded, malformed));
+
+        let latin2 = OutputEncoding::Lossy(encoding_rs::ISO_8859_2);
+        let lossy = write("lossy.html", PugOptions::new().output_encoding(latin2)).unwrap();
+        assert!(
+            lossy.starts_with(b"<p>Caf\xE9 cr&#232;me &#8364;5"),
+            "{:?}",
+            lossy
+        );
+        let (decoded, malformed) = encoding_rs::ISO_8859_2.decode_without_bom_handling(&lossy);
+        assert_eq!(
+            ("<p>Café cr&#232;me &#8364;5</p>", false),
+            (&*decoded, malformed)
+        );
+
+        let cp1252 = OutputEncoding::Lossy(encoding_rs::WINDOWS_1252);
+        let strict = PugOptions::new().output_encoding(cp1252).strict_encoding();
+        assert_eq!(
+            b"<p>Caf\xE9 cr\xE8me \x805</p>",
+            &*write("cp1252.html", strict.clone()).unwrap()
+        );
+        let strict = strict.output_encoding(latin2);
+        match write("strict.html", strict) {
+            Err(CompileError::Unencodable {
+                encoding,
+                character,
+            }) => assert_eq!(("ISO-8859-2", 'è'), (&*encoding, character)),
+            other => panic!("{:?}", other),
+        }
+        assert!(!dir.path().join("strict.html").exists());
+    }
+}
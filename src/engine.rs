@@ -0,0 +1,149 @@
+//! Options configured once and shared by everything that renders.
+
+use crate::{
+    evaluate_string_with_options, evaluate_with_options, CompileError, ErrorPageOptions, PugOptions,
+};
+use std::path::PathBuf;
+
+/// The options every render starts from.
+///
+/// Framework integrations look one of these up from application state and
+/// fall back to `PugEngine::default()` when none is installed.
+#[derive(Clone, Default)]
+pub struct PugEngine {
+    defaults: PugOptions,
+    debug_errors: bool,
+}
+
+impl PugEngine {
+    pub fn new(defaults: PugOptions) -> Self {
+        PugEngine {
+            defaults,
+            debug_errors: false,
+        }
+    }
+
+    pub fn defaults(&self) -> &PugOptions {
+        &self.defaults
+    }
+
+    /// Compiles the file `template` with `overrides` merged over the
+    /// defaults; see `merged`.
+    pub fn render(
+        &self,
+        template: impl Into<PathBuf>,
+        overrides: PugOptions,
+    ) -> Result<String, CompileError> {
+        evaluate_with_options(template, self.merged(overrides))
+    }
+
+    /// `render` for a template held in a string.
+    pub fn render_str(
+        &self,
+        template: impl Into<String>,
+        overrides: PugOptions,
+    ) -> Result<String, CompileError> {
+        evaluate_string_with_options(template.into(), self.merged(overrides))
+    }
+
+    /// The defaults with `overrides` merged over them; see
+    /// `PugOptions::merge`.
+    pub fn merged(&self, overrides: PugOptions) -> PugOptions {
+        self.defaults.clone().merge(overrides)
+    }
+
+    /// Makes the framework integrations answer failed renders with
+    /// `CompileError::to_html`, source excerpt included. Meant for
+    /// development only.
+    pub fn debug_errors(mut self, debug_errors: bool) -> Self {
+        self.debug_errors = debug_errors;
+        self
+    }
+
+    /// The debug error page for `error`, if debug errors are on.
+    #[cfg_attr(
+        not(any(
+            feature = "actix",
+            feature = "axum",
+            feature = "rocket",
+            feature = "warp"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn error_page(&self, error: &CompileError) -> Option<String> {
+        if self.debug_errors {
+            Some(error.to_html(ErrorPageOptions::new()))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Flag;
+    use serde_json::json;
+
+    fn args(options: PugOptions) -> Vec<String> {
+        options.into_iter().collect()
+    }
+
+    #[test]
+    fn overrides_win_and_the_rest_is_inherited() {
+        let defaults = PugOptions::new()
+            .pretty()
+            .doctype("html".into())
+            .with_object(json!({ "name": "default" }));
+        let engine = PugEngine::new(defaults);
+
+        assert_eq!(
+            args(engine.defaults().clone()),
+            args(engine.merged(PugOptions::new()))
+        );
+        let merged = engine.merged(PugOptions::new().with_object(json!({ "name": "pug" })));
+        assert_eq!(
+            vec![
+                "--obj",
+                r#"{"name":"pug"}"#,
+                "--pretty",
+                "--doctype",
+                "html"
+            ],
+            args(merged)
+        );
+        let compact = engine.merged(PugOptions::new().set_flag(Flag::Pretty, false));
+        assert_eq!(
+            vec!["--obj", r#"{"name":"default"}"#, "--doctype", "html"],
+            args(compact)
+        );
+
+        let raw = engine.merged(PugOptions::new().normalize_newlines(false));
+        assert!(!raw.normalize_newlines);
+        assert!(engine.merged(PugOptions::new().no_debug()).pretty);
+        assert!(engine.defaults().pretty);
+        assert!(engine.defaults().normalize_newlines);
+        assert_eq!(
+            vec![
+                "--obj",
+                r#"{"name":"default"}"#,
+                "--pretty",
+                "--doctype",
+                "html"
+            ],
+            args(engine.defaults().clone())
+        );
+    }
+
+    #[test]
+    fn renders_with_the_merged_options() {
+        let engine = PugEngine::new(PugOptions::new().with_object(json!({ "name": "default" })));
+        let html = engine.render("test/greeting.pug", PugOptions::new());
+        assert_eq!("<p>Hello, default!</p>", html.unwrap());
+        let html = engine.render_str(
+            "p Hi, #{name}",
+            PugOptions::new().with_object(json!({ "name": "pug" })),
+        );
+        assert_eq!("<p>Hi, pug</p>", html.unwrap());
+    }
+}
@@ -0,0 +1,154 @@
+//! What runs pug, so tests can stand in for the process.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+#[cfg(any(test, feature = "testing"))]
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Runs the pug command line for a compile.
+///
+/// Set one with `PugOptions::executor` to compile without spawning pug.
+/// Output interpretation, post-processing and error parsing all still
+/// happen, on whatever the executor returns. It is called on the calling
+/// thread, from the async functions too, and `PugOptions::timeout` doesn't
+/// apply to it.
+pub trait Executor: Send + Sync {
+    /// Runs `program` with `args`, feeding it `stdin`, and waits for it.
+    fn run(&self, program: &Path, args: &[String], stdin: &[u8]) -> io::Result<Output>;
+}
+
+/// Spawns the program, which is what compiles do without an executor.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessExecutor;
+
+impl Executor for ProcessExecutor {
+    fn run(&self, program: &Path, args: &[String], stdin: &[u8]) -> io::Result<Output> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut pipe) = child.stdin.take() {
+            crate::invocation::tolerate_closed_stdin(pipe.write_all(stdin))?;
+        }
+        child.wait_with_output()
+    }
+}
+
+/// One run a `MockExecutor` answered.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedRun {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub stdin: Vec<u8>,
+}
+
+/// An `Executor` answering every run with the same canned output and
+/// recording what it was asked to run. Clones share their record.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Clone)]
+pub struct MockExecutor {
+    output: Output,
+    runs: Arc<Mutex<Vec<RecordedRun>>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MockExecutor {
+    /// Exits successfully, printing `stdout`.
+    pub fn succeeding(stdout: impl Into<Vec<u8>>) -> Self {
+        MockExecutor::exiting(0, stdout, "")
+    }
+
+    /// Exits with `code`, printing `stderr`.
+    pub fn failing(code: i32, stderr: impl Into<Vec<u8>>) -> Self {
+        MockExecutor::exiting(code, "", stderr)
+    }
+
+    /// Also prints `stderr`, for warnings from compiles that succeed.
+    pub fn with_stderr(mut self, stderr: impl Into<Vec<u8>>) -> Self {
+        self.output.stderr = stderr.into();
+        self
+    }
+
+    fn exiting(code: i32, stdout: impl Into<Vec<u8>>, stderr: impl Into<Vec<u8>>) -> Self {
+        MockExecutor {
+            output: Output {
+                status: exit_status(code),
+                stdout: stdout.into(),
+                stderr: stderr.into(),
+            },
+            runs: Arc::default(),
+        }
+    }
+
+    /// Every run so far, oldest first.
+    pub fn runs(&self) -> Vec<RecordedRun> {
+        self.runs.lock().unwrap().clone()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Executor for MockExecutor {
+    fn run(&self, program: &Path, args: &[String], stdin: &[u8]) -> io::Result<Output> {
+        self.runs.lock().unwrap().push(RecordedRun {
+            program: program.to_owned(),
+            args: args.to_vec(),
+            stdin: stdin.to_vec(),
+        });
+        Ok(self.output.clone())
+    }
+}
+
+#[cfg(all(any(test, feature = "testing"), unix))]
+fn exit_status(code: i32) -> std::process::ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw(code << 8)
+}
+
+#[cfg(all(any(test, feature = "testing"), windows))]
+fn exit_status(code: i32) -> std::process::ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_string_with_options, evaluate_with_options, CompileError, PugOptions};
+
+    #[test]
+    fn mocks_record_what_pug_would_have_run() {
+        let pug = MockExecutor::succeeding("<h1>mocked</h1>");
+        let options = PugOptions::new()
+            .binary("my-pug")
+            .pretty()
+            .executor(pug.clone());
+        let html = evaluate_with_options("test/hello.pug", options).unwrap();
+        assert_eq!("<h1>mocked</h1>", html);
+        assert_eq!(
+            vec![RecordedRun {
+                program: "my-pug".into(),
+                args: vec!["--path".into(), "test/hello.pug".into(), "--pretty".into()],
+                stdin: std::fs::read("test/hello.pug").unwrap(),
+            }],
+            pug.runs()
+        );
+
+        let stderr = "Error: Pug:1:3\n  > 1|   p\n---------^\n\nunexpected token \"indent\"\n";
+        let failing = PugOptions::new().executor(MockExecutor::failing(1, stderr));
+        let error = evaluate_string_with_options("  p".into(), failing).unwrap_err();
+        assert!(matches!(&error, CompileError::PugError(_)), "{:?}", error);
+        assert_eq!(Some(3), error.details().unwrap().column);
+    }
+
+    #[test]
+    fn the_process_executor_spawns_pug() {
+        let pug = PugOptions::new().executor(ProcessExecutor);
+        let html = evaluate_string_with_options("h1 spawned".into(), pug).unwrap();
+        assert_eq!("<h1>spawned</h1>", html);
+    }
+}
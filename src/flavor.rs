@@ -0,0 +1,92 @@
+//! The two pug command lines in the wild and where their flags differ.
+
+use crate::CompileError;
+use std::fmt;
+
+/// Which pug command line a binary is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CliFlavor {
+    /// The `pug-cli` 1.x package, which everything here was written against.
+    #[default]
+    PugCli,
+    /// The newer CLI built for pug 3. It spells `--no-debug` as
+    /// `--no-compile-debug`, has no `--pretty` (pug 3 deprecated pretty
+    /// printing) and can report errors as JSON.
+    Pug3,
+}
+
+impl CliFlavor {
+    /// Tells the flavors apart by the flags `--help` lists; only the pug 3 CLI
+    /// has `--no-compile-debug`.
+    pub(crate) fn detect(help: &str) -> CliFlavor {
+        if help.contains("--no-compile-debug") {
+            CliFlavor::Pug3
+        } else {
+            CliFlavor::PugCli
+        }
+    }
+
+    /// This flavor's spelling of a `pug-cli` flag, or `None` if it lacks it.
+    pub(crate) fn spell(self, flag: &'static str) -> Option<&'static str> {
+        match (self, flag) {
+            (CliFlavor::Pug3, "--no-debug") => Some("--no-compile-debug"),
+            (CliFlavor::Pug3, "--pretty") => None,
+            _ => Some(flag),
+        }
+    }
+
+    /// Fails if `flag` is one this flavor doesn't have.
+    pub(crate) fn require(self, flag: &'static str) -> Result<(), CompileError> {
+        match self.spell(flag) {
+            Some(_) => Ok(()),
+            None => Err(CompileError::InvalidOptions(format!(
+                "{} isn't supported by {}",
+                flag, self
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CliFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliFlavor::PugCli => write!(f, "pug-cli 1.x"),
+            CliFlavor::Pug3 => write!(f, "the pug 3 CLI"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUG_CLI_HELP: &str = "  Usage: pug [options] [dir|file ...]\n\n  Options:\n\n    -h, --help             output usage information\n    -D, --no-debug         compile without debugging (smaller functions)\n    -P, --pretty           compile pretty HTML output\n";
+    const PUG3_HELP: &str = "Usage: pug [options] [dir|file ...]\n\nOptions:\n  -P, --no-compile-debug  compile without debugging\n  --doctype <str>        specify the doctype\n";
+
+    #[test]
+    fn adapts_flags_to_the_detected_flavor() {
+        let pug_cli = CliFlavor::detect(PUG_CLI_HELP);
+        let pug3 = CliFlavor::detect(PUG3_HELP);
+        assert_eq!(CliFlavor::PugCli, pug_cli);
+        assert_eq!(CliFlavor::Pug3, pug3);
+        assert_eq!(CliFlavor::PugCli, CliFlavor::detect(""));
+
+        for (flavor, flag, spelled) in &[
+            (pug_cli, "--no-debug", Some("--no-debug")),
+            (pug_cli, "--pretty", Some("--pretty")),
+            (pug_cli, "--doctype", Some("--doctype")),
+            (pug3, "--no-debug", Some("--no-compile-debug")),
+            (pug3, "--pretty", None),
+            (pug3, "--doctype", Some("--doctype")),
+        ] {
+            assert_eq!(*spelled, flavor.spell(flag), "{} {}", flavor, flag);
+        }
+
+        let error = pug3.require("--pretty").unwrap_err().to_string();
+        assert_eq!(
+            "invalid options: --pretty isn't supported by the pug 3 CLI",
+            error
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! Fluent (`.ftl`) translations handed to templates as locals.
+//!
+//! Each message becomes a string, nested by the `-`s in its id, so
+//! `nav-home = Accueil` is `t.nav.home`. A message's attribute `.title` is
+//! `t.nav.home.title`; one with attributes as well as a value has the value
+//! beside them, as `t.nav.home.value`. As the strings are resolved before pug runs, only
+//! what doesn't depend on the render is filled in: text, string and number
+//! literals, and references to other messages and terms. Variables and
+//! function calls are left as written, and select expressions take their
+//! default `*[...]` variant.
+
+use crate::{locale, CompileError, PugJsonObject, PugOptions};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The key translations go under when `PugOptions::translations_key` isn't set.
+pub(crate) const DEFAULT_KEY: &str = "t";
+/// Where the value of a message with attributes too goes, beside them.
+const VALUE: &str = "value";
+
+struct Message {
+    value: Option<String>,
+    attributes: Vec<(String, String)>,
+    file: PathBuf,
+    line: usize,
+}
+
+/// Merges the catalog `PugOptions::translations_fluent` names into the
+/// locals of `options`, if it names one.
+pub(crate) fn inject(options: &mut PugOptions) -> Result<(), CompileError> {
+    let (dir, locale) = match &options.translations {
+        Some(translations) => translations,
+        None => return Ok(()),
+    };
+    let key = options.translations_key.as_deref().unwrap_or(DEFAULT_KEY);
+    let mut translations = Map::new();
+    translations.insert(key.to_owned(), catalog(dir, locale)?);
+    let mut locals = match &options.object {
+        Some(locals) => locale::to_json(locals)?,
+        None => Value::Object(Map::new()),
+    };
+    if !locals.is_object() {
+        return Err(CompileError::InvalidOptions(
+            "translations can only be added to locals that are an object".into(),
+        ));
+    }
+    locale::merge(&mut locals, Value::Object(translations));
+    options.object = Some(PugJsonObject::Json(locals));
+    Ok(())
+}
+
+/// The messages for `locale` in `dir`, as nested JSON.
+///
+/// A locale's messages are read from `<dir>/<locale>.ftl` and every `.ftl`
+/// file in `<dir>/<locale>/`. For `fr-CA` it falls back on `fr`: messages
+/// missing from `fr-CA` come from `fr`, and at least one of them has to exist.
+pub(crate) fn catalog(dir: &Path, locale: &str) -> Result<Value, CompileError> {
+    let mut messages = HashMap::new();
+    let mut found = false;
+    for locale in fallback_chain(locale).iter().rev() {
+        let mut files = Vec::new();
+        let file = dir.join(format!("{}.ftl", locale));
+        if file.is_file() {
+            files.push(file);
+        }
+        let locale_dir = dir.join(locale);
+        if locale_dir.is_dir() {
+            let mut ftl: Vec<PathBuf> = std::fs::read_dir(&locale_dir)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| entry.map(|entry| entry.path()))
+                        .collect::<Result<_, _>>()
+                })
+                .map_err(CompileError::io_at("read translations in", &locale_dir))?;
+            ftl.retain(|path| path.extension().is_some_and(|ext| ext == "ftl"));
+            ftl.sort();
+            files.extend(ftl);
+        }
+        found |= !files.is_empty();
+        for file in files {
+            let source = std::fs::read_to_string(&file)
+                .map_err(CompileError::io_at("read translations", &file))?;
+            messages.extend(parse(&source, &file)?);
+        }
+    }
+    if !found {
+        return Err(CompileError::Translations(format!(
+            "no translations for {} in {}",
+            locale,
+            dir.display()
+        )));
+    }
+
+    let mut ids: Vec<&String> = messages.keys().filter(|id| !id.starts_with('-')).collect();
+    ids.sort();
+    let mut tree = Map::new();
+    for id in ids {
+        let message = &messages[id];
+        let path: Vec<&str> = id.split('-').collect();
+        let mut resolver = Resolver {
+            messages: &messages,
+            stack: vec![id.clone()],
+        };
+        let located = |reason: String| {
+            CompileError::Translations(format!(
+                "{}:{}: {}",
+                message.file.display(),
+                message.line,
+                reason
+            ))
+        };
+        if let Some(value) = &message.value {
+            let text = resolver.pattern(value).map_err(located)?;
+            let mut path = path.clone();
+            if !message.attributes.is_empty() {
+                if message.attributes.iter().any(|(name, _)| name == VALUE) {
+                    let reason = format!("{}'s attribute .{} would hide its value", id, VALUE);
+                    return Err(located(reason));
+                }
+                path.push(VALUE);
+            }
+            insert(&mut tree, &path, text).map_err(located)?;
+        }
+        for (name, attribute) in &message.attributes {
+            let text = resolver.pattern(attribute).map_err(located)?;
+            let path: Vec<&str> = path.iter().copied().chain([name.as_str()]).collect();
+            insert(&mut tree, &path, text).map_err(located)?;
+        }
+    }
+    Ok(Value::Object(tree))
+}
+
+/// `fr-CA` and then `fr`, most specific first.
+fn fallback_chain(locale: &str) -> Vec<String> {
+    let parts: Vec<&str> = locale.split(['-', '_']).collect();
+    (1..=parts.len())
+        .rev()
+        .map(|len| parts[..len].join("-"))
+        .collect()
+}
+
+/// The messages and terms in the `.ftl` `source` read from `file`.
+fn parse(source: &str, file: &Path) -> Result<HashMap<String, Message>, CompileError> {
+    let invalid = |line: usize, reason: &str| -> CompileError {
+        CompileError::Translations(format!("{}:{}: {}", file.display(), line, reason))
+    };
+    // each entry's id, first line and the lines of its body
+    let mut entries: Vec<(String, usize, String, Vec<&str>)> = Vec::new();
+    let mut open = false;
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        if line.starts_with(' ') || (open && line.trim().is_empty()) {
+            match entries.last_mut() {
+                Some(entry) if open => entry.3.push(line),
+                _ if line.trim().is_empty() => {}
+                _ => return Err(invalid(number, "an indented line belongs to no message")),
+            }
+        } else if line.trim().is_empty() || line.starts_with('#') {
+            open = false;
+        } else {
+            let (id, first) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(number, "expected `id = value`"))?;
+            let id = id.trim_end();
+            if !identifier(id.strip_prefix('-').unwrap_or(id)) {
+                return Err(invalid(number, &format!("{:?} isn't a message id", id)));
+            }
+            entries.push((
+                id.to_owned(),
+                number,
+                first.trim_start().to_owned(),
+                Vec::new(),
+            ));
+            open = true;
+        }
+    }
+
+    let mut messages = HashMap::new();
+    for (id, line, first, body) in entries {
+        // attributes, like the value, run until the next attribute outside
+        // a placeable
+        let mut parts: Vec<(Option<String>, String, Vec<&str>)> = vec![(None, first, Vec::new())];
+        let mut depth = braces(&parts[0].1);
+        for text in body {
+            let attribute = text
+                .trim_start()
+                .strip_prefix('.')
+                .and_then(|rest| rest.split_once('='))
+                .filter(|(name, _)| depth == 0 && identifier(name.trim_end()));
+            match attribute {
+                Some((name, value)) => {
+                    let value = value.trim_start().to_owned();
+                    depth = braces(&value);
+                    parts.push((Some(name.trim_end().to_owned()), value, Vec::new()));
+                }
+                None => {
+                    depth += braces(text);
+                    parts.last_mut().unwrap().2.push(text);
+                }
+            }
+        }
+        let mut message = Message {
+            value: None,
+            attributes: Vec::new(),
+            file: file.to_owned(),
+            line,
+        };
+        for (name, first, lines) in parts {
+            let pattern = pattern(&first, &lines);
+            match name {
+                Some(name) => message.attributes.push((name, pattern)),
+                None if !pattern.is_empty() => message.value = Some(pattern),
+                None => {}
+            }
+        }
+        if message.value.is_none() && message.attributes.is_empty() {
+            return Err(invalid(line, &format!("{} has no value", id)));
+        }
+        messages.insert(id, message);
+    }
+    Ok(messages)
+}
+
+/// A pattern's text from its first line and the indented lines after it,
+/// which lose the indentation they have in common.
+fn pattern(first: &str, lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut text: Vec<&str> = Vec::new();
+    if !first.trim().is_empty() {
+        text.push(first.trim_end());
+    }
+    for line in lines {
+        text.push(line.get(indent..).unwrap_or("").trim_end());
+    }
+    while text.last().is_some_and(|line| line.is_empty()) {
+        text.pop();
+    }
+    text.join("\n")
+}
+
+/// How many more `{` than `}` `text` has.
+fn braces(text: &str) -> i32 {
+    text.chars()
+        .map(|c| match c {
+            '{' => 1,
+            '}' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn identifier(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn insert(tree: &mut Map<String, Value>, path: &[&str], text: String) -> Result<(), String> {
+    let clash = || format!("{} is both a message and a group of them", path.join("-"));
+    let (last, groups) = path.split_last().expect("ids aren't empty");
+    let mut tree = tree;
+    for group in groups {
+        tree = match tree
+            .entry(group.to_string())
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(group) => group,
+            _ => return Err(clash()),
+        };
+    }
+    if tree.contains_key(*last) {
+        return Err(clash());
+    }
+    tree.insert(last.to_string(), Value::String(text));
+    Ok(())
+}
+
+struct Resolver<'a> {
+    messages: &'a HashMap<String, Message>,
+    /// The messages being resolved, to catch ones that refer to themselves.
+    stack: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn pattern(&mut self, pattern: &str) -> Result<String, String> {
+        let mut text = String::new();
+        let mut rest = pattern;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                return Err("a `}` has no `{`".into());
+            }
+            text.push_str(&rest[..open]);
+            let mut depth = 0;
+            let close = rest[open..]
+                .char_indices()
+                .find(|&(_, c)| {
+                    depth += braces(c.encode_utf8(&mut [0; 4]));
+                    depth == 0
+                })
+                .map(|(close, _)| open + close)
+                .ok_or("a `{` is never closed")?;
+            text.push_str(&self.placeable(&rest[open + 1..close])?);
+            rest = &rest[close + 1..];
+        }
+        text.push_str(rest);
+        Ok(text)
+    }
+
+    fn placeable(&mut self, inner: &str) -> Result<String, String> {
+        let expression = inner.trim();
+        if let Some((_, variants)) = expression.split_once("->") {
+            return self.default_variant(variants);
+        }
+        if let Some(literal) = expression
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+        {
+            return Ok(literal.replace("\\\"", "\"").replace("\\\\", "\\"));
+        }
+        if expression.parse::<f64>().is_ok() {
+            return Ok(expression.to_owned());
+        }
+        if expression.starts_with('$') || expression.contains('(') {
+            // filled in at render time, which this isn't
+            return Ok(format!("{{{}}}", inner));
+        }
+        let (id, attribute) = match expression.split_once('.') {
+            Some((id, attribute)) => (id, Some(attribute)),
+            None => (expression, None),
+        };
+        if !identifier(id.strip_prefix('-').unwrap_or(id)) {
+            return Err(format!("can't read the placeable {{{}}}", inner));
+        }
+        self.reference(id, attribute)
+    }
+
+    fn reference(&mut self, id: &str, attribute: Option<&str>) -> Result<String, String> {
+        let message = self
+            .messages
+            .get(id)
+            .ok_or_else(|| format!("{} refers to {}, which doesn't exist", self.stack[0], id))?;
+        let pattern = match attribute {
+            Some(attribute) => message
+                .attributes
+                .iter()
+                .find(|(name, _)| name == attribute)
+                .map(|(_, pattern)| pattern),
+            None => message.value.as_ref(),
+        }
+        .ok_or_else(|| format!("{} has no {}", id, attribute.unwrap_or("value")))?;
+        if self.stack.iter().any(|seen| seen == id) {
+            return Err(format!("{} refers to itself", id));
+        }
+        self.stack.push(id.to_owned());
+        let text = self.pattern(pattern);
+        self.stack.pop();
+        text
+    }
+
+    /// The text of the `*[...]` variant in a select expression's `variants`.
+    fn default_variant(&mut self, variants: &str) -> Result<String, String> {
+        let mut default: Option<(String, Vec<&str>)> = None;
+        let mut in_default = false;
+        for line in variants.lines() {
+            let trimmed = line.trim_start();
+            let start = trimmed.strip_prefix('*').unwrap_or(trimmed);
+            if let Some((_, first)) = start.strip_prefix('[').and_then(|key| key.split_once(']')) {
+                in_default = trimmed.starts_with('*');
+                if in_default {
+                    default = Some((first.trim_start().to_owned(), Vec::new()));
+                }
+            } else if in_default {
+                default.as_mut().unwrap().1.push(line);
+            }
+        }
+        let (first, lines) = default.ok_or("a select expression has no `*[...]` variant")?;
+        self.pattern(&pattern(&first, &lines))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate_string_with_options;
+    use serde_json::json;
+
+    fn render(locale: &str) -> Result<String, CompileError> {
+        let template = "p #{t.nav.home} / #{t.nav.about.title} / #{t.cart}\n\
+                        a(title=t.nav.contact.title) #{t.nav.contact.value}"
+            .to_owned();
+        let options = PugOptions::new().translations_fluent(Path::new("test/i18n"), locale);
+        evaluate_string_with_options(template, options)
+    }
+
+    #[test]
+    fn renders_each_locale() {
+        assert_eq!(
+            "<p>Home / About Acme / { $count } items in the cart</p>\
+             <a title=\"Write to Acme\">Contact</a>",
+            render("en").unwrap()
+        );
+        assert_eq!(
+            "<p>Accueil / À propos de Acme / { $count } articles</p>\
+             <a title=\"Écrire à Acme\">Contact</a>",
+            render("fr").unwrap()
+        );
+        // fr-CA only says home differently; the rest falls back on fr
+        assert_eq!(
+            "<p>Bienvenue / À propos de Acme / { $count } articles</p>\
+             <a title=\"Écrire à Acme\">Contact</a>",
+            render("fr-CA").unwrap()
+        );
+    }
+
+    #[test]
+    fn merges_under_the_key() {
+        let options = PugOptions::new()
+            .with_object(json!({ "strings": { "site": "Acme" }, "user": "ada" }))
+            .translations_fluent(Path::new("test/i18n"), "fr")
+            .translations_key("strings");
+        let template = "p #{strings.site}: #{strings.nav.home}, #{user}".to_owned();
+        assert_eq!(
+            "<p>Acme: Accueil, ada</p>",
+            evaluate_string_with_options(template, options).unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_missing_locales_and_broken_files() {
+        let error = render("de").unwrap_err();
+        assert!(
+            matches!(error, CompileError::Translations(_)),
+            "{:?}",
+            error
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("en.ftl"), "ok = fine\nnot a message\n").unwrap();
+        let error = catalog(dir.path(), "en").unwrap_err().to_string();
+        assert!(
+            error.contains("en.ftl:2: expected `id = value`"),
+            "{}",
+            error
+        );
+
+        std::fs::write(dir.path().join("en.ftl"), "a = { b }\nb = { a }\n").unwrap();
+        let error = catalog(dir.path(), "en").unwrap_err().to_string();
+        assert!(error.contains("refers to itself"), "{}", error);
+
+        std::fs::write(dir.path().join("en.ftl"), "a = A\n    .value = B\n").unwrap();
+        let error = catalog(dir.path(), "en").unwrap_err().to_string();
+        assert!(
+            error.contains("en.ftl:1: a's attribute .value"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Rendering partials for HTMX-style swaps, where only the markup inside the
+//! document wrapper is wanted.
+
+use crate::{evaluate_with_options, CompileError, PugOptions};
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+/// Compiles `template` and returns it as a fragment: a leading doctype is
+/// dropped, and a sole `<html>` (with its `<head>`) or `<body>` wrapper is
+/// replaced by what it contains.
+///
+/// Output that was already a fragment comes back exactly as compiled. Output
+/// that was unwrapped has the whitespace at both ends of its content trimmed,
+/// so pretty and compact output unwrap to the same boundaries.
+pub fn render_fragment(
+    template: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    evaluate_with_options(template, options).map(|html| unwrap_fragment(&html).into_owned())
+}
+
+/// Like `render_fragment`, failing with `CompileError::PostProcessing` if
+/// the output needed unwrapping, for partials that must never grow a layout.
+pub fn render_fragment_strict(
+    template: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let html = evaluate_with_options(template, options)?;
+    match unwrap_fragment(&html) {
+        Cow::Borrowed(_) => Ok(html),
+        Cow::Owned(_) => Err(CompileError::PostProcessing(
+            "expected a bare fragment, got a doctype or document wrapper".into(),
+        )),
+    }
+}
+
+/// The unwrapping `render_fragment` does, for HTML compiled some other way.
+/// Borrowed when there was nothing to unwrap.
+pub fn unwrap_fragment(html: &str) -> Cow<'_, str> {
+    let mut inner = html.trim();
+    let mut unwrapped = false;
+    if let Some(rest) = strip_tag(inner, "!doctype") {
+        inner = rest.trim_start();
+        unwrapped = true;
+    }
+    if let Some(rest) = strip_element(inner, "html") {
+        inner = rest;
+        if let Some(rest) = strip_tag(inner, "head") {
+            inner = match find_ignore_case(rest, "</head>") {
+                Some(end) => rest[end + "</head>".len()..].trim_start(),
+                None => rest,
+            };
+        }
+        unwrapped = true;
+    }
+    if let Some(rest) = strip_element(inner, "body") {
+        inner = rest;
+        unwrapped = true;
+    }
+    if unwrapped {
+        Cow::Owned(inner.to_owned())
+    } else {
+        Cow::Borrowed(html)
+    }
+}
+
+/// What follows an opening `<tag ...>` at the start of `html`.
+fn strip_tag<'a>(html: &'a str, tag: &str) -> Option<&'a str> {
+    let after = html.get(1 + tag.len()..)?;
+    let named = html.starts_with('<')
+        && html
+            .get(1..1 + tag.len())
+            .is_some_and(|name| name.eq_ignore_ascii_case(tag));
+    if !named || !after.starts_with(|c: char| c == '>' || c.is_ascii_whitespace()) {
+        return None;
+    }
+    after.find('>').map(|end| &after[end + 1..])
+}
+
+/// The trimmed content of `html` if it is exactly one `tag` element.
+fn strip_element<'a>(html: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = strip_tag(html, tag)?;
+    let close = format!("</{}>", tag);
+    let end = rest.len().checked_sub(close.len())?;
+    if rest.get(end..)?.eq_ignore_ascii_case(&close) {
+        Some(rest[..end].trim())
+    } else {
+        None
+    }
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.to_ascii_lowercase().find(needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fragments_pass_through_untouched() {
+        let html = evaluate_with_options("test/fragment/card.pug", PugOptions::new()).unwrap();
+        let card = render_fragment("test/fragment/card.pug", PugOptions::new()).unwrap();
+        assert_eq!(html, card);
+        assert_eq!(
+            card,
+            render_fragment_strict("test/fragment/card.pug", PugOptions::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn documents_unwrap_to_their_body() {
+        let card = render_fragment("test/fragment/card.pug", PugOptions::new()).unwrap();
+        let page = render_fragment("test/fragment/page.pug", PugOptions::new()).unwrap();
+        assert_eq!(card, page);
+
+        let pretty = render_fragment("test/fragment/page.pug", PugOptions::new().pretty()).unwrap();
+        assert!(pretty.starts_with("<div class=\"card\">"), "{}", pretty);
+        assert!(pretty.ends_with("</div>"), "{}", pretty);
+        assert_eq!(card, unwrap_fragment(&format!("<body>\n{}\n</body>", card)));
+
+        let strict = render_fragment_strict("test/fragment/page.pug", PugOptions::new());
+        assert!(matches!(strict, Err(CompileError::PostProcessing(_))));
+    }
+}
@@ -0,0 +1,207 @@
+//! Per-page metadata kept at the top of a template in unbuffered comments:
+//!
+//! ```text
+//! //- ---
+//! //- title: Home
+//! //- tags:
+//! //-   - news
+//! //- ---
+//! h1= title
+//! ```
+//!
+//! The block holds either a JSON object or simple YAML: `key: value` lines,
+//! values that are scalars or inline JSON, and `- item` lists under a key
+//! with no value. Nested YAML mappings aren't understood; write those as JSON.
+
+use crate::CompileError;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+const FENCE: &str = "---";
+
+/// Splits the front matter off `source`, returning it as an object (empty if
+/// there is none) and the source without it.
+///
+/// The block's lines are blanked rather than removed, so line numbers in pug
+/// errors still match the file.
+pub fn parse_front_matter(source: &str) -> Result<(Value, String), CompileError> {
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let open = match lines.iter().position(|line| !line.trim().is_empty()) {
+        Some(open) if comment(lines[open]).map(str::trim) == Some(FENCE) => open,
+        _ => return Ok((Value::Object(Map::new()), source.to_owned())),
+    };
+    let close = lines[open + 1..]
+        .iter()
+        .position(|line| comment(line).map(str::trim) == Some(FENCE))
+        .map(|close| open + 1 + close)
+        .ok_or_else(|| invalid(open, "the block is never closed with `//- ---`"))?;
+
+    let mut body = Vec::new();
+    for (index, line) in lines.iter().enumerate().take(close).skip(open + 1) {
+        match comment(line) {
+            Some(content) => body.push((index, content.strip_prefix(' ').unwrap_or(content))),
+            None if line.trim().is_empty() => {}
+            None => return Err(invalid(index, "every line needs to be a `//-` comment")),
+        }
+    }
+    let front_matter = parse_block(&body)?;
+
+    let mut stripped = String::with_capacity(source.len());
+    for (index, line) in lines.iter().enumerate() {
+        if (open..=close).contains(&index) {
+            stripped.extend(line.strip_suffix('\n').map(|_| '\n'));
+        } else {
+            stripped.push_str(line);
+        }
+    }
+    Ok((front_matter, stripped))
+}
+
+/// `parse_front_matter` for the template at `path`.
+pub fn read_front_matter(path: impl AsRef<Path>) -> Result<(Value, String), CompileError> {
+    let path = path.as_ref();
+    let source =
+        std::fs::read_to_string(path).map_err(CompileError::io_at("read template", path))?;
+    parse_front_matter(&source)
+}
+
+/// A block of the same JSON or simple YAML outside a template, as
+/// `PugOptions::sidecar_locals` reads.
+pub(crate) fn parse_yaml(text: &str) -> Result<Value, CompileError> {
+    let body: Vec<(usize, &str)> = text.lines().enumerate().collect();
+    parse_block(&body)
+}
+
+/// What follows `//-` on an unbuffered comment line, without the line ending.
+fn comment(line: &str) -> Option<&str> {
+    line.trim_start()
+        .strip_prefix("//-")
+        .map(|content| content.trim_end_matches(['\r', '\n']))
+}
+
+fn invalid(index: usize, reason: &str) -> CompileError {
+    CompileError::FrontMatter(format!("line {}: {}", index + 1, reason))
+}
+
+fn parse_block(body: &[(usize, &str)]) -> Result<Value, CompileError> {
+    let text: String = body.iter().map(|(_, line)| format!("{}\n", line)).collect();
+    if text.trim_start().starts_with('{') {
+        return match serde_json::from_str(&text) {
+            Ok(object @ Value::Object(_)) => Ok(object),
+            Ok(_) => Err(invalid(body[0].0, "JSON front matter must be an object")),
+            Err(e) => Err(CompileError::FrontMatter(e.to_string())),
+        };
+    }
+
+    let mut object = Map::new();
+    let mut list: Option<(String, Vec<Value>)> = None;
+    for &(index, line) in body {
+        let content = line.trim();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let item = if content == "-" {
+            Some("")
+        } else {
+            content.strip_prefix("- ")
+        };
+        if let Some(item) = item {
+            match &mut list {
+                Some((_, items)) => items.push(scalar(item.trim())),
+                None => return Err(invalid(index, "a list item needs a `key:` line above it")),
+            }
+            continue;
+        }
+        if line.starts_with(' ') {
+            return Err(invalid(index, "nested mappings aren't supported; use JSON"));
+        }
+        let (key, value) = content
+            .split_once(':')
+            .ok_or_else(|| invalid(index, "expected `key: value`"))?;
+        if let Some((key, items)) = list.take() {
+            object.insert(key, Value::Array(items));
+        }
+        let (key, value) = (key.trim().to_owned(), value.trim());
+        if value.is_empty() {
+            list = Some((key, Vec::new()));
+        } else {
+            object.insert(key, scalar(value));
+        }
+    }
+    if let Some((key, items)) = list {
+        let value = if items.is_empty() {
+            Value::Null
+        } else {
+            Value::Array(items)
+        };
+        object.insert(key, value);
+    }
+    Ok(Value::Object(object))
+}
+
+fn scalar(value: &str) -> Value {
+    match value {
+        "" | "~" | "null" => return Value::Null,
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Some(quoted) = value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        return Value::String(quoted.replace("''", "'"));
+    }
+    let inline_json = value.starts_with(['"', '[', '{']) || value.parse::<f64>().is_ok();
+    match serde_json::from_str(value) {
+        Ok(parsed) if inline_json => parsed,
+        _ => Value::String(value.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_with_options, PugOptions};
+    use serde_json::json;
+
+    #[test]
+    fn parses_yaml_json_and_nothing() {
+        let (yaml, source) = read_front_matter("test/front_matter/yaml.pug").unwrap();
+        assert_eq!(
+            json!({
+                "title": "Home",
+                "slug": "home",
+                "date": "2024-03-01",
+                "order": 2,
+                "draft": false,
+                "tags": ["news", "rust"]
+            }),
+            yaml
+        );
+        assert_eq!("\n".repeat(10) + "h1 #{title}\np #{slug} #{date}\n", source);
+
+        let (json, _) = read_front_matter("test/front_matter/json.pug").unwrap();
+        assert_eq!("wide", json["layout"]["name"]);
+
+        let none = std::fs::read_to_string("test/front_matter/none.pug").unwrap();
+        assert_eq!(
+            (json!({}), none.clone()),
+            parse_front_matter(&none).unwrap()
+        );
+
+        let unclosed = parse_front_matter("//- ---\n//- title: x\nh1\n");
+        assert!(matches!(unclosed, Err(CompileError::FrontMatter(_))));
+    }
+
+    #[test]
+    fn front_matter_becomes_locals() {
+        let options = PugOptions::new()
+            .with_object(json!({ "title": "Default", "slug": "x" }))
+            .front_matter();
+        let yaml = evaluate_with_options("test/front_matter/yaml.pug", options.clone()).unwrap();
+        assert_eq!("<h1>Home</h1><p>home 2024-03-01</p>", yaml);
+        let none = evaluate_with_options("test/front_matter/none.pug", options).unwrap();
+        assert_eq!("<h1>Default</h1>", none);
+    }
+}
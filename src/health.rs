@@ -0,0 +1,183 @@
+//! Which pug is in use, for health endpoints.
+//!
+//! The `--version` probe runs once per binary and is remembered, so asking on
+//! every request costs a lock and a clone.
+
+use crate::invocation::program;
+use crate::{CliFlavor, CompileError, PugOptions};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+/// The pug binary being run and the versions it reported. Serializable with
+/// the `serde` feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Health {
+    pub binary: PathBuf,
+    pub pug_version: String,
+    /// Told apart by the flags `pug --help` lists.
+    pub flavor: CliFlavor,
+    /// `None` if `node --version` couldn't be run.
+    pub node_version: Option<String>,
+    /// When the versions were probed, which is the first call for this binary.
+    pub probed_at: SystemTime,
+}
+
+/// `health_with` for the default `pug` on `PATH`.
+pub fn health() -> Result<Health, CompileError> {
+    health_with(&PugOptions::new())
+}
+
+/// The health of the binary `options` would run.
+///
+/// Only a successful probe is remembered; after a failure the next call tries
+/// again, so installing pug while the process runs is picked up.
+pub fn health_with(options: &PugOptions) -> Result<Health, CompileError> {
+    let binary = program(options);
+    let probes = probes();
+    if let Some(health) = probes.lock().unwrap().get(&binary) {
+        return Ok(health.clone());
+    }
+
+    let health = probe(&binary)?;
+    let mut probes = probes.lock().unwrap();
+    Ok(probes.entry(binary).or_insert(health).clone())
+}
+
+/// The flavor `health` found for `binary`, without probing it.
+pub(crate) fn known_flavor(binary: &Path) -> Option<CliFlavor> {
+    let probes = probes().lock().unwrap();
+    probes.get(binary).map(|health| health.flavor)
+}
+
+fn probes() -> &'static Mutex<HashMap<PathBuf, Health>> {
+    static PROBES: OnceLock<Mutex<HashMap<PathBuf, Health>>> = OnceLock::new();
+    PROBES.get_or_init(Default::default)
+}
+
+fn probe(binary: &Path) -> Result<Health, CompileError> {
+    let output = run(binary, "--version").map_err(CompileError::io_at("run pug", binary))?;
+    let pug_version = parse_pug_version(&output).ok_or_else(|| {
+        CompileError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "unrecognised `{} --version` output: {:?}",
+                binary.display(),
+                output.trim()
+            ),
+        ))
+    })?;
+    let flavor = CliFlavor::detect(&run(binary, "--help").unwrap_or_default());
+    let node_version = run(Path::new("node"), "--version")
+        .ok()
+        .map(|output| output.trim().to_owned());
+
+    Ok(Health {
+        binary: binary.to_owned(),
+        pug_version,
+        flavor,
+        node_version,
+        probed_at: SystemTime::now(),
+    })
+}
+
+fn run(program: &Path, flag: &str) -> io::Result<String> {
+    let output = Command::new(program)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).into())
+}
+
+/// The version from pug-cli's `pug version: 3.0.2` line.
+pub(crate) fn parse_pug_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("pug version:"))
+        .map(|version| version.trim().to_owned())
+        .filter(|version| !version.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::fake_binary;
+
+    #[test]
+    fn parses_pug_cli_version_output() {
+        assert_eq!(
+            Some("3.0.2".into()),
+            parse_pug_version("pug version: 3.0.2\npug-cli version: 1.0.0-alpha6\n")
+        );
+        assert_eq!(None, parse_pug_version("v18.0.0"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn probes_once_per_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let count = dir.path().join("count");
+        let script = format!(
+            "echo \"$1\" >> '{}'\necho 'pug version: 2.0.4'\n",
+            count.display()
+        );
+        let binary = fake_binary(dir.path(), "pug", &script);
+        let options = PugOptions::new().binary(&binary);
+
+        let first = health_with(&options).unwrap();
+        let second = health_with(&options).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(
+            "--version\n--help\n",
+            std::fs::read_to_string(&count).unwrap()
+        );
+        assert_eq!(binary, first.binary);
+        assert_eq!("2.0.4", first.pug_version);
+        assert_eq!(CliFlavor::PugCli, first.flavor);
+
+        let broken = fake_binary(dir.path(), "broken", "echo nope\n");
+        assert!(health_with(&PugOptions::new().binary(broken)).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn compiles_adapt_to_the_probed_flavor() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"case "$1" in
+  --version) echo 'pug version: 3.0.2' ;;
+  --help) echo '  --no-compile-debug  compile without debugging' ;;
+  *) cat > /dev/null; printf '%s ' "$@" ;;
+esac
+"#;
+        let binary = fake_binary(dir.path(), "pug3", script);
+        let options = || PugOptions::new().binary(&binary);
+        assert_eq!(CliFlavor::Pug3, health_with(&options()).unwrap().flavor);
+
+        let args = crate::evaluate_string_with_options("p".into(), options().no_debug()).unwrap();
+        assert!(args.contains("--no-compile-debug"), "{}", args);
+        let error = crate::evaluate_string_with_options("p".into(), options().pretty())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("the pug 3 CLI"), "{}", error);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_json() {
+        let health = health().unwrap();
+        let json = serde_json::to_value(&health).unwrap();
+        assert_eq!(
+            Some(health.pug_version.as_str()),
+            json["pug_version"].as_str()
+        );
+        let back: Health = serde_json::from_value(json).unwrap();
+        assert_eq!(health, back);
+        assert!(back.node_version.unwrap().starts_with('v'));
+    }
+}
@@ -0,0 +1,179 @@
+//! Structural checks on compiled HTML, which pug doesn't make: raw
+//! interpolation and conditionals can still produce a broken document.
+
+use crate::{evaluate_with_options, CompileError, PugOptions};
+use scraper::node::Node;
+use scraper::{ElementRef, Html};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// A problem found in compiled HTML.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// The parser had to recover from malformed markup, such as an element
+    /// that was never closed; the message is the parser's.
+    Recovered(String),
+    /// More than one element has this `id`.
+    DuplicateId(String),
+    /// An `<li>` whose parent isn't `ul`, `ol` or `menu`.
+    ListItemOutsideList,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::Recovered(message) => write!(f, "malformed markup: {}", message),
+            ValidationIssue::DuplicateId(id) => write!(f, "duplicate id {:?}", id),
+            ValidationIssue::ListItemOutsideList => write!(f, "<li> outside a list"),
+        }
+    }
+}
+
+/// Compiled HTML and the issues found in it.
+#[derive(Clone, Debug)]
+pub struct Validated {
+    pub html: String,
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// Compiles `template` and validates the result, returning the issues
+/// alongside the HTML instead of only logging them. `strict_validation` on
+/// `options` still fails the compile.
+pub fn evaluate_validated(
+    template: impl Into<PathBuf>,
+    mut options: PugOptions,
+) -> Result<Validated, CompileError> {
+    let strict = options.strict_validation;
+    options.validate_output = false;
+    let html = evaluate_with_options(template, options)?;
+    let issues = validate_html(&html);
+    if strict && !issues.is_empty() {
+        return Err(failure(&issues));
+    }
+    Ok(Validated { html, issues })
+}
+
+/// Parses `html` with a standards-compliant parser and lists its issues.
+///
+/// Output starting with a doctype or `<html>` is parsed as a document,
+/// anything else as a fragment.
+pub fn validate_html(html: &str) -> Vec<ValidationIssue> {
+    let start = html
+        .trim_start()
+        .get(..5)
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let parsed = if start == "<!doc" || start == "<html" {
+        Html::parse_document(html)
+    } else {
+        Html::parse_fragment(html)
+    };
+
+    let mut issues: Vec<ValidationIssue> = parsed
+        .errors
+        .iter()
+        .map(|error| ValidationIssue::Recovered(error.to_string()))
+        .collect();
+    let mut ids = BTreeMap::new();
+    walk(parsed.root_element(), &mut ids, &mut issues);
+    issues.extend(
+        ids.into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(id, _)| ValidationIssue::DuplicateId(id)),
+    );
+    issues
+}
+
+fn walk(
+    element: ElementRef<'_>,
+    ids: &mut BTreeMap<String, usize>,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    if let Some(id) = element.value().id() {
+        *ids.entry(id.to_owned()).or_default() += 1;
+    }
+    for child in element.children() {
+        if let Node::Element(child_element) = child.value() {
+            if child_element.name() == "li"
+                && !matches!(element.value().name(), "ul" | "ol" | "menu")
+            {
+                issues.push(ValidationIssue::ListItemOutsideList);
+            }
+            walk(ElementRef::wrap(child).unwrap(), ids, issues);
+        }
+    }
+}
+
+/// Runs on every compile with `validate_output` set.
+pub(crate) fn check(html: &str, strict: bool) -> Result<(), CompileError> {
+    let issues = validate_html(html);
+    if issues.is_empty() {
+        return Ok(());
+    }
+    if strict {
+        return Err(failure(&issues));
+    }
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    for issue in &issues {
+        #[cfg(feature = "log")]
+        log::warn!("compiled HTML: {}", issue);
+        #[cfg(feature = "tracing")]
+        tracing::warn!(issue = %issue, "compiled HTML failed validation");
+    }
+    Ok(())
+}
+
+fn failure(issues: &[ValidationIssue]) -> CompileError {
+    let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+    CompileError::PostProcessing(format!("invalid HTML: {}", issues.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_string_with_options, evaluate_with_options};
+
+    #[test]
+    fn clean_page_has_no_issues() {
+        let validated = evaluate_validated("test/valid.pug", PugOptions::new()).unwrap();
+        assert!(validated.issues.is_empty(), "{:?}", validated.issues);
+        assert!(
+            evaluate_with_options("test/valid.pug", PugOptions::new().strict_validation()).is_ok()
+        );
+    }
+
+    #[test]
+    fn duplicate_ids_are_reported_and_fail_strict_mode() {
+        let validated = evaluate_validated("test/duplicate-id.pug", PugOptions::new()).unwrap();
+        assert_eq!(
+            vec![
+                ValidationIssue::ListItemOutsideList,
+                ValidationIssue::DuplicateId("note".into()),
+            ],
+            validated.issues
+        );
+
+        let page =
+            evaluate_with_options("test/duplicate-id.pug", PugOptions::new().validate_output());
+        assert!(page.is_ok());
+        let strict = evaluate_with_options(
+            "test/duplicate-id.pug",
+            PugOptions::new().strict_validation(),
+        );
+        assert!(
+            matches!(&strict, Err(CompileError::PostProcessing(message)) if message.contains("duplicate id \"note\"")),
+            "{:?}",
+            strict
+        );
+    }
+
+    #[test]
+    fn recovered_markup_is_reported() {
+        let options = PugOptions::new()
+            .with_object(serde_json::json!({ "raw": "<span>unclosed" }))
+            .strict_validation();
+        let result = evaluate_string_with_options("div\n  | !{raw}".into(), options);
+        assert!(matches!(result, Err(CompileError::PostProcessing(_))));
+    }
+}
@@ -0,0 +1,210 @@
+//! Plain `http::Response`s for hyper, tower services, lambda runtimes or
+//! anything else built on the `http` crate.
+
+use crate::{etag, evaluate_string_with_options, evaluate_with_options, CompileError, PugOptions};
+use ::http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use ::http::{Response, StatusCode};
+use std::path::PathBuf;
+
+/// Renders `template` into a 200 `text/html` response.
+pub fn respond(
+    template: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<Response<Vec<u8>>, CompileError> {
+    HtmlResponse::new().respond(template, options)
+}
+
+/// Builds responses around rendered HTML, with control over the status,
+/// extra headers and whether an `ETag` is sent.
+#[derive(Clone, Debug)]
+pub struct HtmlResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    etag: bool,
+}
+
+impl HtmlResponse {
+    pub fn new() -> Self {
+        HtmlResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            etag: false,
+        }
+    }
+
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Adds a header to every response; these are set before, and so may be
+    /// replaced by, `Content-Type`, `Content-Length` and `ETag`.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Sends the body's `ETag`, as `pug_cli::etag` computes it.
+    pub fn etag(mut self) -> Self {
+        self.etag = true;
+        self
+    }
+
+    pub fn respond(
+        &self,
+        template: impl Into<PathBuf>,
+        options: PugOptions,
+    ) -> Result<Response<Vec<u8>>, CompileError> {
+        evaluate_with_options(template, options).map(|html| self.html(html))
+    }
+
+    /// Like `respond`, for pug source rather than a template file.
+    pub fn respond_string(
+        &self,
+        source: String,
+        options: PugOptions,
+    ) -> Result<Response<Vec<u8>>, CompileError> {
+        evaluate_string_with_options(source, options).map(|html| self.html(html))
+    }
+
+    /// Like `respond`, but a 304 Not Modified with no body if
+    /// `if_none_match`, the request's `If-None-Match` header, already lists
+    /// the page's `ETag`. The `ETag` is sent either way.
+    pub fn respond_if_none_match(
+        &self,
+        template: impl Into<PathBuf>,
+        options: PugOptions,
+        if_none_match: Option<&HeaderValue>,
+    ) -> Result<Response<Vec<u8>>, CompileError> {
+        evaluate_with_options(template, options)
+            .map(|html| self.html_if_none_match(html, if_none_match))
+    }
+
+    /// `html`, or a 304 if `if_none_match` lists its `ETag`; see
+    /// `respond_if_none_match`.
+    pub fn html_if_none_match(
+        &self,
+        html: String,
+        if_none_match: Option<&HeaderValue>,
+    ) -> Response<Vec<u8>> {
+        let tag = etag(&html);
+        let matches = if_none_match
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value.split(',').map(str::trim).any(|candidate| {
+                    // If-None-Match compares weakly
+                    candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == tag
+                })
+            });
+        if !matches {
+            return self.response(html, Some(tag));
+        }
+        let mut response = Response::new(Vec::new());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        let headers = response.headers_mut();
+        headers.extend(self.headers.clone());
+        headers.insert(ETAG, HeaderValue::from_str(&tag).unwrap());
+        response
+    }
+
+    /// Wraps HTML that has already been rendered.
+    pub fn html(&self, html: String) -> Response<Vec<u8>> {
+        let etag = if self.etag { Some(etag(&html)) } else { None };
+        self.response(html, etag)
+    }
+
+    fn response(&self, html: String, etag: Option<String>) -> Response<Vec<u8>> {
+        let body = html.into_bytes();
+        let mut response = Response::new(Vec::new());
+        *response.status_mut() = self.status;
+
+        let headers = response.headers_mut();
+        headers.extend(self.headers.clone());
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+        if let Some(tag) = etag {
+            headers.insert(ETAG, HeaderValue::from_str(&tag).unwrap());
+        }
+
+        *response.body_mut() = body;
+        response
+    }
+}
+
+impl Default for HtmlResponse {
+    fn default() -> Self {
+        HtmlResponse::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluate;
+
+    #[test]
+    fn sets_headers_around_the_rendered_body() {
+        let response = respond("test/hello.pug", PugOptions::new()).unwrap();
+        let expected = evaluate("test/hello.pug").unwrap();
+
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("text/html; charset=utf-8", response.headers()[CONTENT_TYPE]);
+        assert_eq!(
+            expected.len().to_string(),
+            response.headers()[CONTENT_LENGTH]
+        );
+        assert!(!response.headers().contains_key(ETAG));
+        assert_eq!(expected.as_bytes(), &response.body()[..]);
+    }
+
+    #[test]
+    fn builder_overrides_status_and_adds_headers() {
+        let builder = HtmlResponse::new()
+            .status(StatusCode::NOT_FOUND)
+            .header(
+                HeaderName::from_static("cache-control"),
+                HeaderValue::from_static("no-store"),
+            )
+            .etag();
+        let first = builder
+            .respond_string("h1 hello pug".into(), PugOptions::new())
+            .unwrap();
+        let second = builder
+            .respond_string("h1 hello pug".into(), PugOptions::new())
+            .unwrap();
+        let other = builder
+            .respond_string("h1 goodbye".into(), PugOptions::new())
+            .unwrap();
+
+        assert_eq!(StatusCode::NOT_FOUND, first.status());
+        assert_eq!("no-store", first.headers()["cache-control"]);
+        assert_eq!(first.headers()[ETAG], second.headers()[ETAG]);
+        assert_ne!(first.headers()[ETAG], other.headers()[ETAG]);
+    }
+
+    #[test]
+    fn answers_a_matching_if_none_match_with_304() {
+        let builder = HtmlResponse::new();
+        let tag = etag("<h1>hello pug</h1>");
+        let conditional = |if_none_match: &str| {
+            let value = HeaderValue::from_str(if_none_match).unwrap();
+            builder
+                .respond_if_none_match("test/hello.pug", PugOptions::new(), Some(&value))
+                .unwrap()
+        };
+
+        for matching in [tag.clone(), format!("\"other\", W/{}", tag), "*".into()] {
+            let response = conditional(&matching);
+            assert_eq!(StatusCode::NOT_MODIFIED, response.status(), "{}", matching);
+            assert_eq!(tag.as_str(), response.headers()[ETAG]);
+            assert!(response.body().is_empty());
+        }
+        let changed = conditional("\"stale\"");
+        assert_eq!(StatusCode::OK, changed.status());
+        assert_eq!(tag.as_str(), changed.headers()[ETAG]);
+        assert_eq!(b"<h1>hello pug</h1>", &changed.body()[..]);
+    }
+}
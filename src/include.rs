@@ -0,0 +1,543 @@
+//! Splicing `include`d and `extend`ed templates into a string template, for
+//! templates with no files around them to include from.
+
+use crate::CompileError;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Replaces the `include` and `extends` lines in `source` with the templates
+/// `resolver` returns for them, recursively, so pug can compile it from a
+/// string.
+///
+/// `resolver` is asked for each path relative to the root: `include nav` in
+/// `partials/page.pug` asks for `partials/nav.pug`, and `include /nav` for
+/// `nav.pug`. Paths without an extension get `.pug`, as pug gives them.
+/// Other files are included as plain text, or through the filter in
+/// `include:filter file`.
+///
+/// Included lines take the indentation of their `include`, converted to the
+/// including template's if one indents with tabs and the other with spaces.
+/// `extends` is resolved here, with `block`, `append` and `prepend`; the
+/// `block` lines stay, so the result renders the same. Line numbers in pug's
+/// errors count lines of the result.
+///
+/// Fails with `CompileError::Include` for a path `resolver` has nothing for,
+/// a cycle, which the error spells out, or an `include` with a block under
+/// it, which isn't supported.
+pub fn inline_includes(
+    source: &str,
+    resolver: impl Fn(&str) -> Option<String>,
+) -> Result<String, CompileError> {
+    inline(source, None, &resolver)
+}
+
+/// `inline_includes` for the template at `name`, a path relative to the
+/// root like `pages/home.pug`, so its relative includes resolve from there.
+#[cfg(feature = "macros")]
+pub(crate) fn inline_includes_of(
+    name: &str,
+    source: &str,
+    resolver: impl Fn(&str) -> Option<String>,
+) -> Result<String, CompileError> {
+    inline(source, Some(name), &resolver)
+}
+
+fn inline(
+    source: &str,
+    name: Option<&str>,
+    resolver: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, CompileError> {
+    let mut inliner = Inliner {
+        resolver,
+        chain: Vec::new(),
+    };
+    let mut inlined = match name {
+        Some(name) => inliner.nested(name.to_owned(), source)?,
+        None => inliner.template(source, None)?,
+    }
+    .join("\n");
+    if source.ends_with('\n') {
+        inlined.push('\n');
+    }
+    Ok(inlined)
+}
+
+/// A resolver for `inline_includes` reading templates from under `dir`.
+/// It finds nothing for a path that is absolute or has a `..` or a drive in
+/// it, so it can't climb out of `dir`.
+pub fn dir_resolver(dir: impl Into<PathBuf>) -> impl Fn(&str) -> Option<String> {
+    let dir = dir.into();
+    move |path| {
+        let mut parts = Path::new(path).components();
+        if !parts.all(|part| matches!(part, Component::Normal(_) | Component::CurDir)) {
+            return None;
+        }
+        std::fs::read_to_string(dir.join(path)).ok()
+    }
+}
+
+/// A resolver for `inline_includes` looking templates up by their path, like
+/// `partials/nav.pug`.
+pub fn map_resolver(templates: HashMap<String, String>) -> impl Fn(&str) -> Option<String> {
+    move |path| templates.get(path).cloned()
+}
+
+struct Inliner<'a> {
+    resolver: &'a dyn Fn(&str) -> Option<String>,
+    /// The files being inlined, outermost first.
+    chain: Vec<String>,
+}
+
+impl Inliner<'_> {
+    /// `source`, the file `name` if it is one, with everything inlined.
+    fn template(&mut self, source: &str, name: Option<&str>) -> Result<Vec<String>, CompileError> {
+        let lines: Vec<&str> = source.lines().collect();
+        let extends = lines
+            .iter()
+            .map(|line| split_indent(line).1)
+            .position(|content| !content.is_empty() && !content.starts_with("//"))
+            .and_then(|index| Some((index, keyword(lines[index].trim(), "extends")?)));
+        match extends {
+            Some((index, path)) => {
+                let (layout, source) = self.fetch(name, path)?;
+                let layout = self.nested(layout, &source)?;
+                let blocks = self.lines(&lines[index + 1..], name)?;
+                extend(layout, blocks)
+            }
+            None => self.lines(&lines, name),
+        }
+    }
+
+    /// `lines` of the file `name` with their includes inlined.
+    fn lines(&mut self, lines: &[&str], name: Option<&str>) -> Result<Vec<String>, CompileError> {
+        let unit = unit(lines);
+        let structure = structural(lines);
+        let mut inlined = Vec::new();
+        for (index, line) in lines.iter().enumerate() {
+            let (indent, content) = split_indent(line);
+            let (filter, path) = match include(content).filter(|_| structure[index]) {
+                Some(include) => include,
+                None => {
+                    inlined.push(line.to_string());
+                    continue;
+                }
+            };
+            let next = lines[index + 1..]
+                .iter()
+                .find(|line| !line.trim().is_empty());
+            if next.is_some_and(|next| split_indent(next).0.len() > indent.len()) {
+                return Err(CompileError::Include(format!(
+                    "`{}` has a block under it, which isn't supported",
+                    content
+                )));
+            }
+
+            let (file, source) = self.fetch(name, path)?;
+            let included: Vec<String> = match filter {
+                Some(filter) => {
+                    let body = unit.as_deref().unwrap_or("  ");
+                    let body = source.lines().map(|line| format!("{}{}", body, line));
+                    std::iter::once(format!(":{}", filter))
+                        .chain(body)
+                        .collect()
+                }
+                None if file.ends_with(".pug") => {
+                    let included = self.nested(file, &source)?;
+                    let from = self::unit(&included);
+                    included
+                        .iter()
+                        .map(|line| restyle(line, from.as_deref(), unit.as_deref()))
+                        .collect()
+                }
+                None => source.lines().map(piped).collect(),
+            };
+            inlined.extend(included.into_iter().map(|line| {
+                if line.is_empty() {
+                    line
+                } else {
+                    format!("{}{}", indent, line)
+                }
+            }));
+        }
+        Ok(inlined)
+    }
+
+    /// Resolves `path` as written in the file `from` and returns it with its
+    /// source.
+    fn fetch(&self, from: Option<&str>, path: &str) -> Result<(String, String), CompileError> {
+        let file = resolve(from, path);
+        if let Some(start) = self.chain.iter().position(|name| name == &file) {
+            let mut cycle = self.chain[start..].to_vec();
+            cycle.push(file);
+            return Err(CompileError::Include(format!(
+                "include cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        match (self.resolver)(&file) {
+            Some(source) => Ok((file, source)),
+            None => Err(CompileError::Include(match from {
+                Some(from) => format!("nothing to include for {} in {}", file, from),
+                None => format!("nothing to include for {}", file),
+            })),
+        }
+    }
+
+    /// The file `name` inlined, with it on the chain meanwhile.
+    fn nested(&mut self, name: String, source: &str) -> Result<Vec<String>, CompileError> {
+        self.chain.push(name.clone());
+        let inlined = self.template(source, Some(&name));
+        self.chain.pop();
+        inlined
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Replace,
+    Append,
+    Prepend,
+}
+
+/// A `block`, `append` or `prepend` in a template that extends another, its
+/// lines unindented.
+struct Block {
+    name: String,
+    mode: Mode,
+    lines: Vec<String>,
+}
+
+/// `layout` with the blocks defined at the top level of `child` filled in,
+/// and the child's mixins and code in front.
+fn extend(layout: Vec<String>, child: Vec<String>) -> Result<Vec<String>, CompileError> {
+    let child_unit = unit(&child);
+    let mut prologue = Vec::new();
+    let mut blocks = Vec::new();
+    let mut index = 0;
+    while index < child.len() {
+        let (indent, content) = split_indent(&child[index]);
+        let end = children_end(&child, index);
+        if content.is_empty() || !indent.is_empty() || content.starts_with("//") {
+            // comments here would render nowhere
+        } else if let Some((mode, name)) = block(content) {
+            let lines = &child[index + 1..end];
+            let inner = lines
+                .iter()
+                .map(|line| split_indent(line))
+                .find(|(_, content)| !content.is_empty())
+                .map_or("", |(indent, _)| indent);
+            blocks.push(Block {
+                name: name.to_owned(),
+                mode,
+                lines: lines
+                    .iter()
+                    .map(|line| line.strip_prefix(inner).unwrap_or(line.trim()).to_owned())
+                    .collect(),
+            });
+        } else if content.starts_with("mixin ") || content.starts_with('-') {
+            prologue.extend_from_slice(&child[index..end]);
+        } else {
+            return Err(CompileError::Include(format!(
+                "only blocks, mixins and code can be at the top level of a template that \
+                 extends another, not `{}`",
+                content
+            )));
+        }
+        index = end;
+    }
+
+    let unit = unit(&layout).or_else(|| child_unit.clone());
+    let unit = unit.as_deref().unwrap_or("  ");
+    let mut lines = layout;
+    // blocks filled in can define the blocks of later rounds
+    while !blocks.is_empty() {
+        let structure = structural(&lines);
+        let mut filled = Vec::with_capacity(lines.len());
+        let mut applied = false;
+        let mut index = 0;
+        while index < lines.len() {
+            let (indent, content) = split_indent(&lines[index]);
+            let position = block(content)
+                .filter(|_| structure[index])
+                .and_then(|(_, name)| blocks.iter().position(|block| block.name == name));
+            let block = match position {
+                Some(position) => blocks.remove(position),
+                None => {
+                    filled.push(lines[index].clone());
+                    index += 1;
+                    continue;
+                }
+            };
+            let end = children_end(&lines, index);
+            let defaults = lines[index + 1..end].iter().cloned();
+            let overriding = block.lines.iter().map(|line| {
+                if line.is_empty() {
+                    String::new()
+                } else {
+                    let line = restyle(line, child_unit.as_deref(), Some(unit));
+                    format!("{}{}{}", indent, unit, line)
+                }
+            });
+            filled.push(format!("{}block {}", indent, block.name));
+            match block.mode {
+                Mode::Replace => filled.extend(overriding),
+                Mode::Append => filled.extend(defaults.chain(overriding)),
+                Mode::Prepend => filled.extend(overriding.chain(defaults)),
+            }
+            applied = true;
+            index = end;
+        }
+        lines = filled;
+        if !applied {
+            break;
+        }
+    }
+    prologue.extend(lines);
+    Ok(prologue)
+}
+
+/// The mode and name of a `block`, `append` or `prepend` line.
+fn block(content: &str) -> Option<(Mode, &str)> {
+    let rest = keyword(content, "block").unwrap_or(content);
+    if let Some(name) = keyword(rest, "append") {
+        return Some((Mode::Append, name));
+    }
+    if let Some(name) = keyword(rest, "prepend") {
+        return Some((Mode::Prepend, name));
+    }
+    keyword(content, "block").map(|name| (Mode::Replace, name))
+}
+
+/// The filter and path of an `include` line.
+fn include(content: &str) -> Option<(Option<&str>, &str)> {
+    let rest = content.strip_prefix("include")?;
+    match rest.strip_prefix(':') {
+        Some(filtered) => {
+            let end = filtered.find(char::is_whitespace)?;
+            let path = keyword(&filtered[end..], "")?;
+            Some((Some(&filtered[..end]), path))
+        }
+        None => Some((None, keyword(rest, "")?)),
+    }
+}
+
+/// What follows `word` and whitespace at the start of `content`.
+fn keyword<'a>(content: &'a str, word: &str) -> Option<&'a str> {
+    let rest = content.strip_prefix(word)?;
+    let argument = rest.trim();
+    if rest.starts_with(char::is_whitespace) && !argument.is_empty() {
+        Some(argument)
+    } else {
+        None
+    }
+}
+
+/// `path` as written in the file `from`, relative to the root.
+fn resolve(from: Option<&str>, path: &str) -> String {
+    let mut parts: Vec<&str> = match from {
+        Some(from) if !path.starts_with('/') => from.split('/').collect(),
+        _ => Vec::new(),
+    };
+    // the file itself
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    let bare = !parts.last().is_some_and(|file| file.contains('.'));
+    let mut resolved = parts.join("/");
+    if bare {
+        resolved.push_str(".pug");
+    }
+    resolved
+}
+
+/// Whether each line is pug, not text in a `.` block, a comment or a filter.
+fn structural(lines: &[impl AsRef<str>]) -> Vec<bool> {
+    let mut text_under = None;
+    lines
+        .iter()
+        .map(|line| {
+            let (indent, content) = split_indent(line.as_ref());
+            match text_under {
+                Some(width) if content.is_empty() || indent.len() > width => false,
+                _ => {
+                    text_under = Some(indent.len()).filter(|_| opens_text(content));
+                    true
+                }
+            }
+        })
+        .collect()
+}
+
+/// Whether the lines under `content` are text rather than pug.
+fn opens_text(content: &str) -> bool {
+    if content.starts_with("//") || content.starts_with(':') || content == "-" {
+        return true;
+    }
+    let tag = match content.strip_suffix('.') {
+        Some(tag) if !tag.is_empty() => tag,
+        _ => return false,
+    };
+    // `p.`, `script(type="module").`, but not `p Some text.`
+    let mut depth = 0;
+    tag.chars().all(|c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        depth > 0 || !c.is_whitespace()
+    })
+}
+
+/// The index past the lines indented under the one at `index`.
+fn children_end(lines: &[String], index: usize) -> usize {
+    let width = split_indent(&lines[index]).0.len();
+    lines[index + 1..]
+        .iter()
+        .position(|line| {
+            let (indent, content) = split_indent(line);
+            !content.is_empty() && indent.len() <= width
+        })
+        .map_or(lines.len(), |end| index + 1 + end)
+}
+
+/// The whitespace in front of `line` and what follows, without trailing
+/// whitespace.
+fn split_indent(line: &str) -> (&str, &str) {
+    let content = line.trim_start_matches([' ', '\t']);
+    (&line[..line.len() - content.len()], content.trim_end())
+}
+
+/// One level of indentation in `lines`, from the first indented line.
+fn unit(lines: &[impl AsRef<str>]) -> Option<String> {
+    lines
+        .iter()
+        .map(|line| split_indent(line.as_ref()))
+        .find(|(indent, content)| !indent.is_empty() && !content.is_empty())
+        .map(|(indent, _)| indent.to_owned())
+}
+
+/// `line` indented by `to` per level instead of `from`.
+fn restyle(line: &str, from: Option<&str>, to: Option<&str>) -> String {
+    match (from, to) {
+        (Some(from), Some(to)) if from != to => {
+            let mut rest = line;
+            let mut levels = 0;
+            while let Some(next) = rest.strip_prefix(from) {
+                rest = next;
+                levels += 1;
+            }
+            format!("{}{}", to.repeat(levels), rest)
+        }
+        _ => line.to_owned(),
+    }
+}
+
+/// A line of a plain text file as piped text, its interpolations escaped.
+fn piped(line: &str) -> String {
+    if line.is_empty() {
+        return "|".into();
+    }
+    let escaped = line
+        .replace("#{", "\\#{")
+        .replace("!{", "\\!{")
+        .replace("#[", "\\#[");
+    format!("| {}", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(templates: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let templates = templates
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect();
+        map_resolver(templates)
+    }
+
+    #[test]
+    fn inlines_nested_includes() {
+        let resolver = templates(&[
+            ("partials/header.pug", "header\n  include nav\n  h1 Title"),
+            ("partials/nav.pug", "nav\n  a(href='/') Home"),
+            (
+                "style.css",
+                "p { color: red }\n\na::after { content: '#{x}' }",
+            ),
+        ]);
+        let source = "html\n  head\n    style\n      include style.css\n  body\n    include partials/header\n    p main\n";
+        assert_eq!(
+            "html\n  head\n    style\n      | p { color: red }\n      |\n      | a::after { content: '\\#{x}' }\n  body\n    header\n      nav\n        a(href='/') Home\n      h1 Title\n    p main\n",
+            inline_includes(source, &resolver).unwrap()
+        );
+
+        let text = "p.\n  include partials/nav\ninclude partials/missing";
+        let error = inline_includes(text, &resolver).unwrap_err();
+        assert_eq!(
+            "include failed: nothing to include for partials/missing.pug",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn reindents_deeply_nested_includes() {
+        let resolver = templates(&[(
+            "row.pug",
+            "tr\n  td\n    if cell\n      span= cell\n    else\n      | -",
+        )]);
+        let source = "table\n\ttbody\n\t\teach cell in cells\n\t\t\tinclude row";
+        assert_eq!(
+            "table\n\ttbody\n\t\teach cell in cells\n\t\t\ttr\n\t\t\t\ttd\n\t\t\t\t\tif cell\n\t\t\t\t\t\tspan= cell\n\t\t\t\t\telse\n\t\t\t\t\t\t| -",
+            inline_includes(source, &resolver).unwrap()
+        );
+    }
+
+    #[test]
+    fn resolves_extends_and_blocks() {
+        let resolver = dir_resolver("test/include");
+        let source = "extends layout\n\nmixin shout(text)\n  strong= text\n\nappend head\n  script(src='/page.js')\nblock content\n  +shout('hi')\n  block aside\nblock aside\n  include /partials/aside\n";
+        assert_eq!(
+            "mixin shout(text)\n  strong= text\n\nhtml\n  head\n    block head\n      title Site\n      script(src='/page.js')\n  body\n    block content\n      +shout('hi')\n      block aside\n        aside Related\n    footer\n      | (c)\n",
+            inline_includes(source, resolver).unwrap()
+        );
+    }
+
+    #[test]
+    fn keeps_dir_resolver_inside_its_dir() {
+        let resolver = dir_resolver("test/include/partials");
+        assert!(resolver("aside.pug").is_some() && resolver("./aside.pug").is_some());
+        for path in [
+            "../layout.pug",
+            "/etc/hostname",
+            "..\\layout.pug",
+            "C:/Windows/win.ini",
+            "C:\\Windows\\win.ini",
+        ] {
+            assert_eq!(None, resolver(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn names_the_cycle() {
+        let resolver = templates(&[
+            ("a.pug", "p a\ninclude b"),
+            ("b.pug", "p b\ninclude dir/c"),
+            ("dir/c.pug", "include ../a"),
+        ]);
+        let error = inline_includes("include a", resolver).unwrap_err();
+        assert!(matches!(error, CompileError::Include(_)));
+        assert_eq!(
+            "include failed: include cycle: a.pug -> b.pug -> dir/c.pug -> a.pug",
+            error.to_string()
+        );
+    }
+}
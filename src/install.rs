@@ -0,0 +1,220 @@
+//! Installing pug-cli with npm when it isn't on `PATH`.
+
+use crate::doctor::resolve;
+use crate::invocation::PUG_BINARY;
+use crate::CompileError;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+static INSTALLED: OnceLock<PathBuf> = OnceLock::new();
+
+/// A lock older than this was left by a process that died mid-install.
+const STALE_LOCK: Duration = Duration::from_secs(600);
+
+/// Returns the pug binary to run, installing pug-cli into `dir` with
+/// `npm install pug-cli --prefix <dir>` if there's none on `PATH` or in `dir`
+/// yet.
+///
+/// `dir` defaults to `pug_cli` in the per-user cache directory. Processes
+/// installing into the same directory wait for each other. Once this returns
+/// an installed binary, compiles that don't set `PugOptions::binary` run it.
+/// npm failing, including for lack of network, is
+/// `CompileError::InstallFailed`.
+pub fn ensure_installed(dir: Option<PathBuf>) -> Result<PathBuf, CompileError> {
+    if let Some(installed) = INSTALLED.get() {
+        return Ok(installed.clone());
+    }
+    if let Some(found) = resolve(Path::new(PUG_BINARY)) {
+        return Ok(found);
+    }
+    let dir = match dir.or_else(default_dir) {
+        Some(dir) => dir,
+        None => {
+            return Err(CompileError::InstallFailed(
+                "no cache directory; pass one to ensure_installed".into(),
+            ))
+        }
+    };
+    let binary = install(Path::new(npm()), &dir)?;
+    Ok(INSTALLED.get_or_init(|| binary).clone())
+}
+
+/// The binary `ensure_installed` installed, if it did.
+pub(crate) fn installed() -> Option<&'static Path> {
+    INSTALLED.get().map(PathBuf::as_path)
+}
+
+fn install(npm: &Path, dir: &Path) -> Result<PathBuf, CompileError> {
+    let binary = installed_binary(dir);
+    if binary.is_file() {
+        return Ok(binary);
+    }
+    let failed = |what: &str, e: io::Error| {
+        CompileError::InstallFailed(format!("couldn't {} {}: {}", what, dir.display(), e))
+    };
+    fs::create_dir_all(dir).map_err(|e| failed("create", e))?;
+    let _lock =
+        InstallLock::acquire(&dir.join(".pug_cli_install.lock")).map_err(|e| failed("lock", e))?;
+    // whoever held the lock may have installed it
+    if binary.is_file() {
+        return Ok(binary);
+    }
+
+    let output = Command::new(npm)
+        .args(["install", "pug-cli", "--no-save", "--prefix"])
+        .arg(dir)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| {
+            CompileError::InstallFailed(format!("couldn't run {}: {}", npm.display(), e))
+        })?;
+    if !output.status.success() {
+        return Err(CompileError::InstallFailed(format!(
+            "npm install pug-cli exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    if binary.is_file() {
+        Ok(binary)
+    } else {
+        Err(CompileError::InstallFailed(format!(
+            "npm succeeded but {} doesn't exist",
+            binary.display()
+        )))
+    }
+}
+
+fn installed_binary(dir: &Path) -> PathBuf {
+    let bin = dir.join("node_modules").join(".bin");
+    if cfg!(windows) {
+        bin.join("pug.cmd")
+    } else {
+        bin.join(PUG_BINARY)
+    }
+}
+
+fn npm() -> &'static str {
+    if cfg!(windows) {
+        "npm.cmd"
+    } else {
+        "npm"
+    }
+}
+
+fn default_dir() -> Option<PathBuf> {
+    let cache = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| Path::new(&home).join("Library/Caches"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    cache.map(|cache| cache.join("pug_cli"))
+}
+
+/// A lock file created exclusively and removed on drop.
+struct InstallLock(PathBuf);
+
+impl InstallLock {
+    fn acquire(path: &Path) -> io::Result<InstallLock> {
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(_) => return Ok(InstallLock(path.to_owned())),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale(path) {
+                        let _ = fs::remove_file(path);
+                    } else {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for InstallLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn is_stale(lock: &Path) -> bool {
+    fs::metadata(lock)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > STALE_LOCK)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_binary;
+
+    /// A fake npm that logs each run and lays out `node_modules/.bin/pug` in
+    /// the `--prefix` directory.
+    fn fake_npm(dir: &Path) -> PathBuf {
+        let script = format!(
+            r#"while [ $# -gt 0 ]; do
+  if [ "$1" = "--prefix" ]; then prefix="$2"; fi
+  shift
+done
+echo run >> '{log}'
+sleep 0.2
+mkdir -p "$prefix/node_modules/.bin"
+printf '#!/bin/sh\necho installed\n' > "$prefix/node_modules/.bin/pug"
+chmod +x "$prefix/node_modules/.bin/pug"
+"#,
+            log = dir.join("npm.log").display()
+        );
+        fake_binary(dir, "npm", &script)
+    }
+
+    #[test]
+    fn installs_once_across_concurrent_callers() {
+        let dir = tempfile::tempdir().unwrap();
+        let npm = fake_npm(dir.path());
+        let prefix = dir.path().join("cache");
+
+        let installs: Vec<_> = (0..3)
+            .map(|_| {
+                let (npm, prefix) = (npm.clone(), prefix.clone());
+                thread::spawn(move || install(&npm, &prefix).unwrap())
+            })
+            .collect();
+        for handle in installs {
+            assert_eq!(installed_binary(&prefix), handle.join().unwrap());
+        }
+        let log = fs::read_to_string(dir.path().join("npm.log")).unwrap();
+        assert_eq!(1, log.lines().count());
+        assert!(!prefix.join(".pug_cli_install.lock").exists());
+    }
+
+    #[test]
+    fn npm_failures_are_install_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let offline = fake_binary(
+            dir.path(),
+            "npm",
+            "echo 'npm ERR! network request failed' >&2\nexit 1\n",
+        );
+        let error = install(&offline, &dir.path().join("cache")).unwrap_err();
+        assert!(
+            matches!(&error, CompileError::InstallFailed(reason) if reason.contains("network")),
+            "{:?}",
+            error
+        );
+
+        let missing = install(&dir.path().join("no-npm"), &dir.path().join("cache"));
+        assert!(matches!(missing, Err(CompileError::InstallFailed(_))));
+    }
+}
@@ -0,0 +1,305 @@
+//! Tracing and logging for every pug spawn, whichever runner makes it.
+//! Without the `tracing` and `log` features everything here but the
+//! `PugOptions::stderr_to` log is a no-op the compiler removes.
+
+use crate::invocation::{Input, Invocation};
+use crate::stderr_mode;
+use crate::CompileError;
+use std::future::Future;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+
+/// One compile.
+///
+/// With `tracing` it is a `pug.compile` span carrying the template path (or
+/// `<string>`), the argv length, and once it's done the output size and exit
+/// status. With `log` the command line is logged at debug before the spawn,
+/// the duration and byte counts at trace afterwards.
+pub(crate) struct Compile {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "log")]
+    started: std::time::Instant,
+    /// `PugOptions::stderr_to`'s file, and what to call the template in it.
+    stderr_log: Option<(PathBuf, String)>,
+}
+
+impl Compile {
+    pub(crate) fn start(invocation: &Invocation) -> Compile {
+        #[cfg(feature = "log")]
+        log::debug!("running {}", command_line(invocation));
+
+        Compile {
+            #[cfg(feature = "tracing")]
+            span: compile_span(invocation),
+            #[cfg(feature = "log")]
+            started: std::time::Instant::now(),
+            stderr_log: invocation
+                .stderr_log
+                .clone()
+                .map(|log| (log, template(invocation))),
+        }
+    }
+
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.enter();
+        f()
+    }
+
+    #[cfg(feature = "tracing")]
+    #[cfg_attr(not(any(feature = "tokio", feature = "async-std")), allow(dead_code))]
+    pub(crate) fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        tracing::Instrument::instrument(future, self.span.clone())
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    #[cfg_attr(not(any(feature = "tokio", feature = "async-std")), allow(dead_code))]
+    pub(crate) fn instrument<F: Future>(&self, future: F) -> F {
+        future
+    }
+
+    /// Records how the child finished, warning with its stderr if it failed.
+    #[cfg_attr(
+        not(any(feature = "tracing", feature = "log")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn finished(&self, output_bytes: usize, status: ExitStatus, stderr: &[u8]) {
+        if let Some((log, template)) = &self.stderr_log {
+            // a log that can't be written to doesn't fail the compile
+            let _ = stderr_mode::append_to_log(log, template, status, stderr);
+        }
+        #[cfg(feature = "log")]
+        {
+            log::trace!(
+                "pug finished in {:?} with {}: {} bytes of output, {} bytes of stderr",
+                self.started.elapsed(),
+                status,
+                output_bytes,
+                stderr.len()
+            );
+            if status.success() && !stderr.is_empty() {
+                log::warn!("pug wrote to stderr: {}", String::from_utf8_lossy(stderr));
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("output_bytes", output_bytes);
+            match status.code() {
+                Some(code) => self.span.record("exit_status", code),
+                None => self
+                    .span
+                    .record("exit_status", tracing::field::display(status)),
+            };
+            if !stderr.is_empty() || !status.success() {
+                self.span.in_scope(|| {
+                    tracing::warn!(stderr = %String::from_utf8_lossy(stderr), "pug compile failed")
+                });
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn failed(&self, error: &CompileError) {
+        #[cfg(feature = "tracing")]
+        self.span
+            .in_scope(|| tracing::warn!(error = %error, "pug compile failed"));
+    }
+
+    /// Records `result` and hands it back.
+    pub(crate) fn observe(
+        &self,
+        result: Result<Output, CompileError>,
+    ) -> Result<Output, CompileError> {
+        match &result {
+            Ok(output) => self.finished(output.stdout.len(), output.status, &output.stderr),
+            Err(error) => self.failed(error),
+        }
+        result
+    }
+}
+
+/// The template's path, or `<string>`.
+fn template(invocation: &Invocation) -> String {
+    match &invocation.input {
+        Input::File(path) => path.to_string_lossy().into_owned(),
+        Input::Bytes(_) => "<string>".to_owned(),
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn compile_span(invocation: &Invocation) -> tracing::Span {
+    tracing::info_span!(
+        "pug.compile",
+        template = %template(invocation),
+        argv_len = invocation.args.len(),
+        output_bytes = tracing::field::Empty,
+        exit_status = tracing::field::Empty,
+    )
+}
+
+/// The program and its arguments, with the value of `--obj` replaced unless
+/// the options asked for locals to be logged; they often hold user data.
+#[cfg(feature = "log")]
+fn command_line(invocation: &Invocation) -> String {
+    let mut line = invocation.program.to_string_lossy().into_owned();
+    let mut redact_next = false;
+    for arg in &invocation.args {
+        line.push(' ');
+        if redact_next {
+            line.push_str("<redacted>");
+        } else {
+            line.push_str(arg);
+        }
+        redact_next = arg == "--obj" && !invocation.log_locals;
+    }
+    line
+}
+
+#[cfg(all(test, feature = "log"))]
+mod log_tests {
+    use crate::{evaluate_string_with_options, PugOptions};
+    use log::{Log, Metadata, Record};
+    use std::sync::{Mutex, Once};
+
+    struct Capture;
+
+    static RECORDS: Mutex<Vec<(log::Level, String)>> = Mutex::new(Vec::new());
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            let message = record.args().to_string();
+            RECORDS.lock().unwrap().push((record.level(), message));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Compiles with `options` and returns the debug records mentioning
+    /// `marker`, which must be unique to the calling test.
+    fn debug_records(marker: &str, options: PugOptions) -> Vec<String> {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+
+        let options = options.with_object(serde_json::json!({ "secret": marker }));
+        evaluate_string_with_options("p #{secret}".into(), options).unwrap();
+        RECORDS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(level, _)| *level == log::Level::Debug)
+            .map(|(_, message)| message.clone())
+            .filter(|message| message.contains("--obj") && message.contains("running"))
+            .collect()
+    }
+
+    #[test]
+    fn locals_are_redacted_by_default() {
+        let records = debug_records("hunter2", PugOptions::new());
+        assert!(records.iter().any(|m| m.contains("--obj <redacted>")));
+        assert!(!records.iter().any(|m| m.contains("hunter2")));
+    }
+
+    #[test]
+    fn log_locals_logs_them() {
+        let records = debug_records("swordfish", PugOptions::new().log_locals());
+        assert!(records.iter().any(|m| m.contains("swordfish")));
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::{evaluate, CompileError};
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::{LookupSpan, Registry};
+
+    /// A span's name and its fields as `name=value` strings.
+    type CapturedSpan = (String, Vec<String>);
+
+    /// Every span, and every event's fields.
+    #[derive(Clone, Default)]
+    struct Captured {
+        spans: Arc<Mutex<Vec<CapturedSpan>>>,
+        events: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Captured {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            let mut fields = Vec::new();
+            attrs.record(&mut Fields(&mut fields));
+            let name = attrs.metadata().name().to_owned();
+            self.spans.lock().unwrap().push((name, fields));
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            let mut spans = self.spans.lock().unwrap();
+            if let Some((_, fields)) = spans.last_mut() {
+                values.record(&mut Fields(fields));
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            let mut fields = Vec::new();
+            event.record(&mut Fields(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+    }
+
+    fn traced<T>(f: impl FnOnce() -> T) -> (T, Captured) {
+        let captured = Captured::default();
+        let subscriber = Registry::default().with(captured.clone());
+        (tracing::subscriber::with_default(subscriber, f), captured)
+    }
+
+    #[test]
+    fn successful_compile_is_traced() {
+        let (result, captured) = traced(|| evaluate("test/hello.pug"));
+        assert!(result.is_ok());
+
+        let spans = captured.spans.lock().unwrap();
+        let (name, fields) = &spans[0];
+        assert_eq!("pug.compile", name);
+        assert!(fields.contains(&"template=test/hello.pug".to_owned()));
+        assert!(fields.iter().any(|field| field.starts_with("argv_len=")));
+        assert!(fields.contains(&"output_bytes=18".to_owned()));
+        assert!(fields.contains(&"exit_status=0".to_owned()));
+        assert!(captured.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn failing_compile_warns_with_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = dir.path().join("bad.pug");
+        std::fs::write(&page, "  h1 indented too early").unwrap();
+
+        let (result, captured) = traced(|| evaluate(&page));
+        assert!(matches!(result, Err(CompileError::PugError(_))));
+
+        let spans = captured.spans.lock().unwrap();
+        assert_eq!("pug.compile", spans[0].0);
+        let events = captured.events.lock().unwrap();
+        assert!(events[0]
+            .iter()
+            .any(|field| field.starts_with("stderr=") && field.contains("unexpected token")));
+    }
+}
@@ -0,0 +1,513 @@
+//! What to run and how to interpret the result, independent of how the child
+//! process is actually spawned. The blocking API and every async backend go
+//! through here so their argv and error handling cannot drift apart.
+
+use super::executor::Executor;
+use super::limits::Limits;
+use super::locale;
+use super::newline::{self, NewlineStyle, TrailingNewline};
+use super::pug_error::{fill_snippet, pug_failure, rename_source, shift_lines, strip_ansi};
+use super::reindent::{self, Indent};
+use super::retry::{retry, RetryPolicy};
+use super::stderr_mode::StderrMode;
+use super::temp_file::TempFile;
+use super::{CompileError, CompileOutput, Prelude, PugJsonObject, PugOptions};
+use std::borrow::Cow;
+use std::io;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) const PUG_BINARY: &str = "pug";
+
+/// Where the child's stdin comes from.
+pub(crate) enum Input {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+pub(crate) struct Invocation {
+    pub(crate) program: PathBuf,
+    pub(crate) args: Vec<String>,
+    pub(crate) input: Input,
+    pub(crate) timeout: Option<Duration>,
+    #[cfg_attr(not(feature = "log"), allow(dead_code))]
+    pub(crate) log_locals: bool,
+    pub(crate) post_process: PostProcess,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) stderr_mode: StderrMode,
+    /// `PugOptions::stderr_to`.
+    pub(crate) stderr_log: Option<PathBuf>,
+    executor: Option<Arc<dyn Executor>>,
+    /// The file `PugOptions::locals_via_file` passed the locals in, to keep
+    /// until pug has exited.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) locals_file: Option<TempFile>,
+}
+
+/// What happens to pug's output before it's handed back.
+#[derive(Clone)]
+pub(crate) struct PostProcess {
+    minify: bool,
+    reindent: Option<Indent>,
+    /// What pug runs under, for telling a limit it hit from a failed compile.
+    pub(crate) limits: Limits,
+    newline: Option<NewlineStyle>,
+    trailing_newline: TrailingNewline,
+    validate_output: bool,
+    strict_validation: bool,
+    #[cfg(feature = "ammonia")]
+    sanitize: Option<crate::SanitizeProfile>,
+    /// Lines of prelude in front of the template.
+    pub(crate) line_offset: u32,
+    source_name: Option<String>,
+    /// A string template's own source, for excerpts pug leaves out.
+    source: Option<String>,
+    /// `PugOptions::warn_unused_locals`'s findings, added to pug's warnings.
+    pub(crate) unused_locals: Vec<String>,
+}
+
+impl PostProcess {
+    pub(crate) fn new(options: &PugOptions) -> Self {
+        PostProcess {
+            minify: options.minify,
+            reindent: options.reindent,
+            limits: options.limits,
+            newline: options.newline,
+            trailing_newline: options.trailing_newline,
+            validate_output: options.validate_output,
+            strict_validation: options.strict_validation,
+            #[cfg(feature = "ammonia")]
+            sanitize: options.sanitize.clone(),
+            line_offset: 0,
+            source_name: options.source_name.clone(),
+            source: None,
+            unused_locals: Vec::new(),
+        }
+    }
+
+    /// Whether the whole document has to be collected before it's handed
+    /// on, to process it or to stop at `max_output_bytes` before any of it
+    /// gets out.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn is_needed(&self) -> bool {
+        self.limits.output.is_some()
+            || self.minify
+            || self.reindent.is_some()
+            || self.newline.is_some()
+            || self.trailing_newline != TrailingNewline::Keep
+            || self.validate_output
+            || self.sanitizes()
+    }
+
+    #[cfg(feature = "ammonia")]
+    fn sanitizes(&self) -> bool {
+        self.sanitize.is_some()
+    }
+
+    #[cfg(not(feature = "ammonia"))]
+    fn sanitizes(&self) -> bool {
+        false
+    }
+
+    /// Pug's error output with the template's own line numbers and name.
+    pub(crate) fn locate(&self, stderr: &str) -> String {
+        let mut located = shift_lines(stderr, self.line_offset);
+        if let Some(source) = &self.source {
+            located = fill_snippet(&located, source);
+        }
+        match &self.source_name {
+            Some(name) => rename_source(&located, name),
+            None => located,
+        }
+    }
+
+    /// Sanitizes, then minifies or re-indents, then fixes up newlines, and
+    /// validates what's left.
+    fn apply(&self, html: &[u8]) -> Result<String, CompileError> {
+        let mut html: String = String::from_utf8_lossy(html).into();
+        #[cfg(feature = "ammonia")]
+        if let Some(profile) = &self.sanitize {
+            html = crate::sanitize::sanitize(&html, profile);
+        }
+        if self.minify {
+            html = minify_html(html.as_bytes());
+        }
+        if let Some(indent) = self.reindent {
+            html = reindent::reindent(&html, indent);
+        }
+        html = newline::apply_trailing(html, self.trailing_newline);
+        if let Some(style) = self.newline {
+            html = newline::convert(&html, style);
+        }
+        if self.validate_output {
+            validate_html(&html, self.strict_validation)?;
+        }
+        Ok(html)
+    }
+}
+
+impl Invocation {
+    pub(crate) fn file(
+        file: impl Into<PathBuf>,
+        options: PugOptions,
+    ) -> Result<Self, CompileError> {
+        let file = file.into();
+        if options.reject_empty_input {
+            let source =
+                std::fs::read(&file).map_err(CompileError::io_at("read template", &file))?;
+            reject_blank(&String::from_utf8_lossy(&source))?;
+        }
+        // pug reads the file from stdin, so it only learns the name from
+        // `--path`; a path the caller chose is kept
+        let mut options = options.stdin();
+        if options.path.is_none() {
+            options = options.with_path(file.clone());
+        }
+        if let Some(locals_for) = options.locals_for.clone() {
+            let page = PugOptions::new().with_object(locals_for(&file)?);
+            options = options.merge(page);
+        }
+        if options.sidecar_locals {
+            if let Some(sidecar) = crate::sidecar::find(&file) {
+                let page = PugOptions::new().with_object(crate::sidecar::read(&sidecar)?);
+                options = options.merge(page);
+            }
+        }
+        if options.front_matter {
+            let (front_matter, source) = crate::read_front_matter(&file)?;
+            if front_matter
+                .as_object()
+                .is_some_and(|page| !page.is_empty())
+            {
+                let mut locals = match &options.object {
+                    Some(locals) => locale::to_json(locals)?,
+                    None => serde_json::Value::Object(Default::default()),
+                };
+                locale::merge(&mut locals, front_matter);
+                options = options.with_object(locals);
+                return Invocation::new(options, Input::Bytes(source.into_bytes()));
+            }
+        }
+        Invocation::new(options, Input::File(file))
+    }
+
+    pub(crate) fn string(s: String, options: PugOptions) -> Result<Self, CompileError> {
+        if options.reject_empty_input {
+            reject_blank(&s)?;
+        }
+        if let Some(position) = s.find('\0') {
+            return Err(CompileError::InvalidInput {
+                position,
+                reason: "the template contains a NUL byte".into(),
+            });
+        }
+        let normalize = |source: String| {
+            if options.normalize_newlines {
+                newline::normalize_input(source)
+            } else {
+                source
+            }
+        };
+        let s = normalize(s);
+        let mut source = String::new();
+        for prelude in &options.preludes {
+            let (prelude, reason) = match prelude {
+                Prelude::Source(prelude) => {
+                    (prelude.clone(), "a prelude contains a NUL byte".into())
+                }
+                Prelude::File(path) => (
+                    std::fs::read_to_string(path)
+                        .map_err(CompileError::io_at("read prelude", path))?,
+                    format!("the prelude {} contains a NUL byte", path.display()),
+                ),
+            };
+            if let Some(position) = prelude.find('\0') {
+                return Err(CompileError::InvalidInput { position, reason });
+            }
+            source.push_str(&normalize(prelude));
+            if !source.is_empty() && !source.ends_with('\n') {
+                source.push('\n');
+            }
+        }
+        let line_offset = source.matches('\n').count() as u32;
+        source.push_str(&s);
+
+        let mut invocation = Invocation::new(options.stdin(), Input::Bytes(source.into_bytes()))?;
+        invocation.post_process.line_offset = line_offset;
+        invocation.post_process.source = Some(s);
+        Ok(invocation)
+    }
+
+    /// Pug compiling `files` itself, into `PugOptions::out_dir`, with nothing
+    /// on stdin.
+    pub(crate) fn files(files: &[PathBuf], options: PugOptions) -> Result<Self, CompileError> {
+        if options.sandbox {
+            for file in files {
+                let source =
+                    std::fs::read(file).map_err(CompileError::io_at("read template", file))?;
+                crate::sandbox::check_source(&String::from_utf8_lossy(&source))?;
+            }
+        }
+        let mut invocation = Invocation::new(options, Input::Bytes(Vec::new()))?;
+        let files = files.iter().map(|file| file.to_string_lossy().into_owned());
+        invocation.args.extend(files);
+        Ok(invocation)
+    }
+
+    fn new(mut options: PugOptions, input: Input) -> Result<Self, CompileError> {
+        if options.flavor.is_none() {
+            options.flavor = crate::health::known_flavor(&program(&options));
+        }
+        options.validate()?;
+        crate::version::check(&options)?;
+        let source = || match &input {
+            Input::File(path) => {
+                std::fs::read(path).map_err(CompileError::io_at("read template", path))
+            }
+            Input::Bytes(bytes) => Ok(bytes.clone()),
+        };
+        if options.sandbox {
+            crate::sandbox::apply(&mut options);
+            crate::sandbox::check_source(&String::from_utf8_lossy(&source()?))?;
+        }
+        #[cfg(feature = "i18n")]
+        crate::fluent::inject(&mut options)?;
+        #[cfg(feature = "schema")]
+        crate::schema::check_locals(&options)?;
+        let mut post_process = PostProcess::new(&options);
+        // locals pug loads itself, from a JS module, can't be looked into
+        if let (true, Some(Ok(locals))) = (
+            options.warn_unused_locals,
+            options.object.as_ref().map(locale::to_json),
+        ) {
+            post_process.unused_locals =
+                crate::unused_locals(&String::from_utf8_lossy(&source()?), &locals);
+        }
+        let locals_file = match &options.object {
+            Some(object @ PugJsonObject::Json(_)) | Some(object @ PugJsonObject::Raw(_))
+                if options.locals_via_file =>
+            {
+                let locals = String::from(object.clone());
+                let file = TempFile::private(".json", locals.as_bytes()).map_err(
+                    CompileError::io_at("write locals file in", &std::env::temp_dir()),
+                )?;
+                options.object = Some(PugJsonObject::Path(file.path().to_owned()));
+                Some(file)
+            }
+            _ => None,
+        };
+        let invocation = Invocation {
+            program: program(&options),
+            timeout: options.timeout,
+            log_locals: options.log_locals,
+            post_process,
+            executor: options.executor.clone(),
+            retry: options.retry.clone(),
+            stderr_mode: options.stderr_mode.unwrap_or_default(),
+            stderr_log: options.stderr_log.clone(),
+            locals_file,
+            args: options.into_iter().collect(),
+            input,
+        };
+        reject_nul_args(&invocation.args)?;
+        Ok(invocation)
+    }
+
+    /// The command running pug under `PugOptions::memory_limit` and
+    /// `cpu_time_limit`, without arguments or stdio yet.
+    pub(crate) fn command(&self) -> std::process::Command {
+        self.post_process.limits.command(&self.program)
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn has_executor(&self) -> bool {
+        self.executor.is_some()
+    }
+
+    /// Runs the invocation with `PugOptions::executor`, or `None` if there
+    /// isn't one and pug should be spawned.
+    pub(crate) fn run_executor(&self) -> Option<Result<Output, CompileError>> {
+        let executor = self.executor.as_ref()?;
+        let stdin = match &self.input {
+            Input::File(path) => match std::fs::read(path) {
+                Ok(source) => Cow::Owned(source),
+                Err(e) => return Some(Err(CompileError::io_at("read template", path)(e))),
+            },
+            Input::Bytes(bytes) => Cow::Borrowed(bytes.as_slice()),
+        };
+        let output = retry(self.retry.as_ref(), || {
+            executor.run(&self.program, &self.args, &stdin)
+        });
+        Some(output.map_err(CompileError::io_at("run pug", &self.program)))
+    }
+}
+
+/// `CompileError::EmptyInput` for a template with nothing but whitespace.
+fn reject_blank(source: &str) -> Result<(), CompileError> {
+    if source.trim().is_empty() {
+        Err(CompileError::EmptyInput)
+    } else {
+        Ok(())
+    }
+}
+
+/// `CompileError::InvalidInput` for an argument with a NUL byte, which
+/// can't be passed to a process at all. The value isn't quoted, since it may
+/// be the locals.
+fn reject_nul_args(args: &[String]) -> Result<(), CompileError> {
+    for (at, arg) in args.iter().enumerate() {
+        if let Some(position) = arg.find('\0') {
+            let reason = match at.checked_sub(1).map(|flag| &args[flag]) {
+                Some(flag) if flag.starts_with("--") => {
+                    format!("the value of {} contains a NUL byte", flag)
+                }
+                _ => "an argument contains a NUL byte".into(),
+            };
+            return Err(CompileError::InvalidInput { position, reason });
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn program(options: &PugOptions) -> PathBuf {
+    if let Some(binary) = &options.binary {
+        return binary.clone();
+    }
+    #[cfg(feature = "auto-install")]
+    if let Some(installed) = crate::install::installed() {
+        return installed.to_owned();
+    }
+    PathBuf::from(PUG_BINARY)
+}
+
+/// Treats the child closing stdin early as the end of input rather than an
+/// error; pug stops reading when it fails, and its stderr says why.
+pub(crate) fn tolerate_closed_stdin(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// The HTML pug wrote after `post_process`, or its stderr as an error.
+#[cfg_attr(not(any(feature = "tokio", feature = "async-std")), allow(dead_code))]
+pub(crate) fn interpret_output(
+    output: Output,
+    post_process: PostProcess,
+) -> Result<String, CompileError> {
+    interpret_output_with_warnings(output, post_process).map(|output| output.html)
+}
+
+/// `interpret_output`, keeping what a successful pug wrote to stderr as
+/// warnings.
+pub(crate) fn interpret_output_with_warnings(
+    output: Output,
+    post_process: PostProcess,
+) -> Result<CompileOutput, CompileError> {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(failure(output.status, &stderr, &post_process));
+    }
+    if let Some(limit) = post_process.limits.output {
+        if output.stdout.len() as u64 > limit {
+            return Err(CompileError::OutputLimit(limit));
+        }
+    }
+    let mut warnings = warnings(&strip_ansi(&stderr));
+    warnings.extend(
+        post_process
+            .unused_locals
+            .iter()
+            .map(|local| format!("local `{}` isn't used by the template", local)),
+    );
+    Ok(CompileOutput {
+        html: post_process.apply(&output.stdout)?,
+        warnings,
+    })
+}
+
+/// The error for pug exiting with `status`.
+pub(crate) fn failure(
+    status: ExitStatus,
+    stderr: &str,
+    post_process: &PostProcess,
+) -> CompileError {
+    let stderr = &*if post_process.limits.preserve_colors {
+        Cow::Borrowed(stderr)
+    } else {
+        strip_ansi(stderr)
+    };
+    if let Some(exceeded) = post_process.limits.exceeded(status, stderr) {
+        return exceeded;
+    }
+    if stderr.trim().is_empty() {
+        CompileError::PugError(format!("pug exited with {}", status))
+    } else {
+        pug_failure(post_process.locate(stderr))
+    }
+}
+
+/// The lines of stderr that aren't blank or pug-cli's `rendered <file>`
+/// progress notes.
+fn warnings(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with("rendered "))
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(feature = "minify")]
+fn minify_html(html: &[u8]) -> String {
+    let cfg = minify_html::Cfg {
+        keep_closing_tags: true,
+        keep_html_and_head_opening_tags: true,
+        ..minify_html::Cfg::default()
+    };
+    String::from_utf8_lossy(&minify_html::minify(html, &cfg)).into()
+}
+
+#[cfg(feature = "html-validate")]
+fn validate_html(html: &str, strict: bool) -> Result<(), CompileError> {
+    crate::html_validate::check(html, strict)
+}
+
+/// `PugOptions::validate_output` only exists with the feature.
+#[cfg(not(feature = "html-validate"))]
+fn validate_html(_: &str, _: bool) -> Result<(), CompileError> {
+    Ok(())
+}
+
+/// `PugOptions::minify` only exists with the feature, so this is unreachable.
+#[cfg(not(feature = "minify"))]
+fn minify_html(html: &[u8]) -> String {
+    String::from_utf8_lossy(html).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(invocation: Result<Invocation, CompileError>) -> Vec<String> {
+        invocation.unwrap().args
+    }
+
+    #[test]
+    fn caller_set_options_win() {
+        let welcome = "emails/welcome.pug";
+        let file = || PugOptions::new().stdin();
+        let string = Invocation::string("p".into(), PugOptions::new().with_path(welcome));
+        assert_eq!(vec!["--path", welcome], args(string));
+        let stdin = Invocation::file("test/hello.pug", file());
+        assert_eq!(vec!["--path", "test/hello.pug"], args(stdin));
+        let both = Invocation::file("test/hello.pug", file().with_path(welcome));
+        assert_eq!(vec!["--path", welcome], args(both));
+        assert_eq!(
+            Vec::<String>::new(),
+            args(Invocation::string("p".into(), file()))
+        );
+    }
+}
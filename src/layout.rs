@@ -0,0 +1,130 @@
+//! Rendering a fragment that can't say `extends` itself inside a layout.
+
+use crate::{evaluate_string_with_options, CompileError, Prelude, PugOptions};
+use std::path::Path;
+
+/// What the wrapper template is called, next to the layout, for resolving
+/// the `extends` and the layout's own includes.
+const WRAPPER: &str = "__render_in_layout.pug";
+
+/// Renders `fragment_source` as the contents of `block` in `layout` and
+/// returns the whole page, as if the fragment had started with
+/// `extends <layout>` and `block <block>` and been indented under it.
+///
+/// The fragment is re-indented as a whole, so nesting survives: whatever
+/// indentation all its lines share is taken off and each line goes one
+/// level under the block, with a tab if the fragment indents with tabs.
+/// Relative includes in the layout resolve from its directory, which
+/// replaces any `PugOptions::with_path`.
+///
+/// Errors in the fragment are reported at its own line numbers. A `block`
+/// the layout doesn't have fails with pug's error for it, on line 2 of the
+/// wrapper.
+pub fn render_in_layout(
+    layout: &Path,
+    block: &str,
+    fragment_source: &str,
+    mut options: PugOptions,
+) -> Result<String, CompileError> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    if block.is_empty() || !block.chars().all(is_name) {
+        return Err(CompileError::InvalidOptions(format!(
+            "{:?} isn't a block name",
+            block
+        )));
+    }
+    let name = match layout.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(CompileError::InvalidOptions(format!(
+                "{} isn't a layout file",
+                layout.display()
+            )))
+        }
+    };
+    let dir = layout.parent().unwrap_or_else(|| Path::new(""));
+    // ahead of any other preludes: pug wants `extends` first
+    let wrapper = format!("extends {}\nblock {}", name, block);
+    options.preludes.insert(0, Prelude::Source(wrapper));
+    let options = options.with_path(dir.join(WRAPPER));
+    evaluate_string_with_options(indent_under_block(fragment_source), options)
+}
+
+/// `source` with its common indentation swapped for one level more.
+fn indent_under_block(source: &str) -> String {
+    let source = source.replace("\r\n", "\n");
+    let lines: Vec<&str> = source.lines().collect();
+    let mut common: Option<&str> = None;
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indent,
+            Some(common) => {
+                let shared = common
+                    .char_indices()
+                    .zip(indent.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(indent.len()), |((at, _), _)| at);
+                &common[..shared]
+            }
+        });
+    }
+    let common = common.unwrap_or("").len();
+    let level = if lines
+        .iter()
+        .any(|line| line[common.min(line.len())..].starts_with('\t'))
+    {
+        "\t"
+    } else {
+        "  "
+    };
+    let mut indented = String::new();
+    for line in lines {
+        if !line.trim().is_empty() {
+            indented.push_str(level);
+            indented.push_str(&line[common..]);
+        }
+        indented.push('\n');
+    }
+    indented
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn reindents_as_a_whole() {
+        assert_eq!(
+            "  ul\n    li\n      a one\n\n  p two\n",
+            indent_under_block("    ul\n      li\n        a one\n\n    p two")
+        );
+        assert_eq!(
+            "\tul\n\t\tli one\n",
+            indent_under_block("ul\r\n\tli one\r\n")
+        );
+    }
+
+    #[test]
+    fn fills_the_layouts_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = dir.path().join("layout.pug");
+        let source = "html\n  body\n    main\n      block content\n        p default\n";
+        fs::write(&layout, source).unwrap();
+        let fragment = "\n  section.post\n    h1 News\n    ul\n      li one\n      li two\n";
+
+        let page = render_in_layout(&layout, "content", fragment, PugOptions::new()).unwrap();
+        assert_eq!(
+            "<html><body><main><section class=\"post\"><h1>News</h1>\
+             <ul><li>one</li><li>two</li></ul></section></main></body></html>",
+            page
+        );
+
+        let error = render_in_layout(&layout, "sidebar", fragment, PugOptions::new()).unwrap_err();
+        let details = error.details().unwrap();
+        assert_eq!(2, details.line);
+        assert!(details.message.contains("sidebar"), "{}", details.message);
+        assert!(render_in_layout(&layout, "two words", "p", PugOptions::new()).is_err());
+    }
+}
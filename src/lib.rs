@@ -1,17 +1,192 @@
 extern crate serde_json;
-use std::convert::{From, Into, TryInto};
+use std::convert::{From, Into};
 use std::error;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, prelude::*};
-use std::iter::{IntoIterator, Iterator};
-use std::path::PathBuf;
-use std::process::{Command, Output, Stdio};
+use std::io::prelude::*;
+use std::iter::IntoIterator;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+pub mod build;
+#[cfg(test)]
+extern crate self as pug_cli;
+mod command;
+mod context;
+mod doctor;
+#[cfg(feature = "macros")]
+mod embedded;
+#[cfg(feature = "encoding")]
+mod encoding;
+mod engine;
+mod executor;
+mod flavor;
+#[cfg(feature = "i18n")]
+mod fluent;
+mod fragment;
+mod front_matter;
+mod health;
+mod include;
+#[cfg(feature = "auto-install")]
+mod install;
+mod instrument;
+mod invocation;
+mod layout;
+mod limits;
+mod locale;
+mod locals_usage;
+mod mixin;
+mod newline;
+mod output_naming;
+mod pug_error;
+mod registry;
+mod reindent;
+#[cfg(feature = "remote")]
+mod remote;
+mod render;
+mod retry;
+mod sandbox;
+#[cfg(feature = "ammonia")]
+mod sanitize;
+mod schema;
+mod sha256;
+mod sidecar;
+mod stderr_mode;
+mod syntax;
+mod temp_file;
+#[cfg(test)]
+mod test_support;
+mod version;
+use build::HashNaming;
+pub use command::{process_output, PugCommand};
+pub use context::evaluate_string_in_context;
+pub use doctor::{doctor, doctor_with, Check, DoctorReport};
+#[cfg(feature = "macros")]
+pub use embedded::EmbeddedTemplates;
+#[cfg(feature = "encoding")]
+pub use encoding::OutputEncoding;
+#[cfg(feature = "encoding")]
+pub use encoding_rs;
+pub use engine::PugEngine;
+pub use executor::{Executor, ProcessExecutor};
+pub use flavor::CliFlavor;
+pub use fragment::{render_fragment, render_fragment_strict, unwrap_fragment};
+pub use front_matter::{parse_front_matter, read_front_matter};
+pub use health::{health, health_with, Health};
+pub use include::{dir_resolver, inline_includes, map_resolver};
+#[cfg(feature = "auto-install")]
+pub use install::ensure_installed;
+use instrument::Compile;
+use invocation::{interpret_output_with_warnings, tolerate_closed_stdin, Input, Invocation};
+pub use layout::render_in_layout;
+use limits::Limits;
+pub use locale::render_locales;
+pub use locals_usage::{undefined_locals, unused_locals};
+pub use mixin::render_mixin;
+pub use newline::{NewlineStyle, TrailingNewline};
+pub use output_naming::OutputNaming;
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub use pug_cli_macros::embed_templates as __embed_templates;
+#[cfg(feature = "macros")]
+pub use pug_cli_macros::include_pug;
+use retry::retry;
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub extern crate serde_json as __serde_json;
+
+/// Embeds the source of every `.pug` file under a directory, relative to
+/// `CARGO_MANIFEST_DIR`, as `EmbeddedTemplates` to render with locals at
+/// runtime: `static TEMPLATES: EmbeddedTemplates = templates!("templates");`.
+///
+/// Each is named by its path from the directory without the extension, as
+/// with `TemplateRegistry::register_dir`. The files are tracked like
+/// `include_str!`, so editing one triggers a rebuild; adding one needs the
+/// crate rebuilt. A file that isn't UTF-8, or two names that differ only in
+/// case, is a compile error.
+#[cfg(feature = "macros")]
+#[macro_export]
+macro_rules! templates {
+    ($dir:literal $(,)?) => {
+        $crate::EmbeddedTemplates::new($crate::__embed_templates!($dir))
+    };
+}
+
+/// Renders an inline template: `pug!("li.item #{name}", { "name": name })`.
+///
+/// The locals are anything `serde_json::json!` accepts; the template is passed
+/// to pug exactly as written, so raw strings keep their indentation. Expands
+/// to `evaluate_string_with_options` and returns its result.
+#[cfg(feature = "macros")]
+#[macro_export]
+macro_rules! pug {
+    ($template:expr $(,)?) => {
+        $crate::evaluate_string_with_options(
+            ::std::string::String::from($template),
+            $crate::PugOptions::new(),
+        )
+    };
+    ($template:expr, $($locals:tt)+) => {
+        $crate::evaluate_string_with_options(
+            ::std::string::String::from($template),
+            $crate::PugOptions::new().with_object($crate::__serde_json::json!($($locals)+)),
+        )
+    };
+}
+pub use pug_error::{ErrorPageOptions, PugErrorDetails, PugErrorKind, StackFrame};
+pub use registry::{Reload, TemplateRegistry};
+pub use reindent::Indent;
+#[cfg(feature = "remote")]
+pub use remote::{evaluate_url, RemoteTemplate};
+pub use render::{Render, RenderError};
+pub use retry::{RetryOn, RetryPolicy};
+#[cfg(feature = "ammonia")]
+pub use sanitize::SanitizeProfile;
+pub use schema::ValidationError;
+pub use stderr_mode::StderrMode;
+pub use syntax::{check_syntax, check_syntax_string};
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "async-std")]
+pub mod async_std;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "dom")]
+pub mod dom;
+#[cfg(feature = "email")]
+pub mod email;
+#[cfg(feature = "html-validate")]
+pub mod html_validate;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "node-api")]
+pub mod node_api;
+#[cfg(feature = "rocket")]
+pub mod rocket;
+pub mod site;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(feature = "warp")]
+pub mod warp;
+
+/// The locals a template is compiled with. `Send` and `Sync`.
+#[derive(Clone)]
 pub enum PugJsonObject {
     Json(serde_json::Value),
     Raw(String),
+    /// A JSON file.
     Path(PathBuf),
+    /// A `.js`, `.cjs` or `.mjs` module exporting the locals, which pug
+    /// loads itself. It can't be merged with other locals: not by
+    /// `PugOptions::merge`, front matter or `render_locales`.
+    JsModule(PathBuf),
 }
 
 impl From<serde_json::Value> for PugJsonObject {
@@ -38,16 +213,127 @@ impl From<&str> for PugJsonObject {
     }
 }
 
-impl Into<String> for PugJsonObject {
-    fn into(self) -> String {
-        match self {
-            PugJsonObject::Json(value) => format!("'{}'", value),
+impl From<PugJsonObject> for String {
+    fn from(object: PugJsonObject) -> String {
+        match object {
+            PugJsonObject::Json(value) => value.to_string(),
             PugJsonObject::Raw(value) => value,
-            PugJsonObject::Path(value) => String::from(value.to_string_lossy()),
+            PugJsonObject::Path(value) | PugJsonObject::JsModule(value) => {
+                String::from(value.to_string_lossy())
+            }
+        }
+    }
+}
+
+/// `PugJsonObject::sniff`.
+impl std::str::FromStr for PugJsonObject {
+    type Err = CompileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PugJsonObject::sniff(s)
+    }
+}
+
+impl PugJsonObject {
+    /// Locals from one string that may be inline JSON or a file, as a
+    /// command-line `--obj` or a config value is:
+    ///
+    /// - `raw:<locals>` is `Raw`, passed to pug unchecked, so it may be
+    ///   anything pug accepts, JavaScript object literals included.
+    /// - `@<path>` is a file, which must exist.
+    /// - Anything else starting with `{` or `[`, once trimmed, is parsed as
+    ///   JSON, and a parse error is the error; it's never taken for a path.
+    /// - Anything else is a file, which must exist.
+    ///
+    /// Files ending in `.js`, `.cjs` or `.mjs` are `JsModule`s and the rest
+    /// `Path`s. Failures are `CompileError::Locals`, suggesting the prefixes
+    /// when the string could have been meant either way.
+    pub fn sniff(s: &str) -> Result<PugJsonObject, CompileError> {
+        if let Some(raw) = s.strip_prefix("raw:") {
+            return Ok(PugJsonObject::Raw(raw.to_owned()));
+        }
+        if let Some(path) = s.strip_prefix('@') {
+            return file_locals(path)
+                .ok_or_else(|| CompileError::Locals(format!("there's no file at {}", path)));
+        }
+        if s.trim_start().starts_with(['{', '[']) {
+            return serde_json::from_str(s)
+                .map(PugJsonObject::Json)
+                .map_err(|e| CompileError::Locals(format!("not valid JSON: {}", e)));
+        }
+        file_locals(s).ok_or_else(|| {
+            CompileError::Locals(format!(
+                "{:?} is neither JSON, which starts with `{{` or `[`, nor an existing file; \
+                 write `raw:{}` to pass it to pug as it is or `@{}` for a path",
+                s, s, s
+            ))
+        })
+    }
+
+    /// What `Debug` and `Display` show instead of inline locals, which often
+    /// hold user data. `None` for files, whose paths are shown as they are.
+    fn redacted(&self) -> Option<String> {
+        match self {
+            PugJsonObject::Json(value) => {
+                let bytes = value.to_string().len();
+                Some(match value.as_object() {
+                    Some(object) => format!(
+                        "<redacted, {} bytes, {} top-level keys>",
+                        bytes,
+                        object.len()
+                    ),
+                    None => format!("<redacted, {} bytes>", bytes),
+                })
+            }
+            PugJsonObject::Raw(raw) => Some(format!("<redacted, {} bytes>", raw.len())),
+            PugJsonObject::Path(_) | PugJsonObject::JsModule(_) => None,
         }
     }
+
+    fn fmt_debug(&self, f: &mut fmt::Formatter<'_>, redact: bool) -> fmt::Result {
+        let redacted = self.redacted().filter(|_| redact);
+        let mut tuple = match self {
+            PugJsonObject::Json(_) => f.debug_tuple("Json"),
+            PugJsonObject::Raw(_) => f.debug_tuple("Raw"),
+            PugJsonObject::Path(_) => f.debug_tuple("Path"),
+            PugJsonObject::JsModule(_) => f.debug_tuple("JsModule"),
+        };
+        match (redacted, self) {
+            (Some(redacted), _) => tuple.field(&format_args!("{}", redacted)),
+            (None, PugJsonObject::Json(value)) => tuple.field(value),
+            (None, PugJsonObject::Raw(raw)) => tuple.field(raw),
+            (None, PugJsonObject::Path(path) | PugJsonObject::JsModule(path)) => tuple.field(path),
+        };
+        tuple.finish()
+    }
+}
+
+/// Summarizes inline locals rather than printing them.
+impl fmt::Debug for PugJsonObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_debug(f, true)
+    }
+}
+
+/// The locals in the file at `path`, if there is one.
+fn file_locals(path: &str) -> Option<PugJsonObject> {
+    let path = PathBuf::from(path);
+    if !path.is_file() {
+        return None;
+    }
+    let module = path
+        .extension()
+        .is_some_and(|ext| ext == "js" || ext == "cjs" || ext == "mjs");
+    Some(if module {
+        PugJsonObject::JsModule(path)
+    } else {
+        PugJsonObject::Path(path)
+    })
 }
 
+/// How to compile a template. `Send` and `Sync`, so one set of options can be
+/// built once and cloned from any thread.
+#[derive(Clone)]
 pub struct PugOptions {
     version: bool,
     object: Option<PugJsonObject>,
@@ -58,6 +344,147 @@ pub struct PugOptions {
     stdin: bool,
     pretty: bool,
     doctype: Option<String>,
+    binary: Option<PathBuf>,
+    timeout: Option<Duration>,
+    log_locals: bool,
+    minify: bool,
+    newline: Option<NewlineStyle>,
+    trailing_newline: TrailingNewline,
+    validate_output: bool,
+    strict_validation: bool,
+    #[cfg(feature = "ammonia")]
+    sanitize: Option<SanitizeProfile>,
+    #[cfg(feature = "node-api")]
+    filters: Vec<(String, PathBuf)>,
+    preludes: Vec<Prelude>,
+    flavor: Option<CliFlavor>,
+    source_name: Option<String>,
+    reject_empty_input: bool,
+    normalize_newlines: bool,
+    front_matter: bool,
+    locals_via_file: bool,
+    warn_unused_locals: bool,
+    sidecar_locals: bool,
+    reindent: Option<Indent>,
+    output_naming: Option<OutputNaming>,
+    hashed_output: Option<HashNaming>,
+    #[cfg(feature = "compress")]
+    precompress: Option<build::Precompress>,
+    assets: Option<build::Assets>,
+    version_check: Option<version::VersionCheck>,
+    #[cfg(feature = "encoding")]
+    output_encoding: Option<encoding::Encode>,
+    #[cfg(feature = "schema")]
+    locals_schema: Option<schema::LocalsSchema>,
+    #[cfg(feature = "i18n")]
+    translations: Option<(PathBuf, String)>,
+    #[cfg(feature = "i18n")]
+    translations_key: Option<String>,
+    executor: Option<Arc<dyn Executor>>,
+    locals_for: Option<LocalsFor>,
+    retry: Option<RetryPolicy>,
+    stderr_mode: Option<StderrMode>,
+    stderr_log: Option<PathBuf>,
+    limits: Limits,
+    sandbox: bool,
+    /// The `Flag`s set explicitly, on or off, one bit each.
+    flags_set: u16,
+    /// Why `merge` couldn't combine these, reported by `validate`.
+    merge_error: Option<String>,
+}
+
+/// `PugOptions::locals_for`'s callback.
+type LocalsFor = Arc<dyn Fn(&Path) -> Result<PugJsonObject, CompileError> + Send + Sync>;
+
+/// A preset for `PugOptions::profile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// `PugOptions::development`.
+    Development,
+    /// `PugOptions::production`.
+    Production,
+}
+
+/// A yes-or-no option, for `PugOptions::set_flag`. Each is also the
+/// builder method of the same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Pretty,
+    NoDebug,
+    Client,
+    LogLocals,
+    RejectEmptyInput,
+    NormalizeNewlines,
+    FrontMatter,
+    LocalsViaFile,
+    WarnUnusedLocals,
+    SidecarLocals,
+}
+
+impl Flag {
+    const ALL: [Flag; 10] = [
+        Flag::Pretty,
+        Flag::NoDebug,
+        Flag::Client,
+        Flag::LogLocals,
+        Flag::RejectEmptyInput,
+        Flag::NormalizeNewlines,
+        Flag::FrontMatter,
+        Flag::LocalsViaFile,
+        Flag::WarnUnusedLocals,
+        Flag::SidecarLocals,
+    ];
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// `overrides` deep-merged over `base`, or `overrides` if either isn't JSON.
+/// A JS module can't be merged at all, which is the error.
+fn merge_locals(base: PugJsonObject, overrides: PugJsonObject) -> (PugJsonObject, Option<String>) {
+    let module = [&base, &overrides].iter().find_map(|object| match object {
+        PugJsonObject::JsModule(module) => Some(locale::unmergeable(module)),
+        _ => None,
+    });
+    if module.is_some() {
+        return (overrides, module);
+    }
+    match (locale::to_json(&base), locale::to_json(&overrides)) {
+        (Ok(mut base), Ok(overrides)) => {
+            locale::merge(&mut base, overrides);
+            (PugJsonObject::Json(base), None)
+        }
+        _ => (overrides, None),
+    }
+}
+
+/// `PugJsonObject::JsModule` has to exist and look like a module.
+fn check_js_module(module: &std::path::Path) -> Result<(), CompileError> {
+    let is_module = module
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ["js", "cjs", "mjs"].contains(&extension));
+    if !is_module {
+        return Err(CompileError::InvalidOptions(format!(
+            "{} isn't a .js, .cjs or .mjs module",
+            module.display()
+        )));
+    }
+    if !module.is_file() {
+        return Err(CompileError::InvalidOptions(format!(
+            "the locals module {} doesn't exist",
+            module.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Pug source put in front of string templates.
+#[derive(Clone, Debug)]
+enum Prelude {
+    Source(String),
+    File(PathBuf),
 }
 
 impl PugOptions {
@@ -72,6 +499,89 @@ impl PugOptions {
             stdin: false,
             pretty: false,
             doctype: None,
+            binary: None,
+            timeout: None,
+            log_locals: false,
+            minify: false,
+            newline: None,
+            trailing_newline: TrailingNewline::Keep,
+            validate_output: false,
+            strict_validation: false,
+            #[cfg(feature = "ammonia")]
+            sanitize: None,
+            #[cfg(feature = "node-api")]
+            filters: Vec::new(),
+            preludes: Vec::new(),
+            flavor: None,
+            source_name: None,
+            reject_empty_input: false,
+            normalize_newlines: true,
+            front_matter: false,
+            locals_via_file: false,
+            warn_unused_locals: false,
+            sidecar_locals: false,
+            reindent: None,
+            output_naming: None,
+            hashed_output: None,
+            #[cfg(feature = "compress")]
+            precompress: None,
+            assets: None,
+            version_check: None,
+            #[cfg(feature = "encoding")]
+            output_encoding: None,
+            #[cfg(feature = "schema")]
+            locals_schema: None,
+            #[cfg(feature = "i18n")]
+            translations: None,
+            #[cfg(feature = "i18n")]
+            translations_key: None,
+            executor: None,
+            locals_for: None,
+            retry: None,
+            stderr_mode: None,
+            stderr_log: None,
+            limits: Limits::default(),
+            sandbox: false,
+            flags_set: 0,
+            merge_error: None,
+        }
+    }
+
+    /// For working on templates: `pretty` on and `no_debug` off, so output
+    /// is readable and errors point at template lines. Pug's arguments are
+    /// `--pretty`.
+    ///
+    /// Both flags count as set, so merging these over other options, or
+    /// other options over these, replaces them as any setter would.
+    pub fn development() -> Self {
+        PugOptions::profile(Profile::Development)
+    }
+
+    /// For serving: `no_debug` on, `pretty` off and, with the `minify`
+    /// feature, `minify` on. Pug's arguments are `--no-debug`.
+    ///
+    /// As with `development`, both flags count as set. `minify` can't be
+    /// turned off again, nor combined with `pretty` or `reindent`, so start
+    /// from `development` for those.
+    pub fn production() -> Self {
+        PugOptions::profile(Profile::Production)
+    }
+
+    /// `development` or `production`, picked at runtime.
+    pub fn profile(profile: Profile) -> Self {
+        let options = PugOptions::new();
+        match profile {
+            Profile::Development => options
+                .set_flag(Flag::Pretty, true)
+                .set_flag(Flag::NoDebug, false),
+            Profile::Production => {
+                let options = options
+                    .set_flag(Flag::Pretty, false)
+                    .set_flag(Flag::NoDebug, true);
+                #[cfg(feature = "minify")]
+                let options = options.minify();
+                options
+            }
         }
     }
 
@@ -85,6 +595,8 @@ impl PugOptions {
         self
     }
 
+    /// The file name pug resolves includes against and names in errors.
+    /// Compiling a file sets it to that file unless it's already set.
     pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
         self.path = Some(path.into());
         self
@@ -95,14 +607,12 @@ impl PugOptions {
         self
     }
 
-    pub fn no_debug(mut self) -> Self {
-        self.no_debug = true;
-        self
+    pub fn no_debug(self) -> Self {
+        self.set_flag(Flag::NoDebug, true)
     }
 
-    pub fn client(mut self) -> Self {
-        self.client = true;
-        self
+    pub fn client(self) -> Self {
+        self.set_flag(Flag::Client, true)
     }
 
     pub fn stdin(mut self) -> Self {
@@ -110,15 +620,677 @@ impl PugOptions {
         self
     }
 
-    pub fn pretty(mut self) -> Self {
-        self.pretty = true;
-        self
+    pub fn pretty(self) -> Self {
+        self.set_flag(Flag::Pretty, true)
     }
 
     pub fn doctype(mut self, dt: String) -> Self {
         self.doctype = Some(dt);
         self
     }
+
+    /// Runs the given executable instead of the `pug` found on `PATH`.
+    pub fn binary(mut self, binary: impl Into<PathBuf>) -> Self {
+        self.binary = Some(binary.into());
+        self
+    }
+
+    /// Kills pug and fails with `CompileError::Timeout` if it runs longer than
+    /// this.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Lets the `log` feature include the value of `--obj` when it logs the
+    /// command line. Locals are redacted by default since they often hold
+    /// user data.
+    pub fn log_locals(self) -> Self {
+        self.set_flag(Flag::LogLocals, true)
+    }
+
+    /// Minifies the compiled HTML before it's returned or written: whitespace
+    /// collapsed, comments stripped and attribute quotes dropped where that's
+    /// safe. `pre`, `textarea`, `script` and `style` content is left as is.
+    /// Can't be combined with `pretty`.
+    #[cfg(feature = "minify")]
+    pub fn minify(mut self) -> Self {
+        self.minify = true;
+        self
+    }
+
+    /// Converts the line endings of the compiled HTML, outside `pre` elements.
+    /// Output is left as pug wrote it unless this is set.
+    pub fn newline(mut self, style: NewlineStyle) -> Self {
+        self.newline = Some(style);
+        self
+    }
+
+    /// Keeps, ensures or trims a newline at the end of the compiled HTML, for
+    /// every way of compiling and the files written from it. `Keep` is the
+    /// default.
+    pub fn trailing_newline(mut self, trailing: TrailingNewline) -> Self {
+        self.trailing_newline = trailing;
+        self
+    }
+
+    /// Parses the compiled HTML and warns, through the `log` and `tracing`
+    /// features, about any `html_validate::ValidationIssue` in it. Use
+    /// `html_validate::evaluate_validated` to get the issues back with the
+    /// HTML.
+    #[cfg(feature = "html-validate")]
+    pub fn validate_output(mut self) -> Self {
+        self.validate_output = true;
+        self
+    }
+
+    /// Like `validate_output`, but output with issues fails with
+    /// `CompileError::PostProcessing` listing them.
+    #[cfg(feature = "html-validate")]
+    pub fn strict_validation(mut self) -> Self {
+        self.validate_output = true;
+        self.strict_validation = true;
+        self
+    }
+
+    /// Runs the compiled HTML through ammonia, for templates that interpolate
+    /// untrusted locals with `!{}`. A whole document keeps its doctype and
+    /// `<head>`; only the body is sanitized.
+    #[cfg(feature = "ammonia")]
+    pub fn sanitize(mut self, profile: SanitizeProfile) -> Self {
+        self.sanitize = Some(profile);
+        self
+    }
+
+    /// Registers the JavaScript file `js_file` as the filter `:name`, for
+    /// `node_api` to `require` before compiling. The file exports the
+    /// filter function, `module.exports = (text, options) => ...`.
+    ///
+    /// pug's CLI can't load filters, so options with any fail `validate`
+    /// anywhere but `node_api`. A file that doesn't exist fails with
+    /// `CompileError::FilterFileMissing`, one that throws or doesn't export a
+    /// function with `CompileError::FilterRegistration`.
+    #[cfg(feature = "node-api")]
+    pub fn filter(mut self, name: &str, js_file: PathBuf) -> Self {
+        self.filters.push((name.to_owned(), js_file));
+        self
+    }
+
+    /// Puts `source` in front of every template compiled from a string, so
+    /// those can use mixins they have no file to `include` from. Preludes are
+    /// joined in the order they're added, each on lines of its own.
+    ///
+    /// Line numbers in errors are shifted back to count from the template's
+    /// first line; an error inside a prelude keeps pug's numbering.
+    pub fn prelude(mut self, source: impl Into<String>) -> Self {
+        self.preludes.push(Prelude::Source(source.into()));
+        self
+    }
+
+    /// Like `prelude`, reading the source from `path` at compile time.
+    pub fn prelude_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.preludes.push(Prelude::File(path.into()));
+        self
+    }
+
+    /// Names the template in errors, in place of pug's `Pug` for templates
+    /// read from stdin. Unlike `with_path` it isn't passed to pug, so it has
+    /// no effect on how includes resolve.
+    pub fn source_name(mut self, name: impl Into<String>) -> Self {
+        self.source_name = Some(name.into());
+        self
+    }
+
+    /// Fails templates that are empty or only whitespace with
+    /// `CompileError::EmptyInput` instead of compiling them. Otherwise they
+    /// compile to an empty string.
+    pub fn reject_empty_input(self) -> Self {
+        self.set_flag(Flag::RejectEmptyInput, true)
+    }
+
+    /// Whether string templates and preludes have `\r\n` and lone `\r`
+    /// turned into `\n` before pug sees them, which is the default. Pass
+    /// `false` to send them byte for byte. This only affects the input; the
+    /// output follows `newline` and `trailing_newline`.
+    pub fn normalize_newlines(self, normalize: bool) -> Self {
+        self.set_flag(Flag::NormalizeNewlines, normalize)
+    }
+
+    /// Takes the front matter (see `parse_front_matter`) off file templates
+    /// and merges it over the locals, so each page can carry its own title
+    /// or layout. Pages without any compile as usual; string templates are
+    /// left alone.
+    pub fn front_matter(self) -> Self {
+        self.set_flag(Flag::FrontMatter, true)
+    }
+
+    /// Hands pug its locals in a temp file instead of on the command line.
+    ///
+    /// Any user on the machine can read a process's command line, with `ps`
+    /// or from `/proc/<pid>/cmdline`, so locals passed as `--obj <json>` are
+    /// visible to all of them while pug runs. With this, `--obj` is the path
+    /// of a file only the current user can read (`0600` on Unix), which is
+    /// overwritten and removed as soon as pug exits. It doesn't keep the
+    /// locals from root or from the same user, and a process killed before
+    /// it can clean up leaves the file behind. Locals given as a path or
+    /// `PugJsonObject::JsModule` are already files and are passed as they are.
+    pub fn locals_via_file(self) -> Self {
+        self.set_flag(Flag::LocalsViaFile, true)
+    }
+
+    /// Adds a warning to `evaluate_with_warnings` and
+    /// `evaluate_string_with_warnings` for each top-level local the template
+    /// never mentions, found with `unused_locals`.
+    pub fn warn_unused_locals(self) -> Self {
+        self.set_flag(Flag::WarnUnusedLocals, true)
+    }
+
+    /// Merges each file template's sidecar, `page.json` (or `page.yaml`,
+    /// `page.yml`) next to `page.pug`, over the locals for that page only,
+    /// after `locals_for` and before front matter. Pages without one compile
+    /// as usual; one that doesn't parse, or isn't an object, fails its page
+    /// with `CompileError::Sidecar`.
+    pub fn sidecar_locals(self, sidecars: bool) -> Self {
+        self.set_flag(Flag::SidecarLocals, sidecars)
+    }
+
+    /// Turns `flag` on or off. Unlike leaving it alone, this counts as
+    /// setting it when `PugEngine` merges per-render options over its
+    /// defaults, so `set_flag(Flag::Pretty, false)` turns `pretty` back off.
+    pub fn set_flag(mut self, flag: Flag, on: bool) -> Self {
+        *self.flag_mut(flag) = on;
+        self.flags_set |= flag.bit();
+        self
+    }
+
+    /// These options with every option `overrides` sets taking the place of
+    /// theirs, and the rest kept. What counts as set:
+    ///
+    /// | option | set in `overrides` when | merged |
+    /// |---|---|---|
+    /// | a `Flag` (`pretty`, `no_debug`, ...) | its builder or `set_flag` was called, even to turn it off | replaced |
+    /// | `minify`, `validate_output`, `strict_validation`, `version`, `stdin`, `sandbox`, `preserve_error_colors` | turned on | turned on |
+    /// | locals (`with_object`) | given | deep-merged, see below |
+    /// | `preludes`, `filter`s | any were added | replaced, not appended |
+    /// | `trailing_newline` | not `Keep` | replaced |
+    /// | `memory_limit`, `cpu_time_limit`, `max_output_bytes` | given | replaced, each on its own |
+    /// | anything else | given | replaced |
+    ///
+    /// Locals merge the way `render_locales` merges them: objects both sides
+    /// have are merged key by key, and any other value in `overrides` wins.
+    /// Locals that aren't JSON (a missing file, say) don't merge; the
+    /// override's replace the base's. A `PugJsonObject::JsModule` on either
+    /// side makes the merged options fail `validate`.
+    pub fn merge(self, mut overrides: PugOptions) -> Self {
+        let mut merged = self;
+        for flag in Flag::ALL {
+            if overrides.flags_set & flag.bit() != 0 {
+                merged = merged.set_flag(flag, *overrides.flag_mut(flag));
+            }
+        }
+        let PugOptions {
+            version,
+            object,
+            path,
+            out_dir,
+            no_debug: _,
+            client: _,
+            stdin,
+            pretty: _,
+            doctype,
+            binary,
+            timeout,
+            log_locals: _,
+            minify,
+            newline,
+            trailing_newline,
+            validate_output,
+            strict_validation,
+            #[cfg(feature = "ammonia")]
+            sanitize,
+            #[cfg(feature = "node-api")]
+            filters,
+            preludes,
+            flavor,
+            source_name,
+            reject_empty_input: _,
+            normalize_newlines: _,
+            front_matter: _,
+            locals_via_file: _,
+            warn_unused_locals: _,
+            sidecar_locals: _,
+            reindent,
+            output_naming,
+            hashed_output,
+            #[cfg(feature = "compress")]
+            precompress,
+            assets,
+            version_check,
+            #[cfg(feature = "encoding")]
+            output_encoding,
+            #[cfg(feature = "schema")]
+            locals_schema,
+            #[cfg(feature = "i18n")]
+            translations,
+            #[cfg(feature = "i18n")]
+            translations_key,
+            executor,
+            locals_for,
+            retry,
+            stderr_mode,
+            stderr_log,
+            limits,
+            sandbox,
+            flags_set: _,
+            merge_error,
+        } = overrides;
+        fn or<T>(set: Option<T>, default: &mut Option<T>) {
+            if set.is_some() {
+                *default = set;
+            }
+        }
+        merged.version |= version;
+        merged.stdin |= stdin;
+        merged.minify |= minify;
+        merged.sandbox |= sandbox;
+        merged.validate_output |= validate_output;
+        merged.strict_validation |= strict_validation;
+        merged.object = match (merged.object.take(), object) {
+            (Some(base), Some(over)) => {
+                let (object, error) = merge_locals(base, over);
+                merged.merge_error = merged.merge_error.or(error);
+                Some(object)
+            }
+            (base, over) => over.or(base),
+        };
+        merged.merge_error = merged.merge_error.or(merge_error);
+        or(path, &mut merged.path);
+        or(out_dir, &mut merged.out_dir);
+        or(doctype, &mut merged.doctype);
+        or(binary, &mut merged.binary);
+        or(timeout, &mut merged.timeout);
+        or(newline, &mut merged.newline);
+        #[cfg(feature = "ammonia")]
+        or(sanitize, &mut merged.sanitize);
+        or(flavor, &mut merged.flavor);
+        or(source_name, &mut merged.source_name);
+        or(reindent, &mut merged.reindent);
+        or(output_naming, &mut merged.output_naming);
+        or(hashed_output, &mut merged.hashed_output);
+        #[cfg(feature = "compress")]
+        or(precompress, &mut merged.precompress);
+        or(assets, &mut merged.assets);
+        or(version_check, &mut merged.version_check);
+        #[cfg(feature = "encoding")]
+        or(output_encoding, &mut merged.output_encoding);
+        #[cfg(feature = "schema")]
+        or(locals_schema, &mut merged.locals_schema);
+        #[cfg(feature = "i18n")]
+        or(translations, &mut merged.translations);
+        #[cfg(feature = "i18n")]
+        or(translations_key, &mut merged.translations_key);
+        or(executor, &mut merged.executor);
+        or(locals_for, &mut merged.locals_for);
+        or(retry, &mut merged.retry);
+        or(stderr_mode, &mut merged.stderr_mode);
+        or(stderr_log, &mut merged.stderr_log);
+        or(limits.memory, &mut merged.limits.memory);
+        or(limits.cpu_time, &mut merged.limits.cpu_time);
+        or(limits.output, &mut merged.limits.output);
+        merged.limits.preserve_colors |= limits.preserve_colors;
+        if trailing_newline != PugOptions::new().trailing_newline {
+            merged.trailing_newline = trailing_newline;
+        }
+        if !preludes.is_empty() {
+            merged.preludes = preludes;
+        }
+        #[cfg(feature = "node-api")]
+        if !filters.is_empty() {
+            merged.filters = filters;
+        }
+        merged
+    }
+
+    fn flag_mut(&mut self, flag: Flag) -> &mut bool {
+        match flag {
+            Flag::Pretty => &mut self.pretty,
+            Flag::NoDebug => &mut self.no_debug,
+            Flag::Client => &mut self.client,
+            Flag::LogLocals => &mut self.log_locals,
+            Flag::RejectEmptyInput => &mut self.reject_empty_input,
+            Flag::NormalizeNewlines => &mut self.normalize_newlines,
+            Flag::FrontMatter => &mut self.front_matter,
+            Flag::LocalsViaFile => &mut self.locals_via_file,
+            Flag::WarnUnusedLocals => &mut self.warn_unused_locals,
+            Flag::SidecarLocals => &mut self.sidecar_locals,
+        }
+    }
+
+    /// Re-indents the output with `indent` per level, whether or not
+    /// `pretty` is set; see `Indent`. Unlike `pretty` this is done here, not
+    /// by pug, so it works with CLIs that dropped `--pretty` too.
+    pub fn reindent(mut self, indent: Indent) -> Self {
+        self.reindent = Some(indent);
+        self
+    }
+
+    /// Where `build::compile_dir` and `tokio::watch_stream` write each
+    /// page, instead of its path with `.html`; see `OutputNaming`.
+    pub fn output_naming(mut self, naming: OutputNaming) -> Self {
+        self.output_naming = Some(naming);
+        self
+    }
+
+    /// Has `build::compile_dir` give each page a content-hashed name and
+    /// write `manifest.json`; see `HashNaming`.
+    pub fn hashed_output(mut self, naming: HashNaming) -> Self {
+        self.hashed_output = Some(naming);
+        self
+    }
+
+    /// Has `build::compile_dir` and `build::compile_batch` write a
+    /// compressed copy of each page in every encoding given, `home.html.gz`
+    /// next to `home.html`, listed in `BuildOutput::compressed` and
+    /// `BatchFile::compressed`. Pages under `precompress_min_size` are left
+    /// uncompressed.
+    #[cfg(feature = "compress")]
+    pub fn precompress(mut self, encodings: &[build::Compression]) -> Self {
+        self.precompress
+            .get_or_insert_with(Default::default)
+            .encodings = encodings.to_vec();
+        self
+    }
+
+    /// The smallest page `precompress` compresses, 1 KiB unless set.
+    #[cfg(feature = "compress")]
+    pub fn precompress_min_size(mut self, bytes: u64) -> Self {
+        self.precompress
+            .get_or_insert_with(Default::default)
+            .min_size = bytes;
+        self
+    }
+
+    /// Has `build::compile_dir` copy the static files under the source
+    /// directory that `assets` picks to the same place in the output
+    /// directory, listed in `BuildOutput::assets`.
+    pub fn assets(mut self, assets: build::Assets) -> Self {
+        self.assets = Some(assets);
+        self
+    }
+
+    /// Fails every compile with `CompileError::VersionMismatch` unless the
+    /// pug it would run satisfies `requirement`, a Cargo-style range such as
+    /// `">=3, <4"`, `"^3.0"` or `"3.*"`, read by `semver::VersionReq`. The
+    /// binary is asked its `--version` once, as `health` does, and the
+    /// answer is remembered per binary.
+    pub fn require_version(mut self, requirement: &str) -> Self {
+        self.version_check
+            .get_or_insert_with(Default::default)
+            .requirement = Some(requirement.to_owned());
+        self
+    }
+
+    /// Lets `require_version` pass a pug whose `--version` doesn't say
+    /// which version it is, for custom builds. A known version outside the
+    /// range still fails.
+    pub fn allow_unknown_version(mut self) -> Self {
+        self.version_check
+            .get_or_insert_with(Default::default)
+            .allow_unknown = true;
+        self
+    }
+
+    /// Has pages written to files as `encoding` rather than UTF-8: by
+    /// `evaluate_to_file`, `build::compile_dir`, `build::compile_batch` and
+    /// the watchers. Returned HTML, and sitemaps, stay UTF-8. Unless pages
+    /// are still plain UTF-8, `compile_dir`'s constants become `&[u8]`.
+    #[cfg(feature = "encoding")]
+    pub fn output_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.output_encoding
+            .get_or_insert_with(Default::default)
+            .encoding = encoding;
+        self
+    }
+
+    /// Fails a write with `CompileError::Unencodable` when the page has a
+    /// character the `output_encoding` has no bytes for, instead of writing
+    /// it as a character reference.
+    #[cfg(feature = "encoding")]
+    pub fn strict_encoding(mut self) -> Self {
+        self.output_encoding
+            .get_or_insert_with(Default::default)
+            .strict = true;
+        self
+    }
+
+    /// Checks the locals against the JSON Schema `schema` before each
+    /// render, failing with `CompileError::LocalsValidation` listing what
+    /// doesn't conform instead of running pug. The locals checked are the
+    /// ones pug would get, after `merge` and front matter.
+    #[cfg(feature = "schema")]
+    pub fn locals_schema(mut self, schema: serde_json::Value) -> Self {
+        self.locals_schema = Some(schema::LocalsSchema::Inline(schema));
+        self
+    }
+
+    /// Like `locals_schema`, reading the schema from `path` at each render.
+    #[cfg(feature = "schema")]
+    pub fn locals_schema_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.locals_schema = Some(schema::LocalsSchema::File(path.into()));
+        self
+    }
+
+    /// Adds the Fluent translations for `locale` in `dir` to the locals,
+    /// under `t` unless `translations_key` says otherwise, so templates can
+    /// write `#{t.nav.home}` for the message `nav-home`.
+    ///
+    /// The catalog is `<dir>/<locale>.ftl` plus every `.ftl` file in
+    /// `<dir>/<locale>/`, falling back from `fr-CA` to `fr` for messages it
+    /// lacks. It's read at each render; a locale with no files or a file
+    /// that doesn't parse fails with `CompileError::Translations` before pug
+    /// runs. Variables like `{ $count }` can't be filled in ahead of the
+    /// render and are left as written.
+    #[cfg(feature = "i18n")]
+    pub fn translations_fluent(mut self, dir: &std::path::Path, locale: &str) -> Self {
+        self.translations = Some((dir.to_owned(), locale.to_owned()));
+        self
+    }
+
+    /// The local `translations_fluent` puts the messages under, instead of `t`.
+    /// Messages are merged over whatever the locals already have there.
+    #[cfg(feature = "i18n")]
+    pub fn translations_key(mut self, key: &str) -> Self {
+        self.translations_key = Some(key.to_owned());
+        self
+    }
+
+    /// Calls `locals_for` with each template file compiled with these
+    /// options, `compile_batch`'s and `compile_dir`'s included, and merges
+    /// what it returns over the other locals the way `merge` does, front
+    /// matter going over both. It's called from several threads at once in
+    /// parallel builds; an error fails that one file's compile.
+    pub fn locals_for<F>(mut self, locals_for: F) -> Self
+    where
+        F: Fn(&Path) -> Result<PugJsonObject, CompileError> + Send + Sync + 'static,
+    {
+        self.locals_for = Some(Arc::new(locals_for));
+        self
+    }
+
+    /// Compiles with `executor` instead of spawning pug; see `Executor`.
+    pub fn executor(mut self, executor: impl Executor + 'static) -> Self {
+        self.executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// Retries starting pug (or running the `executor`) when it fails the way
+    /// `policy` says is transient. Compile errors are never retried.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Caps the memory pug may use at about `bytes`, failing the compile
+    /// with `CompileError::MemoryLimit` instead of letting a runaway template
+    /// take the machine down.
+    ///
+    /// Node's heap is capped with `--max-old-space-size`, added to any
+    /// `NODE_OPTIONS` already set. On Unix the address space is limited too,
+    /// with room for what V8 reserves up front, so it stops native memory
+    /// as well; on Windows only the heap cap applies. Executors aren't
+    /// limited.
+    pub fn memory_limit(mut self, bytes: u64) -> Self {
+        self.limits.memory = Some(bytes);
+        self
+    }
+
+    /// Caps the CPU time pug may use, failing the compile with
+    /// `CompileError::CpuTimeLimit` when it runs out. Unlike `timeout` this
+    /// counts only time spent computing, rounded up to whole seconds.
+    ///
+    /// Enforced with `RLIMIT_CPU` and so only on Unix; elsewhere it's
+    /// ignored, and `timeout` is the way to stop a template that never
+    /// finishes. Executors aren't limited.
+    pub fn cpu_time_limit(mut self, limit: Duration) -> Self {
+        self.limits.cpu_time = Some(limit);
+        self
+    }
+
+    /// Kills pug and fails with `CompileError::OutputLimit` once it has
+    /// written more than `bytes` of HTML, or of errors. Output from executors
+    /// is checked once they've returned instead. The async writer functions
+    /// collect the page rather than stream it, so none of one over the limit
+    /// is written.
+    pub fn max_output_bytes(mut self, bytes: u64) -> Self {
+        self.limits.output = Some(bytes);
+        self
+    }
+
+    /// Compiles templates from people who aren't trusted with the machine:
+    ///
+    /// - `include` and `extends` fail with `CompileError::Sandbox` before
+    ///   pug runs, so a template can't read other files;
+    /// - `timeout`, `cpu_time_limit`, `memory_limit` and `max_output_bytes`
+    ///   default to 5 seconds, 2 seconds, 256 MiB and 1 MiB where they
+    ///   aren't set;
+    /// - pug runs with an empty environment apart from `PATH` and the
+    ///   variables that turn its colors off.
+    ///
+    /// `PugJsonObject::JsModule` locals and `node_api`'s `basedir` can't be
+    /// combined with it. Code in the template (`- ...`, `= ...`) still runs
+    /// as JavaScript inside node: the sandbox bounds what it costs and what
+    /// it can see of this process, not what node lets it do.
+    pub fn sandbox(mut self) -> Self {
+        self.sandbox = true;
+        self
+    }
+
+    /// Lets pug color its output. By default it runs with `FORCE_COLOR=0` and
+    /// `NO_COLOR=1`, and any ANSI escapes it prints anyway are taken out of
+    /// `CompileError::PugError` and warnings; with this they're kept, for
+    /// passing pug's errors through to a terminal. `CompileError::details`
+    /// reads past them either way.
+    pub fn preserve_error_colors(mut self) -> Self {
+        self.limits.preserve_colors = true;
+        self
+    }
+
+    /// Where pug's stderr goes; `StderrMode::Capture` unless this is set.
+    /// `Inherit` and `Tee` show its progress and warnings live, for command
+    /// line tools. Executors and `build::render_files` always capture.
+    pub fn stderr_mode(mut self, mode: StderrMode) -> Self {
+        self.stderr_mode = Some(mode);
+        self
+    }
+
+    /// Appends what pug writes to stderr to the file at `path`, creating it
+    /// and its directories if need be, under a header with the time, the
+    /// template and the exit status, once per compile. Errors and warnings
+    /// are still worked out from it as usual. Compiles running in parallel
+    /// each write their block whole. Nothing is logged with
+    /// `StderrMode::Inherit`, and `build::render_files` isn't logged.
+    pub fn stderr_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stderr_log = Some(path.into());
+        self
+    }
+
+    /// Spells flags the way `flavor` does instead of detecting it. Without
+    /// this, a binary `health` has already probed is run as the flavor it
+    /// reported, and any other as `CliFlavor::PugCli`.
+    pub fn flavor(mut self, flavor: CliFlavor) -> Self {
+        self.flavor = Some(flavor);
+        self
+    }
+
+    /// Checks for options that contradict each other. Every compile runs this
+    /// first and fails with `CompileError::InvalidOptions` rather than
+    /// spawning pug. Options the CLI flavor lacks are rejected too.
+    pub fn validate(&self) -> Result<(), CompileError> {
+        if self.minify && self.pretty {
+            return Err(CompileError::InvalidOptions(
+                "minify and pretty can't both be set".into(),
+            ));
+        }
+        if self.minify && self.reindent.is_some() {
+            return Err(CompileError::InvalidOptions(
+                "minify and reindent can't both be set".into(),
+            ));
+        }
+        if self.sandbox {
+            sandbox::check_options(self)?;
+        }
+        if let Some(PugJsonObject::JsModule(module)) = &self.object {
+            check_js_module(module)?;
+        }
+        // pug reads the file itself, and would only say it couldn't
+        if let Some(PugJsonObject::Path(path)) = &self.object {
+            std::fs::metadata(path).map_err(CompileError::io_at("read locals", path))?;
+        }
+        if let Some(reason) = &self.merge_error {
+            return Err(CompileError::InvalidOptions(reason.clone()));
+        }
+        #[cfg(feature = "node-api")]
+        if let Some((name, _)) = self.filters.first() {
+            return Err(CompileError::InvalidOptions(format!(
+                "pug's CLI can't load the filter :{}; render with node_api",
+                name
+            )));
+        }
+        let flavor = self.flavor.unwrap_or_default();
+        for (set, flag) in [(self.pretty, "--pretty"), (self.no_debug, "--no-debug")] {
+            if set {
+                flavor.require(flag)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `html` as the bytes `output_encoding` has written to files.
+    pub(crate) fn file_bytes<'a>(
+        &self,
+        html: &'a str,
+    ) -> Result<std::borrow::Cow<'a, [u8]>, CompileError> {
+        #[cfg(feature = "encoding")]
+        if let Some(encode) = &self.output_encoding {
+            return encode.apply(html);
+        }
+        Ok(std::borrow::Cow::Borrowed(html.as_bytes()))
+    }
+
+    /// Whether `file_bytes` can be something other than the HTML's UTF-8.
+    pub(crate) fn encodes_files(&self) -> bool {
+        #[cfg(feature = "encoding")]
+        if let Some(encode) = &self.output_encoding {
+            return !encode.is_utf8();
+        }
+        false
+    }
+}
+
+impl Default for PugOptions {
+    fn default() -> Self {
+        PugOptions::new()
+    }
 }
 
 impl IntoIterator for PugOptions {
@@ -127,6 +1299,8 @@ impl IntoIterator for PugOptions {
 
     fn into_iter(self) -> Self::IntoIter {
         let mut result: Vec<String> = Vec::new();
+        let flavor = self.flavor.unwrap_or_default();
+        let spell = |flag| flavor.spell(flag).map(String::from);
 
         if self.version {
             result.push("--verison".into())
@@ -149,11 +1323,11 @@ impl IntoIterator for PugOptions {
         }
 
         if self.pretty {
-            result.push("--pretty".into())
+            result.extend(spell("--pretty"))
         }
 
         if self.no_debug {
-            result.push("--no-debug".into())
+            result.extend(spell("--no-debug"))
         }
         if self.client {
             result.push("--client".into())
@@ -168,84 +1342,782 @@ impl IntoIterator for PugOptions {
     }
 }
 
-pub enum CompileError {
-    Io(std::io::Error),
-    PugError(String),
+impl PugOptions {
+    /// `Debug` output that shows the locals too, for development.
+    pub fn debug_unredacted(&self) -> impl fmt::Debug + '_ {
+        Unredacted(self)
+    }
+
+    fn fmt_debug(&self, f: &mut fmt::Formatter<'_>, redact: bool) -> fmt::Result {
+        // destructured so a new option can't be left out
+        let PugOptions {
+            version,
+            object,
+            path,
+            out_dir,
+            no_debug,
+            client,
+            stdin,
+            pretty,
+            doctype,
+            binary,
+            timeout,
+            log_locals,
+            minify,
+            newline,
+            trailing_newline,
+            validate_output,
+            strict_validation,
+            #[cfg(feature = "ammonia")]
+            sanitize,
+            #[cfg(feature = "node-api")]
+            filters,
+            preludes,
+            flavor,
+            source_name,
+            reject_empty_input,
+            normalize_newlines,
+            front_matter,
+            locals_via_file,
+            warn_unused_locals,
+            sidecar_locals,
+            reindent,
+            output_naming,
+            hashed_output,
+            #[cfg(feature = "compress")]
+            precompress,
+            assets,
+            version_check,
+            #[cfg(feature = "encoding")]
+            output_encoding,
+            #[cfg(feature = "schema")]
+            locals_schema,
+            #[cfg(feature = "i18n")]
+            translations,
+            #[cfg(feature = "i18n")]
+            translations_key,
+            executor,
+            locals_for,
+            retry,
+            stderr_mode,
+            stderr_log,
+            limits,
+            sandbox,
+            flags_set: _,
+            merge_error,
+        } = self;
+        let object = object.as_ref().map(|object| DebugLocals { object, redact });
+        let mut debug = f.debug_struct("PugOptions");
+        debug
+            .field("version", version)
+            .field("object", &object)
+            .field("path", path)
+            .field("out_dir", out_dir)
+            .field("no_debug", no_debug)
+            .field("client", client)
+            .field("stdin", stdin)
+            .field("pretty", pretty)
+            .field("doctype", doctype)
+            .field("binary", binary)
+            .field("timeout", timeout)
+            .field("log_locals", log_locals)
+            .field("minify", minify)
+            .field("newline", newline)
+            .field("trailing_newline", trailing_newline)
+            .field("validate_output", validate_output)
+            .field("strict_validation", strict_validation);
+        #[cfg(feature = "ammonia")]
+        debug.field("sanitize", sanitize);
+        #[cfg(feature = "node-api")]
+        debug.field("filters", filters);
+        debug
+            .field("preludes", preludes)
+            .field("flavor", flavor)
+            .field("source_name", source_name)
+            .field("reject_empty_input", reject_empty_input)
+            .field("normalize_newlines", normalize_newlines)
+            .field("front_matter", front_matter)
+            .field("locals_via_file", locals_via_file)
+            .field("warn_unused_locals", warn_unused_locals)
+            .field("sidecar_locals", sidecar_locals)
+            .field("reindent", reindent)
+            .field("output_naming", output_naming)
+            .field("hashed_output", hashed_output);
+        #[cfg(feature = "compress")]
+        debug.field("precompress", precompress);
+        debug
+            .field("assets", assets)
+            .field("version_check", version_check);
+        #[cfg(feature = "encoding")]
+        debug.field("output_encoding", output_encoding);
+        #[cfg(feature = "schema")]
+        debug.field("locals_schema", locals_schema);
+        #[cfg(feature = "i18n")]
+        debug
+            .field("translations", translations)
+            .field("translations_key", translations_key);
+        debug
+            .field("executor", &executor.as_ref().map(|_| "<dyn Executor>"))
+            .field("locals_for", &locals_for.as_ref().map(|_| "<fn>"))
+            .field("retry", retry)
+            .field("stderr_mode", stderr_mode)
+            .field("stderr_log", stderr_log)
+            .field("limits", limits)
+            .field("sandbox", sandbox)
+            .field("merge_error", merge_error)
+            .finish()
+    }
 }
 
-impl error::Error for CompileError {}
+struct DebugLocals<'a> {
+    object: &'a PugJsonObject,
+    redact: bool,
+}
 
-impl fmt::Display for CompileError {
+impl fmt::Debug for DebugLocals<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self {
-            CompileError::PugError(pug_error) => write!(f, "{:?}", pug_error),
-            CompileError::Io(io_error) => write!(f, "{}", io_error),
-        }
+        self.object.fmt_debug(f, self.redact)
     }
 }
 
-impl fmt::Debug for CompileError {
+struct Unredacted<'a>(&'a PugOptions);
+
+impl fmt::Debug for Unredacted<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self {
-            CompileError::PugError(pug_error) => write!(f, "Pug Error: {}", pug_error),
-            CompileError::Io(io_error) => write!(f, "{:?}", io_error),
-        }
+        self.0.fmt_debug(f, false)
     }
 }
-fn process_output(output: io::Result<Output>) -> Result<String, CompileError> {
-    match output {
-        Ok(output) => {
-            if output.stderr.len() > 0 {
-                Err(CompileError::PugError(
-                    String::from_utf8_lossy(&output.stderr).into(),
-                ))
-            } else {
-                Ok(String::from_utf8_lossy(&output.stdout).into())
+
+/// Inline locals are summarized; `debug_unredacted` shows them.
+impl fmt::Debug for PugOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_debug(f, true)
+    }
+}
+
+/// The arguments pug is given, separated by spaces, with inline locals
+/// summarized as in `Debug`.
+impl fmt::Display for PugOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = self.object.as_ref().and_then(PugJsonObject::redacted);
+        let mut args = self.clone().into_iter();
+        let mut first = true;
+        while let Some(arg) = args.next() {
+            if !first {
+                f.write_str(" ")?;
+            }
+            first = false;
+            f.write_str(&arg)?;
+            if let (true, Some(redacted)) = (arg == "--obj", &redacted) {
+                args.next();
+                write!(f, " {}", redacted)?;
             }
         }
-        Err(err) => Err(CompileError::Io(err)),
+        Ok(())
     }
 }
 
-pub fn evaluate_with_options(
+/// Why a compile failed. `Send`, `Sync` and `'static`, so it fits in
+/// `Box<dyn Error + Send + Sync>`.
+pub enum CompileError {
+    /// An I/O error with no file to blame, such as on a pipe to pug.
+    Io(std::io::Error),
+    /// An I/O error while doing `op`, `"read template"` say, to `path`.
+    IoAt {
+        path: PathBuf,
+        op: &'static str,
+        source: std::io::Error,
+    },
+    PugError(String),
+    Cancelled,
+    RendererExited(String),
+    Timeout(Duration),
+    Panicked(String),
+    InvalidOptions(String),
+    PostProcessing(String),
+    InstallFailed(String),
+    /// A template used a filter whose jstransformer package isn't installed.
+    MissingFilter {
+        filter: String,
+        /// The command that installs it.
+        hint: String,
+    },
+    /// `PugOptions::reject_empty_input` was set and the template was blank.
+    EmptyInput,
+    /// `PugOptions::front_matter` was set and a template's block didn't parse.
+    FrontMatter(String),
+    /// `TemplateRegistry::render` was asked for a name nothing is registered as.
+    UnknownTemplate(String),
+    /// Pug ran out of the `PugOptions::memory_limit` it was given, in bytes.
+    MemoryLimit(u64),
+    /// Pug used up its `PugOptions::cpu_time_limit`.
+    CpuTimeLimit(Duration),
+    /// The file given for a `PugOptions::filter` doesn't exist.
+    FilterFileMissing {
+        filter: String,
+        path: PathBuf,
+    },
+    /// A `PugOptions::filter` file threw when it was loaded, or doesn't
+    /// export a function.
+    FilterRegistration {
+        filter: String,
+        reason: String,
+    },
+    /// `inline_includes` couldn't resolve an `include` or `extends`.
+    Include(String),
+    /// `PugOptions::output_naming` gave more than one template the same page.
+    OutputCollision {
+        page: PathBuf,
+        templates: Vec<PathBuf>,
+    },
+    /// The locals didn't conform to `PugOptions::locals_schema`.
+    LocalsValidation(Vec<ValidationError>),
+    /// The catalog for `PugOptions::translations_fluent` is missing or
+    /// doesn't parse.
+    Translations(String),
+    /// `build::dependency_graph` found templates that include or extend each
+    /// other in a cycle, listed with the first repeated at the end.
+    DependencyCycle(Vec<PathBuf>),
+    /// Pug wrote more than `PugOptions::max_output_bytes`.
+    OutputLimit(u64),
+    /// A template broke a `PugOptions::sandbox` rule, named in the message.
+    Sandbox(String),
+    /// The manifest for `build::build_from_manifest` is unreadable or
+    /// invalid.
+    Manifest(String),
+    /// A `PugOptions::sidecar_locals` data file is unreadable or isn't an
+    /// object.
+    Sidecar {
+        path: PathBuf,
+        reason: String,
+    },
+    /// `PugJsonObject::sniff` couldn't make locals of a string.
+    Locals(String),
+    /// A template string, or a value headed for pug's command line such as
+    /// raw locals, has a byte pug can't be given; `position` is its byte
+    /// offset in that string.
+    InvalidInput {
+        position: usize,
+        reason: String,
+    },
+    /// `evaluate_url` couldn't download the template, or got an error status.
+    Fetch {
+        url: String,
+        reason: String,
+    },
+    /// The template at the URL is over `RemoteTemplate::max_bytes`.
+    RemoteTooLarge {
+        url: String,
+        limit: u64,
+    },
+    /// The template at the URL didn't hash to `RemoteTemplate::sha256`.
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    /// `render_mixin` called a mixin `library` doesn't define.
+    UnknownMixin {
+        library: PathBuf,
+        mixin: String,
+    },
+    /// The pug that would run is outside `PugOptions::require_version`.
+    /// `installed` is its version, or why that couldn't be told.
+    VersionMismatch {
+        installed: String,
+        required: String,
+    },
+    /// With `PugOptions::strict_encoding`, the page had characters the
+    /// `output_encoding` can't write; `character` is the first of them.
+    Unencodable {
+        encoding: String,
+        character: char,
+    },
+}
+
+impl error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CompileError::Io(io_error) => Some(io_error),
+            CompileError::IoAt { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl CompileError {
+    /// Makes `CompileError::IoAt`s for `op` on `path`, for `map_err`.
+    pub(crate) fn io_at(
+        op: &'static str,
+        path: &Path,
+    ) -> impl FnOnce(std::io::Error) -> CompileError {
+        let path = path.to_owned();
+        move |source| CompileError::IoAt { path, op, source }
+    }
+}
+
+/// Fails to compile if a public type stops being `Send + Sync + 'static`,
+/// which sharing it in application state, moving it into a parallel iterator
+/// or boxing it as `dyn Error + Send + Sync` all need.
+#[allow(dead_code)]
+fn assert_public_types_are_send_sync() {
+    fn send_sync<T: Send + Sync + 'static>() {}
+    send_sync::<PugOptions>();
+    send_sync::<PugJsonObject>();
+    send_sync::<CompileError>();
+    send_sync::<PugEngine>();
+    send_sync::<TemplateRegistry>();
+    send_sync::<RenderError>();
+    send_sync::<Box<dyn Render>>();
+    #[cfg(feature = "tokio")]
+    {
+        send_sync::<tokio::AsyncRenderer>();
+        send_sync::<tokio::SpawnRenderer>();
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            CompileError::PugError(pug_error) => write!(f, "{:?}", pug_error),
+            CompileError::Io(io_error) => write!(f, "{}", io_error),
+            CompileError::IoAt { path, op, source } => {
+                write!(f, "failed to {} '{}': {}", op, path.display(), source)
+            }
+            CompileError::Cancelled => write!(f, "compile cancelled"),
+            CompileError::RendererExited(reason) => write!(f, "{}", reason),
+            CompileError::Timeout(timeout) => write!(f, "pug timed out after {:?}", timeout),
+            CompileError::Panicked(message) => write!(f, "render panicked: {}", message),
+            CompileError::InvalidOptions(reason) => write!(f, "invalid options: {}", reason),
+            CompileError::PostProcessing(reason) => write!(f, "post-processing failed: {}", reason),
+            CompileError::InstallFailed(reason) => write!(f, "installing pug failed: {}", reason),
+            CompileError::MissingFilter { filter, hint } => {
+                write!(f, "filter :{} isn't installed; run `{}`", filter, hint)
+            }
+            CompileError::EmptyInput => write!(f, "the template is empty"),
+            CompileError::FrontMatter(reason) => write!(f, "invalid front matter: {}", reason),
+            CompileError::UnknownTemplate(name) => write!(f, "no template named {:?}", name),
+            CompileError::MemoryLimit(bytes) => {
+                write!(f, "pug exceeded its memory limit of {} bytes", bytes)
+            }
+            CompileError::CpuTimeLimit(limit) => {
+                write!(f, "pug exceeded its CPU time limit of {:?}", limit)
+            }
+            CompileError::FilterFileMissing { filter, path } => write!(
+                f,
+                "the file for filter :{}, {}, doesn't exist",
+                filter,
+                path.display()
+            ),
+            CompileError::FilterRegistration { filter, reason } => {
+                write!(f, "filter :{} couldn't be loaded: {}", filter, reason)
+            }
+            CompileError::Include(reason) => write!(f, "include failed: {}", reason),
+            CompileError::OutputCollision { page, templates } => {
+                let templates: Vec<_> = templates.iter().map(|t| t.display().to_string()).collect();
+                write!(
+                    f,
+                    "{} would all be written to {}",
+                    templates.join(", "),
+                    page.display()
+                )
+            }
+            CompileError::LocalsValidation(errors) => {
+                let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "locals don't match the schema: {}", errors.join("; "))
+            }
+            CompileError::Translations(reason) => {
+                write!(f, "translations couldn't be loaded: {}", reason)
+            }
+            CompileError::DependencyCycle(cycle) => {
+                let cycle: Vec<_> = cycle.iter().map(|t| t.display().to_string()).collect();
+                write!(f, "templates depend on each other: {}", cycle.join(" -> "))
+            }
+            CompileError::OutputLimit(bytes) => {
+                write!(f, "pug wrote more than its output limit of {} bytes", bytes)
+            }
+            CompileError::Sandbox(rule) => write!(f, "sandboxed template rejected: {}", rule),
+            CompileError::Manifest(reason) => write!(f, "invalid build manifest: {}", reason),
+            CompileError::Sidecar { path, reason } => {
+                write!(f, "invalid locals in {}: {}", path.display(), reason)
+            }
+            CompileError::Locals(reason) => write!(f, "invalid locals: {}", reason),
+            CompileError::InvalidInput { position, reason } => {
+                write!(f, "invalid input at byte {}: {}", position, reason)
+            }
+            CompileError::Fetch { url, reason } => write!(f, "fetching {} failed: {}", url, reason),
+            CompileError::RemoteTooLarge { url, limit } => {
+                write!(f, "{} is over the {} byte limit", url, limit)
+            }
+            CompileError::ChecksumMismatch {
+                url,
+                expected,
+                actual,
+            } => write!(f, "{} has SHA-256 {}, expected {}", url, actual, expected),
+            CompileError::UnknownMixin { library, mixin } => {
+                write!(f, "{} has no mixin {:?}", library.display(), mixin)
+            }
+            CompileError::VersionMismatch {
+                installed,
+                required,
+            } => write!(f, "pug {} doesn't satisfy {}", installed, required),
+            CompileError::Unencodable {
+                encoding,
+                character,
+            } => write!(f, "{:?} can't be written in {}", character, encoding),
+        }
+    }
+}
+
+impl fmt::Debug for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            CompileError::PugError(pug_error) => write!(f, "Pug Error: {}", pug_error),
+            CompileError::Io(io_error) => write!(f, "{:?}", io_error),
+            CompileError::IoAt { path, op, source } => {
+                write!(f, "Io At: {} {} ({:?})", op, path.display(), source)
+            }
+            CompileError::Cancelled => write!(f, "Cancelled"),
+            CompileError::RendererExited(reason) => write!(f, "Renderer Exited: {}", reason),
+            CompileError::Timeout(timeout) => write!(f, "Timeout: {:?}", timeout),
+            CompileError::Panicked(message) => write!(f, "Panicked: {}", message),
+            CompileError::InvalidOptions(reason) => write!(f, "Invalid Options: {}", reason),
+            CompileError::PostProcessing(reason) => write!(f, "Post-processing: {}", reason),
+            CompileError::InstallFailed(reason) => write!(f, "Install Failed: {}", reason),
+            CompileError::MissingFilter { filter, hint } => {
+                write!(f, "Missing Filter: {} ({})", filter, hint)
+            }
+            CompileError::EmptyInput => write!(f, "Empty Input"),
+            CompileError::FrontMatter(reason) => write!(f, "Front Matter: {}", reason),
+            CompileError::UnknownTemplate(name) => write!(f, "Unknown Template: {}", name),
+            CompileError::MemoryLimit(bytes) => write!(f, "Memory Limit: {} bytes", bytes),
+            CompileError::CpuTimeLimit(limit) => write!(f, "CPU Time Limit: {:?}", limit),
+            CompileError::FilterFileMissing { filter, path } => {
+                write!(f, "Filter File Missing: {} ({})", filter, path.display())
+            }
+            CompileError::FilterRegistration { filter, reason } => {
+                write!(f, "Filter Registration: {} ({})", filter, reason)
+            }
+            CompileError::Include(reason) => write!(f, "Include: {}", reason),
+            CompileError::OutputCollision { page, templates } => {
+                write!(f, "Output Collision: {} ({:?})", page.display(), templates)
+            }
+            CompileError::LocalsValidation(errors) => {
+                write!(f, "Locals Validation: {:?}", errors)
+            }
+            CompileError::Translations(reason) => write!(f, "Translations: {}", reason),
+            CompileError::DependencyCycle(cycle) => write!(f, "Dependency Cycle: {:?}", cycle),
+            CompileError::OutputLimit(bytes) => write!(f, "Output Limit: {} bytes", bytes),
+            CompileError::Sandbox(rule) => write!(f, "Sandbox: {}", rule),
+            CompileError::Manifest(reason) => write!(f, "Manifest: {}", reason),
+            CompileError::Sidecar { path, reason } => {
+                write!(f, "Sidecar: {} ({})", path.display(), reason)
+            }
+            CompileError::Locals(reason) => write!(f, "Locals: {}", reason),
+            CompileError::InvalidInput { position, reason } => {
+                write!(f, "Invalid Input: {} (byte {})", reason, position)
+            }
+            CompileError::Fetch { url, reason } => write!(f, "Fetch: {} ({})", url, reason),
+            CompileError::RemoteTooLarge { url, limit } => {
+                write!(f, "Remote Too Large: {} ({} bytes)", url, limit)
+            }
+            CompileError::ChecksumMismatch {
+                url,
+                expected,
+                actual,
+            } => write!(f, "Checksum Mismatch: {} ({} != {})", url, actual, expected),
+            CompileError::UnknownMixin { library, mixin } => {
+                write!(f, "Unknown Mixin: +{} ({})", mixin, library.display())
+            }
+            CompileError::VersionMismatch {
+                installed,
+                required,
+            } => write!(f, "Version Mismatch: {} ({})", installed, required),
+            CompileError::Unencodable {
+                encoding,
+                character,
+            } => write!(f, "Unencodable: {:?} ({})", character, encoding),
+        }
+    }
+}
+/// Reads `pipe` to its end, or until more than `limit` bytes have come
+/// through, which sets `exceeded` and stops reading. With `tee` what's read
+/// is copied to this process's stderr too.
+fn read_in_background(
+    pipe: impl Read + Send + 'static,
+    limit: Option<u64>,
+    tee: bool,
+    exceeded: Arc<AtomicBool>,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut pipe = pipe.take(limit.map_or(u64::MAX, |limit| limit + 1));
+        let mut buffer = Vec::new();
+        if tee {
+            buffer = stderr_mode::tee(&mut pipe);
+        } else {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        if limit.is_some_and(|limit| buffer.len() as u64 > limit) {
+            exceeded.store(true, Ordering::SeqCst);
+        }
+        buffer
+    })
+}
+
+/// Like `Child::wait_with_output`, but kills and reaps the child if it's still
+/// running once `timeout` has passed, or as soon as it has written more than
+/// `output_limit` bytes to stdout or stderr, and copies stderr through for
+/// `StderrMode::Tee`.
+///
+/// `input` is written to stdin from a thread of its own, so the timeout
+/// covers a child that never reads it. The child is killed and reaped
+/// before any error is returned.
+fn wait_with_timeout(
+    mut child: Child,
+    input: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    output_limit: Option<u64>,
+    stderr_mode: StderrMode,
+) -> Result<Output, CompileError> {
+    // dropping stdin once it's written tells pug the template is complete
+    let writer = child.stdin.take().map(|mut stdin| {
+        thread::spawn(move || match input {
+            Some(input) => tolerate_closed_stdin(stdin.write_all(&input)),
+            None => Ok(()),
+        })
+    });
+    let written = move || match writer.map(JoinHandle::join) {
+        Some(Ok(written)) => written.map_err(CompileError::Io),
+        Some(Err(panic)) => std::panic::resume_unwind(panic),
+        None => Ok(()),
+    };
+    let tee = stderr_mode == StderrMode::Tee;
+    if timeout.is_none() && output_limit.is_none() && !tee {
+        let output = child.wait_with_output().map_err(CompileError::Io);
+        return written().and(output);
+    }
+
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| read_in_background(pipe, output_limit, false, exceeded.clone()));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| read_in_background(pipe, output_limit, tee, exceeded.clone()));
+    let collect = |pipe: Option<JoinHandle<Vec<u8>>>| {
+        pipe.map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default()
+    };
+    let over_limit = || match output_limit {
+        Some(limit) if exceeded.load(Ordering::SeqCst) => Some(CompileError::OutputLimit(limit)),
+        _ => None,
+    };
+
+    let started = Instant::now();
+    let status = loop {
+        let error = match over_limit() {
+            Some(error) => error,
+            None => match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => match timeout.filter(|&timeout| started.elapsed() >= timeout) {
+                    Some(timeout) => CompileError::Timeout(timeout),
+                    None => {
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                },
+                Err(e) => CompileError::Io(e),
+            },
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        let _ = written();
+        return Err(error);
+    };
+
+    let output = Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    };
+    written()?;
+    match over_limit() {
+        Some(error) => Err(error),
+        None => Ok(output),
+    }
+}
+
+/// What a successful compile produced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileOutput {
+    pub html: String,
+    /// What pug wrote to stderr, one line each, apart from its progress notes.
+    pub warnings: Vec<String>,
+}
+
+pub fn evaluate_with_options(
     file: impl Into<PathBuf>,
     options: PugOptions,
 ) -> Result<String, CompileError> {
-    let options = options.stdin().with_path(file);
+    evaluate_with_warnings(file, options).map(|output| output.html)
+}
+
+/// Like `evaluate_with_options`, also returning pug's warnings. Pug only
+/// fails a compile by exiting unsuccessfully; stderr from a compile that
+/// succeeded ends up here.
+pub fn evaluate_with_warnings(
+    file: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<CompileOutput, CompileError> {
+    let invocation = Invocation::file(file, options)?;
+    let post_process = invocation.post_process.clone();
+    run_file(invocation).and_then(|output| interpret_output_with_warnings(output, post_process))
+}
+
+/// Compiles `file` and writes the page to `out`, creating its directory,
+/// in the `PugOptions::output_encoding` if one is set. The returned HTML is
+/// what was compiled, as UTF-8.
+pub fn evaluate_to_file(
+    file: impl Into<PathBuf>,
+    out: impl AsRef<Path>,
+    options: PugOptions,
+) -> Result<CompileOutput, CompileError> {
+    let out = out.as_ref();
+    let bytes_for = options.clone();
+    let output = evaluate_with_warnings(file, options)?;
+    let bytes = bytes_for.file_bytes(&output.html)?;
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent).map_err(CompileError::io_at("create directory", parent))?;
+    }
+    std::fs::write(out, &bytes).map_err(CompileError::io_at("write page", out))?;
+    Ok(output)
+}
+
+/// Like `evaluate_with_options`, also returning the HTML's `etag`, for
+/// answering conditional requests.
+pub fn evaluate_with_etag(
+    file: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<(String, String), CompileError> {
+    evaluate_with_options(file, options).map(|html| {
+        let etag = etag(&html);
+        (html, etag)
+    })
+}
+
+/// A strong `ETag` for `html`: the hex SHA-256 of its bytes, quoted, so the
+/// same page always gets the same tag.
+pub fn etag(html: &str) -> String {
+    format!("\"{}\"", sha256::sha256_hex(html.as_bytes()))
+}
+
+/// Runs pug on `file` and returns its exit status, stdout and stderr exactly
+/// as they came back, for callers with their own idea of what counts as a
+/// failure.
+///
+/// No error interpretation is applied, and neither is any output option
+/// (`minify`, `newline`, ...): only failing to run pug, reading `file` and
+/// `PugOptions::timeout` are errors.
+pub fn evaluate_raw(file: impl Into<PathBuf>, options: PugOptions) -> Result<Output, CompileError> {
+    run_file(Invocation::file(file, options)?)
+}
 
-    let mut command = Command::new("pug");
+fn run_file(invocation: Invocation) -> Result<Output, CompileError> {
+    let compile = Compile::start(&invocation);
 
-    if let Some(path) = &options.path {
-        match File::open(path) {
-            Ok(file) => {
-                command.stdin(file);
-                ()
+    let output = compile.in_scope(|| {
+        if let Some(output) = invocation.run_executor() {
+            return output;
+        }
+        let mut command = invocation.command();
+
+        match &invocation.input {
+            Input::File(path) => match File::open(path) {
+                Ok(file) => {
+                    command.stdin(file);
+                }
+                Err(e) => return Err(CompileError::io_at("open template", path)(e)),
+            },
+            // the file's source with its front matter taken off
+            Input::Bytes(_) => {
+                command.stdin(Stdio::piped());
             }
-            Err(e) => return Err(CompileError::Io(e)),
         }
-    }
-    command.args(options);
-    process_output(command.output())
+        command
+            .args(invocation.args)
+            .stdout(Stdio::piped())
+            .stderr(invocation.stderr_mode.stdio());
+        let child = retry(invocation.retry.as_ref(), || command.spawn())
+            .map_err(CompileError::io_at("run pug", &invocation.program))?;
+        let input = match invocation.input {
+            Input::Bytes(bytes) => Some(bytes),
+            Input::File(_) => None,
+        };
+        wait_with_timeout(
+            child,
+            input,
+            invocation.timeout,
+            invocation.post_process.limits.output,
+            invocation.stderr_mode,
+        )
+    });
+    compile.observe(output)
 }
 
 pub fn evaluate_string_with_options(
     s: String,
     options: PugOptions,
 ) -> Result<String, CompileError> {
-    let options = options.stdin();
-    let mut command = Command::new("pug");
-    let mut child = command
-        .args(options)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| CompileError::Io(e))?;
-    let mut stdin = child.stdin.as_mut().unwrap();
-    stdin
-        .write_all(s.as_bytes())
-        .map_err(|e| CompileError::Io(e))?;
-    let output = child.wait_with_output();
-    process_output(output)
+    evaluate_string_with_warnings(s, options).map(|output| output.html)
+}
+
+/// `evaluate_with_warnings` for a template held in a string.
+pub fn evaluate_string_with_warnings(
+    s: String,
+    options: PugOptions,
+) -> Result<CompileOutput, CompileError> {
+    let invocation = Invocation::string(s, options)?;
+    let post_process = invocation.post_process.clone();
+    run_string(invocation).and_then(|output| interpret_output_with_warnings(output, post_process))
+}
+
+/// `evaluate_raw` for a template held in a string. Preludes are still put in
+/// front of it.
+pub fn evaluate_string_raw(s: String, options: PugOptions) -> Result<Output, CompileError> {
+    run_string(Invocation::string(s, options)?)
+}
+
+fn run_string(invocation: Invocation) -> Result<Output, CompileError> {
+    let compile = Compile::start(&invocation);
+
+    let output = compile.in_scope(|| {
+        if let Some(output) = invocation.run_executor() {
+            return output;
+        }
+        let mut command = invocation.command();
+        command
+            .args(invocation.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(invocation.stderr_mode.stdio());
+        let child = retry(invocation.retry.as_ref(), || command.spawn())
+            .map_err(CompileError::io_at("run pug", &invocation.program))?;
+        // pug reads until end of input, empty or not
+        let input = match invocation.input {
+            Input::Bytes(bytes) => Some(bytes),
+            Input::File(_) => None,
+        };
+        wait_with_timeout(
+            child,
+            input,
+            invocation.timeout,
+            invocation.post_process.limits.output,
+            invocation.stderr_mode,
+        )
+    });
+    compile.observe(output)
 }
 
 pub fn evaluate_string(s: String) -> Result<String, CompileError> {
@@ -261,13 +2133,37 @@ pub fn evaluate(file: impl Into<PathBuf>) -> Result<String, CompileError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(unix)]
+    use executor::MockExecutor;
+    use test_support::{fake_binary, process_exists};
 
+    // These expect the default, TrailingNewline::Keep, and pug writing no final
+    // newline when it reads from stdin.
     #[test]
     fn evaluate_file() {
-        let pug_options = PugOptions::new();
         assert_eq!("<h1>hello pug</h1>", evaluate("test/hello.pug").unwrap());
     }
 
+    #[test]
+    fn paths_with_spaces_and_unicode() {
+        let hero = "test/paths/Landing Pages/Été 2024/hero.pug";
+        assert_eq!("<h1>Été 2024</h1>", evaluate(hero).unwrap());
+        let notes = evaluate("test/paths/Landing Pages/Vol. 2./notes..pug").unwrap();
+        assert_eq!("<p>notes</p>", notes);
+
+        let args: Vec<String> = PugOptions::new()
+            .with_path(hero)
+            .out_dir("out/Été 2024.")
+            .into_iter()
+            .collect();
+        assert_eq!(vec!["--path", hero, "--out", "out/Été 2024."], args);
+
+        let broken = "test/paths/Broken Pages/à refaire.pug";
+        let details = evaluate(broken).unwrap_err().details().unwrap();
+        assert!(details.filename.ends_with(broken), "{}", details.filename);
+        assert_eq!((1, Some(3)), (details.line, details.column));
+    }
+
     #[test]
     fn evaluate_with_string() {
         assert_eq!(
@@ -276,6 +2172,719 @@ mod tests {
         );
     }
 
+    #[test]
+    fn evaluate_with_string_and_json_value() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_string_with_options(
+                String::from("h1 hello #{language}"),
+                PugOptions::new().with_object(serde_json::json!({"language": "pug"}))
+            )
+            .unwrap()
+        )
+    }
+
+    #[test]
+    fn warns_about_unused_locals() {
+        let locals = serde_json::json!({ "name": "Ada", "title": "old key" });
+        let template = || "h1 #{heading}\np #{name}".to_string();
+        let output = evaluate_string_with_warnings(
+            template(),
+            PugOptions::new()
+                .with_object(locals.clone())
+                .warn_unused_locals(),
+        )
+        .unwrap();
+        assert_eq!("<h1></h1><p>Ada</p>", output.html);
+        assert_eq!(
+            vec!["local `title` isn't used by the template"],
+            output.warnings
+        );
+
+        let quiet =
+            evaluate_string_with_warnings(template(), PugOptions::new().with_object(locals));
+        assert!(quiet.unwrap().warnings.is_empty());
+    }
+
+    #[test]
+    fn profiles_set_their_flags() {
+        let args = |options: PugOptions| options.into_iter().collect::<Vec<_>>();
+        assert_eq!(vec!["--pretty"], args(PugOptions::development()));
+        assert_eq!(vec!["--no-debug"], args(PugOptions::production()));
+        assert_eq!(
+            args(PugOptions::production()),
+            args(PugOptions::profile(Profile::Production))
+        );
+        #[cfg(feature = "minify")]
+        assert!(PugOptions::production().minify);
+        assert_eq!(
+            vec!["--pretty", "--no-debug", "--doctype", "html"],
+            args(PugOptions::development().no_debug().doctype("html".into()))
+        );
+
+        // merged either way round, the profile's flags replace the others'
+        let merged = PugOptions::development().merge(PugOptions::production());
+        assert_eq!(vec!["--no-debug"], args(merged));
+        let merged = PugOptions::new()
+            .no_debug()
+            .merge(PugOptions::development());
+        assert_eq!(vec!["--pretty"], args(merged));
+        let engine = PugEngine::new(PugOptions::production());
+        let merged = engine.merged(PugOptions::new().set_flag(Flag::NoDebug, false));
+        assert!(args(merged).is_empty());
+    }
+
+    #[test]
+    fn etags_follow_the_rendered_html() {
+        let render = |name| {
+            let locals = PugOptions::new().with_object(serde_json::json!({ "name": name }));
+            evaluate_with_etag("test/greeting.pug", locals).unwrap()
+        };
+        let (html, tag) = render("Ada");
+        assert_eq!("<p>Hello, Ada!</p>", html);
+        assert_eq!(etag(&html), tag);
+        assert!(tag.starts_with('"') && tag.ends_with('"') && tag.len() == 66);
+        assert_eq!(tag, render("Ada").1);
+        assert_ne!(tag, render("Grace").1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_covers_a_child_that_never_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > '{}'\nexec sleep 30\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "deaf-pug", &script);
+
+        let timeout = Duration::from_millis(300);
+        let started = Instant::now();
+        let result = evaluate_string_with_options(
+            "p x\n".repeat(1_000_000),
+            PugOptions::new().binary(binary).timeout(timeout),
+        );
+
+        assert!(matches!(result, Err(CompileError::Timeout(t)) if t == timeout));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        assert!(!process_exists(&pid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > '{}'\nexec sleep 30\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "slow-pug", &script);
+
+        let timeout = Duration::from_millis(300);
+        let result = evaluate_with_options(
+            "test/hello.pug",
+            PugOptions::new().binary(binary).timeout(timeout),
+        );
+
+        assert!(matches!(result, Err(CompileError::Timeout(t)) if t == timeout));
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        assert!(!process_exists(&pid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sandbox_rejects_includes_and_bounds_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let ran = dir.path().join("ran");
+        let never = fake_binary(dir.path(), "never", &format!("touch '{}'\n", ran.display()));
+        let include = evaluate_string_with_options(
+            "p before\ninclude /etc/hostname".into(),
+            PugOptions::new().binary(never).sandbox(),
+        );
+        match include {
+            Err(CompileError::Sandbox(rule)) => assert!(rule.contains("`include`"), "{}", rule),
+            other => panic!("{:?}", other),
+        }
+        assert!(!ran.exists());
+
+        let flood = fake_binary(
+            dir.path(),
+            "flood",
+            "cat > /dev/null\nexec yes '<p>flood</p>'\n",
+        );
+        let started = Instant::now();
+        let flooded =
+            evaluate_string_with_options("p".into(), PugOptions::new().binary(&flood).sandbox());
+        assert!(matches!(flooded, Err(CompileError::OutputLimit(limit)) if limit == 1 << 20));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let errors = fake_binary(
+            dir.path(),
+            "errors",
+            "cat > /dev/null
+exec yes error >&2
+",
+        );
+        let flooded =
+            evaluate_string_with_options("p".into(), PugOptions::new().binary(&errors).sandbox());
+        assert!(matches!(flooded, Err(CompileError::OutputLimit(_))));
+
+        let env = fake_binary(
+            dir.path(),
+            "env",
+            "cat > /dev/null\nprintf '<p>%s</p>' \"${HOME:-unset}\"\n",
+        );
+        let output =
+            evaluate_string_with_options("p".into(), PugOptions::new().binary(env).sandbox());
+        assert_eq!("<p>unset</p>", output.unwrap());
+        let output = evaluate_string_with_options("p hello".into(), PugOptions::new().sandbox());
+        assert_eq!("<p>hello</p>", output.unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stderr_modes_capture_and_forward() {
+        let dir = tempfile::tempdir().unwrap();
+        let pug = |name: &str, script: &str| {
+            let script = format!("cat > /dev/null\n{}\n", script);
+            PugOptions::new().binary(fake_binary(dir.path(), name, &script))
+        };
+        let warning = "printf 'warning: live\\n' >&2\nprintf '<p>ok</p>'";
+        let failing = "printf 'Error: Pug:1:1\\n  > 1| p\\n\\nbroken\\n' >&2\nexit 1";
+
+        // run again as a child, whose stderr this test can read
+        if std::env::var_os("PUG_CLI_STDERR_CHILD").is_some() {
+            let tee = pug("tee-pug", warning).stderr_mode(StderrMode::Tee);
+            let output = evaluate_with_warnings("test/hello.pug", tee).unwrap();
+            assert_eq!(vec!["warning: live"], output.warnings);
+            let tee = pug("tee-string-pug", failing).stderr_mode(StderrMode::Tee);
+            let error = evaluate_string_with_options("p".into(), tee).unwrap_err();
+            assert_eq!("broken", error.details().unwrap().message);
+            let inherit = pug("inherit-pug", failing).stderr_mode(StderrMode::Inherit);
+            match evaluate_with_options("test/hello.pug", inherit) {
+                Err(CompileError::PugError(text)) => assert!(text.starts_with("pug exited")),
+                other => panic!("{:?}", other),
+            }
+            return;
+        }
+
+        let error = evaluate_with_options("test/hello.pug", pug("capture-pug", failing));
+        assert_eq!("broken", error.unwrap_err().details().unwrap().message);
+        let captured = pug("quiet-pug", warning).stderr_mode(StderrMode::Capture);
+        let output = evaluate_with_warnings("test/hello.pug", captured).unwrap();
+        assert_eq!(vec!["warning: live"], output.warnings);
+
+        let child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["tests::stderr_modes_capture_and_forward", "--exact"])
+            .env("PUG_CLI_STDERR_CHILD", "1")
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&child.stderr);
+        assert!(child.status.success(), "{}", stderr);
+        assert_eq!(1, stderr.matches("warning: live").count(), "{}", stderr);
+        assert_eq!(2, stderr.matches("broken").count(), "{}", stderr);
+    }
+
+    #[test]
+    fn tells_throwing_locals_from_broken_templates() {
+        let module = PugJsonObject::JsModule("test/throwing-locals.js".into());
+        let options = PugOptions::new().with_object(module);
+        let error = evaluate_string_with_options("p #{year}".into(), options).unwrap_err();
+        match error.kind() {
+            PugErrorKind::JavaScriptRuntime { message, frame, .. } => {
+                assert_eq!("Error: no year in these locals", message);
+                let frame = frame.unwrap();
+                assert!(
+                    frame.file.ends_with("test/throwing-locals.js"),
+                    "{:?}",
+                    frame
+                );
+                assert_eq!((4, Some(11)), (frame.line, frame.column));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let broken = evaluate_string("main\n  h1 ok\n p wrong".into()).unwrap_err();
+        assert_eq!(PugErrorKind::TemplateSyntax, broken.kind());
+    }
+
+    #[test]
+    fn sniffs_json_and_paths() {
+        let sniff = |s: &str| s.parse::<PugJsonObject>();
+        match sniff(r#" {"name": "Ada"}"#) {
+            Ok(PugJsonObject::Json(json)) => assert_eq!("Ada", json["name"]),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(sniff("[1, 2]"), Ok(PugJsonObject::Json(_))));
+        match sniff("{name: 'Ada'}") {
+            Err(CompileError::Locals(reason)) => assert!(reason.contains("JSON"), "{}", reason),
+            other => panic!("{:?}", other),
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("locals.json");
+        std::fs::write(&file, "{}").unwrap();
+        let file = file.to_str().unwrap();
+        assert!(matches!(
+            sniff(file),
+            Ok(PugJsonObject::Path(path)) if path == Path::new(file)
+        ));
+        assert!(matches!(
+            sniff("test/throwing-locals.js"),
+            Ok(PugJsonObject::JsModule(_))
+        ));
+        match sniff("test/missing.json") {
+            Err(CompileError::Locals(reason)) => {
+                assert!(reason.contains("`raw:test/missing.json`"), "{}", reason);
+                assert!(reason.contains("`@test/missing.json`"), "{}", reason);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert!(matches!(
+            sniff("raw:{name: 'Ada'}"),
+            Ok(PugJsonObject::Raw(raw)) if raw == "{name: 'Ada'}"
+        ));
+        assert!(matches!(
+            sniff(&format!("@{}", file)),
+            Ok(PugJsonObject::Path(_))
+        ));
+        match sniff("@test/missing.json") {
+            Err(CompileError::Locals(reason)) => {
+                assert_eq!("there's no file at test/missing.json", reason)
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_nul_bytes_before_running_pug() {
+        let pug = MockExecutor::succeeding("<p></p>");
+        let options = PugOptions::new().executor(pug.clone());
+        match evaluate_string_with_options("p one\np t\0wo".into(), options.clone()) {
+            Err(CompileError::InvalidInput { position, reason }) => {
+                assert_eq!(9, position);
+                assert!(reason.contains("template"), "{}", reason);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let locals = PugJsonObject::Raw("{\"name\":\"a\0b\"}".into());
+        match evaluate_string_with_options("p #{name}".into(), options.clone().with_object(locals))
+        {
+            Err(CompileError::InvalidInput { position, reason }) => {
+                assert_eq!(10, position);
+                assert_eq!("the value of --obj contains a NUL byte", reason);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let prelude = dir.path().join("mixins.pug");
+        std::fs::write(&prelude, "mixin a\n  p a\0").unwrap();
+        let preluded = vec![
+            (options.clone().prelude("mixin b\n  p\0 b"), 11, false),
+            (
+                options.clone().prelude("mixin b").prelude_file(&prelude),
+                13,
+                true,
+            ),
+        ];
+        for (options, at, named) in preluded {
+            match evaluate_string_with_options("p".into(), options) {
+                Err(CompileError::InvalidInput { position, reason }) => {
+                    assert_eq!(at, position);
+                    assert!(reason.contains("prelude"), "{}", reason);
+                    assert_eq!(named, reason.contains("mixins.pug"), "{}", reason);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(pug.runs().is_empty());
+
+        // JSON locals are escaped on the way to pug, so a NUL in them is fine
+        let locals = serde_json::json!({ "name": "a\0b" });
+        let options = PugOptions::new().executor(pug.clone()).with_object(locals);
+        evaluate_string_with_options("p #{name}".into(), options).unwrap();
+        assert!(pug.runs()[0]
+            .args
+            .iter()
+            .any(|arg| arg.contains("a\\u0000b")));
+    }
+
+    #[test]
+    fn string_templates_report_pug_errors() {
+        let broken = "main\n  h1 ok\n p wrong\n".to_string();
+        match evaluate_string_with_options(broken.clone(), PugOptions::new()) {
+            Err(CompileError::PugError(stderr)) => {
+                assert!(stderr.contains("Pug:3:2"), "{}", stderr)
+            }
+            other => panic!("{:?}", other),
+        }
+        let details = evaluate_string(broken).unwrap_err().details().unwrap();
+        assert_eq!((3, Some(2)), (details.line, details.column));
+        assert!(details.message.contains("Inconsistent indentation"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn strips_colors_from_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let colored = fake_binary(
+            dir.path(),
+            "colored-pug",
+            "cat > /dev/null\nprintf '\\033[31mError: Pug:1:2\\033[39m\\n  > 1| p\\n\\n\\033[1mbad (%s)\\033[22m\\n' \"$NO_COLOR\" >&2\nexit 1\n",
+        );
+        let options = || PugOptions::new().binary(&colored);
+
+        let error = evaluate_with_options("test/hello.pug", options()).unwrap_err();
+        match &error {
+            CompileError::PugError(stderr) => {
+                assert_eq!("Error: Pug:1:2\n  > 1| p\n\nbad (1)\n", stderr)
+            }
+            other => panic!("{:?}", other),
+        }
+        let details = error.details().unwrap();
+        assert_eq!((1, Some(2)), (details.line, details.column));
+
+        let error =
+            evaluate_with_options("test/hello.pug", options().preserve_error_colors()).unwrap_err();
+        assert!(
+            matches!(&error, CompileError::PugError(stderr) if stderr.starts_with("\u{1b}[31m")),
+            "{:?}",
+            error
+        );
+        let details = error.details().unwrap();
+        assert_eq!(1, details.line);
+        assert!(details.message.starts_with("bad ("), "{}", details.message);
+    }
+
+    #[test]
+    fn warnings_leave_the_compile_successful() {
+        let warning = "Warning: missing space before text for line 1 of jade file \"Pug\"";
+        let pug = MockExecutor::succeeding("<p>x</p>")
+            .with_stderr(format!("  rendered page.html\n{}\n", warning));
+        let options = || PugOptions::new().executor(pug.clone());
+
+        let output = evaluate_with_warnings("test/hello.pug", options()).unwrap();
+        assert_eq!("<p>x</p>", output.html);
+        assert_eq!(vec![warning.to_owned()], output.warnings);
+        assert_eq!(
+            "<p>x</p>",
+            evaluate_with_options("test/hello.pug", options()).unwrap()
+        );
+        let html = evaluate_string_with_warnings("p x".into(), options())
+            .unwrap()
+            .html;
+        assert_eq!("<p>x</p>", html);
+        assert_eq!(3, pug.runs().len());
+    }
+
+    #[test]
+    fn errors_carry_the_marked_snippet() {
+        let error = evaluate("test/snippet.pug").unwrap_err();
+        let details = error.details().unwrap();
+        assert_eq!((7, Some(7)), (details.line, details.marked));
+        let numbers: Vec<u32> = details.snippet.iter().map(|(number, _)| *number).collect();
+        assert_eq!(vec![5, 6, 7, 8], numbers[..4]);
+        assert_eq!("      b indented under text", details.snippet[2].1);
+
+        let rendered = error.render_snippet().unwrap();
+        assert!(
+            rendered.contains("    6|     | piped text\n  > 7|       b indented under text\n-------------^\n    8|"),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.ends_with("\nunexpected token \"indent\"\n"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn raw_output_is_handed_back_untouched() {
+        let output = evaluate_raw("test/snippet.pug", PugOptions::new()).unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(":7:7"));
+        assert!(evaluate_with_options("test/snippet.pug", PugOptions::new()).is_err());
+
+        let ensure = PugOptions::new().trailing_newline(TrailingNewline::Ensure);
+        let output = evaluate_raw("test/hello.pug", ensure).unwrap();
+        assert!(output.status.success());
+        assert_eq!(b"<h1>hello pug</h1>", &output.stdout[..]);
+        assert!(evaluate_string_raw("h1 ok".into(), PugOptions::new())
+            .unwrap()
+            .status
+            .success());
+    }
+
+    #[test]
+    fn crlf_string_templates_compile_like_lf() {
+        let lf = std::fs::read_to_string("test/newline.pug").unwrap();
+        let crlf = lf.replace('\n', "\r\n");
+        assert_eq!(
+            evaluate_string_with_options(lf.clone(), PugOptions::new().pretty()).unwrap(),
+            evaluate_string_with_options(crlf.clone(), PugOptions::new().pretty()).unwrap()
+        );
+        assert_eq!(
+            evaluate_string(std::fs::read_to_string("test/hello.pug").unwrap()).unwrap(),
+            evaluate_string("h1 hello pug\r\n".into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn newline_normalization_can_be_turned_off() {
+        let pug = MockExecutor::succeeding("");
+        let options = || PugOptions::new().executor(pug.clone());
+        let source = "p a\r\np b\rp c".to_owned();
+
+        evaluate_string_with_options(source.clone(), options()).unwrap();
+        evaluate_string_with_options(source.clone(), options().normalize_newlines(false)).unwrap();
+        let piped: Vec<Vec<u8>> = pug.runs().into_iter().map(|run| run.stdin).collect();
+        assert_eq!(vec![b"p a\np b\np c".to_vec(), source.into_bytes()], piped);
+    }
+
+    #[test]
+    fn empty_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty_file = dir.path().join("empty.pug");
+        std::fs::write(&empty_file, "").unwrap();
+        let reject = || PugOptions::new().reject_empty_input();
+
+        for blank in &["", "  \n\t\n"] {
+            assert_eq!("", evaluate_string(blank.to_string()).unwrap());
+            let rejected = evaluate_string_with_options(blank.to_string(), reject());
+            assert!(matches!(rejected, Err(CompileError::EmptyInput)));
+        }
+        assert_eq!("", evaluate(&empty_file).unwrap());
+        let rejected = evaluate_with_options(&empty_file, reject());
+        assert!(matches!(rejected, Err(CompileError::EmptyInput)));
+
+        let html = evaluate_string_with_options("h1 hello pug".into(), reject()).unwrap();
+        assert_eq!("<h1>hello pug</h1>", html);
+    }
+
+    #[test]
+    fn string_templates_can_use_prelude_mixins() {
+        let options = || {
+            PugOptions::new()
+                .prelude_file("test/prelude/buttons.pug")
+                .prelude("mixin note(text)\n  p.note #{text}")
+        };
+        let html = evaluate_string_with_options(
+            "div\n  +button(\"Save\")\n  +note(\"saved\")".into(),
+            options(),
+        )
+        .unwrap();
+        assert_eq!(
+            r#"<div><button class="btn">Save</button><p class="note">saved</p></div>"#,
+            html
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trailing_newline_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let with = fake_binary(
+            dir.path(),
+            "with",
+            "cat > /dev/null\nprintf '<p>x</p>\\n'\n",
+        );
+        let without = fake_binary(
+            dir.path(),
+            "without",
+            "cat > /dev/null\nprintf '<p>x</p>'\n",
+        );
+
+        for (mode, expected) in &[
+            (TrailingNewline::Ensure, "<p>x</p>\n"),
+            (TrailingNewline::Trim, "<p>x</p>"),
+        ] {
+            for binary in &[&with, &without] {
+                let options = || PugOptions::new().binary(binary).trailing_newline(*mode);
+                assert_eq!(
+                    *expected,
+                    evaluate_with_options("test/hello.pug", options()).unwrap()
+                );
+                assert_eq!(
+                    *expected,
+                    evaluate_string_with_options("p x".into(), options()).unwrap()
+                );
+            }
+        }
+
+        let keep = |binary| {
+            PugOptions::new()
+                .binary(binary)
+                .trailing_newline(TrailingNewline::Keep)
+        };
+        assert_eq!(
+            "<p>x</p>\n",
+            evaluate_with_options("test/hello.pug", keep(&with)).unwrap()
+        );
+        assert_eq!(
+            "<p>x</p>",
+            evaluate_string_with_options("p x".into(), keep(&without)).unwrap()
+        );
+
+        let crlf = PugOptions::new()
+            .binary(&without)
+            .trailing_newline(TrailingNewline::Ensure)
+            .newline(NewlineStyle::CrLf);
+        assert_eq!(
+            "<p>x</p>\r\n",
+            evaluate_string_with_options("p x".into(), crlf).unwrap()
+        );
+    }
+
+    #[test]
+    fn newline_styles_round_trip_outside_pre() {
+        let pretty = || PugOptions::new().pretty();
+        let plain = evaluate_with_options("test/newline.pug", pretty()).unwrap();
+        let crlf = evaluate_with_options("test/newline.pug", pretty().newline(NewlineStyle::CrLf))
+            .unwrap();
+        let lf =
+            evaluate_with_options("test/newline.pug", pretty().newline(NewlineStyle::Lf)).unwrap();
+
+        assert!(plain.contains("<pre>keep\nlines</pre>"));
+        assert!(crlf.contains("<pre>keep\nlines</pre>"));
+        assert!(crlf.contains("<div>\r\n  <p>one</p>"));
+        assert_eq!(plain, crlf.replace("\r\n", "\n"));
+        assert_eq!(plain, lf);
+        assert_eq!(plain, newline::convert(&crlf, NewlineStyle::Lf));
+    }
+
+    #[cfg(feature = "minify")]
+    #[test]
+    fn minify_keeps_preformatted_content() {
+        /// Each text node with its whitespace collapsed.
+        fn text(html: &str) -> Vec<String> {
+            html.split('<')
+                .filter_map(|part| part.split_once('>'))
+                .map(|(_, text)| text.split_whitespace().collect::<Vec<_>>().join(" "))
+                .filter(|text| !text.is_empty())
+                .collect()
+        }
+
+        let plain = evaluate("test/minify.pug").unwrap();
+        let minified =
+            evaluate_with_options("test/minify.pug", PugOptions::new().minify()).unwrap();
+
+        assert!(minified.len() < plain.len());
+        assert!(!minified.contains("<!--"));
+        assert!(minified.contains("class=card"));
+        for preserved in &[
+            "<pre>keep   this\n  exactly</pre>",
+            "<textarea>and   this</textarea>",
+            "<script>if (a  <  b) {   run();  }</script>",
+        ] {
+            assert!(plain.contains(preserved));
+            assert!(minified.contains(preserved), "{}", minified);
+        }
+        assert_eq!(text(&plain), text(&minified));
+    }
+
+    #[cfg(feature = "minify")]
+    #[test]
+    fn minify_and_pretty_are_rejected_before_spawning() {
+        let options = PugOptions::new()
+            .minify()
+            .pretty()
+            .binary("/nonexistent/pug");
+        assert!(matches!(
+            options.validate(),
+            Err(CompileError::InvalidOptions(_))
+        ));
+        assert!(matches!(
+            evaluate_string_with_options("p".into(), options),
+            Err(CompileError::InvalidOptions(_))
+        ));
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn include_pug_matches_a_runtime_compile() {
+        const HELLO: &str = include_pug!("test/hello.pug");
+        assert_eq!(evaluate("test/hello.pug").unwrap(), HELLO);
+
+        const PRETTY: &str = include_pug!("test/hello.pug", pretty, doctype = "html");
+        let options = PugOptions::new().pretty().doctype("html".into());
+        assert_eq!(
+            evaluate_with_options("test/hello.pug", options).unwrap(),
+            PRETTY
+        );
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn pug_macro_renders_inline_templates() {
+        assert_eq!("<h1>hello pug</h1>", pug!("h1 hello pug").unwrap());
+
+        let items = ["one", "two"];
+        let html = pug!(
+            r#"ul#list
+  li.item #{first}
+  li.item
+    b #{second}"#,
+            { "first": items[0], "second": items[1] }
+        );
+        assert_eq!(
+            r#"<ul id="list"><li class="item">one</li><li class="item"><b>two</b></li></ul>"#,
+            html.unwrap()
+        );
+    }
+
+    #[test]
+    fn io_errors_name_the_path() {
+        let error = evaluate("test/missing.pug").unwrap_err();
+        assert!(
+            matches!(&error, CompileError::IoAt { path, op: "open template", source }
+                if path == Path::new("test/missing.pug") && source.kind() == std::io::ErrorKind::NotFound),
+            "{:?}",
+            error
+        );
+        assert!(
+            error
+                .to_string()
+                .starts_with("failed to open template 'test/missing.pug': "),
+            "{}",
+            error
+        );
+
+        let locals = PathBuf::from("test/missing-locals.json");
+        let options = PugOptions::new().with_object(locals.clone());
+        match evaluate_with_options("test/hello.pug", options).unwrap_err() {
+            CompileError::IoAt { path, op, .. } => assert_eq!((locals, "read locals"), (path, op)),
+            other => panic!("{:?}", other),
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        std::fs::write(&out, "a file, not a directory").unwrap();
+        let report = build::compile_batch(["test/hello.pug"], out.join("pages"), PugOptions::new());
+        match &report.files[0].error {
+            Some(CompileError::IoAt { path, op, .. }) => {
+                assert_eq!((&out.join("pages"), "create directory"), (path, *op))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn works_with_anyhow() {
+        fn compile() -> anyhow::Result<String> {
+            Ok(evaluate("test/missing.pug")?)
+        }
+        let error = compile().unwrap_err();
+        let compile_error = error.downcast_ref::<CompileError>().unwrap();
+        assert!(matches!(compile_error, CompileError::IoAt { .. }));
+        assert!(error::Error::source(compile_error).is_some());
+
+        let boxed: Box<dyn error::Error + Send + Sync> =
+            CompileError::PugError("Pug:1:1 broken".into()).into();
+        match boxed.downcast::<CompileError>().map(|error| *error) {
+            Ok(CompileError::PugError(message)) => assert_eq!("Pug:1:1 broken", message),
+            _ => panic!("expected the pug message back"),
+        }
+    }
+
     #[test]
     fn evaluate_with_string_and_json() {
         assert_eq!(
@@ -287,4 +2896,366 @@ mod tests {
             .unwrap()
         )
     }
+
+    /// Every option as text, so whole option sets can be compared.
+    fn fields(options: &PugOptions) -> Vec<(&'static str, String)> {
+        let o = options.clone();
+        let preludes: Vec<String> = o
+            .preludes
+            .iter()
+            .map(|prelude| match prelude {
+                Prelude::Source(source) => source.clone(),
+                Prelude::File(path) => path.display().to_string(),
+            })
+            .collect();
+        vec![
+            ("version", o.version.to_string()),
+            ("object", format!("{:?}", o.object.map(String::from))),
+            ("path", format!("{:?}", o.path)),
+            ("out_dir", format!("{:?}", o.out_dir)),
+            ("no_debug", o.no_debug.to_string()),
+            ("client", o.client.to_string()),
+            ("stdin", o.stdin.to_string()),
+            ("pretty", o.pretty.to_string()),
+            ("doctype", format!("{:?}", o.doctype)),
+            ("binary", format!("{:?}", o.binary)),
+            ("timeout", format!("{:?}", o.timeout)),
+            ("log_locals", o.log_locals.to_string()),
+            ("newline", format!("{:?}", o.newline)),
+            ("trailing_newline", format!("{:?}", o.trailing_newline)),
+            ("preludes", format!("{:?}", preludes)),
+            ("flavor", format!("{:?}", o.flavor)),
+            ("source_name", format!("{:?}", o.source_name)),
+            ("reject_empty_input", o.reject_empty_input.to_string()),
+            ("normalize_newlines", o.normalize_newlines.to_string()),
+            ("front_matter", o.front_matter.to_string()),
+            ("locals_via_file", o.locals_via_file.to_string()),
+            ("warn_unused_locals", o.warn_unused_locals.to_string()),
+            ("sidecar_locals", o.sidecar_locals.to_string()),
+            ("reindent", format!("{:?}", o.reindent)),
+            ("output_naming", format!("{:?}", o.output_naming)),
+            ("hashed_output", format!("{:?}", o.hashed_output)),
+            ("executor", o.executor.is_some().to_string()),
+            ("locals_for", o.locals_for.is_some().to_string()),
+            ("retry", format!("{:?}", o.retry)),
+            ("memory_limit", format!("{:?}", o.limits.memory)),
+            ("cpu_time_limit", format!("{:?}", o.limits.cpu_time)),
+            ("max_output_bytes", format!("{:?}", o.limits.output)),
+            ("sandbox", o.sandbox.to_string()),
+            (
+                "preserve_error_colors",
+                o.limits.preserve_colors.to_string(),
+            ),
+            ("stderr_mode", format!("{:?}", o.stderr_mode)),
+            ("stderr_log", format!("{:?}", o.stderr_log)),
+            #[cfg(feature = "compress")]
+            ("precompress", format!("{:?}", o.precompress)),
+            ("assets", format!("{:?}", o.assets)),
+            ("version_check", format!("{:?}", o.version_check)),
+            #[cfg(feature = "encoding")]
+            ("output_encoding", format!("{:?}", o.output_encoding)),
+            #[cfg(feature = "node-api")]
+            ("filters", format!("{:?}", o.filters)),
+            #[cfg(feature = "schema")]
+            ("locals_schema", format!("{:?}", o.locals_schema)),
+            #[cfg(feature = "i18n")]
+            ("translations", format!("{:?}", o.translations)),
+            #[cfg(feature = "i18n")]
+            ("translations_key", format!("{:?}", o.translations_key)),
+        ]
+    }
+
+    fn fully_set() -> PugOptions {
+        let options = PugOptions::new()
+            .with_object(serde_json::json!({ "site": { "name": "Acme", "year": 1999 } }))
+            .with_path("base.pug")
+            .out_dir("base")
+            .pretty()
+            .doctype("html".into())
+            .binary("base-pug")
+            .timeout(Duration::from_secs(1))
+            .newline(NewlineStyle::Lf)
+            .prelude("//- base")
+            .flavor(CliFlavor::PugCli)
+            .source_name("base")
+            .reindent(Indent::Tabs)
+            .output_naming(OutputNaming::default())
+            .hashed_output(HashNaming::default())
+            .retry(RetryPolicy::new(2, Duration::from_millis(1)))
+            .memory_limit(1 << 30)
+            .cpu_time_limit(Duration::from_secs(1))
+            .max_output_bytes(1 << 20);
+        #[cfg(feature = "node-api")]
+        let options = options.filter("base", "base.js".into());
+        #[cfg(feature = "compress")]
+        let options = options.precompress(&[build::Compression::Gzip { level: 6 }]);
+        #[cfg(feature = "encoding")]
+        let options = options.output_encoding(OutputEncoding::Utf8Bom);
+        #[cfg(feature = "schema")]
+        let options = options.locals_schema(serde_json::json!({ "type": "object" }));
+        #[cfg(feature = "i18n")]
+        let options = options
+            .translations_fluent(std::path::Path::new("base"), "en")
+            .translations_key("base");
+        options
+    }
+
+    #[test]
+    fn merging_overrides_each_option_alone() {
+        let second = Duration::from_secs(2);
+        let overrides = vec![
+            ("version", PugOptions::new().version()),
+            (
+                "object",
+                PugOptions::new().with_object(serde_json::json!({ "site": 2 })),
+            ),
+            ("path", PugOptions::new().with_path("over.pug")),
+            ("out_dir", PugOptions::new().out_dir("over")),
+            ("no_debug", PugOptions::new().no_debug()),
+            ("client", PugOptions::new().client()),
+            ("stdin", PugOptions::new().stdin()),
+            ("pretty", PugOptions::new().set_flag(Flag::Pretty, false)),
+            ("doctype", PugOptions::new().doctype("xml".into())),
+            ("binary", PugOptions::new().binary("over-pug")),
+            ("timeout", PugOptions::new().timeout(second)),
+            ("log_locals", PugOptions::new().log_locals()),
+            ("newline", PugOptions::new().newline(NewlineStyle::CrLf)),
+            (
+                "trailing_newline",
+                PugOptions::new().trailing_newline(TrailingNewline::Trim),
+            ),
+            ("preludes", PugOptions::new().prelude("//- over")),
+            ("flavor", PugOptions::new().flavor(CliFlavor::Pug3)),
+            ("source_name", PugOptions::new().source_name("over")),
+            ("reject_empty_input", PugOptions::new().reject_empty_input()),
+            (
+                "normalize_newlines",
+                PugOptions::new().normalize_newlines(false),
+            ),
+            ("front_matter", PugOptions::new().front_matter()),
+            ("locals_via_file", PugOptions::new().locals_via_file()),
+            ("warn_unused_locals", PugOptions::new().warn_unused_locals()),
+            ("sidecar_locals", PugOptions::new().sidecar_locals(true)),
+            ("reindent", PugOptions::new().reindent(Indent::Spaces(4))),
+            (
+                "output_naming",
+                PugOptions::new().output_naming(OutputNaming::new("{name}.htm").unwrap()),
+            ),
+            (
+                "hashed_output",
+                PugOptions::new().hashed_output(HashNaming::new(4).unwrap()),
+            ),
+            ("executor", PugOptions::new().executor(ProcessExecutor)),
+            (
+                "locals_for",
+                PugOptions::new().locals_for(|_: &Path| Ok(serde_json::json!({}).into())),
+            ),
+            (
+                "retry",
+                PugOptions::new().retry(RetryPolicy::new(3, second)),
+            ),
+            ("memory_limit", PugOptions::new().memory_limit(2 << 30)),
+            ("cpu_time_limit", PugOptions::new().cpu_time_limit(second)),
+            (
+                "max_output_bytes",
+                PugOptions::new().max_output_bytes(2 << 20),
+            ),
+            ("sandbox", PugOptions::new().sandbox()),
+            (
+                "preserve_error_colors",
+                PugOptions::new().preserve_error_colors(),
+            ),
+            (
+                "stderr_mode",
+                PugOptions::new().stderr_mode(StderrMode::Inherit),
+            ),
+            ("stderr_log", PugOptions::new().stderr_to("over.log")),
+            #[cfg(feature = "compress")]
+            ("precompress", PugOptions::new().precompress_min_size(0)),
+            (
+                "assets",
+                PugOptions::new().assets(build::Assets::AllButTemplates),
+            ),
+            ("version_check", PugOptions::new().require_version(">=3")),
+            #[cfg(feature = "encoding")]
+            (
+                "output_encoding",
+                PugOptions::new().output_encoding(OutputEncoding::Utf8),
+            ),
+            #[cfg(feature = "node-api")]
+            (
+                "filters",
+                PugOptions::new().filter("over", "over.js".into()),
+            ),
+            #[cfg(feature = "schema")]
+            (
+                "locals_schema",
+                PugOptions::new().locals_schema_file("over.schema.json"),
+            ),
+            #[cfg(feature = "i18n")]
+            (
+                "translations",
+                PugOptions::new().translations_fluent(std::path::Path::new("over"), "fr"),
+            ),
+            #[cfg(feature = "i18n")]
+            (
+                "translations_key",
+                PugOptions::new().translations_key("over"),
+            ),
+        ];
+        let base = fields(&fully_set());
+        let names: Vec<_> = overrides.iter().map(|(name, _)| *name).collect();
+        let all: Vec<_> = base.iter().map(|(name, _)| *name).collect();
+        assert_eq!(all, names, "every option needs a case");
+
+        for (field, options) in overrides {
+            let expected = fields(&options);
+            let merged = fields(&fully_set().merge(options));
+            for ((name, merged), ((_, base), (_, over))) in
+                merged.iter().zip(base.iter().zip(&expected))
+            {
+                if name == &field {
+                    assert_ne!(base, merged, "{} wasn't overridden", name);
+                    assert_eq!(over, merged, "{} wasn't overridden", name);
+                } else {
+                    assert_eq!(base, merged, "{} changed overriding {}", name, field);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merging_nothing_changes_nothing() {
+        assert_eq!(
+            fields(&fully_set()),
+            fields(&fully_set().merge(PugOptions::new()))
+        );
+        assert_eq!(
+            fields(&PugOptions::new()),
+            fields(&PugOptions::new().merge(PugOptions::new()))
+        );
+    }
+
+    #[test]
+    fn merging_deep_merges_locals() {
+        let over = serde_json::json!({ "site": { "year": 2024 }, "page": "home" });
+        let merged = fully_set().merge(PugOptions::new().with_object(over));
+        assert_eq!(
+            Some(r#"{"page":"home","site":{"name":"Acme","year":2024}}"#.to_owned()),
+            merged.object.map(String::from)
+        );
+
+        let unreadable = PugOptions::new().with_object(PathBuf::from("test/missing.json"));
+        let merged = fully_set().merge(unreadable);
+        assert!(matches!(merged.object, Some(PugJsonObject::Path(_))));
+        let merged = PugOptions::new().merge(PugOptions::new().with_object(r#"{"a": 1}"#));
+        assert_eq!(
+            Some(r#"{"a": 1}"#.to_owned()),
+            merged.object.map(String::from)
+        );
+    }
+
+    #[test]
+    fn js_module_locals() {
+        let module = PugJsonObject::JsModule("test/locals.js".into());
+        let options = PugOptions::new().with_object(module.clone());
+        let html = evaluate_string_with_options("p #{language} #{year}".into(), options);
+        assert_eq!("<p>pug 2024</p>", html.unwrap());
+
+        for bad in ["test/hello.pug", "test/missing.js"] {
+            let options = PugOptions::new().with_object(PugJsonObject::JsModule(bad.into()));
+            let error = evaluate_string_with_options("p".into(), options).unwrap_err();
+            assert!(
+                matches!(error, CompileError::InvalidOptions(_)),
+                "{:?}",
+                error
+            );
+        }
+
+        let base = PugOptions::new().with_object(module);
+        let merged = base.merge(PugOptions::new().with_object(serde_json::json!({ "a": 1 })));
+        let error = merged.validate().unwrap_err();
+        assert!(
+            error.to_string().contains("test/locals.js can't be merged"),
+            "{}",
+            error
+        );
+        let replaced = PugOptions::new()
+            .merge(PugOptions::new().with_object(PugJsonObject::JsModule("test/locals.js".into())));
+        assert!(replaced.validate().is_ok());
+    }
+
+    /// Records the `--obj` file pug is given and what's in it while it runs.
+    #[derive(Clone, Default)]
+    struct PeekAtLocals(Arc<std::sync::Mutex<Vec<(PathBuf, String, u32)>>>);
+
+    impl Executor for PeekAtLocals {
+        fn run(
+            &self,
+            program: &std::path::Path,
+            args: &[String],
+            stdin: &[u8],
+        ) -> std::io::Result<Output> {
+            let at = args.iter().position(|arg| arg == "--obj").unwrap();
+            let path = PathBuf::from(&args[at + 1]);
+            let metadata = std::fs::metadata(&path)?;
+            #[cfg(unix)]
+            let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777;
+            #[cfg(not(unix))]
+            let mode = 0o600;
+            let contents = std::fs::read_to_string(&path)?;
+            self.0.lock().unwrap().push((path, contents, mode));
+            executor::MockExecutor::succeeding("<p>ok</p>").run(program, args, stdin)
+        }
+    }
+
+    #[test]
+    fn locals_via_file_keep_them_off_the_command_line() {
+        let locals = serde_json::json!({ "token": "s3cret" });
+        let peek = PeekAtLocals::default();
+        let options = PugOptions::new()
+            .with_object(locals.clone())
+            .locals_via_file()
+            .executor(peek.clone());
+        evaluate_string_with_options("p".into(), options).unwrap();
+        let (path, contents, mode) = peek.0.lock().unwrap().pop().unwrap();
+        assert_eq!(locals.to_string(), contents);
+        assert_eq!(0o600, mode);
+        assert!(path.starts_with(std::env::temp_dir()));
+        assert!(!path.exists(), "{} was left behind", path.display());
+
+        let options = PugOptions::new().with_object(locals).locals_via_file();
+        let html = evaluate_string_with_options("p #{token}".into(), options.clone());
+        assert_eq!("<p>s3cret</p>", html.unwrap());
+        let args = Invocation::string("p".into(), options).unwrap().args;
+        assert!(!args.iter().any(|arg| arg.contains("s3cret")), "{:?}", args);
+    }
+
+    #[test]
+    fn debug_and_display_redact_locals() {
+        let options = PugOptions::new()
+            .with_object(serde_json::json!({ "user": "ada", "token": "s3cret" }))
+            .pretty();
+        let debug = format!("{:?}", options);
+        assert!(!debug.contains("s3cret"), "{}", debug);
+        assert!(
+            debug.contains("object: Some(Json(<redacted, 31 bytes, 2 top-level keys>))"),
+            "{}",
+            debug
+        );
+        assert!(debug.contains("pretty: true"), "{}", debug);
+        let unredacted = format!("{:?}", options.debug_unredacted());
+        assert!(unredacted.contains("s3cret"), "{}", unredacted);
+        assert_eq!(
+            "--obj <redacted, 31 bytes, 2 top-level keys> --pretty",
+            options.to_string()
+        );
+
+        let raw = PugOptions::new().with_object("{\"token\":\"s3cret\"}");
+        assert!(!format!("{:?}", raw).contains("s3cret"));
+        assert_eq!("--obj <redacted, 18 bytes>", raw.to_string());
+        let file = PugOptions::new().with_object(PathBuf::from("locals.json"));
+        assert!(format!("{:?}", file).contains("Path(\"locals.json\")"));
+        assert_eq!("--obj locals.json", file.to_string());
+    }
 }
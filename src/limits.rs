@@ -0,0 +1,258 @@
+//! Memory, CPU and output limits for the pug process.
+
+use crate::CompileError;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+
+/// Address space allowed on top of `memory_limit` on Unix, for what V8
+/// reserves at startup; node doesn't start with much less than a gigabyte.
+#[cfg(unix)]
+const ADDRESS_SPACE_ALLOWANCE: u64 = 2 << 30;
+
+/// The limits set with `PugOptions::memory_limit`, `cpu_time_limit` and
+/// `max_output_bytes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) memory: Option<u64>,
+    pub(crate) cpu_time: Option<Duration>,
+    pub(crate) output: Option<u64>,
+    /// Run pug with nothing of this process's environment but `PATH`, for
+    /// `PugOptions::sandbox`.
+    pub(crate) clear_env: bool,
+    /// Leave pug's colored output alone, for `PugOptions::preserve_error_colors`.
+    pub(crate) preserve_colors: bool,
+}
+
+impl Limits {
+    /// A command running `program` under these limits.
+    pub(crate) fn command(&self, program: &Path) -> Command {
+        let mut command = Command::new(program);
+        if self.clear_env {
+            command.env_clear();
+            // enough to find node through pug's `#!/usr/bin/env node`
+            for name in ["PATH", "SYSTEMROOT"] {
+                if let Some(value) = std::env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        if !self.preserve_colors {
+            command.env("FORCE_COLOR", "0").env("NO_COLOR", "1");
+        }
+        if let Some(memory) = self.memory {
+            let mut node_options = if self.clear_env {
+                Default::default()
+            } else {
+                std::env::var_os("NODE_OPTIONS").unwrap_or_default()
+            };
+            if !node_options.is_empty() {
+                node_options.push(" ");
+            }
+            node_options.push(format!("--max-old-space-size={}", (memory >> 20).max(1)));
+            command.env("NODE_OPTIONS", node_options);
+        }
+        #[cfg(unix)]
+        self.set_rlimits(&mut command);
+        command
+    }
+
+    #[cfg(unix)]
+    fn set_rlimits(&self, command: &mut Command) {
+        use std::os::unix::process::CommandExt;
+
+        if self.memory.is_none() && self.cpu_time.is_none() {
+            return;
+        }
+        let address_space = self
+            .memory
+            .map(|memory| memory.saturating_add(ADDRESS_SPACE_ALLOWANCE));
+        // the soft limit sends SIGXCPU; the hard one a second later, SIGKILL
+        let cpu_time = self
+            .cpu_time
+            .map(|cpu_time| cpu_time.as_secs() + u64::from(cpu_time.subsec_nanos() > 0))
+            .map(|seconds| seconds.max(1));
+        let limit = move |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            // SAFETY: setrlimit is async-signal-safe and `limit` outlives the call
+            if unsafe { libc::setrlimit(resource, &limit) } == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        };
+        // SAFETY: the closure only calls setrlimit, which is safe between fork
+        // and exec
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(bytes) = address_space {
+                    limit(libc::RLIMIT_AS, bytes, bytes)?;
+                }
+                if let Some(seconds) = cpu_time {
+                    limit(libc::RLIMIT_CPU, seconds, seconds + 1)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// The limit a failed compile ran into, judged by how pug died.
+    pub(crate) fn exceeded(&self, status: ExitStatus, stderr: &str) -> Option<CompileError> {
+        let signal = signal(status);
+        if let Some(cpu_time) = self.cpu_time {
+            if signal == Some(SIGXCPU) || signal == Some(SIGKILL) {
+                return Some(CompileError::CpuTimeLimit(cpu_time));
+            }
+        }
+        let memory = self.memory?;
+        let out_of_memory = stderr.contains("heap out of memory")
+            || stderr.contains("Allocation failed")
+            || matches!(signal, Some(SIGABRT) | Some(SIGSEGV) | Some(SIGKILL));
+        if out_of_memory {
+            Some(CompileError::MemoryLimit(memory))
+        } else {
+            None
+        }
+    }
+
+    #[cfg(test)]
+    fn env(&self, name: &str) -> Option<std::ffi::OsString> {
+        self.command(Path::new("node"))
+            .get_envs()
+            .find(|(set, _)| *set == name)
+            .and_then(|(_, value)| value.map(ToOwned::to_owned))
+    }
+}
+
+/// What an async pipe read fails with once more than `max_output_bytes` have
+/// come through it, for `pipe_error` to turn back into
+/// `CompileError::OutputLimit`.
+#[derive(Debug)]
+struct OverLimit(u64);
+
+impl fmt::Display for OverLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pug wrote more than {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for OverLimit {}
+
+/// Fails once `buffer`, read from one of pug's pipes, holds more than
+/// `limit` bytes.
+#[cfg_attr(not(any(feature = "tokio", feature = "async-std")), allow(dead_code))]
+pub(crate) fn check_read(buffer: &[u8], limit: Option<u64>) -> io::Result<()> {
+    match limit {
+        Some(limit) if buffer.len() as u64 > limit => Err(io::Error::other(OverLimit(limit))),
+        _ => Ok(()),
+    }
+}
+
+/// `error` as a `CompileError`: `OutputLimit` if `check_read` failed.
+#[cfg_attr(not(any(feature = "tokio", feature = "async-std")), allow(dead_code))]
+pub(crate) fn pipe_error(error: io::Error) -> CompileError {
+    match error.get_ref().and_then(|e| e.downcast_ref::<OverLimit>()) {
+        Some(OverLimit(limit)) => CompileError::OutputLimit(*limit),
+        None => CompileError::Io(error),
+    }
+}
+
+const SIGABRT: i32 = 6;
+const SIGKILL: i32 = 9;
+const SIGSEGV: i32 = 11;
+const SIGXCPU: i32 = 24;
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(&status)
+}
+
+/// Windows has no signals; the rlimits aren't set there either.
+#[cfg(not(unix))]
+fn signal(_: ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_binary;
+    use crate::{evaluate_string_with_options, PugOptions};
+    use std::time::Instant;
+
+    fn node(dir: &Path, name: &str, script: &str) -> std::path::PathBuf {
+        let script = format!("cat > /dev/null\nexec node -e '{}'\n", script);
+        fake_binary(dir, name, &script)
+    }
+
+    #[test]
+    fn passes_the_heap_limit_to_node() {
+        let limits = Limits {
+            memory: Some(64 << 20),
+            cpu_time: None,
+            ..Limits::default()
+        };
+        let node_options = limits.env("NODE_OPTIONS").unwrap();
+        assert!(node_options
+            .to_string_lossy()
+            .ends_with("--max-old-space-size=64"));
+        assert_eq!(None, Limits::default().env("NODE_OPTIONS"));
+    }
+
+    #[test]
+    fn turns_colors_off_unless_asked() {
+        let limits = Limits::default();
+        assert_eq!(Some("0".into()), limits.env("FORCE_COLOR"));
+        assert_eq!(Some("1".into()), limits.env("NO_COLOR"));
+        let sandboxed = Limits {
+            clear_env: true,
+            ..Limits::default()
+        };
+        assert_eq!(Some("1".into()), sandboxed.env("NO_COLOR"));
+        let colored = Limits {
+            preserve_colors: true,
+            ..Limits::default()
+        };
+        assert_eq!(None, colored.env("FORCE_COLOR"));
+        assert_eq!(None, colored.env("NO_COLOR"));
+    }
+
+    #[test]
+    fn memory_hungry_templates_fail_fast() {
+        let dir = tempfile::tempdir().unwrap();
+        let hungry = node(
+            dir.path(),
+            "hungry-pug",
+            "let s = []; for (;;) s.push(\"x\".repeat(1 << 16) + s.length)",
+        );
+        let started = Instant::now();
+        let options = PugOptions::new().binary(hungry).memory_limit(16 << 20);
+        let error = evaluate_string_with_options("p".into(), options).unwrap_err();
+        assert!(
+            matches!(error, CompileError::MemoryLimit(limit) if limit == 16 << 20),
+            "{:?}",
+            error
+        );
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn busy_templates_run_out_of_cpu_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let busy = node(dir.path(), "busy-pug", "for (;;) {}");
+        let options = PugOptions::new()
+            .binary(busy)
+            .cpu_time_limit(Duration::from_millis(500));
+        let error = evaluate_string_with_options("p".into(), options).unwrap_err();
+        assert!(
+            matches!(error, CompileError::CpuTimeLimit(_)),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Rendering one template once per locale.
+
+use crate::{evaluate_with_options, CompileError, PugJsonObject, PugOptions};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// Renders `template` once for each locale, its locals deep-merged over the
+/// locals in `base_options` and a `locale` local set to its name. Results
+/// come back in the order the locales were given.
+///
+/// Objects are merged key by key; any other value in a locale replaces the
+/// base's. Locals given as a raw string or file must be JSON here.
+pub fn render_locales<I>(
+    template: impl Into<PathBuf>,
+    base_options: PugOptions,
+    locales: I,
+) -> Vec<(String, Result<String, CompileError>)>
+where
+    I: IntoIterator<Item = (String, PugJsonObject)>,
+{
+    let template = template.into();
+    locales
+        .into_iter()
+        .map(|(locale, locals)| {
+            let options = localized(&base_options, &locale, locals);
+            let html = options.and_then(|options| evaluate_with_options(&template, options));
+            (locale, html)
+        })
+        .collect()
+}
+
+fn localized(
+    base_options: &PugOptions,
+    locale: &str,
+    locals: PugJsonObject,
+) -> Result<PugOptions, CompileError> {
+    let mut merged = match &base_options.object {
+        Some(base) => to_json(base)?,
+        None => Value::Object(Default::default()),
+    };
+    merge(&mut merged, to_json(&locals)?);
+    match &mut merged {
+        Value::Object(merged) => {
+            merged.insert("locale".into(), locale.into());
+        }
+        _ => {
+            return Err(CompileError::InvalidOptions(format!(
+                "locals for {} aren't an object",
+                locale
+            )))
+        }
+    }
+    Ok(base_options.clone().with_object(merged))
+}
+
+pub(crate) fn to_json(object: &PugJsonObject) -> Result<Value, CompileError> {
+    let invalid = |e: serde_json::Error| {
+        CompileError::InvalidOptions(format!("locals aren't valid JSON: {}", e))
+    };
+    match object {
+        PugJsonObject::Json(value) => Ok(value.clone()),
+        PugJsonObject::Raw(raw) => serde_json::from_str(raw).map_err(invalid),
+        PugJsonObject::Path(path) => {
+            let raw =
+                std::fs::read_to_string(path).map_err(CompileError::io_at("read locals", path))?;
+            serde_json::from_str(&raw).map_err(invalid)
+        }
+        PugJsonObject::JsModule(module) => Err(CompileError::InvalidOptions(unmergeable(module))),
+    }
+}
+
+/// Why locals from the JS module `module` can't be merged.
+pub(crate) fn unmergeable(module: &Path) -> String {
+    format!(
+        "locals from the JS module {} can't be merged with other locals",
+        module.display()
+    )
+}
+
+/// Merges `over` into `base`, recursing into objects both have.
+pub(crate) fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_each_locale_in_order() {
+        let base = PugOptions::new().with_object(json!({
+            "greeting": "Hello",
+            "site": { "name": "Acme", "tagline": "Since 1999" }
+        }));
+        let locales = vec![
+            ("fr".to_owned(), json!({ "greeting": "Bonjour" }).into()),
+            (
+                "de".to_owned(),
+                r#"{ "greeting": "Hallo", "site": { "name": "Acme GmbH" } }"#.into(),
+            ),
+        ];
+
+        let rendered = render_locales("test/locale.pug", base, locales);
+        let rendered: Vec<(&str, String)> = rendered
+            .iter()
+            .map(|(locale, html)| (locale.as_str(), html.as_ref().unwrap().clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "fr",
+                    "<h1>Bonjour</h1><p>fr: Acme, Since 1999</p>".to_owned()
+                ),
+                (
+                    "de",
+                    "<h1>Hallo</h1><p>de: Acme GmbH, Since 1999</p>".to_owned()
+                ),
+            ],
+            rendered
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Which locals a template reads, for catching a local renamed on one side
+//! only.
+//!
+//! This is a scan of the source, not a parse. Identifiers are picked out of
+//! `#{}` and `!{}` interpolations, attribute values, `=` and `-` code, mixin
+//! call arguments, and `each`, `for`, `if`, `else if`, `unless`, `while`,
+//! `case` and `when` expressions; properties (`page.title`) are skipped
+//! apart from `locals.title`. So a local only reached by computed access
+//! (`locals[key]`) counts as unused, and a mixin parameter or a `- var`
+//! with the same name as a local counts as the local being used.
+
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// The top-level keys of `locals` that `template_source` never mentions,
+/// sorted. See the module docs for what counts as a mention.
+pub fn unused_locals(template_source: &str, locals: &Value) -> Vec<String> {
+    let referenced = referenced(template_source);
+    match locals.as_object() {
+        Some(locals) => locals
+            .keys()
+            .filter(|key| !referenced.contains(key.as_str()))
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The inverse of `unused_locals`: names `template_source` reads that
+/// neither `locals` nor the template itself (`each`, `- var`, mixin
+/// parameters) provides, and that aren't JavaScript globals, sorted.
+pub fn undefined_locals(template_source: &str, locals: &Value) -> Vec<String> {
+    let declared = declared(template_source);
+    referenced(template_source)
+        .into_iter()
+        .filter(|name| locals.get(name).is_none())
+        .filter(|name| !declared.contains(name) && !GLOBALS.contains(&name.as_str()))
+        .collect()
+}
+
+const KEYWORDS: &[&str] = &[
+    "break",
+    "case",
+    "catch",
+    "const",
+    "continue",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "in",
+    "instanceof",
+    "let",
+    "new",
+    "null",
+    "of",
+    "return",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+];
+
+const GLOBALS: &[&str] = &[
+    "Array",
+    "Boolean",
+    "Date",
+    "Infinity",
+    "JSON",
+    "Math",
+    "NaN",
+    "Number",
+    "Object",
+    "RegExp",
+    "String",
+    "console",
+    "decodeURIComponent",
+    "encodeURIComponent",
+    "isNaN",
+    "locals",
+    "parseFloat",
+    "parseInt",
+    "undefined",
+];
+
+/// Every name read in an expression position.
+fn referenced(source: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for expression in expressions(source) {
+        identifiers(&expression, &mut names);
+    }
+    names
+}
+
+/// The JavaScript in each line of `source` that pug would evaluate.
+fn expressions(source: &str) -> Vec<String> {
+    let mut expressions = Vec::new();
+    let mut code_block: Option<usize> = None;
+    for line in source.lines() {
+        let indent = line.len() - line.trim_start().len();
+        let text = line.trim();
+        if let Some(block) = code_block {
+            if text.is_empty() || indent > block {
+                expressions.push(text.to_owned());
+                continue;
+            }
+            code_block = None;
+        }
+        interpolations(text, &mut expressions);
+        if text.starts_with("//") {
+            continue;
+        }
+        if text == "-" {
+            code_block = Some(indent);
+            continue;
+        }
+        if let Some(code) = text.strip_prefix('-') {
+            expressions.push(code.to_owned());
+            continue;
+        }
+        let keyword = text.split_whitespace().next().unwrap_or_default();
+        let rest = text[keyword.len()..].trim();
+        match keyword {
+            "if" | "unless" | "while" | "case" | "when" => expressions.push(rest.to_owned()),
+            "else" => expressions.push(rest.trim_start_matches("if").to_owned()),
+            "each" | "for" => {
+                if let Some((_, list)) = rest.split_once(" in ") {
+                    expressions.push(list.to_owned());
+                }
+            }
+            _ => tag_expressions(text, &mut expressions),
+        }
+    }
+    expressions
+}
+
+/// What's inside each `#{}` and `!{}` in `text`.
+fn interpolations(text: &str, expressions: &mut Vec<String>) {
+    let mut rest = text;
+    while let Some(at) = rest.find("{") {
+        let opens = at > 0 && matches!(rest.as_bytes()[at - 1], b'#' | b'!');
+        if let (true, Some(end)) = (opens, rest[at..].find('}')) {
+            expressions.push(rest[at + 1..at + end].to_owned());
+            rest = &rest[at + end..];
+        } else {
+            rest = &rest[at + 1..];
+        }
+    }
+}
+
+/// The attribute values, mixin arguments and `=` code on a tag line.
+fn tag_expressions(text: &str, expressions: &mut Vec<String>) {
+    let head_end = text
+        .find(|c: char| c.is_whitespace() || c == '(' || c == '=' || c == '!')
+        .unwrap_or(text.len());
+    let mut rest = &text[head_end..];
+    if let Some(inner) = rest.strip_prefix('(') {
+        let end = closing_paren(inner);
+        let attributes = &inner[..end];
+        if text.starts_with('+') {
+            expressions.push(attributes.to_owned());
+        } else {
+            for attribute in split_top_level(attributes) {
+                if let Some((_, value)) = attribute.split_once('=') {
+                    expressions.push(value.trim_start_matches('=').to_owned());
+                }
+            }
+        }
+        rest = inner.get(end + 1..).unwrap_or_default();
+    }
+    let rest = rest.trim_start();
+    if let Some(code) = rest.strip_prefix("!=").or_else(|| rest.strip_prefix('=')) {
+        expressions.push(code.to_owned());
+    }
+}
+
+/// Where the `)` closing an already opened `(` is, skipping nested ones and
+/// strings.
+fn closing_paren(text: &str) -> usize {
+    let mut depth = 0;
+    let mut quote = None;
+    for (at, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return at,
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// `attributes` split at the commas between them.
+fn split_top_level(attributes: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0, None, 0);
+    for (at, c) in attributes.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(&attributes[start..at]);
+                start = at + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&attributes[start..]);
+    parts
+}
+
+/// The names in `expression` that aren't properties, keywords or inside
+/// strings; `locals.name` counts as `name`.
+fn identifiers(expression: &str, names: &mut BTreeSet<String>) {
+    let chars: Vec<char> = expression.chars().collect();
+    let is_start = |c: char| c.is_alphabetic() || c == '_' || c == '$';
+    let mut quote = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(q) = quote {
+            if c == '\\' {
+                i += 1;
+            } else if c == q {
+                quote = None;
+            }
+            i += 1;
+            continue;
+        }
+        if matches!(c, '"' | '\'' | '`') {
+            quote = Some(c);
+            i += 1;
+            continue;
+        }
+        if !is_start(c) || (i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_')) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+        {
+            i += 1;
+        }
+        let name: String = chars[start..i].iter().collect();
+        let before = chars[..start].iter().rev().find(|c| !c.is_whitespace());
+        let property = before == Some(&'.');
+        let after = chars[i..].iter().find(|c| !c.is_whitespace());
+        // an object literal's key, `{ name: value }`
+        let key = after == Some(&':') && matches!(before, Some('{') | Some(','));
+        if property {
+            let owner = chars[..start]
+                .iter()
+                .rev()
+                .skip_while(|c| c.is_whitespace());
+            let owner: String = owner
+                .skip(1)
+                .take(6)
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .collect();
+            if owner == "locals" {
+                names.insert(name);
+            }
+        } else if !key && !KEYWORDS.contains(&name.as_str()) {
+            names.insert(name);
+        }
+    }
+}
+
+/// Names the template gives values itself.
+fn declared(source: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for line in source.lines() {
+        let text = line.trim();
+        let keyword = text.split_whitespace().next().unwrap_or_default();
+        let rest = text[keyword.len()..].trim();
+        match keyword {
+            "each" | "for" => {
+                if let Some((bound, _)) = rest.split_once(" in ") {
+                    identifiers(bound, &mut names);
+                }
+            }
+            "mixin" => {
+                if let Some((_, params)) = rest.split_once('(') {
+                    identifiers(params.trim_end_matches(')'), &mut names);
+                }
+            }
+            _ => {
+                let code = text.strip_prefix('-').unwrap_or(text).trim();
+                for declaration in ["var ", "let ", "const "] {
+                    if let Some(rest) = code.strip_prefix(declaration) {
+                        let name: String = rest
+                            .chars()
+                            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                            .collect();
+                        names.insert(name);
+                    }
+                }
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn unused(source: &str, keys: &[&str]) -> Vec<String> {
+        let locals: serde_json::Map<String, Value> =
+            keys.iter().map(|key| (key.to_string(), json!(1))).collect();
+        unused_locals(source, &Value::Object(locals))
+    }
+
+    #[test]
+    fn finds_locals_in_each_position() {
+        for (source, key) in [
+            ("p Hello #{name}!", "name"),
+            ("div !{html}", "html"),
+            ("a(href=url, class='x') link", "url"),
+            ("a(href='/' + slug) link", "slug"),
+            ("img(src=images[0], alt=`${title}`)", "images"),
+            ("ul\n  each item, i in items\n    li= item", "items"),
+            ("if user.admin\n  p admin", "user"),
+            ("else if guest\n  p guest", "guest"),
+            ("unless hidden\n  p shown", "hidden"),
+            ("p= message", "message"),
+            ("p!= raw", "raw"),
+            ("+card(product, 2)", "product"),
+            ("case kind\n  when 'a'\n    p a", "kind"),
+            ("p #{locals.greeting}", "greeting"),
+            ("- var shown = count * 2\np= shown", "count"),
+        ] {
+            assert!(unused(source, &[key]).is_empty(), "{} in {:?}", key, source);
+        }
+    }
+
+    #[test]
+    fn reports_what_is_never_read() {
+        let source = "h1 #{page.title}\n//- nothing about #{old}\np total\np(data-x='price')";
+        assert_eq!(
+            vec!["price", "title", "total"],
+            unused(source, &["page", "title", "total", "price"])
+        );
+        // a `-` block's code counts, like a one-line `-`
+        let block = "-\n  var label = prefix + suffix\n  var n = 1\np= label";
+        assert!(unused(block, &["prefix", "suffix"]).is_empty());
+    }
+
+    #[test]
+    fn reports_names_nothing_provides() {
+        let source = "- var total = 0\neach item in items\n  p #{item.name} #{Math.round(total)} #{currency}\nmixin price(amount)\n  span= amount";
+        assert_eq!(
+            vec!["currency"],
+            undefined_locals(source, &json!({ "items": [] }))
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! `pug-rs`, a command line front end for this crate.
+//!
+//! Exits with 0 when everything compiled, 1 when a template failed to
+//! compile, and 2 for bad arguments. `pug-rs doctor` checks the install
+//! instead, exiting with 1 if something is wrong.
+
+use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
+use pug_cli::tokio::{watch_stream_with, WatchOptions};
+use pug_cli::{doctor_with, evaluate_with_options, CompileError, PugJsonObject, PugOptions};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "pug-rs",
+    version,
+    about = "Compile pug templates",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Templates to compile; directories are searched for `.pug` files.
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Locals, as inline JSON or the path of a JSON file; `raw:` or `@`
+    /// in front forces one or the other.
+    #[arg(short = 'O', long = "obj", value_name = "JSON|PATH")]
+    obj: Option<String>,
+
+    /// Writes each template to DIR as `.html` instead of printing it.
+    #[arg(short = 'o', long = "out", value_name = "DIR")]
+    out: Option<PathBuf>,
+
+    #[arg(short = 'P', long)]
+    pretty: bool,
+
+    #[arg(short = 'D', long = "no-debug")]
+    no_debug: bool,
+
+    #[arg(long, value_name = "DOCTYPE")]
+    doctype: Option<String>,
+
+    /// Runs this pug executable instead of the one on PATH.
+    #[arg(long, value_name = "PATH")]
+    pug: Option<PathBuf>,
+
+    /// After compiling, keeps recompiling templates in the given
+    /// directories as they change. Output goes to `--out`, or next to each
+    /// template.
+    #[arg(short = 'w', long)]
+    watch: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Checks that pug, node and the out dir are usable.
+    Doctor {
+        /// Also checks that DIR can be written to.
+        #[arg(short = 'o', long = "out", value_name = "DIR")]
+        out: Option<PathBuf>,
+
+        /// Checks this pug executable instead of the one on PATH.
+        #[arg(long, value_name = "PATH")]
+        pug: Option<PathBuf>,
+    },
+}
+
+const COMPILE_FAILED: u8 = 1;
+const USAGE: u8 = 2;
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if let Some(Commands::Doctor { out, pug }) = args.command {
+        return doctor(out, pug);
+    }
+    let options = match pug_options(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("pug-rs: {}", message);
+            return ExitCode::from(USAGE);
+        }
+    };
+    if args.watch {
+        if let Some(file) = args.inputs.iter().find(|input| !input.is_dir()) {
+            eprintln!("pug-rs: --watch needs directories, not {}", file.display());
+            return ExitCode::from(USAGE);
+        }
+    }
+
+    let mut failed = false;
+    for (root, template) in templates(&args.inputs) {
+        let result = evaluate_with_options(&template, options.clone())
+            .and_then(|html| write(&args, root.as_deref(), &template, &html));
+        if let Err(error) = result {
+            eprintln!("{}", error.describe(&template));
+            failed = true;
+        }
+    }
+
+    if args.watch {
+        watch(&args, options);
+    }
+    if failed {
+        ExitCode::from(COMPILE_FAILED)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Exits with 1 if any check failed.
+fn doctor(out: Option<PathBuf>, pug: Option<PathBuf>) -> ExitCode {
+    let mut options = PugOptions::new();
+    if let Some(out) = out {
+        options = options.out_dir(out);
+    }
+    if let Some(pug) = pug {
+        options = options.binary(pug);
+    }
+    let report = doctor_with(options);
+    print!("{}", report);
+    if report.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(COMPILE_FAILED)
+    }
+}
+
+fn pug_options(args: &Args) -> Result<PugOptions, String> {
+    let mut options = PugOptions::new();
+    if let Some(obj) = &args.obj {
+        let locals: PugJsonObject = obj.parse().map_err(|e| format!("--obj: {}", e))?;
+        options = options.with_object(locals);
+    }
+    if args.pretty {
+        options = options.pretty();
+    }
+    if args.no_debug {
+        options = options.no_debug();
+    }
+    if let Some(doctype) = &args.doctype {
+        options = options.doctype(doctype.clone());
+    }
+    if let Some(pug) = &args.pug {
+        options = options.binary(pug);
+    }
+    Ok(options)
+}
+
+/// Each template with the directory it was found under, if any, so output
+/// can keep its relative path.
+fn templates(inputs: &[PathBuf]) -> Vec<(Option<PathBuf>, PathBuf)> {
+    let mut templates = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let found = pug_cli::build::templates_in(input);
+            templates.extend(found.into_iter().map(|path| (Some(input.clone()), path)));
+        } else {
+            templates.push((None, input.clone()));
+        }
+    }
+    templates
+}
+
+fn write(
+    args: &Args,
+    root: Option<&Path>,
+    template: &Path,
+    html: &str,
+) -> Result<(), CompileError> {
+    let out = match &args.out {
+        Some(out) => out,
+        None => {
+            let mut stdout = io::stdout();
+            return writeln!(stdout, "{}", html).map_err(CompileError::Io);
+        }
+    };
+    let relative = match root {
+        Some(root) => template.strip_prefix(root).unwrap_or(template),
+        None => Path::new(template.file_name().unwrap_or_default()),
+    };
+    let page = out.join(relative).with_extension("html");
+    if let Some(parent) = page.parent() {
+        fs::create_dir_all(parent).map_err(|source| CompileError::IoAt {
+            path: parent.to_owned(),
+            op: "create directory",
+            source,
+        })?;
+    }
+    fs::write(&page, html).map_err(|source| CompileError::IoAt {
+        path: page.clone(),
+        op: "write page",
+        source,
+    })
+}
+
+fn watch(args: &Args, options: PugOptions) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("pug-rs: couldn't start the watcher: {}", e);
+            return;
+        }
+    };
+    runtime.block_on(async {
+        let streams = args.inputs.iter().map(|dir| {
+            let output_dir = args.out.clone().unwrap_or_else(|| dir.clone());
+            let watch_options = WatchOptions::new().output_dir(output_dir);
+            watch_stream_with(dir, options.clone(), watch_options)
+        });
+        let mut events = futures_util::stream::select_all(streams);
+        while let Some(event) = events.next().await {
+            match event.result {
+                Ok(_) => eprintln!("compiled {} in {:?}", event.path.display(), event.duration),
+                Err(error) => eprintln!("{}", error.describe(&event.path)),
+            }
+        }
+    });
+}
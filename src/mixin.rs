@@ -0,0 +1,133 @@
+//! Rendering one mixin from a library file, for previews and component tests.
+
+use crate::{evaluate_string_with_options, CompileError, PugErrorKind, PugOptions};
+use serde_json::Value;
+use std::path::Path;
+
+/// What the calling template is called, next to the library, for resolving
+/// the `include` and the library's own includes.
+const WRAPPER: &str = "__render_mixin.pug";
+
+/// Renders `+mixin(args...)` from the mixins in `library` and returns the
+/// fragment it produces. Each argument is passed as the JavaScript literal
+/// for its JSON.
+///
+/// The call is made from a template next to the library, which replaces any
+/// `PugOptions::with_path`; `basedir` still applies to absolute includes. A
+/// mixin the library doesn't define fails with `CompileError::UnknownMixin`.
+pub fn render_mixin(
+    library: &Path,
+    mixin: &str,
+    args: &[Value],
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    if mixin.is_empty() || !mixin.chars().all(is_name) {
+        return Err(CompileError::InvalidOptions(format!(
+            "{:?} isn't a mixin name",
+            mixin
+        )));
+    }
+    let name = match library.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(CompileError::InvalidOptions(format!(
+                "{} isn't a mixin library",
+                library.display()
+            )))
+        }
+    };
+    let dir = library.parent().unwrap_or_else(|| Path::new(""));
+    let args: Vec<String> = args.iter().map(js_literal).collect();
+    let call = format!("include {}\n+{}({})\n", name, mixin, args.join(", "));
+    let options = options.with_path(dir.join(WRAPPER));
+    evaluate_string_with_options(call, options).map_err(|error| {
+        if is_missing_mixin(&error) {
+            CompileError::UnknownMixin {
+                library: library.to_owned(),
+                mixin: mixin.to_owned(),
+            }
+        } else {
+            error
+        }
+    })
+}
+
+/// `value` as a JavaScript expression. JSON is one already, apart from the
+/// line and paragraph separators older engines won't take in a string.
+fn js_literal(value: &Value) -> String {
+    value
+        .to_string()
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+}
+
+/// Whether `error` is pug calling an undefined mixin from the wrapper, which
+/// it only finds out at runtime.
+fn is_missing_mixin(error: &CompileError) -> bool {
+    match error.kind() {
+        PugErrorKind::JavaScriptRuntime {
+            message,
+            frame: Some(frame),
+            ..
+        } => {
+            frame.file.ends_with(WRAPPER)
+                && message.contains("pug_mixins")
+                && message.contains("is not a function")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    const LIBRARY: &str = "mixin card(title, item)\n  .card\n    h2 #{title}\n    p #{item.body}\n";
+
+    #[test]
+    fn escapes_strings_as_js_literals() {
+        assert_eq!(
+            r#""say \"hi\"\nthen 'bye'""#,
+            js_literal(&json!("say \"hi\"\nthen 'bye'"))
+        );
+        assert_eq!(r#""a\u2028b""#, js_literal(&json!("a\u{2028}b")));
+        assert_eq!(
+            r#"{"tags":["</x>"]}"#,
+            js_literal(&json!({ "tags": ["</x>"] }))
+        );
+    }
+
+    #[test]
+    fn renders_one_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("components.pug");
+        fs::write(&library, LIBRARY).unwrap();
+
+        let args = [json!("Say \"hi\"\n"), json!({ "body": "<b>bold</b>" })];
+        assert_eq!(
+            "<div class=\"card\"><h2>Say &quot;hi&quot;\n</h2><p>&lt;b&gt;bold&lt;/b&gt;</p></div>",
+            render_mixin(&library, "card", &args, PugOptions::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn names_unknown_mixins() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = dir.path().join("components.pug");
+        fs::write(&library, LIBRARY).unwrap();
+
+        match render_mixin(&library, "button", &[], PugOptions::new()) {
+            Err(CompileError::UnknownMixin { library: at, mixin }) => {
+                assert_eq!((&*library, "button"), (&*at, mixin.as_str()))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            render_mixin(&library, "card(1)", &[], PugOptions::new()),
+            Err(CompileError::InvalidOptions(_))
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+//! Rewriting the line endings in compiled output.
+
+/// The line ending `PugOptions::newline` converts output to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NewlineStyle {
+    Lf,
+    CrLf,
+    /// `CrLf` on Windows, `Lf` everywhere else.
+    Platform,
+}
+
+impl NewlineStyle {
+    fn ending(self) -> &'static str {
+        match self {
+            NewlineStyle::Lf => "\n",
+            NewlineStyle::CrLf => "\r\n",
+            NewlineStyle::Platform if cfg!(windows) => "\r\n",
+            NewlineStyle::Platform => "\n",
+        }
+    }
+}
+
+/// What to do about a newline at the very end of the compiled output, which
+/// pug writes or leaves off depending on how it was run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingNewline {
+    /// Leave the output as pug wrote it.
+    #[default]
+    Keep,
+    /// End with exactly one newline.
+    Ensure,
+    /// End without a newline.
+    Trim,
+}
+
+/// Applies `trailing` to the end of `html`. Runs before `convert`, which
+/// then gives an ensured newline the right ending.
+/// `\r\n` and lone `\r` in a template's source as `\n`, which is all pug's
+/// indentation handling expects.
+pub(crate) fn normalize_input(source: String) -> String {
+    if !source.contains('\r') {
+        return source;
+    }
+    source.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+pub(crate) fn apply_trailing(mut html: String, trailing: TrailingNewline) -> String {
+    if trailing == TrailingNewline::Keep {
+        return html;
+    }
+    let trimmed = html.trim_end_matches(['\n', '\r']).len();
+    html.truncate(trimmed);
+    if trailing == TrailingNewline::Ensure {
+        html.push('\n');
+    }
+    html
+}
+
+/// Converts every `\n` and `\r\n` in `html` to `style`, except inside `pre`
+/// elements, whose bytes are kept as pug wrote them.
+pub(crate) fn convert(html: &str, style: NewlineStyle) -> String {
+    let ending = style.ending();
+    let mut converted = String::with_capacity(html.len());
+    let mut rest = html;
+    while !rest.is_empty() {
+        let (outside, pre) = match find_pre(rest) {
+            Some(start) => rest.split_at(start),
+            None => (rest, ""),
+        };
+        for (i, line) in outside.split('\n').enumerate() {
+            if i > 0 {
+                converted.push_str(ending);
+            }
+            converted.push_str(line.strip_suffix('\r').unwrap_or(line));
+        }
+        // a `\r` that ended the segment wasn't part of a line ending
+        if outside.ends_with('\r') {
+            converted.push('\r');
+        }
+        let end = pre_end(pre);
+        converted.push_str(&pre[..end]);
+        rest = &pre[end..];
+    }
+    converted
+}
+
+/// Where the next `<pre>` or `<pre ...>` tag starts.
+fn find_pre(html: &str) -> Option<usize> {
+    let lower = html.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find("<pre") {
+        let start = from + found;
+        match lower.as_bytes().get(start + 4) {
+            Some(b'>') | Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') | Some(b'/') => {
+                return Some(start)
+            }
+            _ => from = start + 4,
+        }
+    }
+    None
+}
+
+/// The length of the `pre` element at the start of `pre`, or all of it if it
+/// is never closed.
+fn pre_end(pre: &str) -> usize {
+    match pre.to_ascii_lowercase().find("</pre>") {
+        Some(close) => close + "</pre>".len(),
+        None => pre.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_pre_alone_and_does_not_double_convert() {
+        let html = "<div>\n<p>a</p>\r\n</div><pre class=\"x\">one\ntwo\r\n</pre>\n<prefix>\n";
+
+        assert_eq!(
+            "<div>\r\n<p>a</p>\r\n</div><pre class=\"x\">one\ntwo\r\n</pre>\r\n<prefix>\r\n",
+            convert(html, NewlineStyle::CrLf)
+        );
+        assert_eq!(
+            "<div>\n<p>a</p>\n</div><pre class=\"x\">one\ntwo\r\n</pre>\n<prefix>\n",
+            convert(html, NewlineStyle::Lf)
+        );
+        let crlf = convert(html, NewlineStyle::CrLf);
+        assert_eq!(crlf, convert(&crlf, NewlineStyle::CrLf));
+    }
+}
@@ -0,0 +1,56 @@
+// Renders one template for pug_cli::node_api.
+//
+// The generated lines above this set `pug`, `options`, `locals` and `client`,
+// registering filters on the way.
+// The template arrives on stdin and the HTML (or client function) leaves on
+// stdout. A failure is written to stderr as one line, `pug_cli:error:` and a
+// JSON object, so nothing else on stderr can garble it.
+const source = require('fs').readFileSync(0, 'utf8');
+
+try {
+  const output = client
+    ? pug.compileClient(source, options)
+    : pug.render(source, Object.assign({}, options, locals));
+  process.stdout.write(output);
+} catch (e) {
+  const error = { message: String((e && e.message) || e) };
+  if (e && typeof e.code === 'string' && e.code.startsWith('PUG:')) {
+    Object.assign(error, {
+      code: e.code,
+      msg: e.msg,
+      line: e.line,
+      column: e.column,
+      filename: e.filename,
+    });
+  }
+  fail(error);
+}
+
+// Requires the filter `name` from `file`, failing with the filter's name if it
+// throws or isn't a function.
+function register(name, file) {
+  try {
+    const filter = require(file);
+    if (typeof filter !== 'function') {
+      throw new Error(file + " doesn't export a function");
+    }
+    return filter;
+  } catch (e) {
+    fail({ message: String((e && e.message) || e), filter: name });
+    process.exit();
+  }
+}
+
+function fail(error) {
+  process.stderr.write('\npug_cli:error:' + JSON.stringify(error) + '\n');
+  process.exitCode = 1;
+}
+
+// Loads a module from the project's node_modules, or wherever node finds it.
+function load(name) {
+  try {
+    return require(require.resolve(name, { paths: [process.cwd()] }));
+  } catch (e) {
+    return require(name);
+  }
+}
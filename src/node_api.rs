@@ -0,0 +1,402 @@
+//! Rendering through pug's JavaScript API instead of its command line, for the
+//! options the CLI has no flag for: filters written in JavaScript,
+//! `filterOptions`, plugins, `cache`, `self` and the rest.
+//!
+//! Each compile writes a small script to the temp directory that loads pug,
+//! sets up the options and renders the template piped to it, and runs it
+//! with `node`. Only its owner can read the script, which holds the locals,
+//! and it's removed once node exits. The `pug` package is looked up from the working directory
+//! first, then wherever node looks. Errors come back as the same
+//! `CompileError`s as the CLI's, with the same `PugErrorDetails`.
+
+use crate::invocation::{interpret_output, Invocation};
+use crate::temp_file::TempFile;
+use crate::{locale, run_file, CompileError, PugErrorDetails, PugJsonObject, PugOptions};
+use serde_json::{Map, Value};
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+const SHIM: &str = include_str!("node_api.js");
+
+/// What the script prints to stderr in front of a JSON error.
+const ERROR_PREFIX: &str = "pug_cli:error:";
+
+/// `PugOptions` plus the pug API options the CLI can't pass.
+///
+/// Every `PugOptions` setting still applies: `pretty`, `doctype`,
+/// `no_debug` and `with_path` become `pretty`, `doctype`, `compileDebug`
+/// and `filename`; locals, preludes, front matter, post-processing, limits,
+/// timeouts and executors work as with the CLI. `out_dir` isn't supported.
+#[derive(Clone)]
+pub struct PugApiOptions {
+    base: PugOptions,
+    filters: Vec<(String, String)>,
+    filter_files: Vec<(String, PathBuf)>,
+    filter_options: Map<String, Value>,
+    plugins: Vec<String>,
+    extra: Map<String, Value>,
+    node: Option<PathBuf>,
+    pug_module: Option<String>,
+}
+
+impl From<PugOptions> for PugApiOptions {
+    fn from(base: PugOptions) -> Self {
+        PugApiOptions::new(base)
+    }
+}
+
+impl PugApiOptions {
+    /// Takes over `base`'s `PugOptions::filter`s, which only load here.
+    pub fn new(mut base: PugOptions) -> Self {
+        let filter_files = std::mem::take(&mut base.filters);
+        PugApiOptions {
+            base,
+            filters: Vec::new(),
+            filter_files,
+            filter_options: Map::new(),
+            plugins: Vec::new(),
+            extra: Map::new(),
+            node: None,
+            pug_module: None,
+        }
+    }
+
+    /// Defines the filter `:name` as `function`, JavaScript source for a
+    /// function taking the text and its options:
+    /// `"function (text, options) { return text.toUpperCase(); }"`.
+    pub fn filter(mut self, name: impl Into<String>, function: impl Into<String>) -> Self {
+        self.filters.push((name.into(), function.into()));
+        self
+    }
+
+    /// Options passed to the filter `:name` wherever it's used.
+    pub fn filter_options(mut self, name: impl Into<String>, options: Value) -> Self {
+        self.filter_options.insert(name.into(), options);
+        self
+    }
+
+    /// Loads the pug plugin exported by `module`, a package name or path.
+    pub fn plugin(mut self, module: impl Into<String>) -> Self {
+        self.plugins.push(module.into());
+        self
+    }
+
+    /// Keeps compiled templates in the script's pug cache. Only useful
+    /// together with `with_path`, which pug caches by.
+    pub fn cache(self) -> Self {
+        self.option("cache", Value::Bool(true))
+    }
+
+    /// Puts the locals under `self`, so templates read `self.name`.
+    pub fn self_namespace(self) -> Self {
+        self.option("self", Value::Bool(true))
+    }
+
+    /// The directory absolute includes and extends are resolved against.
+    pub fn basedir(self, basedir: impl Into<PathBuf>) -> Self {
+        let basedir = basedir.into().to_string_lossy().into_owned();
+        self.option("basedir", Value::String(basedir))
+    }
+
+    /// Global names templates may use, like `JSON` or `Math`.
+    pub fn globals<I>(self, globals: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let globals = globals.into_iter().map(|g| Value::String(g.into()));
+        self.option("globals", Value::Array(globals.collect()))
+    }
+
+    /// Sets any other pug option by its JavaScript name. Set last, so it
+    /// takes the place of anything derived from the `PugOptions`.
+    pub fn option(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.extra.insert(name.into(), value);
+        self
+    }
+
+    /// Runs the given executable instead of the `node` found on `PATH`.
+    pub fn node(mut self, node: impl Into<PathBuf>) -> Self {
+        self.node = Some(node.into());
+        self
+    }
+
+    /// Loads pug from `module`, a package name or path, instead of `pug`.
+    pub fn pug_module(mut self, module: impl Into<String>) -> Self {
+        self.pug_module = Some(module.into());
+        self
+    }
+
+    /// The script rendering the template piped to it with these options.
+    fn script(&self) -> Result<String, CompileError> {
+        let base = &self.base;
+        let mut options = Map::new();
+        if base.pretty {
+            options.insert("pretty".into(), Value::Bool(true));
+        }
+        if let Some(doctype) = &base.doctype {
+            options.insert("doctype".into(), Value::String(doctype.clone()));
+        }
+        if base.no_debug {
+            options.insert("compileDebug".into(), Value::Bool(false));
+        }
+        if let Some(path) = &base.path {
+            let filename = path.to_string_lossy().into_owned();
+            options.insert("filename".into(), Value::String(filename));
+        }
+        if !self.filter_options.is_empty() {
+            let filter_options = Value::Object(self.filter_options.clone());
+            options.insert("filterOptions".into(), filter_options);
+        }
+        options.extend(self.extra.clone());
+        let locals = match &base.object {
+            Some(PugJsonObject::JsModule(module)) => {
+                let module = Value::String(module.to_string_lossy().into_owned());
+                format!("require(require('path').resolve({}))", module)
+            }
+            Some(object) => locale::to_json(object)?.to_string(),
+            None => "{}".into(),
+        };
+
+        let module = |name: &str| format!("load({})", Value::String(name.to_owned()));
+        let mut script = format!(
+            "const pug = {};\nconst options = {};\nconst locals = {};\nconst client = {};\n",
+            module(self.pug_module.as_deref().unwrap_or("pug")),
+            Value::Object(options),
+            locals,
+            base.client,
+        );
+        if !self.filters.is_empty() || !self.filter_files.is_empty() {
+            script.push_str("options.filters = {\n");
+            for (name, file) in &self.filter_files {
+                if !file.is_file() {
+                    return Err(CompileError::FilterFileMissing {
+                        filter: name.clone(),
+                        path: file.clone(),
+                    });
+                }
+                // the script runs from the temp directory
+                let file = file
+                    .canonicalize()
+                    .map_err(CompileError::io_at("resolve filter", file))?;
+                let file = Value::String(file.to_string_lossy().into_owned());
+                let name = Value::String(name.clone());
+                script.push_str(&format!("  {}: register({}, {}),\n", name, name, file));
+            }
+            for (name, function) in &self.filters {
+                let name = Value::String(name.clone());
+                script.push_str(&format!("  {}: ({}),\n", name, function));
+            }
+            script.push_str("};\n");
+        }
+        if !self.plugins.is_empty() {
+            let plugins: Vec<String> = self.plugins.iter().map(|p| module(p)).collect();
+            script.push_str(&format!("options.plugins = [{}];\n", plugins.join(", ")));
+        }
+        script.push('\n');
+        script.push_str(SHIM);
+        Ok(script)
+    }
+}
+
+/// `evaluate_with_options`, rendering with pug's API.
+pub fn evaluate(file: impl Into<PathBuf>, options: PugApiOptions) -> Result<String, CompileError> {
+    let invocation = Invocation::file(file, options.base.clone())?;
+    run(invocation, &options)
+}
+
+/// `evaluate_string_with_options`, rendering with pug's API.
+pub fn evaluate_string(source: String, options: PugApiOptions) -> Result<String, CompileError> {
+    let invocation = Invocation::string(source, options.base.clone())?;
+    run(invocation, &options)
+}
+
+fn run(mut invocation: Invocation, options: &PugApiOptions) -> Result<String, CompileError> {
+    if options.base.out_dir.is_some() {
+        return Err(CompileError::InvalidOptions(
+            "node_api renders to a string; out_dir isn't supported".into(),
+        ));
+    }
+    if options.base.sandbox && options.extra.contains_key("basedir") {
+        return Err(CompileError::InvalidOptions(
+            "sandbox: basedir would let templates include files under it".into(),
+        ));
+    }
+    // the script holds the locals, so only its owner may read it
+    let script = TempFile::private(".js", options.script()?.as_bytes()).map_err(
+        CompileError::io_at("write script in", &std::env::temp_dir()),
+    )?;
+    invocation.program = options.node.clone().unwrap_or_else(|| "node".into());
+    invocation.args = vec![script.path().to_string_lossy().into_owned()];
+    let post_process = invocation.post_process.clone();
+    let mut output = run_file(invocation)?;
+    if let Some(error) = structured_error(&output.stderr) {
+        let message = error["message"].as_str().unwrap_or_default().to_owned();
+        if let Some(filter) = error["filter"].as_str() {
+            return Err(CompileError::FilterRegistration {
+                filter: filter.to_owned(),
+                reason: message,
+            });
+        }
+        // as pug's CLI would have printed it, for the error handling it
+        // shares with the CLI: prelude line numbers, source_name, snippets
+        output.stderr = match details(&error) {
+            Some(details) => format!("Error: {}", details.render_snippet()),
+            None => format!("Error: {}\n", message),
+        }
+        .into_bytes();
+    }
+    interpret_output(output, post_process)
+}
+
+/// A pug error's parts from the fields the script sent, with the excerpt
+/// pug put in its message. `None` for errors that aren't pug's own, like
+/// JavaScript throwing.
+fn details(error: &Value) -> Option<PugErrorDetails> {
+    if !error["code"].as_str()?.starts_with("PUG:") {
+        return None;
+    }
+    let number = |field: &str| error[field].as_u64().and_then(|n| n.try_into().ok());
+    let message = error["message"].as_str().unwrap_or_default();
+    let excerpt = PugErrorDetails::parse(&format!("Error: {}", message));
+    Some(PugErrorDetails {
+        // pug leaves it out for templates without a file, and prints `Pug`
+        filename: error["filename"].as_str().unwrap_or("Pug").to_owned(),
+        line: number("line")?,
+        column: number("column"),
+        message: error["msg"].as_str()?.to_owned(),
+        marked: excerpt.as_ref().and_then(|excerpt| excerpt.marked),
+        snippet: excerpt.map(|excerpt| excerpt.snippet).unwrap_or_default(),
+    })
+}
+
+/// The JSON error the script printed, if it failed.
+fn structured_error(stderr: &[u8]) -> Option<Value> {
+    let stderr = String::from_utf8_lossy(stderr);
+    stderr
+        .lines()
+        .find_map(|line| line.strip_prefix(ERROR_PREFIX))
+        .and_then(|error| serde_json::from_str(error).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::process::Command;
+
+    #[test]
+    fn renders_with_options_the_cli_lacks() {
+        let locals = PugOptions::new().with_object(json!({ "name": "pug" }));
+        let options = PugApiOptions::new(locals).self_namespace();
+        let html = evaluate_string("p #{self.name}#{name}".into(), options);
+        assert_eq!("<p>pug</p>", html.unwrap());
+
+        let html = evaluate("test/hello.pug", PugOptions::new().into());
+        assert_eq!("<h1>hello pug</h1>", html.unwrap());
+    }
+
+    #[test]
+    fn reports_pug_errors_like_the_cli() {
+        let error = evaluate_string("  p".into(), PugOptions::new().into()).unwrap_err();
+        assert!(matches!(error, CompileError::PugError(_)), "{:?}", error);
+        let details = error.details().unwrap();
+        assert_eq!("Pug", details.filename);
+        assert_eq!((1, Some(3)), (details.line, details.column));
+        assert_eq!("unexpected token \"indent\"", details.message);
+
+        let missing = PugApiOptions::from(PugOptions::new()).pug_module("no-such-pug");
+        let error = evaluate_string("p".into(), missing).unwrap_err();
+        assert!(error.to_string().contains("no-such-pug"), "{}", error);
+    }
+
+    #[test]
+    fn reads_pug_errors_from_their_fields() {
+        let error = json!({
+            "message": "views/a: b.pug:2:4\n    1| p\n  > 2| p #{\n---------^\n\nEnd of line",
+            "code": "PUG:SYNTAX_ERROR",
+            "msg": "End of line",
+            "line": 2,
+            "column": 4,
+            "filename": "views/a: b.pug",
+        });
+        let details = details(&error).unwrap();
+        assert_eq!(
+            ("views/a: b.pug", 2, Some(4), "End of line"),
+            (
+                details.filename.as_str(),
+                details.line,
+                details.column,
+                details.message.as_str()
+            )
+        );
+        assert_eq!(Some(2), details.marked);
+        assert_eq!(
+            vec![(1, "p".to_owned()), (2, "p #{".to_owned())],
+            details.snippet
+        );
+
+        let bare = json!({ "message": "x", "code": "PUG:X", "msg": "x", "line": 1 });
+        assert_eq!("Pug", super::details(&bare).unwrap().filename);
+        assert_eq!(
+            None,
+            super::details(&json!({ "message": "TypeError: nope" }))
+        );
+    }
+
+    #[test]
+    fn loads_filter_files() {
+        let shout = PugOptions::new().filter("shout", "test/filters/shout.js".into());
+        let html = evaluate_string(":shout\n  hello filters".into(), shout.clone().into());
+        assert_eq!("HELLO FILTERS", html.unwrap());
+        let error = crate::evaluate_string_with_options("p".into(), shout).unwrap_err();
+        assert!(
+            matches!(error, CompileError::InvalidOptions(_)),
+            "{:?}",
+            error
+        );
+
+        let missing = PugOptions::new().filter("icon", "test/filters/icon.js".into());
+        let error = evaluate_string("p".into(), missing.into()).unwrap_err();
+        assert!(
+            matches!(&error, CompileError::FilterFileMissing { filter, .. } if filter == "icon"),
+            "{:?}",
+            error
+        );
+
+        let broken = PugOptions::new().filter("broken", "test/filters/broken.js".into());
+        match evaluate_string("p".into(), broken.into()).unwrap_err() {
+            CompileError::FilterRegistration { filter, reason } => {
+                assert_eq!("broken", filter);
+                assert!(reason.contains("broken on purpose"), "{}", reason);
+            }
+            error => panic!("{:?}", error),
+        }
+    }
+
+    #[test]
+    fn generates_a_valid_script() {
+        let options = PugApiOptions::new(PugOptions::new().pretty().with_path("page.pug"))
+            .filter("shout", "function (text) { return text.toUpperCase(); }")
+            .filter_options("shout", json!({ "loud": true }))
+            .plugin("./my-plugin")
+            .basedir("views")
+            .option("pretty", json!("\t"));
+        let script = options.script().unwrap();
+        assert!(script.contains(r#""shout": (function (text) { return text.toUpperCase(); }),"#));
+        assert!(script.contains(r#"options.plugins = [load("./my-plugin")];"#));
+        assert!(script.contains(
+            r#"const options = {"basedir":"views","filename":"page.pug","filterOptions":{"shout":{"loud":true}},"pretty":"\t"};"#
+        ));
+
+        let script = TempFile::private(".js", script.as_bytes()).unwrap();
+        let check = Command::new("node")
+            .arg("--check")
+            .arg(script.path())
+            .status();
+        assert!(check.unwrap().success());
+        let path = script.path().to_owned();
+        drop(script);
+        assert!(!path.exists());
+    }
+}
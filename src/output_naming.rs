@@ -0,0 +1,175 @@
+//! Naming the pages batch compiles write.
+
+use crate::CompileError;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Where `build::compile_dir` and `tokio::watch_stream` write each page,
+/// relative to the output directory, as a pattern with placeholders:
+///
+/// - `{dir}`: the template's directory relative to the source directory,
+///   empty at the top
+/// - `{name}`: the template's file name without `.pug`
+/// - `{ext}`: `html`
+/// - `{hash}`: 16 hex digits hashing the page's contents, for cache busting
+///
+/// `{name}.partial.html` flattens the tree into fragments, and
+/// `{dir}/{name}/index.html` gives pretty URLs. The default is
+/// `{dir}/{name}.{ext}`. Empty path segments are dropped, so `{dir}/` at the
+/// top level is no directory at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputNaming {
+    pattern: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Dir,
+    Name,
+    Ext,
+    Hash,
+}
+
+impl OutputNaming {
+    /// Parses `pattern`, failing with `CompileError::InvalidOptions` for a
+    /// placeholder other than those above, an unclosed `{`, or a `..` or
+    /// absolute path that would leave the output directory.
+    pub fn new(pattern: &str) -> Result<Self, CompileError> {
+        let invalid = |reason: String| {
+            CompileError::InvalidOptions(format!("output naming {:?}: {}", pattern, reason))
+        };
+        let mut parts = Vec::new();
+        let mut rest = pattern;
+        while let Some(open) = rest.find('{') {
+            parts.push(Part::Text(rest[..open].to_owned()));
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| invalid("a `{` is never closed".into()))?;
+            parts.push(match &rest[open + 1..open + close] {
+                "dir" => Part::Dir,
+                "name" => Part::Name,
+                "ext" => Part::Ext,
+                "hash" => Part::Hash,
+                unknown => {
+                    return Err(invalid(format!(
+                        "unknown placeholder {{{}}}; use {{dir}}, {{name}}, {{ext}} or {{hash}}",
+                        unknown
+                    )))
+                }
+            });
+            rest = &rest[open + close + 1..];
+        }
+        parts.push(Part::Text(rest.to_owned()));
+        parts.retain(|part| part != &Part::Text(String::new()));
+
+        let literal = Path::new(pattern);
+        if literal.is_absolute()
+            || pattern.starts_with('/')
+            || literal.components().any(|c| c == Component::ParentDir)
+        {
+            return Err(invalid(
+                "pages have to stay inside the output directory".into(),
+            ));
+        }
+        Ok(OutputNaming {
+            pattern: pattern.to_owned(),
+            parts,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The page for the template at `relative` in the source directory, with
+    /// the compiled `html`, relative to the output directory.
+    pub(crate) fn page(&self, relative: &Path, html: &str) -> PathBuf {
+        let dir = relative.parent().unwrap_or_else(|| Path::new(""));
+        let name = relative.file_stem().unwrap_or_default().to_string_lossy();
+        let mut page = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => page.push_str(text),
+                Part::Dir => {
+                    let dir: Vec<_> = dir.iter().map(|part| part.to_string_lossy()).collect();
+                    page.push_str(&dir.join("/"));
+                }
+                Part::Name => page.push_str(&name),
+                Part::Ext => page.push_str("html"),
+                Part::Hash => {
+                    let _ = write!(page, "{:016x}", fnv1a(html.as_bytes()));
+                }
+            }
+        }
+        page.split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+}
+
+impl Default for OutputNaming {
+    fn default() -> Self {
+        OutputNaming::new("{dir}/{name}.{ext}").unwrap()
+    }
+}
+
+/// A hash that stays the same across Rust releases, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_placeholders() {
+        let page = |pattern: &str, template: &str| {
+            OutputNaming::new(pattern)
+                .unwrap()
+                .page(Path::new(template), "<p>hi</p>")
+        };
+        assert_eq!(
+            PathBuf::from("blog/post.html"),
+            page("{dir}/{name}.{ext}", "blog/post.pug")
+        );
+        assert_eq!(
+            PathBuf::from("index.html"),
+            page("{dir}/{name}.{ext}", "index.pug")
+        );
+        assert_eq!(
+            PathBuf::from("blog/2024/recap/index.html"),
+            page("{dir}/{name}/index.html", "blog/2024/recap.pug")
+        );
+        assert_eq!(
+            PathBuf::from("post.partial.html"),
+            page("{name}.partial.html", "blog/post.pug")
+        );
+        let hashed = page("{name}.{hash}.html", "a.pug");
+        assert_eq!(hashed, page("{name}.{hash}.html", "b/a.pug"));
+        assert_eq!("a..html".len() + 16, hashed.to_string_lossy().len());
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        for pattern in [
+            "{slug}.html",
+            "{name.html",
+            "../{name}.html",
+            "/{name}.html",
+        ] {
+            let error = OutputNaming::new(pattern).unwrap_err();
+            assert!(
+                matches!(error, CompileError::InvalidOptions(_)),
+                "{}",
+                pattern
+            );
+        }
+        let error = OutputNaming::new("{slug}.html").unwrap_err().to_string();
+        assert!(error.contains("{slug}"), "{}", error);
+    }
+}
@@ -0,0 +1,889 @@
+//! Structure recovered from pug's error output, and an HTML page showing it.
+//!
+//! Pug reports errors as a `file:line:column` header, a few lines of the
+//! template with a `>` marking the failing one, a blank line and the message,
+//! usually followed by node's stack trace:
+//!
+//! ```text
+//! Error: Pug:2:3
+//!     1| h1 ok
+//!   > 2|   p broken
+//! ---------^
+//!
+//! unexpected token "indent"
+//!     at makeError (...)
+//! ```
+
+use crate::CompileError;
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt::Write;
+use std::path::Path;
+
+/// A pug error broken into its parts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PugErrorDetails {
+    /// The file pug names in the header; `Pug` for templates read from stdin.
+    pub filename: String,
+    pub line: u32,
+    pub column: Option<u32>,
+    pub message: String,
+    /// The numbered template lines pug printed around the failing one.
+    pub snippet: Vec<(u32, String)>,
+    /// The snippet line pug marked with `>`.
+    pub marked: Option<u32>,
+}
+
+impl PugErrorDetails {
+    /// Returns `None` if `stderr` doesn't contain a pug error header.
+    pub fn parse(stderr: &str) -> Option<PugErrorDetails> {
+        let stderr = &*strip_ansi(stderr);
+        let mut lines = stderr
+            .lines()
+            .skip_while(|line| parse_header(line).is_none());
+        let (filename, line, column) = match lines.next() {
+            Some(header) => parse_header(header)?,
+            None => return parse_structured(stderr),
+        };
+
+        let mut snippet = Vec::new();
+        let mut marked = None;
+        for text in lines.by_ref() {
+            if text.trim().is_empty() {
+                break;
+            }
+            if let Some((number, source, is_marked)) = parse_snippet_line(text) {
+                if is_marked {
+                    marked = Some(number);
+                }
+                snippet.push((number, source));
+            }
+        }
+
+        let message: Vec<&str> = lines
+            .take_while(|text| !text.trim_start().starts_with("at ") && !text.trim().is_empty())
+            .collect();
+
+        Some(PugErrorDetails {
+            filename,
+            line,
+            column,
+            message: message.join("\n"),
+            snippet,
+            marked,
+        })
+    }
+
+    /// The error the way pug prints it: the location, the snippet with the
+    /// failing line marked and a caret under its column, and the message.
+    pub fn render_snippet(&self) -> String {
+        let mut rendered = String::new();
+        let _ = write!(rendered, "{}:{}", self.filename, self.line);
+        if let Some(column) = self.column {
+            let _ = write!(rendered, ":{}", column);
+        }
+        rendered.push('\n');
+        let marked = self.marked.unwrap_or(self.line);
+        let lines = self
+            .snippet
+            .iter()
+            .map(|(number, text)| (*number, text.as_str()));
+        write_snippet(&mut rendered, lines, marked, self.column);
+        let _ = write!(rendered, "\n{}\n", self.message);
+        rendered
+    }
+}
+
+/// Whether a pug failure was the template or JavaScript it ran; see
+/// `CompileError::kind`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PugErrorKind {
+    /// Pug couldn't lex, parse or compile the template; `CompileError::details`
+    /// says where.
+    TemplateSyntax,
+    /// JavaScript threw: a `PugJsonObject::JsModule` while loading, or code
+    /// in the template while it ran.
+    JavaScriptRuntime {
+        /// As node names it: `TypeError: user is undefined`.
+        message: String,
+        /// Node's `at ...` lines, innermost first.
+        stack: Vec<String>,
+        /// The template line for code in the template; otherwise the
+        /// innermost frame outside node and `node_modules`, which is the
+        /// locals module or something it loaded.
+        frame: Option<StackFrame>,
+    },
+    /// Anything else, like node failing before it got to pug.
+    Unknown,
+}
+
+/// A place in a file named by a stack trace or pug's header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    pub file: String,
+    pub line: u32,
+    pub column: Option<u32>,
+}
+
+impl PugErrorKind {
+    /// Tells the kinds apart by their markers: pug's `file:line:column`
+    /// header for the template, with a JavaScript error's name (`TypeError:`)
+    /// or a frame in the compiled template (`eval at wrap`) when its code
+    /// threw, and node's `Error: message` followed by `at ...` frames for
+    /// anything else that threw.
+    pub fn classify(stderr: &str) -> PugErrorKind {
+        let stderr = &*strip_ansi(stderr);
+        let lines: Vec<&str> = stderr.lines().map(str::trim).collect();
+        let stack = |after: usize| -> Vec<String> {
+            lines[after..]
+                .iter()
+                .skip_while(|line| !line.starts_with("at "))
+                .take_while(|line| line.starts_with("at "))
+                .map(|line| line.to_string())
+                .collect()
+        };
+
+        if let Some(at) = lines.iter().position(|line| parse_header(line).is_some()) {
+            let stack = stack(at);
+            let prefix = error_prefix(lines[at]).unwrap_or("Error: ");
+            let in_template = stack
+                .first()
+                .is_some_and(|frame| frame.contains("eval at wrap"));
+            return match PugErrorDetails::parse(stderr) {
+                Some(details) if prefix != "Error: " || in_template => {
+                    PugErrorKind::JavaScriptRuntime {
+                        message: format!("{}{}", prefix, details.message),
+                        stack,
+                        frame: Some(StackFrame {
+                            file: details.filename,
+                            line: details.line,
+                            column: details.column,
+                        }),
+                    }
+                }
+                _ => PugErrorKind::TemplateSyntax,
+            };
+        }
+        if parse_structured(stderr).is_some() {
+            return PugErrorKind::TemplateSyntax;
+        }
+        for (at, line) in lines.iter().enumerate() {
+            if error_prefix(line).is_none() {
+                continue;
+            }
+            let stack = stack(at + 1);
+            if !stack.is_empty() {
+                return PugErrorKind::JavaScriptRuntime {
+                    message: line.to_string(),
+                    frame: stack.iter().find_map(|frame| user_frame(frame)),
+                    stack,
+                };
+            }
+        }
+        PugErrorKind::Unknown
+    }
+}
+
+/// Where `frame` (`at fn (file:line:column)`) points, unless that's node's
+/// own code, a package's or somewhere without a file.
+fn user_frame(frame: &str) -> Option<StackFrame> {
+    let frame = frame.strip_prefix("at ")?;
+    let location = match frame.split_once(" (") {
+        Some((_, location)) => location.strip_suffix(')')?,
+        None => frame,
+    };
+    // `eval at f (file:1:2), <anonymous>:3:4` ran at the last location
+    let location = location.rsplit(", ").next()?;
+    let (rest, column) = location.rsplit_once(':')?;
+    let (file, line, column) = match rest.rsplit_once(':') {
+        Some((file, line)) if line.parse::<u32>().is_ok() => (file, line, Some(column)),
+        _ => (rest, column, None),
+    };
+    let foreign = file.starts_with("node:")
+        || file.starts_with('<')
+        || file.starts_with("internal/")
+        || file.contains("node_modules");
+    if foreign {
+        return None;
+    }
+    Some(StackFrame {
+        file: file.to_owned(),
+        line: line.parse().ok()?,
+        column: column.and_then(|column| column.parse().ok()),
+    })
+}
+
+/// Pug's excerpt format: `  > 7| text` for the marked line and `    6| text`
+/// for the rest, with `-` up to the column and a `^` under the marked line.
+fn write_snippet<'a>(
+    out: &mut String,
+    lines: impl Iterator<Item = (u32, &'a str)>,
+    marked: u32,
+    column: Option<u32>,
+) {
+    for (number, text) in lines {
+        let preamble = format!(
+            "{}{}| ",
+            if number == marked { "  > " } else { "    " },
+            number
+        );
+        let _ = writeln!(out, "{}{}", preamble, text);
+        if let Some(column) = column.filter(|_| number == marked) {
+            let dashes = preamble.len() + column.saturating_sub(1) as usize;
+            let _ = writeln!(out, "{}^", "-".repeat(dashes));
+        }
+    }
+}
+
+/// Puts lines of `source` into terse pug output that has a header but no
+/// excerpt, so string templates always come with one.
+pub(crate) fn fill_snippet(stderr: &str, source: &str) -> String {
+    let details = match PugErrorDetails::parse(stderr) {
+        Some(details) if details.snippet.is_empty() => details,
+        _ => return stderr.to_owned(),
+    };
+    let header = match stderr.lines().position(|line| parse_header(line).is_some()) {
+        Some(header) => header,
+        None => return stderr.to_owned(),
+    };
+    let first = details.line.saturating_sub(3) + 1;
+    let lines = source
+        .lines()
+        .zip(1..)
+        .map(|(text, number)| (number, text))
+        .skip_while(|(number, _)| *number < first)
+        .take_while(|(number, _)| *number <= details.line + 3);
+    let mut excerpt = String::new();
+    write_snippet(&mut excerpt, lines, details.line, details.column);
+
+    let mut filled: Vec<&str> = stderr.lines().collect();
+    filled.splice(header + 1..header + 1, excerpt.lines());
+    let mut filled = filled.join("\n");
+    if stderr.ends_with('\n') {
+        filled.push('\n');
+    }
+    filled
+}
+
+/// Replaces the file name in pug's error header with `name`.
+pub(crate) fn rename_source(stderr: &str, name: &str) -> String {
+    let mut renamed = Vec::new();
+    let mut done = false;
+    for text in stderr.lines() {
+        match parse_header(text).filter(|_| !done) {
+            Some((_, line, column)) => {
+                let at = text.len() - text.trim_start().len();
+                let prefix = error_prefix(text.trim_start()).unwrap_or("");
+                let mut header = format!("{}{}{}:{}", &text[..at], prefix, name, line);
+                if let Some(column) = column {
+                    let _ = write!(header, ":{}", column);
+                }
+                renamed.push(header);
+                done = true;
+            }
+            None => renamed.push(text.to_owned()),
+        }
+    }
+    let mut renamed = renamed.join("\n");
+    if stderr.ends_with('\n') {
+        renamed.push('\n');
+    }
+    renamed
+}
+
+/// `text` without ANSI escape sequences: colors and other `ESC [` codes,
+/// `ESC ]` titles and links, character set choices and two-character
+/// escapes.
+pub(crate) fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\u{1b}') {
+        return Cow::Borrowed(text);
+    }
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // parameters, then a final byte from `@` to `~`
+            Some('[') => {
+                chars.find(|c| ('@'..='~').contains(c));
+            }
+            // up to a BEL or `ESC \`
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            // character set choices, `ESC ( B`
+            Some('(' | ')' | '*' | '+') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+/// The error for a failed compile's stderr: `CompileError::MissingFilter` if
+/// pug couldn't find a filter, otherwise `CompileError::PugError`.
+pub(crate) fn pug_failure(stderr: String) -> CompileError {
+    match missing_filter(&stderr) {
+        Some(filter) => CompileError::MissingFilter {
+            hint: format!("npm install jstransformer-{}", filter),
+            filter,
+        },
+        None => CompileError::PugError(stderr),
+    }
+}
+
+/// The filter named by pug's `unknown filter ":name"` message, or by node
+/// failing to load its `jstransformer-name` package.
+fn missing_filter(stderr: &str) -> Option<String> {
+    let up_to = |text: &str, quote: char| text.split(quote).next().map(str::to_owned);
+    stderr
+        .lines()
+        .find_map(|line| {
+            let line = line.trim();
+            let line = line.strip_prefix("Error: ").unwrap_or(line);
+            if let Some(rest) = line.strip_prefix("unknown filter \":") {
+                up_to(rest, '"')
+            } else {
+                up_to(
+                    line.strip_prefix("Cannot find module 'jstransformer-")?,
+                    '\'',
+                )
+            }
+        })
+        .filter(|filter| !filter.is_empty())
+}
+
+/// The one-line JSON errors the pug 3 CLI can write instead:
+/// `{"code":"PUG:UNEXPECTED_TOKEN","msg":"...","line":2,"column":3,"filename":"..."}`.
+fn parse_structured(stderr: &str) -> Option<PugErrorDetails> {
+    let error: serde_json::Value = stderr
+        .lines()
+        .find_map(|line| serde_json::from_str(line.trim()).ok())?;
+    Some(PugErrorDetails {
+        filename: error["filename"].as_str().unwrap_or("Pug").to_owned(),
+        line: error["line"].as_u64()?.try_into().ok()?,
+        column: error["column"]
+            .as_u64()
+            .and_then(|column| column.try_into().ok()),
+        message: error["msg"].as_str()?.to_owned(),
+        snippet: Vec::new(),
+        marked: None,
+    })
+}
+
+/// `name:line:column`, or `name:line` when it follows `Error: ` (a bare
+/// `file:line` is more likely node pointing at its own source). `name` may
+/// contain colons of its own.
+fn parse_header(text: &str) -> Option<(String, u32, Option<u32>)> {
+    let text = text.trim();
+    let (text, prefixed) = match error_prefix(text) {
+        Some(prefix) => (&text[prefix.len()..], true),
+        None => (text, false),
+    };
+    let (rest, last) = text.rsplit_once(':')?;
+    let last: u32 = last.parse().ok()?;
+    match rest.rsplit_once(':') {
+        Some((name, line)) if !name.is_empty() && line.parse::<u32>().is_ok() => {
+            Some((name.to_owned(), line.parse().ok()?, Some(last)))
+        }
+        _ if prefixed && !rest.is_empty() => Some((rest.to_owned(), last, None)),
+        _ => None,
+    }
+}
+
+/// The `Error: ` or `TypeError: ` that starts `text`, if one does: what node
+/// puts in front of a thrown error's message.
+fn error_prefix(text: &str) -> Option<&str> {
+    let (name, _) = text.split_once(": ")?;
+    let is_name = name.ends_with("Error") && name.chars().all(|c| c.is_ascii_alphanumeric());
+    is_name.then(|| &text[..name.len() + 2])
+}
+
+/// `  > 7| text` or `    7| text`, and whether it's the marked one; caret
+/// lines and anything else are `None`.
+fn parse_snippet_line(text: &str) -> Option<(u32, String, bool)> {
+    let (number, rest) = text.split_once('|')?;
+    let marked = number.trim_start().starts_with('>');
+    let number = number.trim().trim_start_matches('>').trim().parse().ok()?;
+    Some((
+        number,
+        rest.strip_prefix(' ').unwrap_or(rest).to_owned(),
+        marked,
+    ))
+}
+
+/// Rewrites the line numbers in pug's error output as if the first `offset`
+/// lines of the source weren't there, for sources with a prelude prepended.
+/// Excerpt lines from the prelude are dropped; an error inside the prelude
+/// itself is left as pug reported it.
+pub(crate) fn shift_lines(stderr: &str, offset: u32) -> String {
+    let header = stderr.lines().position(|line| parse_header(line).is_some());
+    let (header, (name, line, column)) = match header {
+        Some(at) => (at, parse_header(stderr.lines().nth(at).unwrap()).unwrap()),
+        None => return stderr.to_owned(),
+    };
+    if offset == 0 || line <= offset {
+        return stderr.to_owned();
+    }
+
+    let mut shifted = Vec::new();
+    let mut lines = stderr.lines();
+    shifted.extend(lines.by_ref().take(header).map(str::to_owned));
+    let original = lines.next().unwrap();
+    let indent = &original[..original.len() - original.trim_start().len()];
+    let prefix = error_prefix(original.trim_start()).unwrap_or("");
+    let mut rewritten = format!("{}{}{}:{}", indent, prefix, name, line - offset);
+    if let Some(column) = column {
+        let _ = write!(rewritten, ":{}", column);
+    }
+    shifted.push(rewritten);
+
+    // the caret under the marked line loses as many dashes as its number
+    // loses digits
+    let narrower = line.to_string().len() - (line - offset).to_string().len();
+    let mut in_snippet = true;
+    for text in lines {
+        if text.trim().is_empty() {
+            in_snippet = false;
+        }
+        if !in_snippet {
+            shifted.push(text.to_owned());
+            continue;
+        }
+        match text.split_once('|') {
+            Some((number, rest)) => {
+                let marker = &number[..number.len() - number.trim_start_matches([' ', '>']).len()];
+                match number.trim_start_matches([' ', '>']).parse::<u32>() {
+                    Ok(n) if n > offset => {
+                        shifted.push(format!("{}{}|{}", marker, n - offset, rest))
+                    }
+                    Ok(_) => {}
+                    Err(_) => shifted.push(text.to_owned()),
+                }
+            }
+            None if text.trim_end().ends_with('^') => {
+                shifted.push(text.replacen('-', "", narrower));
+            }
+            None => shifted.push(text.to_owned()),
+        }
+    }
+    let mut shifted = shifted.join("\n");
+    if stderr.ends_with('\n') {
+        shifted.push('\n');
+    }
+    shifted
+}
+
+/// What `CompileError::to_html` includes.
+#[derive(Clone, Debug)]
+pub struct ErrorPageOptions {
+    include_source: bool,
+}
+
+impl ErrorPageOptions {
+    pub fn new() -> Self {
+        ErrorPageOptions {
+            include_source: true,
+        }
+    }
+
+    /// Whether the page shows the template excerpt and file name. Turn this
+    /// off anywhere the page might be seen by someone other than a developer;
+    /// an error pug's output can't be parsed for then only gets a generic
+    /// message, since the output may hold paths and stack traces.
+    pub fn include_source(mut self, include_source: bool) -> Self {
+        self.include_source = include_source;
+        self
+    }
+}
+
+impl Default for ErrorPageOptions {
+    fn default() -> Self {
+        ErrorPageOptions::new()
+    }
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+h1{color:#b00;font-size:1.4em}\
+pre{background:#f6f6f6;padding:1em;overflow:auto}\
+.marked{background:#fdd;display:block}";
+
+impl CompileError {
+    /// The structured form of a pug error, if pug's output could be parsed.
+    pub fn details(&self) -> Option<PugErrorDetails> {
+        match self {
+            CompileError::PugError(stderr) => PugErrorDetails::parse(stderr),
+            _ => None,
+        }
+    }
+
+    /// Whether pug failed on the template or on JavaScript it ran, from its
+    /// output; `PugErrorKind::Unknown` for errors that didn't come from pug.
+    pub fn kind(&self) -> PugErrorKind {
+        match self {
+            CompileError::PugError(stderr) => PugErrorKind::classify(stderr),
+            _ => PugErrorKind::Unknown,
+        }
+    }
+
+    /// `PugErrorDetails::render_snippet` for terminals, if pug's output could
+    /// be parsed.
+    pub fn render_snippet(&self) -> Option<String> {
+        self.details().map(|details| details.render_snippet())
+    }
+
+    /// The error on one line for a build log, as compiling `path` failed:
+    /// `path:line:column: message` when pug's output can be parsed, and
+    /// `path: error` otherwise.
+    pub fn describe(&self, path: &Path) -> String {
+        match self.details() {
+            Some(details) => match details.column {
+                Some(column) => format!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    details.line,
+                    column,
+                    details.message
+                ),
+                None => format!("{}:{}: {}", path.display(), details.line, details.message),
+            },
+            None => format!("{}: {}", path.display(), self),
+        }
+    }
+
+    /// A self-contained HTML page describing the error.
+    pub fn to_html(&self, options: ErrorPageOptions) -> String {
+        let mut page = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Template error</title>\
+             <style>{}</style></head><body><h1>Template error</h1>",
+            STYLE
+        );
+
+        match self.details() {
+            Some(details) => {
+                let location = match details.column {
+                    Some(column) => format!("line {}, column {}", details.line, column),
+                    None => format!("line {}", details.line),
+                };
+                if options.include_source {
+                    let _ = write!(
+                        page,
+                        "<p>{} at {}</p>",
+                        escape_html(&details.filename),
+                        location
+                    );
+                } else {
+                    let _ = write!(page, "<p>At {}</p>", location);
+                }
+                let _ = write!(page, "<pre>{}</pre>", escape_html(&details.message));
+
+                if options.include_source && !details.snippet.is_empty() {
+                    let marked = details.marked.unwrap_or(details.line);
+                    page.push_str("<pre>");
+                    for (number, text) in &details.snippet {
+                        let class = if *number == marked {
+                            " class=\"marked\""
+                        } else {
+                            ""
+                        };
+                        let _ = writeln!(
+                            page,
+                            "<span{}>{:>4}| {}</span>",
+                            class,
+                            number,
+                            escape_html(text)
+                        );
+                    }
+                    page.push_str("</pre>");
+                }
+            }
+            None if !options.include_source => {
+                page.push_str("<p>The template couldn't be rendered.</p>");
+            }
+            None => {
+                let message = match self {
+                    CompileError::PugError(stderr) => stderr.clone(),
+                    error => error.to_string(),
+                };
+                let _ = write!(page, "<pre>{}</pre>", escape_html(&message));
+            }
+        }
+
+        page.push_str("</body></html>");
+        page
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STDERR: &str = "/usr/lib/node_modules/pug-cli/index.js:174
+    throw err;
+    ^
+
+Error: views/page.pug:2:3
+    1| h1 <b>ok</b>
+  > 2|   p <i>broken</i>
+---------^
+    3| p after
+
+unexpected token \"indent\"
+    at makeError (/usr/lib/node_modules/pug/lib/index.js:1:1)
+";
+
+    #[test]
+    fn parses_pug_errors() {
+        let details = PugErrorDetails::parse(STDERR).unwrap();
+        assert_eq!("views/page.pug", details.filename);
+        assert_eq!(2, details.line);
+        assert_eq!(Some(3), details.column);
+        assert_eq!("unexpected token \"indent\"", details.message);
+        assert_eq!(
+            vec![
+                (1, "h1 <b>ok</b>".to_owned()),
+                (2, "  p <i>broken</i>".to_owned()),
+                (3, "p after".to_owned()),
+            ],
+            details.snippet
+        );
+        assert_eq!(None, PugErrorDetails::parse("something else went wrong"));
+
+        let structured = PugErrorDetails::parse(
+            r#"{"code":"PUG:UNEXPECTED_TOKEN","msg":"unexpected token \"indent\"","line":2,"column":3,"filename":"views/page.pug"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (details.filename, 2, Some(3), details.message),
+            (
+                structured.filename,
+                structured.line,
+                structured.column,
+                structured.message
+            )
+        );
+    }
+
+    #[test]
+    fn parses_colored_errors() {
+        let colored = "\u{1b}[31mError: views/page.pug:2:3\u{1b}[39m\n    1| h1 <b>ok</b>\n  \u{1b}[1m> 2|\u{1b}[22m   p <i>broken</i>\n---------^\n    3| p after\n\n\u{1b}]8;;https://pugjs.org\u{7}unexpected token \"indent\"\u{1b}]8;;\u{7}\u{1b}(B\n\u{1b}[2m    at makeError (/usr/lib/node_modules/pug/lib/index.js:1:1)\u{1b}[0m\n";
+        let plain = STDERR.lines().skip(4).collect::<Vec<_>>().join("\n") + "\n";
+        assert_eq!(plain, strip_ansi(colored));
+
+        let details = PugErrorDetails::parse(colored).unwrap();
+        assert_eq!(PugErrorDetails::parse(STDERR).unwrap(), details);
+        assert!(matches!(strip_ansi(STDERR), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn tells_javascript_errors_from_syntax_errors() {
+        assert_eq!(PugErrorKind::TemplateSyntax, PugErrorKind::classify(STDERR));
+
+        let in_template = "TypeError: views/page.pug:2
+    1| h1 Profile
+  > 2| p= user.name
+    3| p after
+
+Cannot read properties of undefined (reading 'name')
+    at eval (eval at wrap (/srv/node_modules/pug-runtime/wrap.js:6:10), <anonymous>:12:32)
+    at template (eval at wrap (/srv/node_modules/pug-runtime/wrap.js:6:10), <anonymous>:15:7)
+";
+        assert_eq!(
+            PugErrorKind::JavaScriptRuntime {
+                message: "TypeError: Cannot read properties of undefined (reading 'name')".into(),
+                stack: in_template
+                    .lines()
+                    .skip(6)
+                    .map(|l| l.trim().to_owned())
+                    .collect(),
+                frame: Some(StackFrame {
+                    file: "views/page.pug".into(),
+                    line: 2,
+                    column: None,
+                }),
+            },
+            PugErrorKind::classify(in_template)
+        );
+        assert_eq!(2, PugErrorDetails::parse(in_template).unwrap().line);
+        let thrown = in_template.replacen("TypeError", "Error", 1);
+        assert!(matches!(
+            PugErrorKind::classify(&thrown),
+            PugErrorKind::JavaScriptRuntime { message, .. } if message.starts_with("Error: Cannot")
+        ));
+
+        let in_locals = "/srv/site/locals.js:3
+  const year = config.year.toFixed();
+                           ^
+
+TypeError: Cannot read properties of undefined (reading 'toFixed')
+    at Object.<anonymous> (/srv/site/locals.js:3:28)
+    at Module._compile (node:internal/modules/cjs/loader:1256:14)
+    at parseObj (/usr/lib/node_modules/pug-cli/index.js:96:12)
+";
+        match PugErrorKind::classify(in_locals) {
+            PugErrorKind::JavaScriptRuntime {
+                message,
+                stack,
+                frame,
+            } => {
+                assert_eq!(
+                    "TypeError: Cannot read properties of undefined (reading 'toFixed')",
+                    message
+                );
+                assert_eq!(3, stack.len());
+                let frame = frame.unwrap();
+                assert_eq!(
+                    ("/srv/site/locals.js", 3, Some(28)),
+                    (frame.file.as_str(), frame.line, frame.column)
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+        let packaged = in_locals.replace(
+            "(/srv/site/locals.js:3:28)",
+            "(/srv/node_modules/x/i.js:1:1)",
+        );
+        assert!(matches!(
+            PugErrorKind::classify(&packaged),
+            PugErrorKind::JavaScriptRuntime { frame: None, .. }
+        ));
+
+        for other in [
+            "env: 'node': No such file or directory",
+            "Error: spawn failed",
+            "",
+        ] {
+            assert_eq!(PugErrorKind::Unknown, PugErrorKind::classify(other));
+        }
+        assert_eq!(PugErrorKind::Unknown, CompileError::EmptyInput.kind());
+    }
+
+    #[test]
+    fn keeps_spaces_and_colons_in_file_names() {
+        for filename in &[
+            "/srv/Landing Pages/Été 2024/hero.pug",
+            "C:\\Sites\\My Site\\Vol. 2.\\page.pug",
+            " leading space.pug",
+        ] {
+            let stderr = format!("Error: {}:4:2\n  > 4|   p\n\nbad", filename);
+            let details = PugErrorDetails::parse(&stderr).unwrap();
+            assert_eq!(
+                (*filename, 4, Some(2)),
+                (details.filename.as_str(), details.line, details.column)
+            );
+        }
+    }
+
+    #[test]
+    fn renames_the_source() {
+        let renamed = rename_source(STDERR, "emails/welcome");
+        assert_eq!(
+            STDERR.replacen("views/page.pug", "emails/welcome", 1),
+            renamed
+        );
+        let details = PugErrorDetails::parse(&renamed).unwrap();
+        assert_eq!(
+            ("emails/welcome", 2),
+            (details.filename.as_str(), details.line)
+        );
+    }
+
+    #[test]
+    fn recognises_missing_filters() {
+        let unknown = "Error: Pug:1:1\n  > 1| :markdown-it\n-------^\n\nunknown filter \":markdown-it\"\n    at makeError (pug-error/index.js:7:13)\n";
+        let unresolvable = "Error: Cannot find module 'jstransformer-coffee-script'\nRequire stack:\n- /usr/lib/node_modules/pug-filters/lib/run-filter.js\n";
+        for (stderr, filter) in &[(unknown, "markdown-it"), (unresolvable, "coffee-script")] {
+            match pug_failure(stderr.to_string()) {
+                CompileError::MissingFilter {
+                    filter: found,
+                    hint,
+                } => {
+                    assert_eq!(*filter, found);
+                    assert_eq!(format!("npm install jstransformer-{}", filter), hint);
+                }
+                error => panic!("{:?}", error),
+            }
+        }
+        assert_eq!(
+            "filter :markdown-it isn't installed; run `npm install jstransformer-markdown-it`",
+            pug_failure(unknown.into()).to_string()
+        );
+        assert!(matches!(
+            pug_failure(STDERR.into()),
+            CompileError::PugError(_)
+        ));
+    }
+
+    #[test]
+    fn shifts_line_numbers_past_a_prelude() {
+        let shifted = shift_lines(STDERR, 1);
+        assert!(shifted.contains(
+            "Error: views/page.pug:1:3\n  > 1|   p <i>broken</i>\n---------^\n    2| p after\n\n"
+        ));
+        assert!(!shifted.contains("h1 <b>ok</b>"));
+        assert_eq!(STDERR, shift_lines(STDERR, 2));
+    }
+
+    #[test]
+    fn error_page_escapes_and_locates() {
+        let error = CompileError::PugError(STDERR.into());
+        let page = error.to_html(ErrorPageOptions::new());
+        assert!(page.contains("line 2, column 3"));
+        assert!(page.contains("views/page.pug"));
+        assert!(page.contains("p &lt;i&gt;broken&lt;/i&gt;"));
+        assert!(!page.contains("<i>broken"));
+
+        let page = error.to_html(ErrorPageOptions::new().include_source(false));
+        assert!(page.contains("line 2"));
+        assert!(!page.contains("views/page.pug"));
+        assert!(!page.contains("broken"));
+
+        let crashed = CompileError::PugError(
+            "internal/modules/cjs/loader.js\nError: ENOMEM reading /srv/site/views\n".into(),
+        );
+        let unreadable = CompileError::io_at("open template", std::path::Path::new("/srv/a.pug"))(
+            std::io::ErrorKind::NotFound.into(),
+        );
+        for error in [crashed, unreadable] {
+            assert!(error.to_html(ErrorPageOptions::new()).contains("/srv/"));
+            let page = error.to_html(ErrorPageOptions::new().include_source(false));
+            assert!(page.contains("couldn't be rendered"), "{}", page);
+            assert!(!page.contains("/srv/"), "{}", page);
+        }
+    }
+
+    #[test]
+    fn error_page_marks_the_line_pug_marked() {
+        let stderr = "Error: views/page.pug:3:1\n  > 2| p a\n    3| p b\n\nbad";
+        let page = CompileError::PugError(stderr.into()).to_html(ErrorPageOptions::new());
+        assert!(
+            page.contains("<span class=\"marked\">   2| p a</span>"),
+            "{}",
+            page
+        );
+        assert!(page.contains("<span>   3| p b</span>"), "{}", page);
+    }
+}
@@ -0,0 +1,221 @@
+//! Templates registered once under a name and rendered by it.
+
+use crate::build::find_templates;
+use crate::{evaluate_string_with_options, CompileError, PugJsonObject, PugOptions};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// When a `TemplateRegistry` rereads a template's file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reload {
+    /// Read each file the first time it's rendered and keep that source for
+    /// good, so edits need a restart. Meant for production.
+    Pinned,
+    /// Reread a file whenever its modification time changes, so edits show
+    /// up on the next render. Meant for development.
+    OnChange,
+}
+
+/// Named templates for handlers to render from shared application state;
+/// `Send` and `Sync`, so it can sit in an `Arc`.
+///
+/// Sources are kept in memory as `reload` says; each render still runs
+/// pug, with includes resolved from the template's own path. With the
+/// `tokio` feature it's also a `tokio::Render`.
+pub struct TemplateRegistry {
+    options: PugOptions,
+    reload: Reload,
+    templates: BTreeMap<String, Arc<Template>>,
+}
+
+struct Template {
+    path: PathBuf,
+    source: Mutex<Option<Source>>,
+}
+
+struct Source {
+    modified: Option<SystemTime>,
+    text: String,
+}
+
+impl TemplateRegistry {
+    /// A registry rendering with `options` as the base for every template.
+    pub fn new(options: PugOptions, reload: Reload) -> Self {
+        TemplateRegistry {
+            options,
+            reload,
+            templates: BTreeMap::new(),
+        }
+    }
+
+    /// Registers the template at `path` as `name`, replacing any template
+    /// already registered under it. The file isn't read until it's rendered.
+    pub fn register(&mut self, name: impl Into<String>, path: impl Into<PathBuf>) {
+        let template = Template {
+            path: path.into(),
+            source: Mutex::new(None),
+        };
+        self.templates.insert(name.into(), Arc::new(template));
+    }
+
+    /// Registers every `.pug` file under `dir` as `prefix` followed by its
+    /// path relative to `dir`, without the extension and with `/` between
+    /// components: `pages/home.pug` under prefix `site` becomes `site/pages/home`.
+    /// An empty prefix leaves just the relative path.
+    pub fn register_dir(&mut self, prefix: &str, dir: impl AsRef<Path>) {
+        let dir = dir.as_ref();
+        let mut templates = Vec::new();
+        find_templates(dir, &mut templates);
+        for template in templates {
+            let relative = template.strip_prefix(dir).unwrap_or(&template);
+            let mut name = String::from(prefix.trim_end_matches('/'));
+            for component in relative.with_extension("").components() {
+                if !name.is_empty() {
+                    name.push('/');
+                }
+                name.push_str(&component.as_os_str().to_string_lossy());
+            }
+            self.register(name, template);
+        }
+    }
+
+    /// Renders the template registered as `name` with `locals` over the
+    /// registry's options, failing with `CompileError::UnknownTemplate` if
+    /// there's none.
+    pub fn render(
+        &self,
+        name: &str,
+        locals: impl Into<PugJsonObject>,
+    ) -> Result<String, CompileError> {
+        self.deferred(name, locals)?()
+    }
+
+    /// `render` as a closure that owns what it needs, for running elsewhere,
+    /// such as on tokio's blocking pool.
+    pub(crate) fn deferred(
+        &self,
+        name: &str,
+        locals: impl Into<PugJsonObject>,
+    ) -> Result<impl FnOnce() -> Result<String, CompileError> + Send + 'static, CompileError> {
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| CompileError::UnknownTemplate(name.to_owned()))?
+            .clone();
+        let reload = self.reload;
+        let mut options = self.options.clone().with_path(&template.path);
+        // a name the registry's options chose is kept
+        if options.source_name.is_none() {
+            options = options.source_name(template.path.to_string_lossy());
+        }
+        let options = options.with_object(locals);
+        Ok(move || {
+            let source = template.source(reload)?;
+            evaluate_string_with_options(source, options)
+        })
+    }
+
+    /// The registered names, in order.
+    pub fn names(&self) -> Vec<&str> {
+        self.templates.keys().map(String::as_str).collect()
+    }
+}
+
+impl Template {
+    fn source(&self, reload: Reload) -> Result<String, CompileError> {
+        let mut source = self.source.lock().unwrap();
+        if let (Some(cached), Reload::Pinned) = (&*source, reload) {
+            return Ok(cached.text.clone());
+        }
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if let Some(cached) = &*source {
+            if modified.is_some() && cached.modified == modified {
+                return Ok(cached.text.clone());
+            }
+        }
+        let text = fs::read_to_string(&self.path)
+            .map_err(CompileError::io_at("read template", &self.path))?;
+        *source = Some(Source {
+            modified,
+            text: text.clone(),
+        });
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn edit(path: &Path, source: &str) {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        fs::write(path, source).unwrap();
+        // coarse filesystem clocks could otherwise leave the time unchanged
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+    }
+
+    #[test]
+    fn renders_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("pages")).unwrap();
+        fs::write(dir.path().join("pages/home.pug"), "h1 #{title}").unwrap();
+        fs::write(dir.path().join("footer.pug"), "footer bye").unwrap();
+
+        let mut registry = TemplateRegistry::new(PugOptions::new(), Reload::Pinned);
+        registry.register_dir("site", dir.path());
+        registry.register("footer", dir.path().join("footer.pug"));
+        assert_eq!(
+            vec!["footer", "site/footer", "site/pages/home"],
+            registry.names()
+        );
+
+        let home = registry.render("site/pages/home", json!({ "title": "Hi" }));
+        assert_eq!("<h1>Hi</h1>", home.unwrap());
+        let missing = registry.render("nope", json!({})).unwrap_err();
+        assert!(matches!(&missing, CompileError::UnknownTemplate(name) if name == "nope"));
+    }
+
+    #[test]
+    fn names_errors_by_path_unless_told_otherwise() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.pug");
+        fs::write(&path, "p ok\n| piped\n  b bad").unwrap();
+        let filename = |options| {
+            let mut registry = TemplateRegistry::new(options, Reload::Pinned);
+            registry.register("broken", &path);
+            let error = registry.render("broken", json!({})).unwrap_err();
+            error.details().unwrap().filename
+        };
+        assert_eq!(path.to_string_lossy(), filename(PugOptions::new()));
+        let named = PugOptions::new().source_name("views/broken");
+        assert_eq!("views/broken", filename(named));
+    }
+
+    #[test]
+    fn reloads_edits_only_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.pug");
+        fs::write(&path, "p old").unwrap();
+        let registry = |reload| {
+            let mut registry = TemplateRegistry::new(PugOptions::new(), reload);
+            registry.register("page", &path);
+            registry
+        };
+        let (on_change, pinned) = (registry(Reload::OnChange), registry(Reload::Pinned));
+        assert_eq!("<p>old</p>", on_change.render("page", json!({})).unwrap());
+        assert_eq!("<p>old</p>", pinned.render("page", json!({})).unwrap());
+
+        edit(&path, "p new");
+        assert_eq!("<p>new</p>", on_change.render("page", json!({})).unwrap());
+        assert_eq!("<p>old</p>", pinned.render("page", json!({})).unwrap());
+    }
+}
@@ -0,0 +1,319 @@
+//! Re-indenting compiled HTML, in place of pug's fixed two-space `--pretty`.
+//!
+//! Block elements go on lines of their own, one level deeper than their
+//! parent. An element holding only text and inline elements stays on one
+//! line, as pug's pretty printer does. `pre`, `textarea`, `script` and
+//! `style` are copied byte for byte, as are comments and doctypes.
+
+/// The indentation `PugOptions::reindent` uses per level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(u8),
+    Tabs,
+}
+
+impl Indent {
+    fn write(self, out: &mut String, depth: usize) {
+        for _ in 0..depth {
+            match self {
+                Indent::Spaces(width) => out.extend((0..width).map(|_| ' ')),
+                Indent::Tabs => out.push('\t'),
+            }
+        }
+    }
+}
+
+const RAW: &[&str] = &["pre", "textarea", "script", "style"];
+
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+const INLINE: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "cite", "code", "data", "dfn", "em", "i", "img", "kbd",
+    "label", "mark", "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+    "wbr",
+];
+
+enum Node<'a> {
+    Text(&'a str),
+    /// Copied as is: comments, doctypes, raw elements and stray end tags.
+    Verbatim(&'a str),
+    Element {
+        name: String,
+        start: &'a str,
+        children: Vec<Node<'a>>,
+        end: Option<&'a str>,
+    },
+}
+
+impl Node<'_> {
+    fn is_inline(&self) -> bool {
+        match self {
+            Node::Text(_) => true,
+            Node::Verbatim(_) => false,
+            Node::Element { name, children, .. } => {
+                INLINE.contains(&name.as_str()) && children.iter().all(Node::is_inline)
+            }
+        }
+    }
+
+    fn write_inline(&self, out: &mut String) {
+        match self {
+            // a pretty printer's line break between inline elements
+            Node::Text(text) if text.trim().is_empty() && text.contains('\n') => {}
+            Node::Text(text) | Node::Verbatim(text) => out.push_str(text),
+            Node::Element {
+                start,
+                children,
+                end,
+                ..
+            } => {
+                out.push_str(start);
+                for child in children {
+                    child.write_inline(out);
+                }
+                out.extend(*end);
+            }
+        }
+    }
+}
+
+/// `html` with one node per line, indented by `indent`. Whitespace between
+/// block elements is replaced; everything else is kept.
+pub(crate) fn reindent(html: &str, indent: Indent) -> String {
+    let mut out = String::with_capacity(html.len());
+    write_block(&parse(html), indent, 0, &mut out);
+    if out.ends_with('\n') && !html.ends_with('\n') {
+        out.pop();
+    }
+    out
+}
+
+fn write_block(nodes: &[Node], indent: Indent, depth: usize, out: &mut String) {
+    let mut line = String::new();
+    let flush = |line: &mut String, out: &mut String| {
+        if !line.trim().is_empty() {
+            indent.write(out, depth);
+            out.push_str(line.trim());
+            out.push('\n');
+        }
+        line.clear();
+    };
+    for node in nodes {
+        if node.is_inline() {
+            node.write_inline(&mut line);
+            continue;
+        }
+        flush(&mut line, out);
+        match node {
+            Node::Element {
+                start,
+                children,
+                end,
+                ..
+            } if !children.iter().all(Node::is_inline) => {
+                indent.write(out, depth);
+                out.push_str(start);
+                out.push('\n');
+                write_block(children, indent, depth + 1, out);
+                if let Some(end) = end {
+                    indent.write(out, depth);
+                    out.push_str(end);
+                    out.push('\n');
+                }
+            }
+            _ => {
+                indent.write(out, depth);
+                node.write_inline(out);
+                out.push('\n');
+            }
+        }
+    }
+    flush(&mut line, out);
+}
+
+fn parse(html: &str) -> Vec<Node<'_>> {
+    // the open elements, each with the children it has so far
+    let mut stack: Vec<(String, &str, Vec<Node>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut at = 0;
+    while at < html.len() {
+        let rest = &html[at..];
+        let (node, len) = match next(rest) {
+            Token::Text(len) => (Some(Node::Text(&rest[..len])), len),
+            Token::Verbatim(len) => (Some(Node::Verbatim(&rest[..len])), len),
+            Token::Start { name, len } if RAW.contains(&name.as_str()) => {
+                let len = raw_end(rest, len, &name);
+                (Some(Node::Verbatim(&rest[..len])), len)
+            }
+            Token::Start { name, len } => {
+                let self_closing = rest[..len].ends_with("/>");
+                if VOID.contains(&name.as_str()) || self_closing {
+                    let node = Node::Element {
+                        name,
+                        start: &rest[..len],
+                        children: Vec::new(),
+                        end: None,
+                    };
+                    (Some(node), len)
+                } else {
+                    stack.push((name, &rest[..len], std::mem::take(&mut nodes)));
+                    (None, len)
+                }
+            }
+            Token::End { name, len } => match stack.iter().rposition(|(open, ..)| *open == name) {
+                Some(open) => {
+                    while stack.len() > open + 1 {
+                        close(&mut stack, &mut nodes, None);
+                    }
+                    close(&mut stack, &mut nodes, Some(&rest[..len]));
+                    (None, len)
+                }
+                None => (Some(Node::Verbatim(&rest[..len])), len),
+            },
+        };
+        nodes.extend(node);
+        at += len;
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut nodes, None);
+    }
+    nodes
+}
+
+/// Pops the innermost open element into its parent's children.
+fn close<'a>(
+    stack: &mut Vec<(String, &'a str, Vec<Node<'a>>)>,
+    nodes: &mut Vec<Node<'a>>,
+    end: Option<&'a str>,
+) {
+    let (name, start, siblings) = stack.pop().expect("an open element");
+    let children = std::mem::replace(nodes, siblings);
+    nodes.push(Node::Element {
+        name,
+        start,
+        children,
+        end,
+    });
+}
+
+enum Token {
+    Text(usize),
+    Verbatim(usize),
+    Start { name: String, len: usize },
+    End { name: String, len: usize },
+}
+
+/// The token at the start of `html`, which isn't empty.
+fn next(html: &str) -> Token {
+    let text = || Token::Text(html[1..].find('<').map_or(html.len(), |end| end + 1));
+    if !html.starts_with('<') {
+        return Token::Text(html.find('<').unwrap_or(html.len()));
+    }
+    if html.starts_with("<!--") {
+        let len = html.find("-->").map_or(html.len(), |end| end + 3);
+        return Token::Verbatim(len);
+    }
+    if html.starts_with("<!") || html.starts_with("<?") {
+        return Token::Verbatim(html.find('>').map_or(html.len(), |end| end + 1));
+    }
+    let (closing, name_at) = match html.strip_prefix("</") {
+        Some(_) => (true, 2),
+        None => (false, 1),
+    };
+    let name: String = html[name_at..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == ':')
+        .collect();
+    if name.is_empty() {
+        return text();
+    }
+    let len = match tag_end(html, name_at + name.len()) {
+        Some(len) => len,
+        None => return Token::Text(html.len()),
+    };
+    let name = name.to_ascii_lowercase();
+    if closing {
+        Token::End { name, len }
+    } else {
+        Token::Start { name, len }
+    }
+}
+
+/// The length of the tag starting `html`, skipping `>` in quoted values.
+fn tag_end(html: &str, from: usize) -> Option<usize> {
+    let mut quote = None;
+    for (at, c) in html.char_indices().skip_while(|(at, _)| *at < from) {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(at + 1),
+            (None, _) => {}
+        }
+    }
+    None
+}
+
+/// Where the raw element starting `html` ends, its end tag included.
+fn raw_end(html: &str, start_len: usize, name: &str) -> usize {
+    let close = format!("</{}", name);
+    let body = html[start_len..].to_ascii_lowercase();
+    match body.find(&close) {
+        Some(at) => {
+            let end = start_len + at;
+            tag_end(html, end + close.len()).unwrap_or(html.len())
+        }
+        None => html.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_with_options, PugOptions};
+
+    #[test]
+    fn nests_block_elements() {
+        let html =
+            "<div class=\"a>b\"><ul><li>one <b>bold</b></li><li>two</li></ul><br><p>text</p></div>";
+        assert_eq!(
+            "<div class=\"a>b\">\n\t<ul>\n\t\t<li>one <b>bold</b></li>\n\t\t<li>two</li>\n\t</ul>\n\t<br>\n\t<p>text</p>\n</div>",
+            reindent(html, Indent::Tabs)
+        );
+        assert_eq!(
+            "<section>\n   <p>x</p>\n</section>",
+            reindent("<section><p>x</p></section>", Indent::Spaces(3))
+        );
+    }
+
+    #[test]
+    fn copies_raw_elements_untouched() {
+        let pre = "<pre>  keep\n    <b>this</b>\n</pre>";
+        let script = "<script>if (a < b) {\n  go();\n}</script>";
+        let html = format!("<div>{}<textarea> x </textarea>{}</div>", pre, script);
+        let expected = format!(
+            "<div>\n  {}\n  <textarea> x </textarea>\n  {}\n</div>",
+            pre, script
+        );
+        assert_eq!(expected, reindent(&html, Indent::Spaces(2)));
+    }
+
+    #[test]
+    fn reindenting_is_idempotent() {
+        let compact = evaluate_with_options("test/reindent.pug", PugOptions::new()).unwrap();
+        let pretty =
+            evaluate_with_options("test/reindent.pug", PugOptions::new().pretty()).unwrap();
+        let once = reindent(&compact, Indent::Tabs);
+        assert_eq!(once, reindent(&once, Indent::Tabs));
+        assert_eq!(once.trim(), reindent(&pretty, Indent::Tabs).trim());
+
+        let reindented = evaluate_with_options(
+            "test/reindent.pug",
+            PugOptions::new().reindent(Indent::Tabs),
+        );
+        assert_eq!(once, reindented.unwrap());
+    }
+}
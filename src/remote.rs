@@ -0,0 +1,293 @@
+//! Compiling templates fetched over HTTP.
+
+use crate::sha256::sha256_hex;
+use crate::{evaluate_string_with_options, CompileError, PugOptions};
+use std::io::Read;
+use std::time::Duration;
+
+/// A template at a URL, with the limits `evaluate_url` fetches it under.
+/// A `&str` or `String` is one with the defaults.
+#[derive(Clone, Debug)]
+pub struct RemoteTemplate {
+    url: String,
+    max_bytes: u64,
+    timeout: Duration,
+    sha256: Option<String>,
+}
+
+impl RemoteTemplate {
+    /// At most 4 MiB, fetched within 30 seconds, unchecked.
+    pub fn new(url: impl Into<String>) -> Self {
+        RemoteTemplate {
+            url: url.into(),
+            max_bytes: 4 << 20,
+            timeout: Duration::from_secs(30),
+            sha256: None,
+        }
+    }
+
+    /// Fails with `CompileError::RemoteTooLarge` past `max_bytes` of body.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Fails with `CompileError::Fetch` if the whole response, body
+    /// included, takes longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fails with `CompileError::ChecksumMismatch` unless the body's SHA-256
+    /// is `hex`, in either case.
+    pub fn sha256(mut self, hex: impl Into<String>) -> Self {
+        self.sha256 = Some(hex.into().to_ascii_lowercase());
+        self
+    }
+
+    pub(crate) fn fetch(&self) -> Result<String, CompileError> {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| self.failed(e))?;
+        let response = client
+            .get(&self.url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| self.failed(e))?;
+        self.check_length(response.content_length())?;
+        let mut body = Vec::new();
+        response
+            .take(self.max_bytes + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| self.failed(e))?;
+        self.accept(body)
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn fetch_async(&self) -> Result<String, CompileError> {
+        let client = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| self.failed(e))?;
+        let mut response = client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| self.failed(e))?;
+        self.check_length(response.content_length())?;
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| self.failed(e))? {
+            body.extend_from_slice(&chunk);
+            if body.len() as u64 > self.max_bytes {
+                break;
+            }
+        }
+        self.accept(body)
+    }
+
+    /// Fails early on a `Content-Length` already over the limit.
+    fn check_length(&self, length: Option<u64>) -> Result<(), CompileError> {
+        match length {
+            Some(length) if length > self.max_bytes => Err(self.too_large()),
+            _ => Ok(()),
+        }
+    }
+
+    fn accept(&self, body: Vec<u8>) -> Result<String, CompileError> {
+        if body.len() as u64 > self.max_bytes {
+            return Err(self.too_large());
+        }
+        if let Some(expected) = &self.sha256 {
+            let actual = sha256_hex(&body);
+            if &actual != expected {
+                return Err(CompileError::ChecksumMismatch {
+                    url: self.url.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        String::from_utf8(body).map_err(|_| self.failed("the body isn't UTF-8"))
+    }
+
+    fn failed(&self, reason: impl ToString) -> CompileError {
+        CompileError::Fetch {
+            url: self.url.clone(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn too_large(&self) -> CompileError {
+        CompileError::RemoteTooLarge {
+            url: self.url.clone(),
+            limit: self.max_bytes,
+        }
+    }
+
+    /// `options` naming the template by its URL, unless they already name it.
+    pub(crate) fn options(&self, options: PugOptions) -> PugOptions {
+        match options.source_name {
+            Some(_) => options,
+            None => options.source_name(self.url.clone()),
+        }
+    }
+}
+
+impl From<&str> for RemoteTemplate {
+    fn from(url: &str) -> Self {
+        RemoteTemplate::new(url)
+    }
+}
+
+impl From<String> for RemoteTemplate {
+    fn from(url: String) -> Self {
+        RemoteTemplate::new(url)
+    }
+}
+
+/// Downloads the template at `url` and compiles it like
+/// `evaluate_string_with_options`, with errors naming the URL.
+///
+/// Includes and extends resolve as they do for any string template, against
+/// `PugOptions::with_path` or `basedir` rather than the URL.
+///
+/// The download uses reqwest's blocking client, which panics if it's called
+/// from within a tokio runtime; async code should call
+/// `tokio::evaluate_url_async` or `async_std::evaluate_url_async` instead.
+pub fn evaluate_url(
+    url: impl Into<RemoteTemplate>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let remote = url.into();
+    let source = remote.fetch()?;
+    evaluate_string_with_options(source, remote.options(options))
+}
+
+/// `evaluate_url` for tokio, fetching with reqwest's async client.
+#[cfg(feature = "tokio")]
+pub async fn evaluate_url_async(
+    url: impl Into<RemoteTemplate>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let remote = url.into();
+    let source = remote.fetch_async().await?;
+    crate::tokio::evaluate_string_with_options_async(source, remote.options(options)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    /// Answers every request with `status` and `body` until the test ends.
+    fn serve(status: &str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/email/welcome.pug",
+            listener.local_addr().unwrap()
+        );
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    const TEMPLATE: &str = "p Welcome, #{name}";
+
+    #[test]
+    fn compiles_a_fetched_template() {
+        let url = serve("200 OK", TEMPLATE);
+        let options = PugOptions::new().with_object(serde_json::json!({ "name": "Ada" }));
+        let remote = RemoteTemplate::new(url.as_str()).sha256(sha256_hex(TEMPLATE.as_bytes()));
+
+        assert_eq!(
+            "<p>Welcome, Ada</p>",
+            evaluate_url(remote, options.clone()).unwrap()
+        );
+        assert_eq!("<p>Welcome, Ada</p>", evaluate_url(url, options).unwrap());
+    }
+
+    #[test]
+    fn tells_fetch_failures_apart() {
+        let missing = serve("404 Not Found", "gone");
+        match evaluate_url(missing.as_str(), PugOptions::new()) {
+            Err(CompileError::Fetch { url, reason }) => {
+                assert_eq!(missing, url);
+                assert!(reason.contains("404"), "{}", reason);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let url = serve("200 OK", TEMPLATE);
+        let small = RemoteTemplate::new(url.as_str()).max_bytes(4);
+        assert!(matches!(
+            evaluate_url(small, PugOptions::new()),
+            Err(CompileError::RemoteTooLarge { limit: 4, .. })
+        ));
+        let pinned = RemoteTemplate::new(url.as_str()).sha256("00");
+        match evaluate_url(pinned, PugOptions::new()) {
+            Err(CompileError::ChecksumMismatch {
+                expected, actual, ..
+            }) => assert_eq!(
+                ("00", sha256_hex(TEMPLATE.as_bytes())),
+                (&*expected, actual)
+            ),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn names_the_url_in_pug_errors() {
+        let url = serve("200 OK", "main\n  h1 ok\n p wrong");
+        let error = evaluate_url(url.as_str(), PugOptions::new()).unwrap_err();
+        assert_eq!(url, error.details().unwrap().filename);
+    }
+
+    #[cfg(feature = "async-std")]
+    #[::async_std::test]
+    async fn fetches_on_async_std() {
+        use crate::async_std::evaluate_url_async;
+
+        let url = serve("200 OK", TEMPLATE);
+        let options = PugOptions::new().with_object(serde_json::json!({ "name": "Ada" }));
+        assert_eq!(
+            "<p>Welcome, Ada</p>",
+            evaluate_url_async(url.as_str(), options).await.unwrap()
+        );
+        let small = RemoteTemplate::new(url).max_bytes(4);
+        assert!(matches!(
+            evaluate_url_async(small, PugOptions::new()).await,
+            Err(CompileError::RemoteTooLarge { .. })
+        ));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[::tokio::test]
+    async fn fetches_asynchronously() {
+        let url = serve("200 OK", TEMPLATE);
+        let options = PugOptions::new().with_object(serde_json::json!({ "name": "Ada" }));
+        assert_eq!(
+            "<p>Welcome, Ada</p>",
+            evaluate_url_async(url.as_str(), options).await.unwrap()
+        );
+        let small = RemoteTemplate::new(url).max_bytes(4);
+        assert!(matches!(
+            evaluate_url_async(small, PugOptions::new()).await,
+            Err(CompileError::RemoteTooLarge { .. })
+        ));
+    }
+}
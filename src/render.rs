@@ -0,0 +1,114 @@
+//! A synchronous abstraction over template engines, so pug can sit behind the
+//! same interface as others.
+
+use crate::{CompileError, PugEngine, PugOptions, TemplateRegistry};
+use serde_json::Value;
+use std::error;
+use std::fmt;
+
+/// Something that renders a template with locals.
+///
+/// Object-safe, so an application supporting several engines can hold a
+/// `Box<dyn Render>`.
+pub trait Render: Send + Sync {
+    fn render(&self, template: &str, locals: &Value) -> Result<String, RenderError>;
+}
+
+/// Why a `Render` implementation failed.
+pub enum RenderError {
+    /// Pug failed.
+    Compile(CompileError),
+    /// Some other engine failed.
+    Other(Box<dyn error::Error + Send + Sync>),
+}
+
+impl From<CompileError> for RenderError {
+    fn from(error: CompileError) -> Self {
+        RenderError::Compile(error)
+    }
+}
+
+impl error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RenderError::Compile(error) => Some(error),
+            RenderError::Other(error) => Some(error.as_ref()),
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Compile(error) => write!(f, "{}", error),
+            RenderError::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl fmt::Debug for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Compile(error) => write!(f, "{:?}", error),
+            RenderError::Other(error) => write!(f, "Other: {:?}", error),
+        }
+    }
+}
+
+/// Treats `template` as a path and compiles it with the engine's defaults;
+/// `PugEngine::default()` renders with default options.
+impl Render for PugEngine {
+    fn render(&self, template: &str, locals: &Value) -> Result<String, RenderError> {
+        let overrides = PugOptions::new().with_object(locals.clone());
+        Ok(PugEngine::render(self, template, overrides)?)
+    }
+}
+
+/// Treats `template` as a registered name.
+impl Render for TemplateRegistry {
+    fn render(&self, template: &str, locals: &Value) -> Result<String, RenderError> {
+        Ok(TemplateRegistry::render(self, template, locals.clone())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Reload;
+    use serde_json::json;
+
+    /// Substitutes `{{name}}` with `locals.name`.
+    struct Mustachio;
+
+    impl Render for Mustachio {
+        fn render(&self, template: &str, locals: &Value) -> Result<String, RenderError> {
+            let name = locals["name"]
+                .as_str()
+                .ok_or_else(|| RenderError::Other("no name".into()))?;
+            Ok(template.replace("{{name}}", name))
+        }
+    }
+
+    #[test]
+    fn engines_are_interchangeable() {
+        let mut registry = TemplateRegistry::new(PugOptions::new(), Reload::Pinned);
+        registry.register("hello", "test/greeting.pug");
+        let engines: Vec<(Box<dyn Render>, &str)> = vec![
+            (Box::new(PugEngine::default()), "test/greeting.pug"),
+            (Box::new(registry), "hello"),
+            (Box::new(Mustachio), "<p>Hello, {{name}}!</p>"),
+        ];
+        for (engine, template) in &engines {
+            let html = engine.render(template, &json!({ "name": "pug" }));
+            assert_eq!("<p>Hello, pug!</p>", html.unwrap());
+        }
+
+        let missing = engines[1].0.render("nope", &json!({})).unwrap_err();
+        assert!(matches!(
+            missing,
+            RenderError::Compile(CompileError::UnknownTemplate(_))
+        ));
+        let mock = engines[2].0.render("", &json!({})).unwrap_err();
+        assert_eq!("no name", mock.to_string());
+    }
+}
@@ -0,0 +1,194 @@
+//! Retrying spawns that fail for lack of resources on a loaded machine.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::time::Duration;
+
+/// A class of spawn failure `RetryPolicy` retries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryOn {
+    /// Out of processes, file descriptors or memory: `EAGAIN`, `EMFILE`,
+    /// `ENFILE` and `ENOMEM`.
+    ResourceExhausted,
+    /// Interrupted by a signal (`EINTR`).
+    Interrupted,
+    /// Any error of this kind.
+    Kind(io::ErrorKind),
+}
+
+impl RetryOn {
+    fn matches(self, error: &io::Error) -> bool {
+        match self {
+            RetryOn::ResourceExhausted => {
+                matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::OutOfMemory
+                ) || (cfg!(unix) && matches!(error.raw_os_error(), Some(23) | Some(24)))
+            }
+            RetryOn::Interrupted => error.kind() == io::ErrorKind::Interrupted,
+            RetryOn::Kind(kind) => error.kind() == kind,
+        }
+    }
+}
+
+/// How `PugOptions::retry` retries starting pug.
+///
+/// Only failing to start the process is retried, never a compile that ran
+/// and failed. Waits double from `base_delay` with each attempt, each scaled
+/// by a random factor between ½ and 1½ so parallel builds don't retry in
+/// lockstep.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    retry_on: Vec<RetryOn>,
+}
+
+impl RetryPolicy {
+    /// Tries up to `max_attempts` times in all, retrying
+    /// `RetryOn::ResourceExhausted`.
+    pub fn new(max_attempts: u32, base_delay: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay,
+            retry_on: vec![RetryOn::ResourceExhausted],
+        }
+    }
+
+    /// Retries `class` as well.
+    pub fn retry_on(mut self, class: RetryOn) -> Self {
+        self.retry_on.push(class);
+        self
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.base_delay * 2u32.saturating_pow(attempt - 1);
+        let jitter = RandomState::new().build_hasher().finish() % 1000;
+        backoff.mul_f64(0.5 + jitter as f64 / 1000.0)
+    }
+}
+
+/// How long to wait before retrying the `attempt`th spawn, which failed with
+/// `error`, or the error to give up with.
+pub(crate) fn next_delay(
+    policy: Option<&RetryPolicy>,
+    attempt: u32,
+    error: io::Error,
+) -> Result<Duration, io::Error> {
+    let policy = match policy {
+        Some(policy) if policy.retry_on.iter().any(|class| class.matches(&error)) => policy,
+        _ => return Err(error),
+    };
+    if attempt < policy.max_attempts {
+        Ok(policy.delay(attempt))
+    } else {
+        Err(io::Error::new(
+            error.kind(),
+            format!("starting pug failed after {} attempts: {}", attempt, error),
+        ))
+    }
+}
+
+/// Calls `spawn` until it succeeds or `policy` gives up.
+pub(crate) fn retry<T>(
+    policy: Option<&RetryPolicy>,
+    mut spawn: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match spawn() {
+            Ok(spawned) => return Ok(spawned),
+            Err(error) => std::thread::sleep(next_delay(policy, attempt, error)?),
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::MockExecutor;
+    use crate::{evaluate_string_with_options, CompileError, Executor, PugOptions};
+    use std::path::Path;
+    use std::process::Output;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    /// Fails with `error` `failures` times, then answers like `pug`.
+    #[derive(Clone)]
+    struct Flaky {
+        failures: u32,
+        error: io::ErrorKind,
+        runs: Arc<AtomicU32>,
+        pug: MockExecutor,
+    }
+
+    impl Executor for Flaky {
+        fn run(&self, program: &Path, args: &[String], stdin: &[u8]) -> io::Result<Output> {
+            if self.runs.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(io::Error::new(
+                    self.error,
+                    "Resource temporarily unavailable",
+                ));
+            }
+            self.pug.run(program, args, stdin)
+        }
+    }
+
+    fn flaky(failures: u32, error: io::ErrorKind) -> Flaky {
+        Flaky {
+            failures,
+            error,
+            runs: Arc::default(),
+            pug: MockExecutor::succeeding("<p>ok</p>"),
+        }
+    }
+
+    #[test]
+    fn retries_transient_spawn_failures() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(1));
+        let pug = flaky(3, io::ErrorKind::WouldBlock);
+        let options = PugOptions::new()
+            .executor(pug.clone())
+            .retry(policy.clone());
+        assert_eq!(
+            "<p>ok</p>",
+            evaluate_string_with_options("p".into(), options).unwrap()
+        );
+        assert_eq!(4, pug.runs.load(Ordering::SeqCst));
+
+        let pug = flaky(4, io::ErrorKind::WouldBlock);
+        let options = PugOptions::new()
+            .executor(pug.clone())
+            .retry(policy.clone());
+        let error = evaluate_string_with_options("p".into(), options).unwrap_err();
+        assert!(
+            matches!(&error, CompileError::IoAt { source, .. } if source.to_string().contains("after 4 attempts")),
+            "{:?}",
+            error
+        );
+
+        let pug = flaky(1, io::ErrorKind::PermissionDenied);
+        let options = PugOptions::new()
+            .executor(pug.clone())
+            .retry(policy.clone());
+        assert!(evaluate_string_with_options("p".into(), options).is_err());
+        assert_eq!(1, pug.runs.load(Ordering::SeqCst));
+
+        let broken = MockExecutor::failing(1, "Error: Pug:1:1\n\nbroken\n");
+        let options = PugOptions::new().executor(broken.clone()).retry(policy);
+        assert!(evaluate_string_with_options("p".into(), options).is_err());
+        assert_eq!(1, broken.runs().len());
+    }
+
+    #[test]
+    fn backs_off_with_jitter() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100));
+        for attempt in 1..=4 {
+            let delay = policy.delay(attempt).as_millis();
+            let backoff = 100 << (attempt - 1);
+            assert!(delay >= backoff / 2 && delay < backoff * 3 / 2, "{}", delay);
+        }
+    }
+}
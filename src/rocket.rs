@@ -0,0 +1,166 @@
+//! Rendering templates from Rocket routes.
+//!
+//! Attach `PugTemplate::fairing(options)` and return `PugTemplate::render`
+//! from a route. Templates are looked up in the directory named by the
+//! `template_dir` config value, `templates` by default. Every render runs pug
+//! afresh, so edited templates show up on the next request in any profile.
+//! Setting `pug_debug_errors = true` answers failed renders with
+//! `CompileError::to_html` rather than handing them to the 500 catcher.
+
+use crate::invocation::program;
+use crate::{evaluate_with_options, PugEngine, PugOptions};
+use rocket::fairing::{AdHoc, Fairing};
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use serde_json::Value;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Managed state installed by the fairing.
+struct PugContext {
+    dir: PathBuf,
+    engine: PugEngine,
+}
+
+/// A named template and its locals, rendered into a `text/html` response.
+///
+/// A failed render is logged and answered with a 500, so it reaches the
+/// application's 500 catcher.
+///
+/// Rendering happens when Rocket asks for the response, on its async worker
+/// thread, because `Responder::respond_to` is not async. Routes rendering
+/// expensive templates should render with `pug_cli::tokio` and return the
+/// HTML in a `content::RawHtml` instead.
+pub struct PugTemplate {
+    name: String,
+    locals: Value,
+}
+
+impl PugTemplate {
+    /// `name` is relative to the template directory; `.pug` is added when it
+    /// has no extension.
+    pub fn render(name: impl Into<String>, locals: Value) -> Self {
+        PugTemplate {
+            name: name.into(),
+            locals,
+        }
+    }
+
+    /// Reads `template_dir` and checks that pug can be run, failing ignition
+    /// otherwise; every `PugTemplate` then renders with `options`.
+    pub fn fairing(options: PugOptions) -> impl Fairing {
+        AdHoc::try_on_ignite("Pug Templates", |rocket| async move {
+            let dir: PathBuf = rocket
+                .figment()
+                .extract_inner("template_dir")
+                .unwrap_or_else(|_| "templates".into());
+            let debug_errors = rocket
+                .figment()
+                .extract_inner("pug_debug_errors")
+                .unwrap_or(false);
+
+            let probe = Command::new(program(&options))
+                .arg("--version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+            if let Err(e) = probe {
+                log::error!("pug could not be run: {}", e);
+                return Err(rocket);
+            }
+
+            let engine = PugEngine::new(options).debug_errors(debug_errors);
+            Ok(rocket.manage(PugContext { dir, engine }))
+        })
+    }
+}
+
+impl<'r> Responder<'r, 'static> for PugTemplate {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let context = req.rocket().state::<PugContext>().ok_or_else(|| {
+            log::error!("PugTemplate used without attaching PugTemplate::fairing");
+            Status::InternalServerError
+        })?;
+
+        let PugTemplate { name, locals } = self;
+        let mut path = context.dir.join(&name);
+        if path.extension().is_none() {
+            path.set_extension("pug");
+        }
+        let options = context.engine.defaults().clone().with_object(locals);
+        let (status, html) = match evaluate_with_options(path, options) {
+            Ok(html) => (Status::Ok, html),
+            Err(e) => match context.engine.error_page(&e) {
+                Some(page) => (Status::InternalServerError, page),
+                None => {
+                    log::error!("failed to render {}: {}", name, e);
+                    return Err(Status::InternalServerError);
+                }
+            },
+        };
+
+        Response::build()
+            .status(status)
+            .header(ContentType::HTML)
+            .sized_body(html.len(), Cursor::new(html))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::error::ErrorKind;
+    use rocket::local::blocking::Client;
+    use rocket::{get, routes};
+    use serde_json::json;
+    use std::fs;
+
+    #[get("/hello")]
+    fn hello() -> PugTemplate {
+        PugTemplate::render("hello", json!({ "language": "pug" }))
+    }
+
+    #[get("/missing")]
+    fn missing() -> PugTemplate {
+        PugTemplate::render("missing", json!({}))
+    }
+
+    fn rocket(dir: &std::path::Path, options: PugOptions) -> rocket::Rocket<rocket::Build> {
+        let figment = rocket::Config::figment().merge(("template_dir", dir));
+        rocket::custom(figment)
+            .attach(PugTemplate::fairing(options))
+            .mount("/", routes![hello, missing])
+    }
+
+    #[test]
+    fn renders_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("hello.pug"), "h1 hello #{language}").unwrap();
+        let client = Client::tracked(rocket(dir.path(), PugOptions::new())).unwrap();
+
+        let response = client.get("/hello").dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(Some(ContentType::HTML), response.content_type());
+        assert_eq!("<h1>hello pug</h1>", response.into_string().unwrap());
+
+        assert_eq!(
+            Status::InternalServerError,
+            client.get("/missing").dispatch().status()
+        );
+    }
+
+    #[test]
+    fn missing_binary_fails_ignition() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = PugOptions::new().binary(dir.path().join("no-such-pug"));
+        match Client::tracked(rocket(dir.path(), options)) {
+            Ok(_) => panic!("ignition succeeded without pug"),
+            // inspecting the error marks it handled; rocket panics otherwise
+            Err(error) => assert!(matches!(error.kind(), ErrorKind::FailedFairings(_))),
+        }
+    }
+}
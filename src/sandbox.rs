@@ -0,0 +1,110 @@
+//! `PugOptions::sandbox`: compiling templates from people who aren't trusted.
+
+use crate::{CompileError, PugJsonObject, PugOptions};
+use std::time::Duration;
+
+/// The `timeout` a sandboxed compile gets unless one was set.
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(5);
+/// The `cpu_time_limit` a sandboxed compile gets unless one was set.
+pub(crate) const CPU_TIME: Duration = Duration::from_secs(2);
+/// The `memory_limit` a sandboxed compile gets unless one was set.
+pub(crate) const MEMORY: u64 = 256 << 20;
+/// The `max_output_bytes` a sandboxed compile gets unless one was set.
+pub(crate) const OUTPUT: u64 = 1 << 20;
+
+/// Fills in the limits `options` left unset and clears pug's environment.
+pub(crate) fn apply(options: &mut PugOptions) {
+    options.timeout.get_or_insert(TIMEOUT);
+    options.limits.cpu_time.get_or_insert(CPU_TIME);
+    options.limits.memory.get_or_insert(MEMORY);
+    options.limits.output.get_or_insert(OUTPUT);
+    options.limits.clear_env = true;
+}
+
+/// The options a sandbox can't be combined with.
+pub(crate) fn check_options(options: &PugOptions) -> Result<(), CompileError> {
+    if let Some(PugJsonObject::JsModule(_)) = &options.object {
+        return Err(CompileError::InvalidOptions(
+            "sandbox: JavaScript module locals run code outside the template".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Fails with `CompileError::Sandbox` if `source` includes or extends another
+/// file, naming the line.
+pub(crate) fn check_source(source: &str) -> Result<(), CompileError> {
+    // read the lines as pug's lexer does: past a byte order mark, with a
+    // lone `\r` ending one too
+    let source = source.strip_prefix('\u{FEFF}').unwrap_or(source);
+    let source = source.replace("\r\n", "\n").replace('\r', "\n");
+    for (number, line) in source.lines().enumerate() {
+        // a tag's block expansion can hold an include too: `div: include x`
+        for part in line.split(": ") {
+            let part = part.trim_start();
+            for keyword in ["include", "extends"] {
+                let reads_file = part.strip_prefix(keyword).is_some_and(|rest| {
+                    rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c == ':')
+                });
+                if reads_file {
+                    return Err(CompileError::Sandbox(format!(
+                        "`{}` would read another file (line {})",
+                        keyword,
+                        number + 1
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_includes_and_extends() {
+        for source in [
+            "include /etc/hostname",
+            "p hi\n  include:markdown-it notes.md",
+            "div: include partial",
+            "extends layout",
+            "\u{FEFF}include /etc/hostname",
+            "p x\rinclude /etc/hostname",
+            "p x\r\n\rextends layout",
+        ] {
+            assert!(
+                matches!(check_source(source), Err(CompileError::Sandbox(_))),
+                "{}",
+                source
+            );
+        }
+        match check_source("p one\n\n  extends ../layout").unwrap_err() {
+            CompileError::Sandbox(rule) => {
+                assert_eq!("`extends` would read another file (line 3)", rule)
+            }
+            error => panic!("{:?}", error),
+        }
+        match check_source("p one\r\rp two\r\ninclude x").unwrap_err() {
+            CompileError::Sandbox(rule) => {
+                assert_eq!("`include` would read another file (line 4)", rule)
+            }
+            error => panic!("{:?}", error),
+        }
+        for source in ["p included", "p: a(href='/include') extends", "// include"] {
+            assert!(check_source(source).is_ok(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn refuses_js_module_locals() {
+        let options = PugOptions::new()
+            .with_object(PugJsonObject::JsModule("locals.js".into()))
+            .sandbox();
+        match options.validate().unwrap_err() {
+            CompileError::InvalidOptions(reason) => assert!(reason.starts_with("sandbox:")),
+            error => panic!("{:?}", error),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Sanitizing compiled HTML with ammonia.
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Which ammonia configuration `PugOptions::sanitize` uses.
+#[derive(Clone, Default)]
+pub enum SanitizeProfile {
+    /// ammonia's defaults, which drop scripts, event handlers and unsafe
+    /// URLs, except that `class` and `id` are kept since templates rely on
+    /// them for styling.
+    #[default]
+    Default,
+    /// A builder configured by the caller.
+    Custom(Arc<ammonia::Builder<'static>>),
+}
+
+impl SanitizeProfile {
+    pub fn custom(builder: ammonia::Builder<'static>) -> Self {
+        SanitizeProfile::Custom(Arc::new(builder))
+    }
+}
+
+impl fmt::Debug for SanitizeProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanitizeProfile::Default => write!(f, "Default"),
+            SanitizeProfile::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Sanitizes `html`. ammonia works on fragments, so for a whole document
+/// only what's inside `<body>` is cleaned and the doctype, `<head>` and the
+/// tags around the body are kept as they are.
+pub(crate) fn sanitize(html: &str, profile: &SanitizeProfile) -> String {
+    let clean = |fragment: &str| match profile {
+        SanitizeProfile::Default => ammonia::Builder::default()
+            .add_generic_attributes(["class", "id"])
+            .clean(fragment)
+            .to_string(),
+        SanitizeProfile::Custom(builder) => builder.clean(fragment).to_string(),
+    };
+
+    match body(html) {
+        Some((start, end)) => format!(
+            "{}{}{}",
+            &html[..start],
+            clean(&html[start..end]),
+            &html[end..]
+        ),
+        None => clean(html),
+    }
+}
+
+/// Where the content of the `<body>` element starts and ends.
+fn body(html: &str) -> Option<(usize, usize)> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<body")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = lower
+        .rfind("</body>")
+        .filter(|end| *end >= start)
+        .unwrap_or(html.len());
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_string_with_options, PugOptions};
+
+    const PAGE: &str = "doctype html\nhtml\n  head\n    title Comments\n    script(src=\"/app.js\")\n  body\n    div.comment\n      | !{comment}";
+
+    #[test]
+    fn smuggled_script_is_removed() {
+        let options = || {
+            PugOptions::new().with_object(serde_json::json!({
+                "comment": "<b>nice</b><script>steal()</script><img src=x onerror=steal()>"
+            }))
+        };
+        let raw = evaluate_string_with_options(PAGE.into(), options()).unwrap();
+        assert!(raw.contains("<script>steal()</script>"));
+
+        let clean =
+            evaluate_string_with_options(PAGE.into(), options().sanitize(SanitizeProfile::Default))
+                .unwrap();
+        assert!(!clean.contains("steal()"), "{}", clean);
+        assert!(clean.starts_with("<!DOCTYPE html><html><head><title>Comments</title><script src=\"/app.js\"></script></head>"));
+        assert!(
+            clean.contains(r#"<div class="comment"><b>nice</b><img src="x"></div>"#),
+            "{}",
+            clean
+        );
+    }
+
+    #[test]
+    fn custom_builder_is_used() {
+        let mut builder = ammonia::Builder::empty();
+        builder.add_tags(["em"]);
+        let options = PugOptions::new()
+            .with_object(serde_json::json!({ "comment": "<em>a</em><b>b</b>" }))
+            .sanitize(SanitizeProfile::custom(builder));
+        let html = evaluate_string_with_options("p !{comment}".into(), options).unwrap();
+        assert_eq!("<em>a</em>b", html);
+    }
+}
@@ -0,0 +1,529 @@
+//! Checking locals against a JSON Schema before pug sees them.
+//!
+//! The keywords understood are the ones that describe data —
+//! `type`, `enum`, `const`, `required`, `properties`,
+//! `additionalProperties`, `items` (a schema, or draft-07's array of them
+//! with `additionalItems`), `minItems`, `maxItems`, `minLength`,
+//! `maxLength`, `pattern`, `minimum`, `maximum`, `exclusiveMinimum`,
+//! `exclusiveMaximum`, `allOf`, `anyOf`, `oneOf`, `not` — and `$ref` to
+//! `#/...` pointers into the same schema. Others, like `format`, are
+//! ignored, as the spec allows for annotations. A `$ref` that comes back
+//! to the same value without checking anything else, `{"$ref": "#"}`,
+//! makes the schema broken rather than recursing forever.
+
+use std::fmt;
+
+/// One way the locals break `PugOptions::locals_schema`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    /// A JSON pointer to the offending value, `""` for the locals themselves.
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: {}", pointer, self.message)
+    }
+}
+
+#[cfg(feature = "schema")]
+pub(crate) use validate::{check_locals, LocalsSchema};
+
+#[cfg(feature = "schema")]
+mod validate {
+    use super::ValidationError;
+    use crate::{locale, CompileError, PugOptions};
+    use serde_json::{Map, Value};
+    use std::path::PathBuf;
+
+    /// The schema `PugOptions::locals_schema` and `locals_schema_file` set.
+    #[derive(Clone, Debug)]
+    pub(crate) enum LocalsSchema {
+        Inline(Value),
+        File(PathBuf),
+    }
+
+    /// Checks the locals `options` will render with against its schema, if
+    /// it has one; no locals are checked as `{}`.
+    pub(crate) fn check_locals(options: &PugOptions) -> Result<(), CompileError> {
+        let schema = match &options.locals_schema {
+            None => return Ok(()),
+            Some(LocalsSchema::Inline(schema)) => schema.clone(),
+            Some(LocalsSchema::File(path)) => {
+                let raw = std::fs::read_to_string(path)
+                    .map_err(CompileError::io_at("read schema", path))?;
+                serde_json::from_str(&raw).map_err(|e| {
+                    CompileError::InvalidOptions(format!(
+                        "locals schema {} isn't valid JSON: {}",
+                        path.display(),
+                        e
+                    ))
+                })?
+            }
+        };
+        let locals = match &options.object {
+            Some(locals) => locale::to_json(locals)?,
+            None => Value::Object(Map::new()),
+        };
+        validate(&schema, &locals)
+    }
+
+    /// Every way `locals` breaks `schema`, as `CompileError::LocalsValidation`.
+    fn validate(schema: &Value, locals: &Value) -> Result<(), CompileError> {
+        let mut validator = Validator {
+            root: schema,
+            errors: Vec::new(),
+            refs: Vec::new(),
+        };
+        validator
+            .check(schema, locals, "")
+            .map_err(|reason| CompileError::InvalidOptions(format!("locals schema: {}", reason)))?;
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(CompileError::LocalsValidation(validator.errors))
+        }
+    }
+
+    struct Validator<'a> {
+        root: &'a Value,
+        errors: Vec<ValidationError>,
+        /// The `$ref`s being followed and the value each is checking, by
+        /// address, to catch cycles.
+        refs: Vec<(String, *const Value)>,
+    }
+
+    impl Validator<'_> {
+        /// Records what's wrong with `value`, failing only if `schema` itself
+        /// is broken.
+        fn check(&mut self, schema: &Value, value: &Value, pointer: &str) -> Result<(), String> {
+            let schema = match schema {
+                Value::Bool(true) => return Ok(()),
+                Value::Bool(false) => {
+                    self.error(pointer, "no value is allowed here".into());
+                    return Ok(());
+                }
+                Value::Object(schema) => schema,
+                _ => return Err(format!("{:?} isn't a schema", schema.to_string())),
+            };
+            if let Some(reference) = schema.get("$ref") {
+                let target = reference
+                    .as_str()
+                    .and_then(|reference| reference.strip_prefix('#'))
+                    .and_then(|pointer| self.root.pointer(pointer))
+                    .ok_or_else(|| format!("can't resolve $ref {}", reference))?;
+                let following = (reference.to_string(), value as *const Value);
+                if self.refs.contains(&following) {
+                    return Err(format!("$ref {} refers back to itself", reference));
+                }
+                self.refs.push(following);
+                let checked = self.check(target, value, pointer);
+                self.refs.pop();
+                checked?;
+            }
+
+            if let Some(types) = schema.get("type") {
+                let allowed: Vec<&str> = match types {
+                    Value::String(name) => vec![name.as_str()],
+                    Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                    _ => return Err("`type` has to be a string or an array".into()),
+                };
+                if !allowed.iter().any(|name| is_type(value, name)) {
+                    let message =
+                        format!("expected {}, got {}", allowed.join(" or "), type_of(value));
+                    self.error(pointer, message);
+                    // the other keywords would only repeat it
+                    return Ok(());
+                }
+            }
+            if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+                if !options.contains(value) {
+                    let options: Vec<String> = options.iter().map(Value::to_string).collect();
+                    self.error(pointer, format!("expected one of {}", options.join(", ")));
+                }
+            }
+            if let Some(expected) = schema.get("const") {
+                if expected != value {
+                    self.error(pointer, format!("expected {}", expected));
+                }
+            }
+
+            match value {
+                Value::Object(object) => self.object(schema, object, pointer)?,
+                Value::Array(items) => {
+                    self.items(schema, items, pointer)?;
+                    self.bounds(
+                        schema,
+                        "minItems",
+                        "maxItems",
+                        items.len(),
+                        "items",
+                        pointer,
+                    );
+                }
+                Value::String(text) => {
+                    let length = text.chars().count();
+                    self.bounds(
+                        schema,
+                        "minLength",
+                        "maxLength",
+                        length,
+                        "characters",
+                        pointer,
+                    );
+                    if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+                        let regex = regex_lite::Regex::new(pattern)
+                            .map_err(|e| format!("invalid pattern {:?}: {}", pattern, e))?;
+                        if !regex.is_match(text) {
+                            self.error(pointer, format!("doesn't match {:?}", pattern));
+                        }
+                    }
+                }
+                Value::Number(number) => {
+                    if let Some(number) = number.as_f64() {
+                        self.range(schema, number, pointer);
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+                for schema in all {
+                    self.check(schema, value, pointer)?;
+                }
+            }
+            if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+                if self.passing(any, value)? == 0 {
+                    self.error(pointer, "matches none of anyOf".into());
+                }
+            }
+            if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+                let passing = self.passing(one, value)?;
+                if passing != 1 {
+                    self.error(
+                        pointer,
+                        format!("matches {} of oneOf, not exactly one", passing),
+                    );
+                }
+            }
+            if let Some(not) = schema.get("not") {
+                if self.passing(std::slice::from_ref(not), value)? == 1 {
+                    self.error(pointer, "matches the schema in `not`".into());
+                }
+            }
+            Ok(())
+        }
+
+        fn object(
+            &mut self,
+            schema: &Map<String, Value>,
+            object: &Map<String, Value>,
+            pointer: &str,
+        ) -> Result<(), String> {
+            if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(name) {
+                        let message = format!("missing required property {:?}", name);
+                        self.error(pointer, message);
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, property) in object {
+                let property_pointer = format!("{}/{}", pointer, escape(name));
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property_schema) => {
+                        self.check(property_schema, property, &property_pointer)?
+                    }
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            let message = format!("unexpected property {:?}", name);
+                            self.error(pointer, message);
+                        }
+                        Some(additional) => self.check(additional, property, &property_pointer)?,
+                        None => {}
+                    },
+                }
+            }
+            Ok(())
+        }
+
+        fn items(
+            &mut self,
+            schema: &Map<String, Value>,
+            items: &[Value],
+            pointer: &str,
+        ) -> Result<(), String> {
+            let tuple = match schema.get("items") {
+                None => return Ok(()),
+                Some(Value::Array(tuple)) => tuple,
+                Some(items_schema) => {
+                    for (index, item) in items.iter().enumerate() {
+                        self.check(items_schema, item, &format!("{}/{}", pointer, index))?;
+                    }
+                    return Ok(());
+                }
+            };
+            for (index, item) in items.iter().enumerate() {
+                let item_pointer = format!("{}/{}", pointer, index);
+                match tuple.get(index).or_else(|| schema.get("additionalItems")) {
+                    Some(Value::Bool(false)) if index >= tuple.len() => {
+                        let message = format!(
+                            "has {} items, more than the {} in items",
+                            items.len(),
+                            tuple.len()
+                        );
+                        self.error(pointer, message);
+                        break;
+                    }
+                    Some(item_schema) => self.check(item_schema, item, &item_pointer)?,
+                    None => break,
+                }
+            }
+            Ok(())
+        }
+
+        fn bounds(
+            &mut self,
+            schema: &Map<String, Value>,
+            min: &str,
+            max: &str,
+            count: usize,
+            what: &str,
+            pointer: &str,
+        ) {
+            let limit = |keyword| schema.get(keyword).and_then(Value::as_u64);
+            if let Some(min) = limit(min).filter(|&min| (count as u64) < min) {
+                self.error(
+                    pointer,
+                    format!("has {} {}, fewer than {}", count, what, min),
+                );
+            }
+            if let Some(max) = limit(max).filter(|&max| (count as u64) > max) {
+                self.error(
+                    pointer,
+                    format!("has {} {}, more than {}", count, what, max),
+                );
+            }
+        }
+
+        fn range(&mut self, schema: &Map<String, Value>, number: f64, pointer: &str) {
+            let limit = |keyword| schema.get(keyword).and_then(Value::as_f64);
+            let mut out_of_range = |expected: &str, limit: f64| {
+                self.error(pointer, format!("{} isn't {} {}", number, expected, limit));
+            };
+            if let Some(minimum) = limit("minimum").filter(|&min| number < min) {
+                out_of_range("at least", minimum);
+            }
+            if let Some(maximum) = limit("maximum").filter(|&max| number > max) {
+                out_of_range("at most", maximum);
+            }
+            if let Some(minimum) = limit("exclusiveMinimum").filter(|&min| number <= min) {
+                out_of_range("more than", minimum);
+            }
+            if let Some(maximum) = limit("exclusiveMaximum").filter(|&max| number >= max) {
+                out_of_range("less than", maximum);
+            }
+        }
+
+        /// How many of `schemas` `value` passes, without recording anything.
+        fn passing(&mut self, schemas: &[Value], value: &Value) -> Result<usize, String> {
+            let mut passing = 0;
+            for schema in schemas {
+                let mut trial = Validator {
+                    root: self.root,
+                    errors: Vec::new(),
+                    refs: self.refs.clone(),
+                };
+                trial.check(schema, value, "")?;
+                if trial.errors.is_empty() {
+                    passing += 1;
+                }
+            }
+            Ok(passing)
+        }
+
+        fn error(&mut self, pointer: &str, message: String) {
+            self.errors.push(ValidationError {
+                pointer: pointer.to_owned(),
+                message,
+            });
+        }
+    }
+
+    fn is_type(value: &Value, name: &str) -> bool {
+        match name {
+            "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+            "number" => value.is_number(),
+            name => type_of(value) == name,
+        }
+    }
+
+    fn type_of(value: &Value) -> &'static str {
+        match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
+
+    /// `name` as a JSON pointer segment.
+    fn escape(name: &str) -> String {
+        name.replace('~', "~0").replace('/', "~1")
+    }
+}
+
+#[cfg(all(test, feature = "schema"))]
+mod tests {
+    use crate::{evaluate_with_options, CompileError, PugOptions};
+    use serde_json::json;
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "required": ["language", "tags"],
+            "properties": {
+                "language": { "type": "string", "minLength": 1 },
+                "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } }
+            },
+            "$defs": { "tag": { "type": "string", "pattern": "^[a-z]+$" } }
+        })
+    }
+
+    #[test]
+    fn renders_conforming_locals() {
+        let options = PugOptions::new()
+            .with_object(json!({ "language": "pug", "tags": ["html"] }))
+            .locals_schema(schema());
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_with_options("test/hello.pug", options).unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_every_violation_at_once() {
+        let options = PugOptions::new()
+            .with_object(json!({ "tags": ["html", 5, "Not Lower"] }))
+            .locals_schema(schema());
+        let errors = match evaluate_with_options("test/hello.pug", options).unwrap_err() {
+            CompileError::LocalsValidation(errors) => errors,
+            error => panic!("{:?}", error),
+        };
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "/: missing required property \"language\"",
+                "/tags/1: expected string, got number",
+                "/tags/2: doesn't match \"^[a-z]+$\"",
+            ],
+            errors
+        );
+    }
+
+    fn violations(schema: serde_json::Value, locals: serde_json::Value) -> Vec<String> {
+        let options = PugOptions::new().with_object(locals).locals_schema(schema);
+        match evaluate_with_options("test/hello.pug", options) {
+            Err(CompileError::LocalsValidation(errors)) => {
+                errors.iter().map(ToString::to_string).collect()
+            }
+            Err(CompileError::InvalidOptions(reason)) => vec![reason],
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn checks_tuple_items() {
+        let schema = json!({
+            "properties": {
+                "point": {
+                    "items": [{ "type": "number" }, { "type": "string" }],
+                    "additionalItems": false
+                }
+            }
+        });
+        assert_eq!(
+            vec![
+                "/point/1: expected string, got number",
+                "/point: has 3 items, more than the 2 in items",
+            ],
+            violations(schema, json!({ "language": "pug", "point": [1, 2, 3] }))
+        );
+    }
+
+    #[test]
+    fn stops_at_ref_cycles() {
+        for schema in [
+            json!({ "$ref": "#" }),
+            json!({ "anyOf": [{ "$ref": "#/$defs/a" }], "$defs": {
+                "a": { "$ref": "#/$defs/b" }, "b": { "$ref": "#/$defs/a" }
+            } }),
+        ] {
+            let reason = &violations(schema, json!({ "language": "pug" }))[0];
+            assert!(reason.contains("refers back to itself"), "{}", reason);
+        }
+
+        // recursing into parts of the value is fine
+        let tree = json!({
+            "$defs": { "node": {
+                "properties": { "children": { "items": { "$ref": "#/$defs/node" } } },
+                "required": ["language"]
+            } },
+            "$ref": "#/$defs/node"
+        });
+        let locals = json!({ "language": "pug", "children": [{ "children": [{}] }] });
+        assert_eq!(
+            vec![
+                "/children/0: missing required property \"language\"",
+                "/children/0/children/0: missing required property \"language\"",
+            ],
+            violations(tree, locals)
+        );
+    }
+
+    #[test]
+    fn validates_the_merged_locals_from_a_schema_file() {
+        // front matter supplies `language`, which the schema requires
+        let dir = tempfile::tempdir().unwrap();
+        let schema_file = dir.path().join("locals.schema.json");
+        std::fs::write(&schema_file, schema().to_string()).unwrap();
+        let page = dir.path().join("page.pug");
+        std::fs::write(
+            &page,
+            "//- ---\n//- language: pug\n//- ---\nh1 hello #{language}",
+        )
+        .unwrap();
+
+        let options = PugOptions::new()
+            .with_object(json!({ "tags": [] }))
+            .front_matter()
+            .locals_schema_file(&schema_file);
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_with_options(&page, options).unwrap()
+        );
+
+        let missing = PugOptions::new().locals_schema_file(dir.path().join("missing.json"));
+        let error = evaluate_with_options(&page, missing).unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                CompileError::IoAt {
+                    op: "read schema",
+                    ..
+                }
+            ),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Per-page locals kept next to a template: `about.pug` reads `about.json`,
+//! or `about.yaml`/`about.yml` in the simple YAML `parse_front_matter`
+//! understands.
+
+use crate::CompileError;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 3] = ["json", "yaml", "yml"];
+
+/// The sidecar `PugOptions::sidecar_locals` would merge for `template`, if
+/// it has one; `.json` wins over `.yaml` over `.yml`.
+pub(crate) fn find(template: &Path) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| template.with_extension(extension))
+        .find(|sidecar| sidecar.is_file())
+}
+
+/// The object in `sidecar`.
+pub(crate) fn read(sidecar: &Path) -> Result<Value, CompileError> {
+    let invalid = |reason: String| CompileError::Sidecar {
+        path: sidecar.to_owned(),
+        reason,
+    };
+    let text = fs::read_to_string(sidecar).map_err(|e| invalid(e.to_string()))?;
+    let locals = if sidecar.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?
+    } else {
+        crate::front_matter::parse_yaml(&text).map_err(|error| match error {
+            CompileError::FrontMatter(reason) => invalid(reason),
+            other => invalid(other.to_string()),
+        })?
+    };
+    match locals {
+        Value::Object(_) => Ok(locals),
+        _ => Err(invalid("expected an object".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn finds_and_reads_each_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = dir.path().join("page.pug");
+        assert_eq!(None, find(&page));
+
+        fs::write(dir.path().join("page.yml"), "title: Yml\ntags:\n  - a\n").unwrap();
+        assert_eq!(Some(dir.path().join("page.yml")), find(&page));
+        assert_eq!(
+            json!({ "title": "Yml", "tags": ["a"] }),
+            read(&find(&page).unwrap()).unwrap()
+        );
+        fs::write(dir.path().join("page.json"), "[1]").unwrap();
+        let json = find(&page).unwrap();
+        assert_eq!(dir.path().join("page.json"), json);
+        match read(&json) {
+            Err(CompileError::Sidecar { path, reason }) => {
+                assert_eq!((json, "expected an object"), (path, reason.as_str()))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
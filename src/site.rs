@@ -0,0 +1,370 @@
+//! Site-wide files generated from what a batch build wrote.
+
+use crate::build::BuildOutput;
+use crate::{read_front_matter, CompileError};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The most URLs one sitemap file may hold, per sitemaps.org.
+const MAX_URLS: usize = 50_000;
+
+/// How `write_sitemap` lists pages.
+#[derive(Clone, Debug)]
+pub struct SitemapOptions {
+    exclude: Vec<String>,
+    lastmod: HashMap<PathBuf, String>,
+    front_matter: bool,
+    max_urls_per_file: usize,
+}
+
+impl SitemapOptions {
+    pub fn new() -> Self {
+        SitemapOptions {
+            exclude: Vec::new(),
+            lastmod: HashMap::new(),
+            front_matter: false,
+            max_urls_per_file: MAX_URLS,
+        }
+    }
+
+    /// Leaves out pages whose path in the output directory matches
+    /// `pattern`, where `*` matches within a directory and `**` across them:
+    /// `drafts/**`, `**/*.partial.html`.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_owned());
+        self
+    }
+
+    /// Gives `page`, a path in the output directory like `about/index.html`,
+    /// the `<lastmod>` `date` instead of its template's modification time.
+    pub fn lastmod(mut self, page: impl Into<PathBuf>, date: &str) -> Self {
+        self.lastmod.insert(page.into(), date.to_owned());
+        self
+    }
+
+    /// Reads each template's front matter for a `sitemap` object with
+    /// `priority`, `changefreq` and `lastmod`, or `sitemap: false` to leave
+    /// the page out. Front matter's simple YAML doesn't nest, so write the
+    /// object as JSON: `//- sitemap: {"priority": 0.8}`.
+    pub fn front_matter(mut self) -> Self {
+        self.front_matter = true;
+        self
+    }
+
+    /// Splits the sitemap into files of at most `max` URLs under a
+    /// `sitemap.xml` index once there are more; 50,000 by default, which is
+    /// also the most the protocol allows.
+    pub fn max_urls_per_file(mut self, max: usize) -> Self {
+        self.max_urls_per_file = max.clamp(1, MAX_URLS);
+        self
+    }
+}
+
+impl Default for SitemapOptions {
+    fn default() -> Self {
+        SitemapOptions::new()
+    }
+}
+
+struct Entry {
+    url: String,
+    lastmod: Option<String>,
+    changefreq: Option<String>,
+    priority: Option<String>,
+}
+
+/// Writes `sitemap.xml` into `out`, the directory `report`'s pages were
+/// built into, listing each page at `base_url` plus its path and returning
+/// the files written.
+///
+/// `index.html` pages stand for their directory, so `about/index.html` is
+/// `<base_url>/about/`; other pages keep their file name. `<lastmod>` is
+/// the template's modification time unless `options` says otherwise. Past
+/// `SitemapOptions::max_urls_per_file` URLs the sitemap is split into
+/// `sitemap-1.xml`, `sitemap-2.xml`, ..., and `sitemap.xml` becomes their
+/// index, which comes first in the files returned.
+pub fn write_sitemap(
+    report: &BuildOutput,
+    base_url: &str,
+    out: &Path,
+    options: SitemapOptions,
+) -> Result<Vec<PathBuf>, CompileError> {
+    let base_url = base_url.trim_end_matches('/');
+    let mut entries = Vec::new();
+    for (page, template) in report.pages.iter().zip(&report.templates) {
+        let relative = page.strip_prefix(out).unwrap_or(page);
+        let path: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+        let path = path.join("/");
+        if options
+            .exclude
+            .iter()
+            .any(|pattern| matches(pattern, &path))
+        {
+            continue;
+        }
+        let sitemap = if options.front_matter {
+            read_front_matter(template)?.0["sitemap"].take()
+        } else {
+            Value::Null
+        };
+        if sitemap == Value::Bool(false) {
+            continue;
+        }
+        let text = |key: &str| match &sitemap[key] {
+            Value::String(text) => Some(text.clone()),
+            Value::Number(number) => Some(number.to_string()),
+            _ => None,
+        };
+        let lastmod = match options.lastmod.get(relative) {
+            Some(date) => Some(date.clone()),
+            None => text("lastmod").or_else(|| modified(template)),
+        };
+        entries.push(Entry {
+            url: format!("{}{}", base_url, url_path(&path)),
+            lastmod,
+            changefreq: text("changefreq"),
+            priority: text("priority"),
+        });
+    }
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+
+    let write = |name: &str, xml: String| {
+        let file = out.join(name);
+        fs::create_dir_all(out).map_err(CompileError::io_at("create directory", out))?;
+        fs::write(&file, xml).map_err(CompileError::io_at("write sitemap", &file))?;
+        Ok::<_, CompileError>(file)
+    };
+    if entries.len() <= options.max_urls_per_file {
+        return Ok(vec![write("sitemap.xml", urlset(&entries))?]);
+    }
+    let mut parts = Vec::new();
+    let mut index = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    ));
+    for (number, chunk) in entries.chunks(options.max_urls_per_file).enumerate() {
+        let name = format!("sitemap-{}.xml", number + 1);
+        parts.push(write(&name, urlset(chunk))?);
+        let _ = writeln!(
+            index,
+            "  <sitemap>\n    <loc>{}</loc>\n  </sitemap>",
+            escape(&format!("{}/{}", base_url, name))
+        );
+    }
+    index.push_str("</sitemapindex>\n");
+    parts.insert(0, write("sitemap.xml", index)?);
+    Ok(parts)
+}
+
+fn urlset(entries: &[Entry]) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    ));
+    for entry in entries {
+        let _ = writeln!(xml, "  <url>\n    <loc>{}</loc>", escape(&entry.url));
+        for (tag, value) in [
+            ("lastmod", &entry.lastmod),
+            ("changefreq", &entry.changefreq),
+            ("priority", &entry.priority),
+        ] {
+            if let Some(value) = value {
+                let _ = writeln!(xml, "    <{}>{}</{}>", tag, escape(value), tag);
+            }
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// The URL path for the page at `path`, percent-encoded.
+fn url_path(path: &str) -> String {
+    let path = match path.strip_suffix("index.html") {
+        Some(dir) if dir.is_empty() || dir.ends_with('/') => dir,
+        _ => path,
+    };
+    let mut url = String::from("/");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            _ => {
+                let _ = write!(url, "%{:02X}", byte);
+            }
+        }
+    }
+    url
+}
+
+/// Whether `path` matches the glob `pattern`.
+pub(crate) fn matches(pattern: &str, path: &str) -> bool {
+    fn segments(pattern: &[&str], path: &[&str]) -> bool {
+        match (pattern.first(), path.first()) {
+            (None, None) => true,
+            (Some(&"**"), _) => {
+                segments(&pattern[1..], path) || (!path.is_empty() && segments(pattern, &path[1..]))
+            }
+            (Some(glob), Some(name)) => segment(glob, name) && segments(&pattern[1..], &path[1..]),
+            _ => false,
+        }
+    }
+    fn segment(glob: &str, name: &str) -> bool {
+        match glob.split_once('*') {
+            None => glob == name,
+            Some((prefix, rest)) => {
+                name.starts_with(prefix)
+                    && (prefix.len()..=name.len())
+                        .any(|at| name.is_char_boundary(at) && segment(rest, &name[at..]))
+            }
+        }
+    }
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    segments(&pattern, &path)
+}
+
+/// When `template` was last modified, as a `YYYY-MM-DD` date in UTC.
+fn modified(template: &Path) -> Option<String> {
+    let modified = fs::metadata(template).and_then(|m| m.modified()).ok()?;
+    let seconds = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(date(seconds / 86_400))
+}
+
+/// The civil date `days` after 1970-01-01.
+fn date(days: u64) -> String {
+    // Howard Hinnant's days_from_civil, run backwards
+    let days = days as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build::compile_dir;
+    use crate::PugOptions;
+
+    fn site() -> (tempfile::TempDir, tempfile::TempDir, BuildOutput) {
+        let src = tempfile::tempdir().unwrap();
+        for (path, source) in [
+            ("index.pug", "h1 home"),
+            ("about/index.pug", "//- ---\n//- sitemap: {\"priority\": 0.8, \"changefreq\": \"monthly\"}\n//- ---\nh1 about"),
+            ("blog/first post.pug", "p first"),
+            ("blog/draft.pug", "//- ---\n//- sitemap: false\n//- ---\np draft"),
+            ("drafts/wip.pug", "p wip"),
+        ] {
+            let path = src.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        let out = tempfile::tempdir().unwrap();
+        let report = compile_dir(src.path(), out.path(), PugOptions::new().front_matter()).unwrap();
+        (src, out, report)
+    }
+
+    #[test]
+    fn maps_pages_to_urls() {
+        assert_eq!("/", url_path("index.html"));
+        assert_eq!("/about/", url_path("about/index.html"));
+        assert_eq!("/blog/first%20post.html", url_path("blog/first post.html"));
+        assert_eq!("/blog/reindex.html", url_path("blog/reindex.html"));
+        assert_eq!("/caf%C3%A9.html", url_path("café.html"));
+
+        assert!(matches("drafts/**", "drafts/a/b.html"));
+        assert!(matches("**/*.partial.html", "blog/card.partial.html"));
+        assert!(matches("**/*.partial.html", "card.partial.html"));
+        assert!(!matches("*.html", "blog/post.html"));
+
+        assert_eq!("1970-01-01", date(0));
+        assert_eq!("2024-02-29", date(19_782));
+    }
+
+    #[test]
+    fn writes_a_sitemap_for_a_build() {
+        let (src, out, report) = site();
+        let options = SitemapOptions::new()
+            .exclude("drafts/**")
+            .front_matter()
+            .lastmod("index.html", "2024-01-31");
+
+        let files = write_sitemap(&report, "https://example.com/", out.path(), options).unwrap();
+        assert_eq!(vec![out.path().join("sitemap.xml")], files);
+        let xml = fs::read_to_string(&files[0]).unwrap();
+        let today = modified(&src.path().join("index.pug")).unwrap();
+        assert_eq!(
+            format!(
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                    "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+                    "  <url>\n    <loc>https://example.com/</loc>\n",
+                    "    <lastmod>2024-01-31</lastmod>\n  </url>\n",
+                    "  <url>\n    <loc>https://example.com/about/</loc>\n",
+                    "    <lastmod>{today}</lastmod>\n",
+                    "    <changefreq>monthly</changefreq>\n",
+                    "    <priority>0.8</priority>\n  </url>\n",
+                    "  <url>\n    <loc>https://example.com/blog/first%20post.html</loc>\n",
+                    "    <lastmod>{today}</lastmod>\n  </url>\n",
+                    "</urlset>\n",
+                ),
+                today = today
+            ),
+            xml
+        );
+    }
+
+    #[test]
+    fn splits_large_sitemaps_under_an_index() {
+        let (_src, out, report) = site();
+        let options = SitemapOptions::new().max_urls_per_file(2);
+
+        let files = write_sitemap(&report, "https://example.com", out.path(), options).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            vec![
+                "sitemap.xml",
+                "sitemap-1.xml",
+                "sitemap-2.xml",
+                "sitemap-3.xml"
+            ],
+            names
+        );
+        let index = fs::read_to_string(&files[0]).unwrap();
+        assert!(index.contains("<sitemapindex "), "{}", index);
+        assert_eq!(3, index.matches("<sitemap>").count());
+        assert!(index.contains("<loc>https://example.com/sitemap-3.xml</loc>"));
+        let urls: usize = files[1..]
+            .iter()
+            .map(|file| fs::read_to_string(file).unwrap().matches("<url>").count())
+            .sum();
+        assert_eq!(5, urls);
+    }
+}
@@ -0,0 +1,214 @@
+//! Where pug's stderr goes while it runs, and the log it can be kept in.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What `PugOptions::stderr_mode` does with pug's stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StderrMode {
+    /// Read into `CompileError::PugError` and `CompileOutput::warnings`.
+    #[default]
+    Capture,
+    /// Passed straight through to this process's stderr. Nothing is read,
+    /// so a failed compile is only `pug exited with ...` and there are no
+    /// warnings.
+    Inherit,
+    /// Copied to this process's stderr as pug writes it, and read as with
+    /// `Capture`.
+    Tee,
+}
+
+impl StderrMode {
+    pub(crate) fn stdio(self) -> Stdio {
+        match self {
+            StderrMode::Inherit => Stdio::inherit(),
+            StderrMode::Capture | StderrMode::Tee => Stdio::piped(),
+        }
+    }
+}
+
+/// Reads `pipe` to the end, copying what arrives to this process's stderr
+/// as it does.
+pub(crate) fn tee(mut pipe: impl Read) -> Vec<u8> {
+    let mut captured = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        match pipe.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => {
+                forward(&chunk[..read]);
+                captured.extend_from_slice(&chunk[..read]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    captured
+}
+
+/// Copies `chunk` of pug's stderr to this process's.
+pub(crate) fn forward(chunk: &[u8]) {
+    let _ = io::stderr().write_all(chunk);
+}
+
+/// Serializes appends to `PugOptions::stderr_to` logs, so blocks from parallel
+/// compiles don't interleave.
+static LOG: Mutex<()> = Mutex::new(());
+
+/// Appends a block for one compile of `template` to the log at `path`: a
+/// header with the time and exit status, then everything pug wrote to stderr.
+pub(crate) fn append_to_log(
+    path: &Path,
+    template: &str,
+    status: ExitStatus,
+    stderr: &[u8],
+) -> io::Result<()> {
+    let mut block = format!(
+        "=== {} {} ({}) ===\n",
+        timestamp(SystemTime::now()),
+        template,
+        status
+    )
+    .into_bytes();
+    block.extend_from_slice(stderr);
+    if !block.ends_with(b"\n") {
+        block.push(b'\n');
+    }
+    let _appending = LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&block)
+}
+
+/// `time` as UTC in RFC 3339, to the second.
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, of_day) = (seconds / 86400, seconds % 86400);
+    // days since 1970-01-01 to a civil date, after Howard Hinnant
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let of_era = z.rem_euclid(146_097);
+    let year_of_era = (of_era - of_era / 1460 + of_era / 36_524 - of_era / 146_096) / 365;
+    let day_of_year = of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate_with_options, evaluate_with_warnings, CompileError, PugOptions};
+
+    #[test]
+    fn formats_utc_timestamps() {
+        let at = |seconds| timestamp(UNIX_EPOCH + std::time::Duration::from_secs(seconds));
+        assert_eq!("1970-01-01T00:00:00Z", at(0));
+        assert_eq!("2000-02-29T23:59:59Z", at(951_868_799));
+        assert_eq!("2026-10-14T08:05:03Z", at(1_791_965_103));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn logs_each_compile_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("logs/pug.log");
+        let pug = |name: &str, script: &str| {
+            let script = format!("cat > /dev/null\n{}\n", script);
+            let binary = crate::test_support::fake_binary(dir.path(), name, &script);
+            PugOptions::new().binary(binary).stderr_to(&log)
+        };
+        let warning = pug(
+            "warning-pug",
+            "echo 'deprecated: first' >&2\nprintf '<p>ok</p>'",
+        );
+        let failing = pug(
+            "failing-pug",
+            "printf 'Error: Pug:1:1\\n  > 1| p\\n\\nsecond broke\\n' >&2\nexit 3",
+        );
+
+        let output = evaluate_with_warnings("test/hello.pug", warning).unwrap();
+        assert_eq!(vec!["deprecated: first"], output.warnings);
+        let error = evaluate_with_options("test/hello.pug", failing).unwrap_err();
+        assert!(matches!(error, CompileError::PugError(_)), "{:?}", error);
+        assert_eq!("second broke", error.details().unwrap().message);
+
+        let log = fs::read_to_string(log).unwrap();
+        let headers: Vec<&str> = log
+            .lines()
+            .filter(|line| line.starts_with("=== "))
+            .collect();
+        assert_eq!(2, headers.len(), "{}", log);
+        assert!(
+            headers[0].ends_with("test/hello.pug (exit status: 0) ==="),
+            "{}",
+            log
+        );
+        assert!(headers[1].ends_with("(exit status: 3) ==="), "{}", log);
+        let first = log.find("deprecated: first").unwrap();
+        let second = log.find("second broke").unwrap();
+        assert!(
+            log.find(headers[1]).unwrap() > first && second > first,
+            "{}",
+            log
+        );
+    }
+
+    #[test]
+    fn parallel_compiles_write_whole_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("pug.log");
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let log = &log;
+                scope.spawn(move || {
+                    let stderr = (0..50)
+                        .map(|line| format!("{} {}\n", n, line))
+                        .collect::<String>();
+                    let pug =
+                        crate::executor::MockExecutor::succeeding("<p></p>").with_stderr(stderr);
+                    let options = PugOptions::new().executor(pug).stderr_to(log);
+                    crate::evaluate_string_with_options("p".into(), options).unwrap();
+                });
+            }
+        });
+
+        let log = fs::read_to_string(log).unwrap();
+        let blocks: Vec<&str> = log.split("=== ").skip(1).collect();
+        assert_eq!(8, blocks.len());
+        for block in blocks {
+            let lines: Vec<&str> = block.lines().skip(1).collect();
+            let n = lines[0].split(' ').next().unwrap();
+            let expected: Vec<String> = (0..50).map(|line| format!("{} {}", n, line)).collect();
+            assert_eq!(expected, lines);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Checking that a template compiles without rendering it, for editors that
+//! reject broken templates as they're saved.
+
+use crate::invocation::{failure, Invocation};
+use crate::{run_file, CompileError, PugOptions};
+use std::path::PathBuf;
+
+/// Checks that the template at `file` compiles, without rendering it. pug
+/// compiles it with `--client`, which needs no locals, and the JavaScript is
+/// thrown away; one pug process and no post-processing, so it's cheap enough
+/// to run on every save.
+///
+/// A syntax error comes back as rendering would have failed with it, so
+/// `CompileError::details` has its line and column. Only syntax is
+/// checked: a template reading a local nobody defined passes, as that fails
+/// only when it's rendered. The locals and `out_dir` in `options` aren't
+/// used, nor is `locals_schema`.
+pub fn check_syntax(file: impl Into<PathBuf>, options: PugOptions) -> Result<(), CompileError> {
+    check(Invocation::file(file, for_checking(options))?)
+}
+
+/// `check_syntax` for a template held in a string.
+pub fn check_syntax_string(source: String, options: PugOptions) -> Result<(), CompileError> {
+    check(Invocation::string(source, for_checking(options))?)
+}
+
+fn for_checking(mut options: PugOptions) -> PugOptions {
+    options.object = None;
+    options.out_dir = None;
+    #[cfg(feature = "schema")]
+    {
+        options.locals_schema = None;
+    }
+    options.client()
+}
+
+fn check(invocation: Invocation) -> Result<(), CompileError> {
+    let post_process = invocation.post_process.clone();
+    // run_file also sends a string template's source, and keeps stderr
+    let output = run_file(invocation)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(failure(output.status, &stderr, &post_process))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_valid_templates() {
+        check_syntax("test/greeting.pug", PugOptions::new()).unwrap();
+        // undefined locals only matter when rendering
+        let source = "div\n  p= user.name\n  p #{missing.deeply.nested}".to_owned();
+        check_syntax_string(source, PugOptions::new()).unwrap();
+    }
+
+    #[test]
+    fn reports_where_syntax_errors_are() {
+        let source = "div\n    p\n  span".to_owned();
+        let error = check_syntax_string(source, PugOptions::new()).unwrap_err();
+        let details = error.details().unwrap();
+        assert_eq!((3, Some(3)), (details.line, details.column));
+        assert!(
+            details.message.starts_with("Inconsistent indentation"),
+            "{}",
+            details.message
+        );
+
+        let error = check_syntax("test/missing.pug", PugOptions::new()).unwrap_err();
+        assert!(
+            matches!(&error, CompileError::IoAt { path, .. } if path.ends_with("missing.pug")),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Temp files only their owner can read, for handing pug data that shouldn't
+//! be world-readable.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file in the temp directory, overwritten and removed when dropped.
+pub(crate) struct TempFile {
+    path: PathBuf,
+    len: usize,
+}
+
+impl TempFile {
+    /// Creates a new file ending in `suffix` holding `contents`, readable
+    /// and writable by the current user only (`0600` on Unix).
+    pub(crate) fn private(suffix: &str, contents: &[u8]) -> io::Result<Self> {
+        Self::private_in(&std::env::temp_dir(), suffix, contents)
+    }
+
+    /// Like `private`, in `dir`. The name is random and a file already there
+    /// is never opened, so it can't collide with one.
+    pub(crate) fn private_in(dir: &Path, suffix: &str, contents: &[u8]) -> io::Result<Self> {
+        let mut attempts = 0;
+        let (path, mut file) = loop {
+            let name = format!(
+                "pug_cli-{}-{:016x}{}",
+                std::process::id(),
+                RandomState::new().build_hasher().finish(),
+                suffix
+            );
+            let path = dir.join(name);
+            match create_private(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 8 => attempts += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        // from here on, dropping it cleans up whatever was written
+        let temp_file = TempFile {
+            path,
+            len: contents.len(),
+        };
+        file.write_all(contents)?;
+        Ok(temp_file)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    /// Overwrites the contents with zeros before removing the file. That's
+    /// best effort: a journaling or copy-on-write filesystem, or an SSD, may
+    /// keep the old blocks around.
+    fn drop(&mut self) {
+        if let Ok(mut file) = OpenOptions::new().write(true).open(&self.path) {
+            let _ = file
+                .write_all(&vec![0; self.len])
+                .and_then(|_| file.sync_data());
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+/// New files in the user's temp directory already inherit an ACL limiting
+/// them to that user.
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
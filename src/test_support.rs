@@ -0,0 +1,68 @@
+//! Helpers shared by the unit tests.
+
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Writes an executable shell script standing in for pug.
+#[cfg(unix)]
+pub(crate) fn fake_binary(dir: &Path, name: &str, script: &str) -> PathBuf {
+    let binary = dir.join(name);
+    fs::write(&binary, format!("#!/bin/sh\n{}", script)).unwrap();
+    fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+    binary
+}
+
+/// Whether a process with this pid is still running. Zombies count as exited.
+#[cfg(unix)]
+pub(crate) fn process_exists(pid: &str) -> bool {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "stat=", "-p", pid.trim()])
+        .output()
+        .unwrap();
+    let state = String::from_utf8_lossy(&output.stdout);
+    !state.trim().is_empty() && !state.trim().starts_with('Z')
+}
+
+/// A fake pug that appends start/end timestamps to `log` and echoes the
+/// `--path` it was given after a short delay.
+#[cfg(all(unix, feature = "tokio"))]
+pub(crate) fn counting_binary(dir: &Path, log: &Path) -> PathBuf {
+    let script = format!(
+        r#"while [ $# -gt 0 ]; do
+  if [ "$1" = "--path" ]; then file="$2"; fi
+  shift
+done
+echo "start $(date +%s%N)" >> "{log}"
+cat > /dev/null
+sleep 0.1
+echo "end $(date +%s%N)" >> "{log}"
+printf '%s' "$file"
+"#,
+        log = log.display()
+    );
+    fake_binary(dir, "counting-pug", &script)
+}
+
+/// The largest number of invocations recorded by `counting_binary` that were
+/// running at the same time.
+#[cfg(all(unix, feature = "tokio"))]
+pub(crate) fn max_overlap(log: &str) -> usize {
+    let mut events: Vec<(u128, i32)> = log
+        .lines()
+        .map(|line| {
+            let (kind, time) = line.split_at(line.find(' ').unwrap());
+            let delta = if kind == "start" { 1 } else { -1 };
+            (time.trim().parse().unwrap(), delta)
+        })
+        .collect();
+    // ends sort before starts at the same instant
+    events.sort();
+    let (mut running, mut max) = (0, 0);
+    for (_, delta) in events {
+        running += delta;
+        max = max.max(running);
+    }
+    max as usize
+}
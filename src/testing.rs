@@ -0,0 +1,312 @@
+//! Assertions for tests of rendered HTML, and `MockExecutor` for compiling
+//! without pug installed.
+//!
+//! Enable the `testing` feature as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! pug_cli = { version = "*", features = ["testing"] }
+//! ```
+
+pub use crate::executor::{MockExecutor, RecordedRun};
+use crate::{evaluate_with_options, PugOptions};
+use scraper::node::Node;
+use scraper::{ElementRef, Html};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Asserts that two pieces of HTML parse to the same DOM.
+///
+/// Whitespace between and around text is insignificant except inside `pre`
+/// and `textarea`, attributes may come in any order, and `<br/>` equals
+/// `<br>`. On a mismatch the panic lists each difference with the path of the
+/// element it was found in.
+#[macro_export]
+macro_rules! assert_html_eq {
+    ($expected:expr, $actual:expr $(,)?) => {
+        if let Some(diff) = $crate::testing::html_diff(&$expected, &$actual) {
+            panic!("HTML differs:\n{}", diff);
+        }
+    };
+}
+
+/// The differences between `expected` and `actual` as `assert_html_eq!`
+/// reports them, one per line, or `None` if they are equivalent.
+pub fn html_diff(expected: &str, actual: &str) -> Option<String> {
+    let expected = Html::parse_document(expected);
+    let actual = Html::parse_document(actual);
+    let expected = normalize(expected.root_element(), false);
+    let actual = normalize(actual.root_element(), false);
+
+    let mut differences = Vec::new();
+    compare(&expected, &actual, "html", &mut differences);
+    if differences.is_empty() {
+        None
+    } else {
+        Some(differences.join("\n"))
+    }
+}
+
+/// Compiles `template` and asserts the result matches the golden file at
+/// `snapshot_path`, panicking with a unified diff if it doesn't.
+///
+/// Relative paths are resolved against `CARGO_MANIFEST_DIR`, so the test
+/// passes whichever directory `cargo test` runs from. With
+/// `UPDATE_SNAPSHOTS=1` in the environment the snapshot is written (and its
+/// directory created) instead of compared.
+pub fn assert_matches_snapshot(
+    template: impl AsRef<Path>,
+    options: PugOptions,
+    snapshot_path: impl AsRef<Path>,
+) {
+    let template = in_manifest_dir(template.as_ref());
+    let snapshot_path = in_manifest_dir(snapshot_path.as_ref());
+    let actual = match evaluate_with_options(&template, options) {
+        Ok(html) => html,
+        Err(error) => panic!("{} failed to compile: {}", template.display(), error),
+    };
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some_and(|update| update == "1") {
+        if let Some(dir) = snapshot_path.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(&snapshot_path, actual).unwrap();
+        return;
+    }
+
+    let expected = match fs::read_to_string(&snapshot_path) {
+        Ok(expected) => expected,
+        Err(error) => panic!(
+            "couldn't read snapshot {}: {}; rerun with UPDATE_SNAPSHOTS=1 to create it",
+            snapshot_path.display(),
+            error
+        ),
+    };
+    if expected != actual {
+        let diff = TextDiff::from_lines(&expected, &actual)
+            .unified_diff()
+            .header(&snapshot_path.to_string_lossy(), "rendered")
+            .to_string();
+        panic!(
+            "{} doesn't match its snapshot; rerun with UPDATE_SNAPSHOTS=1 if the change is intended\n{}",
+            template.display(),
+            diff
+        );
+    }
+}
+
+fn in_manifest_dir(path: &Path) -> PathBuf {
+    match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(root) if path.is_relative() => Path::new(&root).join(path),
+        _ => path.to_owned(),
+    }
+}
+
+#[derive(PartialEq)]
+enum Normalized {
+    Element {
+        name: String,
+        attrs: BTreeMap<String, String>,
+        children: Vec<Normalized>,
+    },
+    Text(String),
+    Comment(String),
+}
+
+impl Normalized {
+    fn summary(&self) -> String {
+        match self {
+            Normalized::Element { name, attrs, .. } => {
+                let mut tag = format!("<{}", name);
+                for (attr, value) in attrs {
+                    let _ = write!(tag, " {}={:?}", attr, value);
+                }
+                tag.push('>');
+                tag
+            }
+            Normalized::Text(text) => format!("text {:?}", text),
+            Normalized::Comment(comment) => format!("comment {:?}", comment),
+        }
+    }
+}
+
+fn normalize(element: ElementRef<'_>, preserve: bool) -> Normalized {
+    let name = element.value().name().to_owned();
+    let preserve = preserve || name == "pre" || name == "textarea";
+    let attrs = element
+        .value()
+        .attrs()
+        .map(|(attr, value)| (attr.to_owned(), value.to_owned()))
+        .collect();
+
+    let mut children = Vec::new();
+    for child in element.children() {
+        match child.value() {
+            Node::Element(_) => {
+                children.push(normalize(ElementRef::wrap(child).unwrap(), preserve))
+            }
+            Node::Text(text) if preserve => children.push(Normalized::Text(text.to_string())),
+            Node::Text(text) => {
+                let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !collapsed.is_empty() {
+                    children.push(Normalized::Text(collapsed));
+                }
+            }
+            Node::Comment(comment) => children.push(Normalized::Comment(comment.trim().to_owned())),
+            _ => {}
+        }
+    }
+    Normalized::Element {
+        name,
+        attrs,
+        children,
+    }
+}
+
+/// Compares two nodes found at `path`, which names `expected` itself.
+fn compare(expected: &Normalized, actual: &Normalized, path: &str, differences: &mut Vec<String>) {
+    use Normalized::Element;
+
+    let (children, actual_children) = match (expected, actual) {
+        (
+            Element {
+                name,
+                attrs,
+                children,
+            },
+            Element {
+                name: actual_name,
+                attrs: actual_attrs,
+                children: actual_children,
+            },
+        ) if name == actual_name => {
+            if attrs != actual_attrs {
+                differences.push(format!(
+                    "{}: expected {}, found {}",
+                    path,
+                    expected.summary(),
+                    actual.summary()
+                ));
+            }
+            (children, actual_children)
+        }
+        _ => {
+            if expected != actual {
+                differences.push(format!(
+                    "{}: expected {}, found {}",
+                    path,
+                    expected.summary(),
+                    actual.summary()
+                ));
+            }
+            return;
+        }
+    };
+
+    for (i, (expected, actual)) in children.iter().zip(actual_children).enumerate() {
+        compare(
+            expected,
+            actual,
+            &child_path(path, children, i),
+            differences,
+        );
+    }
+    let common = children.len().min(actual_children.len());
+    for node in &children[common..] {
+        differences.push(format!("{}: missing {}", path, node.summary()));
+    }
+    for node in &actual_children[common..] {
+        differences.push(format!("{}: unexpected {}", path, node.summary()));
+    }
+}
+
+/// `li` for an only child of its name, `li[2]` for the second of several, and
+/// `#3` for the fourth child when it isn't an element.
+fn child_path(parent: &str, siblings: &[Normalized], i: usize) -> String {
+    let name = match &siblings[i] {
+        Normalized::Element { name, .. } => name,
+        _ => return format!("{} > #{}", parent, i),
+    };
+    let same_name =
+        |node: &Normalized| matches!(node, Normalized::Element { name: n, .. } if n == name);
+    let label = if siblings.iter().filter(|node| same_name(node)).count() > 1 {
+        let nth = siblings[..i].iter().filter(|node| same_name(node)).count() + 1;
+        format!("{}[{}]", name, nth)
+    } else {
+        name.clone()
+    };
+    if parent.is_empty() {
+        label
+    } else {
+        format!("{} > {}", parent, label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{evaluate, evaluate_with_options, PugOptions};
+
+    #[test]
+    fn formatting_differences_are_equal() {
+        let compact = evaluate("test/newline.pug").unwrap();
+        let pretty = evaluate_with_options("test/newline.pug", PugOptions::new().pretty()).unwrap();
+        assert_html_eq!(compact, pretty);
+        assert_html_eq!(
+            r#"<div class="a" id="b"><p>one  two</p><br/></div>"#,
+            "<div id=\"b\" class=\"a\">\n  <p>one\n two</p>\n  <br>\n</div>"
+        );
+    }
+
+    #[test]
+    fn fixtures_match_their_snapshots() {
+        assert_matches_snapshot(
+            "test/hello.pug",
+            PugOptions::new(),
+            "test/snapshots/hello.expected.html",
+        );
+        assert_matches_snapshot(
+            "test/dom.pug",
+            PugOptions::new().pretty(),
+            "test/snapshots/dom.expected.html",
+        );
+    }
+
+    #[test]
+    fn snapshot_mismatch_shows_a_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("hello.expected.html");
+        fs::write(&snapshot, "<h1>goodbye pug</h1>").unwrap();
+
+        let panic = std::panic::catch_unwind(|| {
+            assert_matches_snapshot("test/hello.pug", PugOptions::new(), &snapshot)
+        })
+        .unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(message.contains("-<h1>goodbye pug</h1>"), "{}", message);
+        assert!(message.contains("+<h1>hello pug</h1>"), "{}", message);
+    }
+
+    #[test]
+    fn differences_are_located() {
+        assert!(html_diff("<pre>a  b</pre>", "<pre>a b</pre>").is_some());
+
+        let diff = html_diff(
+            r#"<ul><li class="x">one</li><li>two</li></ul>"#,
+            r#"<ul><li class="y">one</li><li>three</li><li>four</li></ul>"#,
+        )
+        .unwrap();
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(
+            vec![
+                r#"html > body > ul > li[1]: expected <li class="x">, found <li class="y">"#,
+                r#"html > body > ul > li[2] > #0: expected text "two", found text "three""#,
+                r#"html > body > ul: unexpected <li>"#,
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,660 @@
+//! Async versions of the evaluate functions built on `tokio::process`.
+//!
+//! These take the same `PugOptions` and return the same `CompileError` as the
+//! blocking API, but await the pug child instead of parking a thread on it.
+//!
+//! # Cancellation
+//!
+//! Every future here may be dropped at any await point. The pug child is
+//! spawned with `kill_on_drop`, and stdin is written from within the same
+//! future rather than a detached task, so dropping the future kills the child
+//! and closes its stdin together; nothing is left writing to a dead process.
+
+use super::instrument::Compile;
+use super::invocation::{failure, interpret_output, tolerate_closed_stdin, Input, Invocation};
+use super::limits::{check_read, pipe_error};
+use super::retry::next_delay;
+use super::stderr_mode::{self, StderrMode};
+use super::{CompileError, PugOptions};
+use ::tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use ::tokio::process::{Child, ChildStdin, Command};
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Output, Stdio};
+use std::time::Duration;
+
+mod batch;
+pub mod blocking;
+mod render;
+mod renderer;
+mod watch;
+#[cfg(feature = "remote")]
+pub use super::remote::evaluate_url_async;
+pub use batch::{compile_many_async, compile_many_async_cancellable};
+pub use render::{Render, RenderFuture, SpawnRenderer};
+pub use renderer::AsyncRenderer;
+pub use watch::{
+    watch_stream, watch_stream_with, Backpressure, WatchEvent, WatchOptions, WatchStream,
+};
+
+/// Reads `pipe` to the end, copying it to this process's stderr as it comes
+/// when `tee` is set, and failing as soon as more than `limit` bytes have
+/// come through.
+async fn read_all(
+    pipe: Option<impl AsyncRead + Unpin>,
+    tee: bool,
+    limit: Option<u64>,
+) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let pipe = match pipe {
+        Some(pipe) => pipe,
+        None => return Ok(buffer),
+    };
+    let mut pipe = pipe.take(limit.map_or(u64::MAX, |limit| limit + 1));
+    let mut chunk = [0; 8192];
+    loop {
+        let read = pipe.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(buffer);
+        }
+        if tee {
+            stderr_mode::forward(&chunk[..read]);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        check_read(&buffer, limit)?;
+    }
+}
+
+async fn write_input(stdin: Option<ChildStdin>, input: Option<Vec<u8>>) -> io::Result<()> {
+    if let (Some(mut stdin), Some(input)) = (stdin, input) {
+        tolerate_closed_stdin(stdin.write_all(&input).await)?;
+        // dropping stdin closes the pipe so pug knows the template is complete
+    }
+    Ok(())
+}
+
+/// Spawns the invocation with stdout and stderr piped, returning the child
+/// and whatever still has to be written to its stdin.
+async fn spawn(invocation: Invocation) -> Result<(Child, Option<Vec<u8>>), CompileError> {
+    let mut command = Command::from(invocation.command());
+    let retry = invocation.retry;
+    let program = invocation.program;
+    command.args(invocation.args).kill_on_drop(true);
+
+    let input = match invocation.input {
+        Input::File(path) => {
+            let file = ::tokio::fs::File::open(&path)
+                .await
+                .map_err(CompileError::io_at("open template", &path))?;
+            command.stdin(file.into_std().await);
+            None
+        }
+        Input::Bytes(bytes) => {
+            command.stdin(Stdio::piped());
+            Some(bytes)
+        }
+    };
+
+    command
+        .stdout(Stdio::piped())
+        .stderr(invocation.stderr_mode.stdio());
+    let mut attempt = 1;
+    let child = loop {
+        match command.spawn() {
+            Ok(child) => break child,
+            Err(error) => {
+                let delay = next_delay(retry.as_ref(), attempt, error)
+                    .map_err(CompileError::io_at("run pug", &program))?;
+                ::tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    };
+    Ok((child, input))
+}
+
+/// Drives `work` (feeding and draining the child's pipes) and waits for the
+/// child to exit.
+///
+/// If `work` fails, read more than `max_output_bytes` or the child outlives
+/// `timeout`, the child is killed and reaped before the error is returned.
+async fn finish<T>(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    work: impl Future<Output = io::Result<T>>,
+) -> Result<(T, ExitStatus), CompileError> {
+    let complete = async {
+        let value = work.await?;
+        let status = child.wait().await?;
+        Ok::<_, io::Error>((value, status))
+    };
+    let finished = match timeout {
+        None => Some(complete.await),
+        Some(timeout) => ::tokio::select! {
+            finished = complete => Some(finished),
+            _ = ::tokio::time::sleep(timeout) => None,
+        },
+    };
+
+    let error = match finished {
+        Some(Ok(finished)) => return Ok(finished),
+        Some(Err(e)) => pipe_error(e),
+        None => CompileError::Timeout(timeout.unwrap_or_default()),
+    };
+    let _ = child.start_kill();
+    let _ = child.wait().await;
+    Err(error)
+}
+
+/// Spawns the invocation and collects its output.
+///
+/// Stdin is written while stdout and stderr are being drained, so neither a
+/// large template nor a large result can fill a pipe and stall the child.
+///
+/// If the invocation has a timeout and the child outlives it, the child is
+/// killed and reaped before `CompileError::Timeout` is returned. The child is
+/// also spawned with `kill_on_drop`, so dropping the future mid-compile (for
+/// instance under `tokio::time::timeout`) doesn't leave pug running.
+async fn run(invocation: Invocation) -> Result<Output, CompileError> {
+    let compile = Compile::start(&invocation);
+    let output = compile.instrument(collect(invocation)).await;
+    compile.observe(output)
+}
+
+async fn collect(mut invocation: Invocation) -> Result<Output, CompileError> {
+    if let Some(output) = invocation.run_executor() {
+        return output;
+    }
+    let timeout = invocation.timeout;
+    let tee = invocation.stderr_mode == StderrMode::Tee;
+    let limit = invocation.post_process.limits.output;
+    // removed once pug has exited, with the rest of this future
+    let _locals_file = invocation.locals_file.take();
+    let (mut child, input) = spawn(invocation).await?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    let work = async {
+        ::tokio::try_join!(
+            write_input(stdin, input),
+            read_all(stdout, false, limit),
+            read_all(stderr, tee, limit)
+        )
+    };
+    let ((_, stdout, stderr), status) = finish(&mut child, timeout, work).await?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Copies the child's stdout into `writer` as it is produced.
+async fn stream<W>(invocation: Invocation, writer: &mut W) -> Result<u64, CompileError>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let post_process = invocation.post_process.clone();
+    if post_process.is_needed() || invocation.has_executor() {
+        // post-processing and max_output_bytes need the whole document, and
+        // an executor returns it
+        let html = interpret_output(run(invocation).await?, post_process)?;
+        writer
+            .write_all(html.as_bytes())
+            .await
+            .map_err(CompileError::Io)?;
+        writer.flush().await.map_err(CompileError::Io)?;
+        return Ok(html.len() as u64);
+    }
+
+    let compile = Compile::start(&invocation);
+    let (copied, status, stderr) = match compile.instrument(copy_output(invocation, writer)).await {
+        Ok(streamed) => streamed,
+        Err(error) => {
+            compile.failed(&error);
+            return Err(error);
+        }
+    };
+    compile.finished(copied as usize, status, &stderr);
+
+    if status.success() {
+        Ok(copied)
+    } else {
+        Err(failure(
+            status,
+            &String::from_utf8_lossy(&stderr),
+            &post_process,
+        ))
+    }
+}
+
+/// Returns the byte count, exit status and stderr of a streamed compile.
+async fn copy_output<W>(
+    mut invocation: Invocation,
+    writer: &mut W,
+) -> Result<(u64, ExitStatus, Vec<u8>), CompileError>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let timeout = invocation.timeout;
+    let tee = invocation.stderr_mode == StderrMode::Tee;
+    // removed once pug has exited, with the rest of this future
+    let _locals_file = invocation.locals_file.take();
+    let (mut child, input) = spawn(invocation).await?;
+    let stdin = child.stdin.take();
+    let mut stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take();
+
+    // with max_output_bytes set, `stream` collects the output instead
+    let work = async {
+        ::tokio::try_join!(
+            write_input(stdin, input),
+            ::tokio::io::copy(&mut stdout, writer),
+            read_all(stderr, tee, None)
+        )
+    };
+    let ((_, copied, stderr), status) = finish(&mut child, timeout, work).await?;
+    writer.flush().await.map_err(CompileError::Io)?;
+    Ok((copied, status, stderr))
+}
+
+pub async fn evaluate_with_options_async(
+    file: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let invocation = Invocation::file(file, options)?;
+    let post_process = invocation.post_process.clone();
+    run(invocation)
+        .await
+        .and_then(|output| interpret_output(output, post_process))
+}
+
+pub async fn evaluate_string_with_options_async(
+    s: String,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    let invocation = Invocation::string(s, options)?;
+    let post_process = invocation.post_process.clone();
+    run(invocation)
+        .await
+        .and_then(|output| interpret_output(output, post_process))
+}
+
+/// Streams the compiled HTML into `writer` instead of collecting it,
+/// returning the number of bytes written.
+///
+/// Output reaches `writer` before pug has finished, so a failed compile may
+/// leave partial output behind; the result is only `Ok` if pug exited
+/// successfully. If `writer` fails (say, the client hung up) pug is killed.
+/// With `minify` or `newline` the output is collected and processed before
+/// it's written, and with `max_output_bytes` (or `sandbox`) it's collected
+/// so none of a page over the limit is written.
+pub async fn evaluate_to_async_writer<W>(
+    file: impl Into<PathBuf>,
+    options: PugOptions,
+    writer: &mut W,
+) -> Result<u64, CompileError>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    stream(Invocation::file(file, options)?, writer).await
+}
+
+/// Like `evaluate_to_async_writer`, for a template held in a string.
+pub async fn evaluate_string_to_async_writer<W>(
+    s: String,
+    options: PugOptions,
+    writer: &mut W,
+) -> Result<u64, CompileError>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    stream(Invocation::string(s, options)?, writer).await
+}
+
+pub async fn evaluate_string_async(s: String) -> Result<String, CompileError> {
+    let options = PugOptions::new();
+    evaluate_string_with_options_async(s, options).await
+}
+
+pub async fn evaluate_async(file: impl Into<PathBuf>) -> Result<String, CompileError> {
+    let options = PugOptions::new();
+    evaluate_with_options_async(file, options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::{fake_binary, process_exists};
+
+    #[cfg(unix)]
+    async fn wait_for_exit(pid: &str, within: Duration) -> bool {
+        let deadline = std::time::Instant::now() + within;
+        while process_exists(pid) {
+            if std::time::Instant::now() > deadline {
+                return false;
+            }
+            ::tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        true
+    }
+
+    #[::tokio::test]
+    async fn evaluate_file() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_async("test/hello.pug").await.unwrap()
+        );
+    }
+
+    #[::tokio::test]
+    async fn executors_stand_in_for_pug() {
+        let pug = crate::executor::MockExecutor::succeeding("<p>mocked</p>");
+        let options = PugOptions::new().executor(pug.clone());
+        let html = evaluate_string_with_options_async("p".into(), options.clone()).await;
+        assert_eq!("<p>mocked</p>", html.unwrap());
+
+        let mut streamed = Vec::new();
+        evaluate_to_async_writer("test/hello.pug", options, &mut streamed)
+            .await
+            .unwrap();
+        assert_eq!(b"<p>mocked</p>", streamed.as_slice());
+        assert_eq!(2, pug.runs().len());
+    }
+
+    #[::tokio::test]
+    async fn locals_files_outlive_the_spawn() {
+        let options = PugOptions::new()
+            .with_object(serde_json::json!({ "name": "pug" }))
+            .locals_via_file();
+        let html = evaluate_string_with_options_async("p #{name}".into(), options.clone());
+        assert_eq!("<p>pug</p>", html.await.unwrap());
+
+        let mut streamed = Vec::new();
+        evaluate_to_async_writer("test/greeting.pug", options, &mut streamed)
+            .await
+            .unwrap();
+        assert_eq!(b"<p>Hello, pug!</p>", streamed.as_slice());
+    }
+
+    #[::tokio::test]
+    async fn evaluate_with_string() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_string_async(String::from("h1 hello pug"))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[::tokio::test]
+    async fn evaluate_with_string_and_json() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_string_with_options_async(
+                String::from("h1 hello #{language}"),
+                PugOptions::new().with_object(r#"{"language": "pug"}"#)
+            )
+            .await
+            .unwrap()
+        )
+    }
+
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn concurrent_renders() {
+        let renders: Vec<_> = (0..8)
+            .map(|i| {
+                ::tokio::spawn(evaluate_string_with_options_async(
+                    String::from("p #{n}"),
+                    PugOptions::new().with_object(format!(r#"{{"n": {}}}"#, i)),
+                ))
+            })
+            .collect();
+
+        for (i, render) in renders.into_iter().enumerate() {
+            assert_eq!(format!("<p>{}</p>", i), render.await.unwrap().unwrap());
+        }
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test]
+    async fn timeout_kills_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > '{}'\nexec sleep 30\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "slow-pug", &script);
+
+        let timeout = Duration::from_millis(300);
+        let result = evaluate_string_with_options_async(
+            String::from("h1 slow"),
+            PugOptions::new().binary(binary).timeout(timeout),
+        )
+        .await;
+
+        assert!(matches!(result, Err(CompileError::Timeout(t)) if t == timeout));
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        assert!(!process_exists(&pid));
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test]
+    async fn dropping_the_future_kills_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        // never reads stdin, so the writer is blocked when the future is dropped
+        let script = format!("echo $$ > '{}'\nexec sleep 30\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "slow-pug", &script);
+
+        let render = ::tokio::spawn(evaluate_string_with_options_async(
+            "p x\n".repeat(1_000_000),
+            PugOptions::new().binary(binary),
+        ));
+        ::tokio::time::sleep(Duration::from_millis(300)).await;
+        render.abort();
+        assert!(render.await.unwrap_err().is_cancelled());
+
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        assert!(wait_for_exit(&pid, Duration::from_secs(2)).await);
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test]
+    async fn child_exiting_before_reading_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = fake_binary(dir.path(), "failing-pug", "echo oops >&2\nexit 1\n");
+
+        let result = evaluate_string_with_options_async(
+            "p x\n".repeat(1_000_000),
+            PugOptions::new().binary(binary),
+        )
+        .await;
+        assert!(matches!(result, Err(CompileError::PugError(e)) if e.trim() == "oops"));
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test]
+    async fn terse_errors_get_a_snippet_from_the_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = "cat > /dev/null\nprintf 'Error: Pug:2:3\\n\\nboom\\n' >&2\nexit 1\n";
+        let binary = fake_binary(dir.path(), "terse-pug", script);
+
+        let error = evaluate_string_with_options_async(
+            "ul\n  li bad\np after".into(),
+            PugOptions::new().binary(binary),
+        )
+        .await
+        .unwrap_err();
+        let details = error.details().unwrap();
+        assert_eq!("boom", details.message);
+        assert_eq!(Some(2), details.marked);
+        assert_eq!(
+            vec![
+                (1, "ul".to_owned()),
+                (2, "  li bad".to_owned()),
+                (3, "p after".to_owned())
+            ],
+            details.snippet
+        );
+    }
+
+    #[::tokio::test]
+    async fn streams_into_a_writer() {
+        let mut html = Vec::new();
+        let written = evaluate_to_async_writer("test/hello.pug", PugOptions::new(), &mut html)
+            .await
+            .unwrap();
+        assert_eq!(b"<h1>hello pug</h1>", &html[..]);
+        assert_eq!(html.len() as u64, written);
+    }
+
+    /// Accepts `limit` bytes, then fails like a closed connection.
+    struct FailingWriter {
+        limit: usize,
+        written: usize,
+    }
+
+    impl AsyncWrite for FailingWriter {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            if self.written >= self.limit {
+                return std::task::Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let accepted = buf.len().min(self.limit - self.written);
+            self.written += accepted;
+            std::task::Poll::Ready(Ok(accepted))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test]
+    async fn failing_writer_kills_the_child() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("echo $$ > '{}'\nexec yes hello\n", pid_file.display());
+        let binary = fake_binary(dir.path(), "endless-pug", &script);
+
+        let mut writer = FailingWriter {
+            limit: 1000,
+            written: 0,
+        };
+        let result = evaluate_string_to_async_writer(
+            String::from("p endless"),
+            PugOptions::new().binary(binary),
+            &mut writer,
+        )
+        .await;
+
+        assert!(
+            matches!(result, Err(CompileError::Io(e)) if e.kind() == io::ErrorKind::ConnectionReset)
+        );
+        assert_eq!(1000, writer.written);
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        assert!(!process_exists(&pid));
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test]
+    async fn sandbox_rejects_includes_and_bounds_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let ran = dir.path().join("ran");
+        let never = fake_binary(dir.path(), "never", &format!("touch '{}'\n", ran.display()));
+        let include = evaluate_string_with_options_async(
+            "p before\ninclude /etc/hostname".into(),
+            PugOptions::new().binary(never).sandbox(),
+        )
+        .await;
+        assert!(
+            matches!(include, Err(CompileError::Sandbox(_))),
+            "{:?}",
+            include
+        );
+        assert!(!ran.exists());
+
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "echo $$ > '{}'\ncat > /dev/null\nexec yes '<p>flood</p>'\n",
+            pid_file.display()
+        );
+        let flood = fake_binary(dir.path(), "flood", &script);
+        let sandboxed = || PugOptions::new().binary(&flood).sandbox();
+        let flooded = evaluate_string_with_options_async("p".into(), sandboxed()).await;
+        assert!(matches!(flooded, Err(CompileError::OutputLimit(limit)) if limit == 1 << 20));
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        assert!(!process_exists(&pid));
+        let mut page = Vec::new();
+        let streamed = evaluate_string_to_async_writer("p".into(), sandboxed(), &mut page).await;
+        assert!(matches!(streamed, Err(CompileError::OutputLimit(_))));
+        assert!(page.is_empty());
+
+        let errors = fake_binary(
+            dir.path(),
+            "errors",
+            "cat > /dev/null\nexec yes error >&2\n",
+        );
+        let options = PugOptions::new().binary(errors).sandbox();
+        let flooded = evaluate_string_with_options_async("p".into(), options).await;
+        assert!(matches!(flooded, Err(CompileError::OutputLimit(_))));
+
+        let options = PugOptions::new().sandbox();
+        let output = evaluate_string_with_options_async("p hello".into(), options).await;
+        assert_eq!("<p>hello</p>", output.unwrap());
+    }
+
+    #[::tokio::test]
+    async fn prelude_errors_count_from_the_template() {
+        let options = PugOptions::new()
+            .prelude_file("test/prelude/buttons.pug")
+            .prelude("mixin note(text)\n  p.note #{text}");
+        let error = evaluate_string_with_options_async("p ok\n| piped\n  b bad".into(), options)
+            .await
+            .unwrap_err();
+
+        let details = error.details().unwrap();
+        assert_eq!(3, details.line);
+        assert_eq!((1, "p ok".to_owned()), details.snippet[0]);
+    }
+
+    #[::tokio::test]
+    async fn source_names_label_string_template_errors() {
+        let options = PugOptions::new().source_name("emails/welcome");
+        let error = evaluate_string_with_options_async("p ok\n| piped\n  b bad".into(), options)
+            .await
+            .unwrap_err();
+
+        let details = error.details().unwrap();
+        assert_eq!("emails/welcome", details.filename);
+        assert_eq!(3, details.line);
+        assert!(
+            error.to_string().contains("emails/welcome:3:3"),
+            "{}",
+            error
+        );
+    }
+
+    #[::tokio::test]
+    async fn large_input() {
+        let template = "p hello\n".repeat(100_000);
+        let html = evaluate_string_async(template).await.unwrap();
+        assert_eq!("<p>hello</p>".repeat(100_000), html);
+    }
+}
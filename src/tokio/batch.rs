@@ -0,0 +1,119 @@
+//! Compiling many templates from one task with a cap on concurrent children.
+
+use super::evaluate_with_options_async;
+use crate::{CompileError, PugOptions};
+use futures_util::stream::{self, StreamExt};
+use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
+
+/// Compiles every input with at most `concurrency` pug processes running at
+/// once.
+///
+/// Results are returned in input order, whatever order the compiles finish in.
+pub async fn compile_many_async<I>(
+    inputs: I,
+    options: PugOptions,
+    concurrency: usize,
+) -> Vec<(PathBuf, Result<String, CompileError>)>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    compile_many_async_cancellable(inputs, options, concurrency, CancellationToken::new()).await
+}
+
+/// Like `compile_many_async`, but stops once `cancel` is triggered.
+///
+/// Compiles still in flight at that point are abandoned, and they and every
+/// input not yet started report `CompileError::Cancelled`.
+pub async fn compile_many_async_cancellable<I>(
+    inputs: I,
+    options: PugOptions,
+    concurrency: usize,
+    cancel: CancellationToken,
+) -> Vec<(PathBuf, Result<String, CompileError>)>
+where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+{
+    let inputs: Vec<PathBuf> = inputs.into_iter().map(Into::into).collect();
+
+    let compiles = stream::iter(inputs.clone().into_iter().enumerate())
+        .map(|(index, path)| {
+            let options = options.clone();
+            let cancel = cancel.clone();
+            async move {
+                let result = ::tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => Err(CompileError::Cancelled),
+                    result = evaluate_with_options_async(path, options) => result,
+                };
+                (index, result)
+            }
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut results: Vec<Option<Result<String, CompileError>>> =
+        inputs.iter().map(|_| None).collect();
+    let mut compiles = Box::pin(compiles);
+    while let Some((index, result)) = compiles.next().await {
+        results[index] = Some(result);
+    }
+
+    inputs
+        .into_iter()
+        .zip(results)
+        .map(|(path, result)| (path, result.unwrap_or(Err(CompileError::Cancelled))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::{counting_binary, max_overlap};
+    use std::fs;
+
+    #[cfg(unix)]
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn preserves_order_and_bounds_concurrency() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let binary = counting_binary(dir.path(), &log);
+
+        let inputs: Vec<PathBuf> = (0..20)
+            .map(|i| {
+                let path = dir.path().join(format!("page{}.pug", i));
+                fs::write(&path, format!("p {}", i)).unwrap();
+                path
+            })
+            .collect();
+
+        let results = compile_many_async(inputs.clone(), PugOptions::new().binary(binary), 4).await;
+
+        assert_eq!(20, results.len());
+        for (input, (path, result)) in inputs.iter().zip(results) {
+            assert_eq!(input, &path);
+            assert_eq!(input.to_string_lossy(), result.unwrap());
+        }
+        let overlap = max_overlap(&fs::read_to_string(&log).unwrap());
+        assert!(overlap <= 4, "{} compiles ran at once", overlap);
+    }
+
+    #[::tokio::test]
+    async fn cancelled_before_start() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let results = compile_many_async_cancellable(
+            vec!["test/hello.pug", "test/hello.pug"],
+            PugOptions::new(),
+            2,
+            cancel,
+        )
+        .await;
+        assert_eq!(2, results.len());
+        for (_, result) in results {
+            assert!(matches!(result, Err(CompileError::Cancelled)));
+        }
+    }
+}
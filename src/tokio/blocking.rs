@@ -0,0 +1,176 @@
+//! The blocking evaluate functions run on tokio's blocking pool.
+//!
+//! Useful when a caller wants exactly the sync API's behaviour from async
+//! code. Each call occupies a blocking-pool thread for as long as pug runs, so
+//! `Blocking::with_limit` can cap how many of those threads renders may hold.
+
+use crate::{CompileError, PugOptions};
+use ::tokio::sync::Semaphore;
+use ::tokio::task::JoinError;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Runs the blocking API on `spawn_blocking`, optionally limited by a
+/// semaphore.
+///
+/// A render that panics fails with `CompileError::Panicked` instead of taking
+/// the caller down with it.
+#[derive(Clone, Default)]
+pub struct Blocking {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl Blocking {
+    /// No limit beyond the size of the blocking pool itself.
+    pub fn new() -> Self {
+        Blocking { permits: None }
+    }
+
+    /// At most `limit` renders run at once; the rest wait for a permit.
+    pub fn with_limit(limit: usize) -> Self {
+        Blocking::with_semaphore(Arc::new(Semaphore::new(limit.max(1))))
+    }
+
+    /// Shares `semaphore` with whatever else draws permits from it.
+    pub fn with_semaphore(semaphore: Arc<Semaphore>) -> Self {
+        Blocking {
+            permits: Some(semaphore),
+        }
+    }
+
+    pub async fn evaluate(&self, file: impl Into<PathBuf>) -> Result<String, CompileError> {
+        self.evaluate_with_options(file, PugOptions::new()).await
+    }
+
+    pub async fn evaluate_with_options(
+        &self,
+        file: impl Into<PathBuf>,
+        options: PugOptions,
+    ) -> Result<String, CompileError> {
+        let file = file.into();
+        self.run(move || crate::evaluate_with_options(file, options))
+            .await
+    }
+
+    pub async fn evaluate_string(&self, s: String) -> Result<String, CompileError> {
+        self.evaluate_string_with_options(s, PugOptions::new())
+            .await
+    }
+
+    pub async fn evaluate_string_with_options(
+        &self,
+        s: String,
+        options: PugOptions,
+    ) -> Result<String, CompileError> {
+        self.run(move || crate::evaluate_string_with_options(s, options))
+            .await
+    }
+
+    async fn run<F>(&self, render: F) -> Result<String, CompileError>
+    where
+        F: FnOnce() -> Result<String, CompileError> + Send + 'static,
+    {
+        let _permit = match &self.permits {
+            Some(permits) => Some(
+                permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| CompileError::Cancelled)?,
+            ),
+            None => None,
+        };
+        ::tokio::task::spawn_blocking(render)
+            .await
+            .unwrap_or_else(|e| Err(join_error(e)))
+    }
+}
+
+pub(super) fn join_error(error: JoinError) -> CompileError {
+    if !error.is_panic() {
+        return CompileError::Cancelled;
+    }
+    let payload = error.into_panic();
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => (*message).to_owned(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "unknown panic".into()),
+    };
+    CompileError::Panicked(message)
+}
+
+pub async fn evaluate(file: impl Into<PathBuf>) -> Result<String, CompileError> {
+    Blocking::new().evaluate(file).await
+}
+
+pub async fn evaluate_with_options(
+    file: impl Into<PathBuf>,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    Blocking::new().evaluate_with_options(file, options).await
+}
+
+pub async fn evaluate_string(s: String) -> Result<String, CompileError> {
+    Blocking::new().evaluate_string(s).await
+}
+
+pub async fn evaluate_string_with_options(
+    s: String,
+    options: PugOptions,
+) -> Result<String, CompileError> {
+    Blocking::new()
+        .evaluate_string_with_options(s, options)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::{counting_binary, max_overlap};
+    use std::fs;
+
+    #[::tokio::test]
+    async fn evaluates_on_the_blocking_pool() {
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate("test/hello.pug").await.unwrap()
+        );
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            evaluate_string(String::from("h1 hello pug")).await.unwrap()
+        );
+    }
+
+    #[::tokio::test]
+    async fn panics_become_errors() {
+        let result = Blocking::new().run(|| panic!("boom")).await;
+        assert!(matches!(result, Err(CompileError::Panicked(message)) if message == "boom"));
+    }
+
+    #[cfg(unix)]
+    #[::tokio::test(flavor = "multi_thread")]
+    async fn semaphore_caps_concurrent_renders() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let options = PugOptions::new().binary(counting_binary(dir.path(), &log));
+        let page = dir.path().join("page.pug");
+        fs::write(&page, "p page").unwrap();
+
+        let blocking = Blocking::with_limit(3);
+        let renders: Vec<_> = (0..12)
+            .map(|_| {
+                let (blocking, options, page) = (blocking.clone(), options.clone(), page.clone());
+                ::tokio::spawn(async move { blocking.evaluate_with_options(page, options).await })
+            })
+            .collect();
+        for render in renders {
+            render.await.unwrap().unwrap();
+        }
+
+        let overlap = max_overlap(&fs::read_to_string(&log).unwrap());
+        assert!(overlap <= 3, "{} renders ran at once", overlap);
+    }
+}
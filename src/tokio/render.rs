@@ -0,0 +1,111 @@
+//! An object-safe abstraction over the ways of rendering a named template.
+
+use super::blocking::join_error;
+use super::{evaluate_with_options_async, AsyncRenderer};
+use crate::{CompileError, PugOptions, TemplateRegistry};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+
+pub type RenderFuture<'a> = Pin<Box<dyn Future<Output = Result<String, CompileError>> + Send + 'a>>;
+
+/// Something that can render a named template with locals.
+///
+/// The future is boxed so the trait stays object-safe: handlers can hold an
+/// `Arc<dyn Render>` without knowing which backend sits behind it.
+pub trait Render: Send + Sync {
+    fn render<'a>(&'a self, name: &'a str, locals: Value) -> RenderFuture<'a>;
+}
+
+/// Treats `name` as a template path.
+impl Render for AsyncRenderer {
+    fn render<'a>(&'a self, name: &'a str, locals: Value) -> RenderFuture<'a> {
+        Box::pin(self.render_file(name, locals))
+    }
+}
+
+/// Renders the template registered as `name` on tokio's blocking pool, since
+/// the registry reads files and waits on pug.
+impl Render for TemplateRegistry {
+    fn render<'a>(&'a self, name: &'a str, locals: Value) -> RenderFuture<'a> {
+        let render = self.deferred(name, locals);
+        Box::pin(async move {
+            ::tokio::task::spawn_blocking(render?)
+                .await
+                .unwrap_or_else(|e| Err(join_error(e)))
+        })
+    }
+}
+
+/// Renders by spawning pug for every call, treating `name` as a template path.
+#[derive(Clone, Default)]
+pub struct SpawnRenderer {
+    options: PugOptions,
+}
+
+impl SpawnRenderer {
+    pub fn new(options: PugOptions) -> Self {
+        SpawnRenderer { options }
+    }
+}
+
+impl Render for SpawnRenderer {
+    fn render<'a>(&'a self, name: &'a str, locals: Value) -> RenderFuture<'a> {
+        let options = self.options.clone().with_object(locals);
+        Box::pin(evaluate_with_options_async(name, options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// Answers with the requested file name followed by `locals.language`.
+    const FAKE_SHIM: &str = r#"
+        require('readline').createInterface({ input: process.stdin }).on('line', (line) => {
+            const request = JSON.parse(line);
+            const html = request.file + ' ' + request.locals.language;
+            process.stdout.write(JSON.stringify({ id: request.id, html }) + '\n');
+        });
+    "#;
+
+    async fn render_hello(renderer: Arc<dyn Render>, name: &str) -> String {
+        renderer
+            .render(name, json!({ "language": "pug" }))
+            .await
+            .unwrap()
+    }
+
+    #[::tokio::test]
+    async fn renders_through_trait_objects() {
+        let persistent: Arc<dyn Render> = Arc::new(AsyncRenderer::with_command(
+            PugOptions::new(),
+            "node",
+            vec!["-e".into(), FAKE_SHIM.into()],
+        ));
+        let spawning: Arc<dyn Render> = Arc::new(SpawnRenderer::default());
+
+        let dir = tempfile::tempdir().unwrap();
+        let template = dir.path().join("hello.pug");
+        std::fs::write(&template, "h1 hello #{language}").unwrap();
+        let name = template.to_str().unwrap();
+
+        assert_eq!(
+            format!("{} pug", name),
+            render_hello(persistent, name).await
+        );
+        assert_eq!("<h1>hello pug</h1>", render_hello(spawning, name).await);
+
+        let mut registry = TemplateRegistry::new(PugOptions::new(), crate::Reload::Pinned);
+        registry.register("hello", &template);
+        let registry: Arc<dyn Render> = Arc::new(registry);
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            render_hello(registry.clone(), "hello").await
+        );
+        let missing = registry.render("nope", json!({})).await.unwrap_err();
+        assert!(matches!(missing, CompileError::UnknownTemplate(_)));
+    }
+}
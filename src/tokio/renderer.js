@@ -0,0 +1,30 @@
+// Persistent renderer driven by pug_cli::tokio::AsyncRenderer.
+//
+// Reads one JSON request per line on stdin:
+//   {"id": 1, "source": "h1 hi"} or {"id": 1, "file": "page.pug"}
+//   plus optional "locals" and "options" objects
+// and answers each with one JSON line on stdout:
+//   {"id": 1, "html": "<h1>hi</h1>"} or {"id": 1, "error": "..."}
+const pug = require(process.env.PUG_CLI_PUG_MODULE || 'pug');
+const readline = require('readline');
+
+readline.createInterface({ input: process.stdin }).on('line', (line) => {
+  let request;
+  try {
+    request = JSON.parse(line);
+  } catch (e) {
+    return;
+  }
+
+  let response;
+  try {
+    const options = Object.assign({}, request.options, request.locals);
+    const html = request.file !== undefined
+      ? pug.renderFile(request.file, options)
+      : pug.render(request.source, options);
+    response = { id: request.id, html: html };
+  } catch (e) {
+    response = { id: request.id, error: String((e && e.message) || e) };
+  }
+  process.stdout.write(JSON.stringify(response) + '\n');
+});
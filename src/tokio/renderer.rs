@@ -0,0 +1,404 @@
+//! A long-lived node process that renders templates on request, so rendering
+//! doesn't pay for starting node and loading pug every time.
+
+use crate::pug_error::pug_failure;
+use crate::{CompileError, PugOptions};
+use ::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use ::tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+use ::tokio::sync::{mpsc, oneshot, Mutex, Notify};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const SHIM: &str = include_str!("renderer.js");
+
+type Reply = oneshot::Sender<Result<String, CompileError>>;
+
+/// Requests waiting for an answer, or `None` once the child has exited.
+type Pending = Arc<std::sync::Mutex<Option<HashMap<u64, Reply>>>>;
+
+struct Connection {
+    /// Request lines for `write_requests`, which writes each whole even if
+    /// the request that sent it is dropped.
+    requests: mpsc::UnboundedSender<String>,
+    pending: Pending,
+    kill: Arc<Notify>,
+}
+
+impl Connection {
+    fn is_alive(&self) -> bool {
+        self.pending.lock().unwrap().is_some()
+    }
+}
+
+struct Inner {
+    options: PugOptions,
+    program: PathBuf,
+    args: Vec<String>,
+    connection: Mutex<Option<Connection>>,
+    next_id: AtomicU64,
+}
+
+/// Renders templates through one persistent node process.
+///
+/// Concurrent `render` calls share the process; each request is tagged with an
+/// id and matched to its response, so answers may arrive in any order. If the
+/// process dies, every request in flight fails with
+/// `CompileError::RendererExited` and the next call starts a fresh process.
+///
+/// When the options carry a timeout, a render that doesn't answer in time
+/// fails with `CompileError::Timeout` and the process is killed, since a
+/// template stuck in a loop blocks every other request behind it.
+///
+/// The shim loads pug with `require("pug")`, so the module must be resolvable
+/// from the working directory or `NODE_PATH`; setting `PUG_CLI_PUG_MODULE`
+/// points it at a specific install instead.
+///
+/// `Send` and `Sync`, so one renderer can serve every handler.
+#[derive(Clone)]
+pub struct AsyncRenderer {
+    inner: Arc<Inner>,
+}
+
+impl AsyncRenderer {
+    /// Uses `pretty`, `doctype`, `no_debug` and the path from `options` for
+    /// every render.
+    pub fn new(options: PugOptions) -> Self {
+        AsyncRenderer::with_command(options, "node", vec!["-e".into(), SHIM.into()])
+    }
+
+    /// Runs `program` with `args` instead of the bundled shim. The process
+    /// must speak the same line-delimited JSON protocol.
+    pub fn with_command(
+        options: PugOptions,
+        program: impl Into<PathBuf>,
+        args: Vec<String>,
+    ) -> Self {
+        AsyncRenderer {
+            inner: Arc::new(Inner {
+                options,
+                program: program.into(),
+                args,
+                connection: Mutex::new(None),
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Renders pug source with the given locals.
+    pub async fn render(
+        &self,
+        template: impl Into<String>,
+        locals: Value,
+    ) -> Result<String, CompileError> {
+        self.request(json!({ "source": template.into(), "locals": locals }))
+            .await
+    }
+
+    /// Renders a template file with the given locals.
+    pub async fn render_file(
+        &self,
+        path: impl AsRef<Path>,
+        locals: Value,
+    ) -> Result<String, CompileError> {
+        let file = path.as_ref().to_string_lossy();
+        self.request(json!({ "file": file, "locals": locals }))
+            .await
+    }
+
+    async fn request(&self, mut request: Value) -> Result<String, CompileError> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        request["id"] = id.into();
+        request["options"] = self.pug_options();
+        let mut line = request.to_string();
+        line.push('\n');
+
+        let (reply, response) = oneshot::channel();
+        let (pending, kill) = {
+            let mut connection = self.inner.connection.lock().await;
+            if !connection.as_ref().is_some_and(Connection::is_alive) {
+                *connection = Some(self.spawn()?);
+            }
+            let connection = connection.as_mut().unwrap();
+
+            match connection.pending.lock().unwrap().as_mut() {
+                Some(pending) => pending.insert(id, reply),
+                None => return Err(CompileError::RendererExited("renderer exited".into())),
+            };
+            if connection.requests.send(line).is_err() {
+                if let Some(pending) = connection.pending.lock().unwrap().as_mut() {
+                    pending.remove(&id);
+                }
+                return Err(CompileError::RendererExited("renderer exited".into()));
+            }
+            (connection.pending.clone(), connection.kill.clone())
+        };
+
+        let exited = |_| Err(CompileError::RendererExited("renderer exited".into()));
+        match self.inner.options.timeout {
+            None => response.await.unwrap_or_else(exited),
+            Some(timeout) => ::tokio::select! {
+                response = response => response.unwrap_or_else(exited),
+                _ = ::tokio::time::sleep(timeout) => {
+                    // retire the connection before anyone else can queue on it
+                    fail_pending(&pending, "renderer killed after a render timed out");
+                    kill.notify_one();
+                    Err(CompileError::Timeout(timeout))
+                }
+            },
+        }
+    }
+
+    fn pug_options(&self) -> Value {
+        let options = &self.inner.options;
+        let mut pug_options = json!({
+            "pretty": options.pretty,
+            "compileDebug": !options.no_debug,
+        });
+        if let Some(doctype) = &options.doctype {
+            pug_options["doctype"] = doctype.as_str().into();
+        }
+        if let Some(path) = &options.path {
+            pug_options["filename"] = path.to_string_lossy().into();
+        }
+        pug_options
+    }
+
+    fn spawn(&self) -> Result<Connection, CompileError> {
+        let mut child = Command::new(&self.inner.program)
+            .args(&self.inner.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(CompileError::io_at("run the renderer", &self.inner.program))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let pending: Pending = Arc::new(std::sync::Mutex::new(Some(HashMap::new())));
+        let kill = Arc::new(Notify::new());
+
+        let (requests, lines) = mpsc::unbounded_channel();
+        ::tokio::spawn(write_requests(stdin, lines, pending.clone(), kill.clone()));
+        ::tokio::spawn(read_responses(
+            child,
+            stdout,
+            stderr,
+            pending.clone(),
+            kill.clone(),
+        ));
+
+        Ok(Connection {
+            requests,
+            pending,
+            kill,
+        })
+    }
+}
+
+/// Writes request lines to the child until the connection is dropped. A
+/// request is written here rather than by `request` so that dropping its
+/// future can't leave half a line on the pipe for the next one to follow.
+async fn write_requests(
+    mut stdin: ChildStdin,
+    mut lines: mpsc::UnboundedReceiver<String>,
+    pending: Pending,
+    kill: Arc<Notify>,
+) {
+    while let Some(line) = lines.recv().await {
+        if let Err(e) = stdin.write_all(line.as_bytes()).await {
+            fail_pending(&pending, &format!("couldn't write to the renderer: {}", e));
+            kill.notify_one();
+            return;
+        }
+    }
+}
+
+/// Delivers responses until the child closes stdout or `kill` is notified,
+/// then fails whatever is still waiting.
+async fn read_responses(
+    mut child: Child,
+    stdout: ChildStdout,
+    mut stderr: ChildStderr,
+    pending: Pending,
+    kill: Arc<Notify>,
+) {
+    let deliver = async {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let response: Value = match serde_json::from_str(&line) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+            let id = match response["id"].as_u64() {
+                Some(id) => id,
+                None => continue,
+            };
+            let result = match response["html"].as_str() {
+                Some(html) => Ok(html.to_owned()),
+                None => Err(pug_failure(
+                    response["error"].as_str().unwrap_or_default().to_owned(),
+                )),
+            };
+            let reply = pending.lock().unwrap().as_mut().and_then(|p| p.remove(&id));
+            if let Some(reply) = reply {
+                let _ = reply.send(result);
+            }
+        }
+    };
+    let mut error_output = String::new();
+    let killed = ::tokio::select! {
+        _ = async { ::tokio::join!(deliver, stderr.read_to_string(&mut error_output)) } => false,
+        _ = kill.notified() => true,
+    };
+    if killed {
+        let _ = child.start_kill();
+    }
+
+    let reason = match child.wait().await {
+        _ if killed => "renderer killed after a render timed out".to_owned(),
+        Ok(status) if error_output.trim().is_empty() => format!("renderer exited with {}", status),
+        Ok(status) => format!("renderer exited with {}: {}", status, error_output.trim()),
+        Err(e) => e.to_string(),
+    };
+    fail_pending(&pending, &reason);
+}
+
+/// Marks the connection dead and fails every request still waiting on it.
+fn fail_pending(pending: &Pending, reason: &str) {
+    let waiting = pending.lock().unwrap().take().unwrap_or_default();
+    for (_, reply) in waiting {
+        let _ = reply.send(Err(CompileError::RendererExited(reason.to_owned())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Answers `source + " " + locals.n` after `locals.delay` milliseconds,
+    /// and exits when asked to render `die`.
+    const FAKE_SHIM: &str = r#"
+        require('readline').createInterface({ input: process.stdin }).on('line', (line) => {
+            const request = JSON.parse(line);
+            if (request.source === 'die') process.exit(3);
+            setTimeout(() => {
+                const html = request.source + ' ' + request.locals.n;
+                process.stdout.write(JSON.stringify({ id: request.id, html }) + '\n');
+            }, request.locals.delay || 0);
+        });
+    "#;
+
+    fn fake_renderer() -> AsyncRenderer {
+        AsyncRenderer::with_command(
+            PugOptions::new(),
+            "node",
+            vec!["-e".into(), FAKE_SHIM.into()],
+        )
+    }
+
+    #[::tokio::test]
+    async fn pairs_overlapping_responses() {
+        let renderer = fake_renderer();
+        let renders: Vec<_> = (0..10u64)
+            .map(|n| {
+                let renderer = renderer.clone();
+                // later requests answer first
+                let locals = json!({ "n": n, "delay": (10 - n) * 20 });
+                ::tokio::spawn(async move { renderer.render(format!("t{}", n), locals).await })
+            })
+            .collect();
+
+        for (n, render) in renders.into_iter().enumerate() {
+            assert_eq!(format!("t{} {}", n, n), render.await.unwrap().unwrap());
+        }
+    }
+
+    #[::tokio::test]
+    async fn child_death_fails_in_flight_and_respawns() {
+        let renderer = fake_renderer();
+        let slow = {
+            let renderer = renderer.clone();
+            ::tokio::spawn(async move {
+                renderer
+                    .render("slow", json!({ "n": 1, "delay": 5000 }))
+                    .await
+            })
+        };
+        ::tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let died = renderer.render("die", json!({})).await;
+        assert!(matches!(died, Err(CompileError::RendererExited(_))));
+        assert!(matches!(
+            slow.await.unwrap(),
+            Err(CompileError::RendererExited(_))
+        ));
+
+        assert_eq!(
+            "again 2",
+            renderer.render("again", json!({ "n": 2 })).await.unwrap()
+        );
+    }
+
+    #[::tokio::test]
+    async fn timeout_kills_and_respawns() {
+        let renderer = AsyncRenderer::with_command(
+            PugOptions::new().timeout(Duration::from_millis(200)),
+            "node",
+            vec!["-e".into(), FAKE_SHIM.into()],
+        );
+
+        let stuck = renderer
+            .render("stuck", json!({ "n": 1, "delay": 5000 }))
+            .await;
+        assert!(matches!(stuck, Err(CompileError::Timeout(_))));
+        assert_eq!(
+            "next 2",
+            renderer.render("next", json!({ "n": 2 })).await.unwrap()
+        );
+    }
+
+    #[::tokio::test]
+    async fn abandoned_requests_leave_whole_lines() {
+        // a shim that reads slowly, and far more than a pipe holds, so the
+        // write is still going when the render is dropped
+        let shim = format!(
+            "{} process.stdin.on('data', () => {{
+                process.stdin.pause();
+                setTimeout(() => process.stdin.resume(), 50);
+            }});",
+            FAKE_SHIM
+        );
+        let renderer =
+            AsyncRenderer::with_command(PugOptions::new(), "node", vec!["-e".into(), shim]);
+        let large = "x".repeat(2 << 20);
+        let abandoned = {
+            let renderer = renderer.clone();
+            ::tokio::spawn(async move { renderer.render(large, json!({ "n": 1 })).await })
+        };
+        ::tokio::time::sleep(Duration::from_millis(500)).await;
+        abandoned.abort();
+
+        let next = renderer.render("next", json!({ "n": 2 }));
+        let next = ::tokio::time::timeout(Duration::from_secs(20), next).await;
+        assert_eq!("next 2", next.expect("the next render hung").unwrap());
+    }
+
+    #[::tokio::test]
+    async fn renders_with_pug() {
+        let renderer = AsyncRenderer::new(PugOptions::new());
+        assert_eq!(
+            "<h1>hello pug</h1>",
+            renderer
+                .render("h1 hello #{language}", json!({ "language": "pug" }))
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,434 @@
+//! Recompiles templates under a directory as they change and reports each
+//! compile as an item of a `Stream`.
+
+use super::evaluate_with_options_async;
+use crate::{CompileError, ErrorPageOptions, OutputNaming, PugOptions};
+use ::tokio::sync::{mpsc, Notify};
+use ::tokio::task::JoinHandle;
+use futures_core::Stream;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The outcome of recompiling one changed template.
+pub struct WatchEvent {
+    pub path: PathBuf,
+    pub result: Result<String, CompileError>,
+    pub duration: Duration,
+}
+
+/// What the watcher does when the consumer falls behind and the channel is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for the consumer before compiling anything else.
+    Block,
+    /// Discard the oldest queued event to make room for the newest one.
+    DropOldest,
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchOptions {
+    capacity: usize,
+    backpressure: Backpressure,
+    interval: Duration,
+    output_dir: Option<PathBuf>,
+    error_overlay: bool,
+}
+
+impl WatchOptions {
+    pub fn new() -> Self {
+        WatchOptions {
+            capacity: 16,
+            backpressure: Backpressure::Block,
+            interval: Duration::from_millis(250),
+            output_dir: None,
+            error_overlay: false,
+        }
+    }
+
+    /// How many events may be queued before backpressure kicks in.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// How often the directory is scanned for modified templates.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Also writes each compiled template to `dir`, at its path relative to
+    /// the watched directory with an `.html` extension.
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(dir.into());
+        self
+    }
+
+    /// When a compile fails, writes the error's HTML page over the output
+    /// file instead of leaving the last good page in place, so a refresh
+    /// shows what went wrong. The next successful compile replaces it.
+    /// Only has an effect together with `output_dir`.
+    pub fn error_overlay(mut self, error_overlay: bool) -> Self {
+        self.error_overlay = error_overlay;
+        self
+    }
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions::new()
+    }
+}
+
+/// A stream of `WatchEvent`s.
+///
+/// The stream ends after `stop()` is called; dropping it stops the watcher.
+pub struct WatchStream {
+    receiver: Arc<Mutex<mpsc::Receiver<WatchEvent>>>,
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl WatchStream {
+    /// Stops watching. Events already queued are still delivered.
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+}
+
+impl Stream for WatchStream {
+    type Item = WatchEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.lock().unwrap().poll_recv(cx)
+    }
+}
+
+impl Drop for WatchStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub fn watch_stream(dir: impl Into<PathBuf>, options: PugOptions) -> WatchStream {
+    watch_stream_with(dir, options, WatchOptions::new())
+}
+
+/// Like `watch_stream`, with control over buffering and the polling interval.
+///
+/// A template is recompiled when it changes, when a file it includes or
+/// extends does, or when the locals file (a `PugJsonObject::Path` or
+/// `JsModule`) does, which recompiles every template.
+///
+/// Must be called from within a tokio runtime.
+pub fn watch_stream_with(
+    dir: impl Into<PathBuf>,
+    options: PugOptions,
+    watch_options: WatchOptions,
+) -> WatchStream {
+    let (sender, receiver) = mpsc::channel(watch_options.capacity);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let stop = Arc::new(Notify::new());
+
+    let task = ::tokio::spawn(watch(
+        dir.into(),
+        options,
+        watch_options,
+        sender,
+        receiver.clone(),
+        stop.clone(),
+    ));
+
+    WatchStream {
+        receiver,
+        stop,
+        task,
+    }
+}
+
+async fn watch(
+    dir: PathBuf,
+    options: PugOptions,
+    watch_options: WatchOptions,
+    sender: mpsc::Sender<WatchEvent>,
+    receiver: Arc<Mutex<mpsc::Receiver<WatchEvent>>>,
+    stop: Arc<Notify>,
+) {
+    let mut seen = scan(dir.clone(), &options).await;
+    let naming = options.output_naming.clone().unwrap_or_default();
+
+    loop {
+        ::tokio::select! {
+            _ = stop.notified() => return,
+            _ = ::tokio::time::sleep(watch_options.interval) => {}
+        }
+
+        let current = scan(dir.clone(), &options).await;
+        for (path, stamps) in &current {
+            if seen.get(path) == Some(stamps) {
+                continue;
+            }
+
+            let start = Instant::now();
+            let mut result = evaluate_with_options_async(path, options.clone()).await;
+            if let Some(output_dir) = &watch_options.output_dir {
+                let page = match &result {
+                    Ok(html) => Some(html.clone()),
+                    Err(error) if watch_options.error_overlay => {
+                        Some(error.to_html(ErrorPageOptions::new()))
+                    }
+                    Err(_) => None,
+                };
+                if let Some(page) = page {
+                    let output = output_path(&dir, output_dir, path, &naming, &page);
+                    let written = match options.file_bytes(&page) {
+                        Ok(bytes) => write_output(&output, &bytes)
+                            .await
+                            .map_err(CompileError::io_at("write page", &output)),
+                        Err(error) => Err(error),
+                    };
+                    if let Err(error) = written {
+                        result = Err(error);
+                    }
+                }
+            }
+            let event = WatchEvent {
+                path: path.clone(),
+                result,
+                duration: start.elapsed(),
+            };
+
+            let delivered = match watch_options.backpressure {
+                Backpressure::Block => sender.send(event).await.is_ok(),
+                Backpressure::DropOldest => send_dropping_oldest(&sender, &receiver, event),
+            };
+            if !delivered {
+                return;
+            }
+        }
+        seen = current;
+    }
+}
+
+fn output_path(
+    dir: &Path,
+    output_dir: &Path,
+    template: &Path,
+    naming: &OutputNaming,
+    html: &str,
+) -> PathBuf {
+    let relative = template.strip_prefix(dir).unwrap_or(template);
+    output_dir.join(naming.page(relative, html))
+}
+
+async fn write_output(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        ::tokio::fs::create_dir_all(parent).await?;
+    }
+    ::tokio::fs::write(path, bytes).await
+}
+
+/// Returns false once the receiving side has gone away.
+fn send_dropping_oldest(
+    sender: &mpsc::Sender<WatchEvent>,
+    receiver: &Mutex<mpsc::Receiver<WatchEvent>>,
+    mut event: WatchEvent,
+) -> bool {
+    loop {
+        match sender.try_send(event) {
+            Ok(()) => return true,
+            Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            Err(mpsc::error::TrySendError::Full(rejected)) => {
+                let _ = receiver.lock().unwrap().try_recv();
+                event = rejected;
+            }
+        }
+    }
+}
+
+/// When each file a template's page depends on was last modified, `None`
+/// for one that's missing, so a template is recompiled when any of them
+/// changes: the template, what it includes or extends, and the locals file.
+type Stamps = Vec<(PathBuf, Option<SystemTime>)>;
+
+async fn scan(dir: PathBuf, options: &PugOptions) -> HashMap<PathBuf, Stamps> {
+    let locals_files = crate::build::locals_files(options);
+    ::tokio::task::spawn_blocking(move || {
+        let mut templates = Vec::new();
+        collect_templates(&dir, &mut templates);
+        templates
+            .into_iter()
+            .map(|template| {
+                let mut dependencies = BTreeSet::new();
+                crate::build::collect_dependencies(&template, &mut dependencies);
+                dependencies.extend(locals_files.iter().cloned());
+                let stamps = dependencies
+                    .into_iter()
+                    .map(|file| {
+                        let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+                        (file, modified)
+                    })
+                    .collect();
+                (template, stamps)
+            })
+            .collect()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+fn collect_templates(dir: &Path, templates: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_templates(&path, templates);
+        } else if path.extension() == Some(OsStr::new("pug")) {
+            templates.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn fast() -> WatchOptions {
+        WatchOptions::new().interval(Duration::from_millis(20))
+    }
+
+    async fn next_event(events: &mut WatchStream) -> WatchEvent {
+        ::tokio::time::timeout(Duration::from_secs(10), events.next())
+            .await
+            .expect("timed out waiting for a watch event")
+            .expect("watch stream ended early")
+    }
+
+    #[::tokio::test]
+    async fn reports_modified_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = dir.path().join("page.pug");
+        fs::write(&page, "h1 first").unwrap();
+
+        let mut events = watch_stream_with(dir.path(), PugOptions::new(), fast());
+        ::tokio::time::sleep(Duration::from_millis(100)).await;
+
+        fs::write(&page, "h1 second").unwrap();
+        let event = next_event(&mut events).await;
+        assert_eq!(page, event.path);
+        assert_eq!("<h1>second</h1>", event.result.unwrap());
+
+        fs::write(dir.path().join("new.pug"), "p new").unwrap();
+        let event = next_event(&mut events).await;
+        assert_eq!(dir.path().join("new.pug"), event.path);
+        assert_eq!("<p>new</p>", event.result.unwrap());
+    }
+
+    #[::tokio::test]
+    async fn recompiles_pages_whose_locals_or_includes_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        let site = data.path().join("site.json");
+        fs::write(&site, r#"{"site": {"name": "Acme"}}"#).unwrap();
+        let page = dir.path().join("page.pug");
+        fs::write(&page, "include partials/nav\nh1 #{site.name}").unwrap();
+        fs::create_dir(dir.path().join("partials")).unwrap();
+        fs::write(dir.path().join("partials/nav.pug"), "nav home").unwrap();
+
+        let options = PugOptions::new().with_object(site.clone());
+        let mut events = watch_stream_with(dir.path(), options, fast());
+        ::tokio::time::sleep(Duration::from_millis(100)).await;
+
+        fs::write(&site, r#"{"site": {"name": "Globex"}}"#).unwrap();
+        let mut rendered = HashMap::new();
+        for _ in 0..2 {
+            let event = next_event(&mut events).await;
+            rendered.insert(event.path, event.result.unwrap());
+        }
+        assert!(
+            rendered[&page].ends_with("<h1>Globex</h1>"),
+            "{:?}",
+            rendered
+        );
+
+        fs::write(dir.path().join("partials/nav.pug"), "nav start").unwrap();
+        let mut rendered = HashMap::new();
+        for _ in 0..2 {
+            let event = next_event(&mut events).await;
+            rendered.insert(event.path, event.result);
+        }
+        // the page that includes it too, not just the partial
+        assert!(rendered.contains_key(&page), "{:?}", rendered.keys());
+    }
+
+    #[::tokio::test]
+    async fn stop_ends_the_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut events = watch_stream_with(dir.path(), PugOptions::new(), fast());
+        events.stop();
+        let end = ::tokio::time::timeout(Duration::from_secs(10), events.next()).await;
+        assert!(end.unwrap().is_none());
+    }
+
+    #[::tokio::test]
+    async fn drop_oldest_keeps_latest_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = dir.path().join("page.pug");
+        fs::write(&page, "p 0").unwrap();
+
+        let options = fast().capacity(1).backpressure(Backpressure::DropOldest);
+        let mut events = watch_stream_with(dir.path(), PugOptions::new(), options);
+        ::tokio::time::sleep(Duration::from_millis(100)).await;
+
+        for i in 1..=3 {
+            fs::write(&page, format!("p {}", i)).unwrap();
+            ::tokio::time::sleep(Duration::from_millis(300)).await;
+        }
+
+        let event = next_event(&mut events).await;
+        assert_eq!("<p>3</p>", event.result.unwrap());
+    }
+
+    #[::tokio::test]
+    async fn error_overlay_replaces_and_restores_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let page = dir.path().join("nested").join("page.pug");
+        fs::create_dir(page.parent().unwrap()).unwrap();
+        fs::write(&page, "h1 first").unwrap();
+        let output = out.path().join("nested").join("page.html");
+
+        let options = fast().output_dir(out.path()).error_overlay(true);
+        let mut events = watch_stream_with(dir.path(), PugOptions::new(), options);
+        ::tokio::time::sleep(Duration::from_millis(100)).await;
+
+        fs::write(&page, "  h1 <broken>").unwrap();
+        assert!(next_event(&mut events).await.result.is_err());
+        let overlay = fs::read_to_string(&output).unwrap();
+        assert!(overlay.contains("unexpected token"), "{}", overlay);
+        assert!(overlay.contains("&lt;broken&gt;"), "{}", overlay);
+
+        fs::write(&page, "h1 fixed").unwrap();
+        assert_eq!(
+            "<h1>fixed</h1>",
+            next_event(&mut events).await.result.unwrap()
+        );
+        assert_eq!("<h1>fixed</h1>", fs::read_to_string(&output).unwrap());
+    }
+}
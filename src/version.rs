@@ -0,0 +1,177 @@
+//! `PugOptions::require_version`: refusing to compile with a pug outside a
+//! declared range.
+//!
+//! The verdict for each binary and requirement is remembered, on top of the
+//! `health` probe it comes from, so checking on every compile costs a lock.
+//! Requirements and versions are read by `semver`, as Cargo reads them.
+
+use crate::health::health_with;
+use crate::invocation::program;
+use crate::{CompileError, PugOptions};
+use semver::{Version, VersionReq};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// What `PugOptions::require_version` and `allow_unknown_version` set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct VersionCheck {
+    pub(crate) requirement: Option<String>,
+    pub(crate) allow_unknown: bool,
+}
+
+#[derive(Clone)]
+enum Verdict {
+    Satisfied,
+    Mismatch(String),
+    /// `--version` ran but said nothing `parse_pug_version` understands, or
+    /// nothing that's a version.
+    Unknown(String),
+}
+
+type Key = (PathBuf, String);
+
+fn verdicts() -> &'static Mutex<HashMap<Key, Verdict>> {
+    static VERDICTS: OnceLock<Mutex<HashMap<Key, Verdict>>> = OnceLock::new();
+    VERDICTS.get_or_init(Default::default)
+}
+
+/// Fails with `CompileError::VersionMismatch` unless the pug `options` would
+/// run satisfies their requirement, if they have one.
+pub(crate) fn check(options: &PugOptions) -> Result<(), CompileError> {
+    let check = match &options.version_check {
+        Some(check) => check,
+        None => return Ok(()),
+    };
+    let required = match &check.requirement {
+        Some(required) => required,
+        None => return Ok(()),
+    };
+    let key = (program(options), required.clone());
+    let cached = verdicts().lock().unwrap().get(&key).cloned();
+    let verdict = match cached {
+        Some(verdict) => verdict,
+        None => {
+            let verdict = judge(options, required)?;
+            verdicts().lock().unwrap().insert(key, verdict.clone());
+            verdict
+        }
+    };
+    match verdict {
+        Verdict::Satisfied => Ok(()),
+        Verdict::Unknown(_) if check.allow_unknown => Ok(()),
+        Verdict::Mismatch(installed) | Verdict::Unknown(installed) => {
+            Err(CompileError::VersionMismatch {
+                installed,
+                required: required.clone(),
+            })
+        }
+    }
+}
+
+fn judge(options: &PugOptions, required: &str) -> Result<Verdict, CompileError> {
+    let requirement = VersionReq::parse(required).map_err(|e| {
+        CompileError::InvalidOptions(format!("{:?} isn't a version requirement: {}", required, e))
+    })?;
+    let installed = match health_with(options) {
+        Ok(health) => health.pug_version,
+        // the probe ran, but its output had no version in it
+        Err(CompileError::Io(e)) if e.kind() == io::ErrorKind::InvalidData => {
+            return Ok(Verdict::Unknown(e.to_string()))
+        }
+        Err(error) => return Err(error),
+    };
+    Ok(match parse_version(&installed) {
+        Some(version) if requirement.matches(&version) => Verdict::Satisfied,
+        Some(_) => Verdict::Mismatch(installed),
+        None => Verdict::Unknown(installed),
+    })
+}
+
+/// `3.0.2`, or `3.0` or `3` with the missing parts 0. A pre-release such as
+/// `4.0.0-beta.1` only satisfies a requirement that names a pre-release of
+/// the same version, `>=4.0.0-beta.0`, as Cargo has it.
+fn parse_version(text: &str) -> Option<Version> {
+    let text = text.trim();
+    let numbers = text.split(['-', '+']).next()?;
+    let padding = match numbers.split('.').count() {
+        1 => ".0.0",
+        2 => ".0",
+        _ => "",
+    };
+    let padded = format!("{}{}{}", numbers, padding, &text[numbers.len()..]);
+    Version::parse(&padded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::test_support::fake_binary;
+
+    #[test]
+    fn reads_requirements_like_cargo() {
+        let satisfies = |required: &str, version: &str| {
+            VersionReq::parse(required)
+                .unwrap()
+                .matches(&parse_version(version).unwrap())
+        };
+        assert!(satisfies(">=3, <4", "3.0.2"));
+        assert!(!satisfies(">=3, <4", "2.0.4"));
+        assert!(!satisfies(">=3, <4", "4.0.0-beta.1"));
+        assert!(satisfies("3", "3.9.0") && !satisfies("3", "4.0.0"));
+        assert!(satisfies("^0.2.1", "0.2.9") && !satisfies("^0.2.1", "0.3.0"));
+        assert!(satisfies("~3.0.1", "3.0.5") && !satisfies("~3.0.1", "3.1.0"));
+        assert!(satisfies("=3.0", "3.0.7") && !satisfies("=3.0", "3.1.0"));
+        assert!(satisfies("<=3.0", "3.0.7") && satisfies(">2", "3.0.0"));
+        assert!(satisfies("*", "2.0.4") && satisfies("3.x", "3.1") && satisfies("3.*", "3"));
+        assert!(!satisfies("3.*", "4.0.0"));
+        assert!(satisfies(">=3.0.0-rc.1", "3.0.0-rc.2") && satisfies(">=3.0.0-rc.1", "3.0.0"));
+        assert!(!satisfies(">=3.0.0-rc.1", "3.0.0-rc.0") && !satisfies(">=3", "3.1.0-rc.1"));
+        assert!(VersionReq::parse("about 3").is_err());
+        assert_eq!(None, parse_version("nightly"));
+        assert_eq!(None, parse_version("3.0.2.1"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_pugs_outside_the_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let count = dir.path().join("count");
+        let pug = |name: &str, version: &str| {
+            let script = format!(
+                "case \"$1\" in\n  --version) echo >> '{}'; echo '{}' ;;\n  --help) ;;\n  *) cat > /dev/null; printf '<p></p>' ;;\nesac\n",
+                count.display(),
+                version
+            );
+            fake_binary(dir.path(), name, &script)
+        };
+        let compile = |binary: &PathBuf, options: PugOptions| {
+            let options = options.binary(binary).require_version(">=3, <4");
+            crate::evaluate_string_with_options("p".into(), options)
+        };
+
+        let three = pug("pug3", "pug version: 3.0.2");
+        assert_eq!("<p></p>", compile(&three, PugOptions::new()).unwrap());
+        assert_eq!("<p></p>", compile(&three, PugOptions::new()).unwrap());
+        assert_eq!(1, std::fs::read_to_string(&count).unwrap().lines().count());
+
+        let two = pug("pug2", "pug version: 2.0.4");
+        match compile(&two, PugOptions::new()) {
+            Err(CompileError::VersionMismatch {
+                installed,
+                required,
+            }) => assert_eq!(("2.0.4", ">=3, <4"), (&*installed, &*required)),
+            other => panic!("{:?}", other),
+        }
+
+        let exotic = pug("exotic", "pug (custom build)");
+        assert!(matches!(
+            compile(&exotic, PugOptions::new()),
+            Err(CompileError::VersionMismatch { .. })
+        ));
+        let allowed = PugOptions::new().allow_unknown_version();
+        assert_eq!("<p></p>", compile(&exotic, allowed).unwrap());
+    }
+}
@@ -0,0 +1,108 @@
+//! Rendering templates from warp filters.
+//!
+//! `PugEngine` is cheap to clone, so move one into the filter closure:
+//!
+//! ```ignore
+//! let engine = PugEngine::new(PugOptions::new());
+//! let home = warp::path::end()
+//!     .and_then(move || pug_cli::warp::render("pages/home.pug", json!({}), engine.clone()))
+//!     .recover(pug_cli::warp::recover);
+//! ```
+
+use crate::tokio::evaluate_with_options_async;
+use crate::{CompileError, PugEngine};
+use serde_json::Value;
+use std::path::PathBuf;
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+use warp::reply::{self, Reply};
+
+impl Reject for CompileError {}
+
+/// Renders `template` with `locals` over the engine's options, rejecting with
+/// the `CompileError` if it fails. With `debug_errors` on, a failure is
+/// answered directly with the error's HTML page instead.
+pub async fn render(
+    template: impl Into<PathBuf>,
+    locals: Value,
+    engine: PugEngine,
+) -> Result<impl Reply, Rejection> {
+    let options = engine.defaults().clone().with_object(locals);
+    match evaluate_with_options_async(template, options).await {
+        Ok(html) => Ok(reply::with_status(reply::html(html), StatusCode::OK)),
+        Err(error) => match engine.error_page(&error) {
+            Some(page) => Ok(reply::with_status(
+                reply::html(page),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )),
+            None => Err(warp::reject::custom(error)),
+        },
+    }
+}
+
+/// Turns a `CompileError` rejection into a plain-text 500 and passes every
+/// other rejection on. Pug's output, which quotes the template, is only
+/// included in debug builds, as with `actix`.
+pub async fn recover(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    match rejection.find::<CompileError>() {
+        Some(error) => Ok(reply::with_status(
+            error_body(error, cfg!(debug_assertions)),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+        None => Err(rejection),
+    }
+}
+
+fn error_body(error: &CompileError, debug: bool) -> String {
+    if !debug {
+        return "Template rendering failed".to_owned();
+    }
+    let message = match error {
+        CompileError::PugError(message) => message.clone(),
+        error => error.to_string(),
+    };
+    format!("Template rendering failed\n\n{}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PugOptions;
+    use serde_json::json;
+    use std::fs;
+    use warp::Filter;
+
+    #[::tokio::test]
+    async fn renders_and_recovers() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("good.pug"), "h1 hello #{language}").unwrap();
+        fs::write(dir.path().join("bad.pug"), "  h1 indented too early").unwrap();
+
+        let engine = PugEngine::new(PugOptions::new());
+        let root = dir.path().to_owned();
+        let pages = warp::path::param()
+            .and_then(move |name: String| {
+                let template = root.join(format!("{}.pug", name));
+                render(template, json!({ "language": "pug" }), engine.clone())
+            })
+            .recover(recover);
+
+        let response = warp::test::request().path("/good").reply(&pages).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers()["content-type"]
+        );
+        assert_eq!("<h1>hello pug</h1>", response.body());
+
+        let response = warp::test::request().path("/bad").reply(&pages).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.starts_with("Template rendering failed"), "{}", body);
+        assert_eq!(cfg!(debug_assertions), body.contains("unexpected token"));
+
+        let error = CompileError::PugError("  > 1|   h1 indented too early".into());
+        assert_eq!("Template rendering failed", error_body(&error, false));
+        assert!(error_body(&error, true).contains("indented too early"));
+    }
+}
@@ -0,0 +1,4 @@
+// Uppercases the filtered block.
+module.exports = function (text) {
+  return text.toUpperCase();
+};
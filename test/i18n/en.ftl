@@ -0,0 +1,14 @@
+# English
+-brand = Acme
+
+nav-home = Home
+nav-about =
+    .title = About { -brand }
+nav-contact = Contact
+    .title = Write to { -brand }
+
+cart =
+    { $count ->
+        [one] One item
+       *[other] { $count } items
+    } in the cart
@@ -0,0 +1 @@
+nav-home = Bienvenue
@@ -0,0 +1,12 @@
+-brand = Acme
+
+nav-home = Accueil
+nav-about =
+    .title = À propos de { -brand }
+nav-contact = Contact
+    .title = Écrire à { -brand }
+
+cart = { $count ->
+        [one] Un article
+       *[other] { $count } articles
+    }
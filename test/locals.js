@@ -0,0 +1,5 @@
+// Locals computed when pug loads them.
+module.exports = {
+  language: 'pug',
+  year: 2000 + 24,
+};
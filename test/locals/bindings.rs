@@ -0,0 +1,41 @@
+// Generated by pug_cli::build. Do not edit.
+
+#[derive(serde::Serialize)]
+pub struct GreetingLocals {
+    pub title: String,
+    #[serde(rename = "userName")]
+    pub user_name: String,
+    pub visits: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer: Option<String>,
+}
+
+impl GreetingLocals {
+    pub const TEMPLATE: &'static str = "test/locals/greeting.pug";
+
+    pub fn render(&self) -> Result<String, pug_cli::CompileError> {
+        let locals = serde_json::to_value(self).expect("locals serialize to JSON");
+        pug_cli::evaluate_with_options(
+            Self::TEMPLATE,
+            pug_cli::PugOptions::new().with_object(locals),
+        )
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct NestedTodoListLocals {
+    pub items: Vec<serde_json::Value>,
+    pub r#type: serde_json::Value,
+}
+
+impl NestedTodoListLocals {
+    pub const TEMPLATE: &'static str = "test/locals/nested/todo-list.pug";
+
+    pub fn render(&self) -> Result<String, pug_cli::CompileError> {
+        let locals = serde_json::to_value(self).expect("locals serialize to JSON");
+        pug_cli::evaluate_with_options(
+            Self::TEMPLATE,
+            pug_cli::PugOptions::new().with_object(locals),
+        )
+    }
+}
@@ -0,0 +1,6 @@
+// Locals that throw when pug reads them.
+module.exports = {
+  get year() {
+    throw new Error('no year in these locals');
+  },
+};
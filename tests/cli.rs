@@ -0,0 +1,82 @@
+#![cfg(feature = "cli")]
+
+use assert_cmd::Command;
+use std::fs;
+
+fn pug_rs() -> Command {
+    Command::cargo_bin("pug-rs").unwrap()
+}
+
+#[test]
+fn prints_a_compiled_template() {
+    let output = pug_rs()
+        .args(["--obj", r#"{"unused": true}"#, "test/hello.pug"])
+        .output()
+        .unwrap();
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "<h1>hello pug</h1>\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+#[test]
+fn writes_a_directory_into_out() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir(src.path().join("pages")).unwrap();
+    fs::write(src.path().join("pages/about.pug"), "p #{who}").unwrap();
+    let locals = src.path().join("locals.json");
+    fs::write(&locals, r#"{"who": "us"}"#).unwrap();
+    let out = tempfile::tempdir().unwrap();
+
+    pug_rs()
+        .arg("--obj")
+        .arg(&locals)
+        .arg("--out")
+        .arg(out.path())
+        .arg(src.path())
+        .assert()
+        .success();
+    assert_eq!(
+        "<p>us</p>",
+        fs::read_to_string(out.path().join("pages/about.html")).unwrap()
+    );
+}
+
+#[test]
+fn exit_codes() {
+    let src = tempfile::tempdir().unwrap();
+    let broken = src.path().join("broken.pug");
+    fs::write(&broken, "  h1 indented too early").unwrap();
+
+    let output = pug_rs().arg(&broken).output().unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("broken.pug:1:3: "));
+
+    pug_rs().assert().code(2);
+    pug_rs()
+        .args(["--obj", "{not json", "test/hello.pug"])
+        .assert()
+        .code(2);
+    pug_rs()
+        .args(["--watch", "test/hello.pug"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn doctor_reports_a_missing_binary() {
+    let output = pug_rs()
+        .args(["doctor", "--pug", "/nonexistent/pug"])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        report.contains("[FAIL] pug binary: /nonexistent/pug not found"),
+        "{}",
+        report
+    );
+
+    pug_rs().arg("doctor").assert().success();
+}